@@ -0,0 +1,72 @@
+//! Reconstructs the full private key from `t+1` shares
+//!
+//! Meant for supervised disaster-recovery drills, run it on an offline machine only. Requires
+//! `emergency-export` feature:
+//!
+//! ```text
+//! cargo run --example emergency_export --features emergency-export -- \
+//!     --i-understand-this-exposes-the-private-key --format xprv local-share1.json local-share3.json
+//! ```
+
+use std::path::PathBuf;
+
+use anyhow::{bail, Context, Result};
+use structopt::StructOpt;
+
+use curv::elliptic::curves::secp256_k1::Secp256k1;
+
+use multi_party_ecdsa::protocols::multi_party_ecdsa::gg_2020::hd_acount::account_manage::raw_share;
+use multi_party_ecdsa::protocols::multi_party_ecdsa::gg_2020::hd_acount::btc_hd::Network;
+use multi_party_ecdsa::protocols::multi_party_ecdsa::gg_2020::hd_acount::emergency_export::ReconstructedKey;
+
+#[derive(Debug, StructOpt)]
+struct Cli {
+    /// Share files of at least t+1 parties
+    #[structopt(required = true, min_values = 2)]
+    shares: Vec<PathBuf>,
+    /// One of: hex, wif, xprv, mnemonic
+    #[structopt(short, long, default_value = "xprv")]
+    format: String,
+    #[structopt(long)]
+    testnet: bool,
+    /// Confirms that reconstructed key is not protected by threshold setup anymore
+    #[structopt(long)]
+    i_understand_this_exposes_the_private_key: bool,
+    /// Password of sealed shares, shares are read as plain JSON if omitted
+    #[structopt(long, env = "SHARE_PASSWORD", hide_env_values = true)]
+    password: Option<String>,
+}
+
+fn main() -> Result<()> {
+    let args: Cli = Cli::from_args();
+    if !args.i_understand_this_exposes_the_private_key {
+        bail!("refusing to reconstruct the private key without --i-understand-this-exposes-the-private-key");
+    }
+
+    let shares = args
+        .shares
+        .iter()
+        .map(|path| {
+            let share = std::fs::read(path)
+                .with_context(|| format!("cannot read share {}", path.display()))?;
+            raw_share::<Secp256k1>::from_bytes(&share, args.password.as_deref())
+                .with_context(|| format!("parse share {}", path.display()))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let key = ReconstructedKey::reconstruct(&shares).context("reconstruct private key")?;
+
+    let network = if args.testnet {
+        Network::Testnet
+    } else {
+        Network::Mainnet
+    };
+    let exported = match args.format.as_str() {
+        "hex" => key.to_hex(),
+        "wif" => key.to_wif(network),
+        "xprv" => key.to_xprv(network),
+        "mnemonic" => key.to_mnemonic(),
+        format => bail!("unknown format {:?}", format),
+    };
+    println!("{}", exported);
+    Ok(())
+}
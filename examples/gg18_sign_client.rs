@@ -9,11 +9,13 @@ use curv::{
     elliptic::curves::{secp256_k1::Secp256k1, Point, Scalar},
     BigInt,
 };
+use multi_party_ecdsa::protocols::multi_party_ecdsa::gg_2018::PROTOCOL_LABEL;
 use multi_party_ecdsa::protocols::multi_party_ecdsa::gg_2018::party_i::{
     Keys, LocalSignature, PartyPrivate, Phase5ADecom1, Phase5Com1, Phase5Com2, Phase5DDecom2,
     SharedKeys, SignBroadcastPhase1, SignDecommitPhase1, SignKeys,
 };
 use multi_party_ecdsa::utilities::mta::*;
+use multi_party_ecdsa::utilities::transcript::ProofContext;
 use sha2::Sha256;
 
 use paillier::EncryptionKey;
@@ -108,7 +110,12 @@ fn main() {
     let xi_com_vec = Keys::get_commitments_to_xi(&vss_scheme_vec);
     //////////////////////////////////////////////////////////////////////////////
     let (com, decommit) = sign_keys.phase1_broadcast();
-    let (m_a_k, _) = MessageA::a(&sign_keys.k_i, &party_keys.ek, &[]);
+    let (m_a_k, _) = MessageA::a(
+        &sign_keys.k_i,
+        &party_keys.ek,
+        &[],
+        &ProofContext::unbound(party_num_int, PROTOCOL_LABEL),
+    );
     assert!(broadcast(
         &client,
         party_num_int,
@@ -155,11 +162,13 @@ fn main() {
     let mut j = 0;
     for i in 1..THRESHOLD + 2 {
         if i != party_num_int {
+            let alice_ctx = ProofContext::unbound(i, PROTOCOL_LABEL);
             let (m_b_gamma, beta_gamma, _, _) = MessageB::b(
                 &sign_keys.gamma_i,
                 &paillier_key_vector[usize::from(signers_vec[usize::from(i - 1)])],
                 m_a_vec[j].clone(),
                 &[],
+                &alice_ctx,
             )
             .unwrap();
             let (m_b_w, beta_wi, _, _) = MessageB::b(
@@ -167,6 +176,7 @@ fn main() {
                 &paillier_key_vector[usize::from(signers_vec[usize::from(i - 1)])],
                 m_a_vec[j].clone(),
                 &[],
+                &alice_ctx,
             )
             .unwrap();
             m_b_gamma_send_vec.push(m_b_gamma);
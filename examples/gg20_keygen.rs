@@ -4,6 +4,7 @@ use std::path::PathBuf;
 use structopt::StructOpt;
 
 use multi_party_ecdsa::protocols::multi_party_ecdsa::gg_2020::state_machine::keygen::Keygen;
+use multi_party_ecdsa::utilities::transcript::SessionId;
 use round_based::async_runtime::AsyncProtocol;
 
 mod gg20_sm_client;
@@ -24,6 +25,11 @@ struct Cli {
     threshold: u16,
     #[structopt(short, long)]
     number_of_parties: u16,
+
+    /// Identifier of this execution which all the parties agreed on, must be fresh for every
+    /// execution
+    #[structopt(long)]
+    session_id: String,
 }
 
 #[tokio::main]
@@ -44,13 +50,19 @@ async fn main() -> Result<()> {
     tokio::pin!(incoming);
     tokio::pin!(outgoing);
 
-    let keygen = Keygen::new(args.index, args.threshold, args.number_of_parties)?;
+    let session_id = SessionId::from_request_id(args.session_id.as_bytes());
+    let keygen = Keygen::new(
+        args.index,
+        args.threshold,
+        args.number_of_parties,
+        session_id,
+    )?;
     let output = AsyncProtocol::new(keygen, incoming, outgoing)
         .run()
         .await
         .map_err(|e| anyhow!("protocol execution terminated with error: {}", e))?;
     
-    let output = serde_json::to_vec_pretty(&output).context("serialize output")?;
+    let output = serde_json::to_vec_pretty(&output.local_key_hd).context("serialize output")?;
     
     tokio::io::copy(&mut output.as_slice(), &mut output_file)
         .await
@@ -11,6 +11,7 @@ use multi_party_ecdsa::protocols::multi_party_ecdsa::gg_2020::state_machine::sig
     OfflineStage, SignManual,
 };
 use multi_party_ecdsa::protocols::multi_party_ecdsa::gg_2020::hd_acount;
+use multi_party_ecdsa::utilities::transcript::SessionId;
 
 use round_based::async_runtime::AsyncProtocol;
 use round_based::Msg;
@@ -31,6 +32,11 @@ struct Cli {
     parties: Vec<u16>,
     #[structopt(short, long)]
     data_to_sign: String,
+
+    /// Identifier of this execution which all the parties agreed on, must be fresh for every
+    /// execution
+    #[structopt(long)]
+    session_id: String,
 }
 
 #[tokio::main]
@@ -52,7 +58,9 @@ async fn main() -> Result<()> {
     tokio::pin!(incoming);
     tokio::pin!(outgoing);
 
-    let signing = OfflineStage::new(i, args.parties, local_share)?;
+    let session_id = format!("{}-offline", args.session_id);
+    let session_id = SessionId::from_request_id(session_id.as_bytes());
+    let signing = OfflineStage::new(i, args.parties, local_share, session_id)?;
     let completed_offline_stage = AsyncProtocol::new(signing, incoming, outgoing)
         .run()
         .await
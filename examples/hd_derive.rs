@@ -1,27 +1,16 @@
 use std::path::PathBuf;
-use std::convert::TryInto;
-use std::{fs, ops::Deref, time};
-use sha2::{Digest,Sha512};
-use hex;
 
 use anyhow::{anyhow, Context, Result};
-use futures::{SinkExt, StreamExt, TryStreamExt};
+use futures::StreamExt;
 use structopt::StructOpt;
 
-use curv::arithmetic::Converter;
-use curv::BigInt;
-use curv::elliptic::curves::{secp256_k1::Secp256k1, Scalar,};
+use curv::elliptic::curves::secp256_k1::Secp256k1;
 
-use multi_party_ecdsa::protocols::multi_party_ecdsa::gg_2020::state_machine::sign::{
-    OfflineStage, SignManual,
-};
-
-
-use multi_party_ecdsa::protocols::multi_party_ecdsa::gg_2020::hd_acount::account_manage::{account_usage,account_path};
 use multi_party_ecdsa::protocols::multi_party_ecdsa::gg_2020::hd_acount;
+use multi_party_ecdsa::protocols::multi_party_ecdsa::gg_2020::hd_acount::account_manage::{account_purpose,account_usage,account_path};
+use multi_party_ecdsa::protocols::multi_party_ecdsa::gg_2020::state_machine::derive::Derive;
 
 use round_based::async_runtime::AsyncProtocol;
-use round_based::Msg;
 
 mod gg20_sm_client;
 use gg20_sm_client::join_computation;
@@ -31,12 +20,22 @@ struct Cli {
     #[structopt(short, long, default_value = "http://localhost:8000/")]
     address: surf::Url,
 
-    #[structopt(short, long, default_value = "default-signing")]
+    #[structopt(short, long, default_value = "default-derive")]
     room: String,
 
     #[structopt(short, long, default_value = "local-share1.json")]
     local_share: PathBuf,
 
+    #[structopt(short, long)]
+    output: PathBuf,
+
+    #[structopt(short, long)]
+    number_of_parties: u16,
+
+    /// Purpose of the account: 44, 49, 84 or 86
+    #[structopt(long, default_value = "44")]
+    purpose: u32,
+
     #[structopt(long, default_value = "0")]
     coin_type: u32,
 
@@ -44,7 +43,18 @@ struct Cli {
     account_id: u32,
 
     #[structopt(long, default_value = "0")]
-    usage: u32,    
+    usage: u32,
+
+    #[structopt(long, default_value = "0")]
+    address_index: u32,
+
+    /// Full bip44 path like `m/44/60/0/0/17`, overrides the other path options
+    #[structopt(long)]
+    path: Option<String>,
+
+    /// Password of sealed share, the share is read as plain JSON if omitted
+    #[structopt(long, env = "SHARE_PASSWORD", hide_env_values = true)]
+    password: Option<String>,
 }
 
 #[tokio::main]
@@ -53,29 +63,59 @@ async fn main() -> Result<()> {
     let local_share = tokio::fs::read(args.local_share)
         .await
         .context("cannot read local share")?;
-    let raw_share:hd_acount::account_manage::raw_share<Secp256k1> = serde_json::from_slice(&local_share).context("parse local share")?;
-
-
-    let coin_type = args.coin_type;
-    let account_id = args.account_id;
-    
-    let usage = match args.usage {
-        0 => { account_usage::Receive},
-        1 => { account_usage::Change},
-        _ => {
-            panic!("Unknown usage: {}", args.usage);
+    let raw_share = hd_acount::account_manage::raw_share::<Secp256k1>::from_bytes(
+        &local_share,
+        args.password.as_deref(),
+    )
+    .context("parse local share")?;
+
+    let ac_path = match &args.path {
+        Some(path) => account_path::from_path_str(path).context("parse path")?,
+        None => {
+            let usage = match args.usage {
+                0 => { account_usage::Receive},
+                1 => { account_usage::Change},
+                _ => {
+                    panic!("Unknown usage: {}", args.usage);
+                }
+            };
+            let purpose = account_purpose::from_num(args.purpose)
+                .ok_or_else(|| anyhow!("Unknown purpose: {}", args.purpose))?;
+            account_path::init(args.coin_type, args.account_id, usage, args.address_index)
+                .with_purpose(purpose)
         }
     };
-    let ac_path = account_path{
-        coin_type : coin_type,
-        account_index : account_id,
-        usage : usage,
-    };
-    let path = ac_path.get_path_string_bip44();
-    
-    let (tweak_sk, y_sum) = 
-    hd_acount::btc_hd::call_hd_key(path.as_str(), &raw_share.local_key_hd, raw_share.chain_code);
-    
+
+    let mut output_file = tokio::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(args.output)
+        .await
+        .context("cannot create output file")?;
+
+    let (i, incoming, outgoing) = join_computation(args.address, &args.room)
+        .await
+        .context("join computation")?;
+
+    let incoming = incoming.fuse();
+    tokio::pin!(incoming);
+    tokio::pin!(outgoing);
+
+    let derive = Derive::new(i, args.number_of_parties, ac_path.clone(), raw_share)?;
+    let output = AsyncProtocol::new(derive, incoming, outgoing)
+        .run()
+        .await
+        .map_err(|e| anyhow!("protocol execution terminated with error: {}", e))?;
+
+    match ac_path.address(&output.y_sum_s) {
+        Ok(address) => println!("{} address: {}", ac_path.get_path_string_bip44(), address),
+        Err(err) => eprintln!("no address for {}: {}", ac_path.get_path_string_bip44(), err),
+    }
+
+    let output = serde_json::to_vec_pretty(&output).context("serialize output")?;
+    tokio::io::copy(&mut output.as_slice(), &mut output_file)
+        .await
+        .context("save output to file")?;
 
     Ok(())
 }
@@ -3,14 +3,12 @@ use futures::StreamExt;
 use std::path::PathBuf;
 use structopt::StructOpt;
 
-use std::convert::TryInto;
-use std::ops::Deref;
-use sha2::{Digest,Sha512};
-
-use bip32::ChainCode;
-
-use multi_party_ecdsa::protocols::multi_party_ecdsa::gg_2020::state_machine::keygen::Keygen;
-use multi_party_ecdsa::protocols::multi_party_ecdsa::gg_2020::hd_acount::account_manage::raw_share;
+use curv::elliptic::curves::{secp256_k1::Secp256k1, Point};
+use multi_party_ecdsa::protocols::multi_party_ecdsa::gg_2020::party_i::AuxInfoProofs;
+use multi_party_ecdsa::protocols::multi_party_ecdsa::gg_2020::state_machine::keygen::{
+    ImportedSecret, KeyImport, Keygen, KeygenConfig,
+};
+use multi_party_ecdsa::utilities::transcript::SessionId;
 use round_based::async_runtime::AsyncProtocol;
 
 mod gg20_sm_client;
@@ -31,6 +29,36 @@ struct Cli {
     threshold: u16,
     #[structopt(short, long)]
     number_of_parties: u16,
+
+    /// Imports extended private key, given to the importing party only
+    #[structopt(long)]
+    import_xprv: Option<String>,
+    /// Index of the importing party, given to the other parties
+    #[structopt(long, requires = "import-public-key")]
+    importer: Option<u16>,
+    /// Hex-encoded compressed public key being imported, given to the other parties
+    #[structopt(long)]
+    import_public_key: Option<String>,
+
+    /// Generates Paillier key and N_tilde from safe primes
+    #[structopt(long)]
+    safe_primes: bool,
+    /// Bit length of Paillier modulus and N_tilde
+    #[structopt(long, default_value = "2048")]
+    modulus_bits: usize,
+    /// Proves Paillier key and N_tilde correctness with CGGMP21 Πmod and Πprm proofs instead
+    /// of the legacy ones, must be given to all the parties
+    #[structopt(long)]
+    cggmp_proofs: bool,
+
+    /// Seals the output share with the password, the share is saved as plain JSON if omitted
+    #[structopt(long, env = "SHARE_PASSWORD", hide_env_values = true)]
+    password: Option<String>,
+
+    /// Identifier of this execution which all the parties agreed on, must be fresh for every
+    /// execution
+    #[structopt(long)]
+    session_id: String,
 }
 
 #[tokio::main]
@@ -51,25 +79,70 @@ async fn main() -> Result<()> {
     tokio::pin!(incoming);
     tokio::pin!(outgoing);
 
-    let keygen = Keygen::new(args.index, args.threshold, args.number_of_parties)?;
+    let config = KeygenConfig {
+        safe_primes: args.safe_primes,
+        modulus_bits: args.modulus_bits,
+        precomputed: None,
+        aux_proofs: if args.cggmp_proofs {
+            AuxInfoProofs::Cggmp
+        } else {
+            AuxInfoProofs::Legacy
+        },
+    };
+    let session_id = SessionId::from_request_id(args.session_id.as_bytes());
+    let keygen = match (args.import_xprv, args.importer, args.import_public_key) {
+        (Some(xprv), _, _) => {
+            let secret = ImportedSecret::from_xprv(&xprv).context("parse xprv")?;
+            let import = secret.key_import(args.index);
+            println!(
+                "importing public key {}",
+                hex::encode(import.public_key.to_bytes(true))
+            );
+            Keygen::new_import(
+                args.index,
+                args.threshold,
+                args.number_of_parties,
+                import,
+                Some(secret),
+                config,
+                session_id,
+            )?
+        }
+        (None, Some(importer), Some(public_key)) => {
+            let public_key = hex::decode(public_key).context("decode public key")?;
+            let import = KeyImport {
+                importer,
+                public_key: Point::<Secp256k1>::from_bytes(&public_key)
+                    .context("parse public key")?,
+                with_chain_code: true,
+            };
+            Keygen::new_import(
+                args.index,
+                args.threshold,
+                args.number_of_parties,
+                import,
+                None,
+                config,
+                session_id,
+            )?
+        }
+        _ => Keygen::new_with_config(
+            args.index,
+            args.threshold,
+            args.number_of_parties,
+            config,
+            session_id,
+        )?,
+    };
     let output = AsyncProtocol::new(keygen, incoming, outgoing)
         .run()
         .await
         .map_err(|e| anyhow!("protocol execution terminated with error: {}", e))?;
 
-    let chain_code: ChainCode = Sha512::digest(&output.y_sum_s.to_bytes(false).deref())[..32]
-    .try_into()
-    .unwrap();
-
-    // let hd_share_m_44_0_0_0 
-    // = HD_Account::init(&output,chain_code,0,0,account_usage::Receive);
-
-    let rs: raw_share<curv::elliptic::curves::Secp256k1> = raw_share{
-        local_key_hd : output,
-        chain_code : chain_code,
+    let output = match &args.password {
+        Some(password) => output.seal(password).context("seal output")?.to_bytes(),
+        None => serde_json::to_vec_pretty(&output).context("serialize output")?,
     };
-    
-    let output = serde_json::to_vec_pretty(&rs).context("serialize output")?;
     tokio::io::copy(&mut output.as_slice(), &mut output_file)
         .await
         .context("save output to file")?;
@@ -10,15 +10,17 @@ use structopt::StructOpt;
 
 use curv::arithmetic::Converter;
 use curv::BigInt;
-use curv::elliptic::curves::{secp256_k1::Secp256k1, Scalar,};
+use curv::elliptic::curves::secp256_k1::Secp256k1;
 
+use multi_party_ecdsa::protocols::multi_party_ecdsa::gg_2020::state_machine::keygen::LocalKey_HD;
 use multi_party_ecdsa::protocols::multi_party_ecdsa::gg_2020::state_machine::sign::{
     OfflineStage, SignManual,
 };
 
 
-use multi_party_ecdsa::protocols::multi_party_ecdsa::gg_2020::hd_acount::account_manage::{account_usage,account_path};
+use multi_party_ecdsa::protocols::multi_party_ecdsa::gg_2020::hd_acount::account_manage::{account_purpose,account_usage,account_path};
 use multi_party_ecdsa::protocols::multi_party_ecdsa::gg_2020::hd_acount;
+use multi_party_ecdsa::utilities::transcript::SessionId;
 
 use round_based::async_runtime::AsyncProtocol;
 use round_based::Msg;
@@ -43,6 +45,10 @@ struct Cli {
     #[structopt(short, long, default_value = "hello")]
     data_to_sign: String,
 
+    /// Purpose of the account: 44, 49, 84 or 86
+    #[structopt(long, default_value = "44")]
+    purpose: u32,
+
     #[structopt(long, default_value = "0")]
     coin_type: u32,
 
@@ -50,7 +56,23 @@ struct Cli {
     account_id: u32,
 
     #[structopt(long, default_value = "0")]
-    usage: u32,    
+    usage: u32,
+
+    #[structopt(long, default_value = "0")]
+    address_index: u32,
+
+    /// Full bip44 path like `m/44/60/0/0/17`, overrides the other path options
+    #[structopt(long)]
+    path: Option<String>,
+
+    /// Password of sealed share, the share is read as plain JSON if omitted
+    #[structopt(long, env = "SHARE_PASSWORD", hide_env_values = true)]
+    password: Option<String>,
+
+    /// Identifier of this execution which all the parties agreed on, must be fresh for every
+    /// execution
+    #[structopt(long)]
+    session_id: String,
 }
 
 #[tokio::main]
@@ -59,34 +81,41 @@ async fn main() -> Result<()> {
     let local_share = tokio::fs::read(args.local_share)
         .await
         .context("cannot read local share")?;
-    let raw_share:hd_acount::account_manage::raw_share<Secp256k1> = serde_json::from_slice(&local_share).context("parse local share")?;
-
-
-    let coin_type = args.coin_type;
-    let account_id = args.account_id;
-    
-    let usage = match args.usage {
-        0 => { account_usage::Receive},
-        1 => { account_usage::Change},
-        _ => {
-            panic!("Unknown usage: {}", args.usage);
+    let raw_share = hd_acount::account_manage::raw_share::<Secp256k1>::from_bytes(
+        &local_share,
+        args.password.as_deref(),
+    )
+    .context("parse local share")?;
+
+
+    let ac_path = match &args.path {
+        Some(path) => account_path::from_path_str(path).context("parse path")?,
+        None => {
+            let usage = match args.usage {
+                0 => { account_usage::Receive},
+                1 => { account_usage::Change},
+                _ => {
+                    panic!("Unknown usage: {}", args.usage);
+                }
+            };
+            let purpose = account_purpose::from_num(args.purpose)
+                .ok_or_else(|| anyhow!("Unknown purpose: {}", args.purpose))?;
+            account_path{
+                purpose : purpose,
+                coin_type : args.coin_type,
+                account_index : args.account_id,
+                usage : usage,
+                address_index : args.address_index,
+            }
         }
     };
-
-
-    let ac_path = account_path{
-        coin_type : coin_type,
-        account_index : account_id,
-        usage : usage,
-    };
     let path = ac_path.get_path_string_bip44();
     
     // let chain_code: [u8; 32] = [2,159,225,26,220,3,10,90,196,167,143,46,129,232,10,246,148,62,105,17,222,213,78,90,3,12,78,30,209,214,26,84];
 
-    let (tweak_sk, y_sum) = 
-    hd_acount::btc_hd::call_hd_key(path.as_str(), &raw_share.local_key_hd, raw_share.chain_code);
-    
-    let local_share = raw_share.local_key_hd.update_hd_key(&Scalar::<Secp256k1>::zero(), &tweak_sk, &y_sum);
+    // The account has to be chosen before offline stage, presignature is bound to it
+    let local_key_hd = LocalKey_HD::derive(raw_share.local_key_hd, path.as_str(), raw_share.chain_code)
+        .context("derive child key")?;
 
     let number_of_parties = args.parties.len();
 
@@ -99,7 +128,9 @@ async fn main() -> Result<()> {
     tokio::pin!(incoming);
     tokio::pin!(outgoing);
 
-    let signing = OfflineStage::new(i, args.parties, local_share,)?;
+    let session_id = format!("{}-offline", args.session_id);
+    let session_id = SessionId::from_request_id(session_id.as_bytes());
+    let signing = OfflineStage::new_with_tweak(i, args.parties, local_key_hd, session_id)?;
     let completed_offline_stage = AsyncProtocol::new(signing, incoming, outgoing)
         .run()
         .await
@@ -0,0 +1,61 @@
+use std::path::PathBuf;
+
+use anyhow::{Context, Result};
+use structopt::StructOpt;
+
+use curv::elliptic::curves::secp256_k1::Secp256k1;
+
+use multi_party_ecdsa::protocols::multi_party_ecdsa::gg_2020::hd_acount::account_manage::raw_share;
+use multi_party_ecdsa::protocols::multi_party_ecdsa::gg_2020::hd_acount::btc_hd::{self, Network};
+
+#[derive(Debug, StructOpt)]
+enum Cli {
+    /// Exports extended public key of a share at given path
+    Export {
+        #[structopt(short, long, default_value = "local-share1.json")]
+        local_share: PathBuf,
+        #[structopt(short, long, default_value = "m/44/0/0")]
+        path: String,
+        #[structopt(long)]
+        testnet: bool,
+        /// Password of sealed share, the share is read as plain JSON if omitted
+        #[structopt(long, env = "SHARE_PASSWORD", hide_env_values = true)]
+        password: Option<String>,
+    },
+    /// Derives child extended public key from an xpub, without any share
+    Derive {
+        xpub: String,
+        #[structopt(short, long, default_value = "m/0/0")]
+        path: String,
+    },
+}
+
+fn main() -> Result<()> {
+    match Cli::from_args() {
+        Cli::Export {
+            local_share,
+            path,
+            testnet,
+            password,
+        } => {
+            let local_share = std::fs::read(local_share).context("cannot read local share")?;
+            let raw_share = raw_share::<Secp256k1>::from_bytes(&local_share, password.as_deref())
+                .context("parse local share")?;
+            let network = if testnet {
+                Network::Testnet
+            } else {
+                Network::Mainnet
+            };
+            let xpub = raw_share
+                .export_xpub(&path, network)
+                .context("export xpub")?;
+            println!("{}", xpub);
+        }
+        Cli::Derive { xpub, path } => {
+            let (xpub, network) = btc_hd::parse_xpub(&xpub).context("parse xpub")?;
+            let child = btc_hd::derive_xpub(&xpub, &path).context("derive child xpub")?;
+            println!("{}", child.to_string(network.xpub_prefix()));
+        }
+    }
+    Ok(())
+}
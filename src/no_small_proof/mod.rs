@@ -1 +1,4 @@
 pub mod no_small_proof;
+
+#[cfg(test)]
+mod test;
@@ -1,120 +1,224 @@
+//! Proof that Paillier modulus has no small factors, bound to a [ProofContext]
+//!
+//! Prover shows knowledge of factorization `n0 = p * q` with both `p, q` bigger than
+//! `sqrt(n0) / 2^(l + varepsilon)`, committing to the factors with ring-Pedersen parameters
+//! `n_tilde, s, t` of the verifier.
+//!
+//! Challenge is `varepsilon` bits long, and masks add `l` bits of statistical hiding on top of
+//! it. Both are given in the [NoSmallFactorStatement], so prover and verifier must agree on them,
+//! as well as on the domain-separation salt.
+//!
+//! `Z*_n_tilde` has elements of order 2, so verification equations are checked up to sign:
+//! squares of both sides are compared. Otherwise a random exponent in
+//! [NoSmallFactorProof::batch_verify] would cancel the sign with probability 1/2, and batch
+//! verification would accept proofs rejected by [NoSmallFactorProof::verify].
+
 use serde::{Deserialize, Serialize};
-use sha2::{Sha512, Digest};
+use sha2::{Digest, Sha512};
 
-//use aes_gcm::aead::generic_array::typenum::Sqrt;
-use curv::BigInt;
 use curv::arithmetic::traits::*;
-use zk_paillier::zkproofs::SALT_STRING;
+use curv::cryptographic_primitives::hashing::DigestExt;
+use curv::BigInt;
 
+use crate::utilities::transcript::ProofContext;
 
+const NO_SMALL_FACTOR_PROOF: &str = "no-small-factor";
 
-//pub const SALT_STRING: &[u8] =    NiCorrectKeyProof::SALT_STRING &[75, 90, 101, 110];
-//use std::string::String;
+/// Default statistical hiding parameter `l`
+pub const DEFAULT_L: u32 = 256;
+/// Default challenge bit length `varepsilon`
+pub const DEFAULT_VAREPSILON: u32 = 512;
+/// Challenge is derived from SHA-512, so it can't be longer than that
+pub const MAX_VAREPSILON: u32 = 512;
+/// Salt used by [NoSmallFactorStatement::new]
+pub const DEFAULT_SALT: &[u8] = b"gg20-hd/no-small-factor";
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
-pub struct NoSmallFactorSetUp{
-    pub n_tilde_ : BigInt,
-    pub s_ : BigInt,
-    pub t_ : BigInt,
+/// Bit length of random exponents combining equations in [NoSmallFactorProof::batch_verify]
+const BATCH_RANDOMIZER_BITS: usize = 128;
+
+/// Ring-Pedersen parameters of the verifier
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct NoSmallFactorSetUp {
+    pub n_tilde_: BigInt,
+    pub s_: BigInt,
+    pub t_: BigInt,
+}
+
+impl NoSmallFactorSetUp {
+    /// Checks that `n_tilde` is odd and `s, t` are in range `(1; n_tilde)`
+    pub fn is_valid(&self) -> bool {
+        let one = BigInt::one();
+        let in_range = |v: &BigInt| v > &one && v < &self.n_tilde_;
+        &self.n_tilde_ % BigInt::from(2) == one && in_range(&self.s_) && in_range(&self.t_)
+    }
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct NoSmallFactorWitness {
-    pub p_ : BigInt,
-    pub q_ : BigInt,
+    pub p_: BigInt,
+    pub q_: BigInt,
 }
 
-pub struct NoSmallFactorStatement{
+/// Modulus `n0` along with security parameters and salt of the proof
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct NoSmallFactorStatement {
     pub n0_: BigInt,
-    pub l_ : u32,
+    pub l_: u32,
     pub varepsilon_: u32,
+    #[serde(default = "default_salt")]
+    pub salt_: Vec<u8>,
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
-pub struct NoSmallFactorProof {
-    pub p__ : BigInt,
-    pub q__ : BigInt,
-    pub a__ : BigInt,
-    pub b__ : BigInt,
-    pub t__ : BigInt,
-    pub sigma__ : BigInt,
-    pub z1__ : BigInt,
-    pub z2__ : BigInt,
-    pub w1__ : BigInt,
-    pub w2__ : BigInt,
-    pub v__ : BigInt,
-    //pub salt_ : String,
+fn default_salt() -> Vec<u8> {
+    DEFAULT_SALT.to_vec()
+}
+
+impl NoSmallFactorStatement {
+    /// Statement about `n0` with default security parameters and salt
+    pub fn new(n0: BigInt) -> Self {
+        Self::with_security(n0, DEFAULT_L, DEFAULT_VAREPSILON)
+    }
+
+    /// Statement about `n0` with given security parameters and default salt
+    pub fn with_security(n0: BigInt, l: u32, varepsilon: u32) -> Self {
+        NoSmallFactorStatement {
+            n0_: n0,
+            l_: l,
+            varepsilon_: varepsilon,
+            salt_: default_salt(),
+        }
+    }
+
+    /// Replaces domain-separation salt of the statement
+    pub fn with_salt(self, salt: &[u8]) -> Self {
+        NoSmallFactorStatement {
+            salt_: salt.to_vec(),
+            ..self
+        }
+    }
 
+    /// Checks that `n0` is odd and bigger than one, and that security parameters are supported
+    pub fn is_valid(&self) -> bool {
+        let one = BigInt::one();
+        self.n0_ > one
+            && &self.n0_ % BigInt::from(2) == one
+            && self.l_ > 0
+            && self.varepsilon_ > 0
+            && self.varepsilon_ <= MAX_VAREPSILON
+    }
 
+    /// Bound on absolute values of `z1, z2`: `2^(l + varepsilon + 1) * sqrt(n0)`
+    fn limit_alpha_beta(&self) -> BigInt {
+        (BigInt::from(2) << (self.l_ + self.varepsilon_) as usize) * self.n0_.sqrt()
+    }
 }
-#[derive(Clone, Debug, Serialize, Deserialize)]
-pub struct NoSmallFactorProofK {
-    pub z1__ : BigInt,
-    pub z2__ : BigInt,
-    pub w1__ : BigInt,
-    pub w2__ : BigInt,
-    pub v__ : BigInt,
 
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct NoSmallFactorProof {
+    pub p__: BigInt,
+    pub q__: BigInt,
+    pub a__: BigInt,
+    pub b__: BigInt,
+    pub t__: BigInt,
+    pub sigma__: BigInt,
+    pub z1__: BigInt,
+    pub z2__: BigInt,
+    pub w1__: BigInt,
+    pub w2__: BigInt,
+    pub v__: BigInt,
+}
 
+/// Compact form of [NoSmallFactorProof]
+///
+/// Carries the challenge instead of commitments `A, B, T`, which verifier recomputes from the
+/// responses.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct NoSmallFactorProofK {
+    pub e__: BigInt,
+    pub p__: BigInt,
+    pub q__: BigInt,
+    pub sigma__: BigInt,
+    pub z1__: BigInt,
+    pub z2__: BigInt,
+    pub w1__: BigInt,
+    pub w2__: BigInt,
+    pub v__: BigInt,
 }
 
-impl NoSmallFactorProof{
-    //pub fn SetSalt(String &salt)->() { Self.salt_ = salt; }
-    pub fn prove(setup : &NoSmallFactorSetUp, statement : &NoSmallFactorStatement, witness : &NoSmallFactorWitness)
-    ->Option<NoSmallFactorProof>{
+impl NoSmallFactorProof {
+    /// Returns `None` if witness doesn't factor `n0` or statement is not valid
+    pub fn prove(
+        setup: &NoSmallFactorSetUp,
+        statement: &NoSmallFactorStatement,
+        witness: &NoSmallFactorWitness,
+        ctx: &ProofContext,
+    ) -> Option<NoSmallFactorProof> {
         let n_tilde = &setup.n_tilde_;
         let s = &setup.s_;
         let t = &setup.t_;
-        
+
         let n0 = &statement.n0_;
-        let l = &statement.l_;
-        let varepsilon = &statement.varepsilon_;
+        let l = statement.l_ as usize;
+        let varepsilon = statement.varepsilon_ as usize;
 
         let p = &witness.p_;
         let q = &witness.q_;
+        let one = BigInt::one();
+        if !statement.is_valid() || p <= &one || q <= &one || &(p * q) != n0 {
+            return None;
+        }
 
-        let sqrt_n0 = n0.sqrt();
-        let limit_alpha_beta = (BigInt::from(2) << (l + varepsilon) as usize) * sqrt_n0;
+        let limit_alpha_beta = statement.limit_alpha_beta();
         // 2^l * n_tilde
-        let limit_mu_nu = (BigInt::from(2) << (*l as usize)) * n_tilde;
+        let limit_mu_nu = (BigInt::from(2) << l) * n_tilde;
         // 2^l * n0 * n_tilde
         let limit_sigma = &limit_mu_nu * n0;
         // 2^(l + varepsilon) * n0 * n_tilde
-        let limit_r = &limit_sigma << *varepsilon as usize;
+        let limit_r = &limit_sigma << varepsilon;
         // 2^(l + varepsilon) * n_tilde
-        let limit_x_y = &limit_mu_nu << *varepsilon as usize;
-        
+        let limit_x_y = &limit_mu_nu << varepsilon;
+
         let alpha = BigInt::sample_below(&limit_alpha_beta);
         let beta = BigInt::sample_below(&limit_alpha_beta);
         let mu = BigInt::sample_below(&limit_mu_nu);
         let nu = BigInt::sample_below(&limit_mu_nu);
         let sigma__ = BigInt::sample_below(&limit_sigma);
         let r = BigInt::sample_below(&limit_r);
-        let x = BigInt::sample_below( &limit_x_y);
+        let x = BigInt::sample_below(&limit_x_y);
         let y = BigInt::sample_below(&limit_x_y);
 
         // P = s^p * t^mu  mod n_tilde
-        let p__ = (BigInt::mod_pow(s,p,n_tilde) * BigInt::mod_pow(t,&mu, n_tilde)) % n_tilde;
+        let p__ = BigInt::mod_mul(
+            &BigInt::mod_pow(s, p, n_tilde),
+            &BigInt::mod_pow(t, &mu, n_tilde),
+            n_tilde,
+        );
         // Q = s^q * t^nu  mod n_tilde
-        let q__ = (BigInt::mod_pow(s,q, n_tilde) * BigInt::mod_pow(t,&nu, n_tilde)) % n_tilde;
+        let q__ = BigInt::mod_mul(
+            &BigInt::mod_pow(s, q, n_tilde),
+            &BigInt::mod_pow(t, &nu, n_tilde),
+            n_tilde,
+        );
         // A = s^alpha * t^x  mod n_tilde
-        let a__ = (BigInt::mod_pow(s,&alpha, n_tilde) * BigInt::mod_pow(t,&x, n_tilde)) % n_tilde;
+        let a__ = BigInt::mod_mul(
+            &BigInt::mod_pow(s, &alpha, n_tilde),
+            &BigInt::mod_pow(t, &x, n_tilde),
+            n_tilde,
+        );
         // B = s^beta * t^y  mod n_tilde
-        let b__ = (BigInt::mod_pow(s,&beta, n_tilde) * BigInt::mod_pow(t,&y, n_tilde)) % n_tilde;
+        let b__ = BigInt::mod_mul(
+            &BigInt::mod_pow(s, &beta, n_tilde),
+            &BigInt::mod_pow(t, &y, n_tilde),
+            n_tilde,
+        );
         // T = Q^alpha * t^r  mod n_tilde
-        let t__ = (BigInt::mod_pow(&q__,&alpha, n_tilde) * BigInt::mod_pow(t,&r, n_tilde)) % n_tilde;
-
-        let mut sha512_hasher = Sha512::new();
-        sha512_hasher.update(&n0.to_bytes());
-        sha512_hasher.update(&p__.to_bytes());
-        sha512_hasher.update(&q__.to_bytes());
-        sha512_hasher.update(&a__.to_bytes());
-        sha512_hasher.update(&b__.to_bytes());
-        sha512_hasher.update(&t__.to_bytes());
-        sha512_hasher.update(&SALT_STRING);
-        let sha512_digest = sha512_hasher.finalize();
-        let e = BigInt::from_bytes(&sha512_digest);
-        //if (&e & BigInt::one()) == BigInt::zero(){ e = -e;}
+        let t__ = BigInt::mod_mul(
+            &BigInt::mod_pow(&q__, &alpha, n_tilde),
+            &BigInt::mod_pow(t, &r, n_tilde),
+            n_tilde,
+        );
+
+        let e = challenge(setup, statement, [&p__, &q__, &a__, &b__, &t__], ctx);
 
         let sigma_tilde = &sigma__ - &nu * p;
         let z1__ = alpha + &e * p;
@@ -123,75 +227,309 @@ impl NoSmallFactorProof{
         let w2__ = y + &e * &nu;
         let v__ = r + &e * sigma_tilde;
 
-        Some(NoSmallFactorProof{
-            p__ : p__,
-            q__ : q__,
-            a__ : a__,
-            b__ : b__,
-            sigma__ : sigma__,
-            t__ : t__,
-            z1__ : z1__,
-            z2__ : z2__,
-            w1__ : w1__,
-            w2__ : w2__,
-            v__ : v__,
+        Some(NoSmallFactorProof {
+            p__,
+            q__,
+            a__,
+            b__,
+            t__,
+            sigma__,
+            z1__,
+            z2__,
+            w1__,
+            w2__,
+            v__,
         })
-        //todo!()
     }
-    pub fn verify(&self,setup : &NoSmallFactorSetUp, statement : &NoSmallFactorStatement)
-    ->bool{
-        let n_tilde = &setup.n_tilde_;
-        let s = &setup.s_;
-        let t = &setup.t_;
-        
-        let n0 = &statement.n0_;
-        let l = &statement.l_;
-        let varepsilon = &statement.varepsilon_;
 
-        let sqrt_n0 = &n0.sqrt();
-        let limit_alpha_beta = (BigInt::from(2) << (l + varepsilon) as usize) * sqrt_n0;
-        if self.z1__ > limit_alpha_beta.clone() || self.z1__ < BigInt::zero() - &limit_alpha_beta{return false;}
-        if self.z2__ > limit_alpha_beta.clone() || self.z2__ < BigInt::zero() - &limit_alpha_beta{return false;}
+    pub fn verify(
+        &self,
+        setup: &NoSmallFactorSetUp,
+        statement: &NoSmallFactorStatement,
+        ctx: &ProofContext,
+    ) -> bool {
+        let e = match self.precheck(setup, statement, ctx) {
+            Some(e) => e,
+            None => return false,
+        };
+        self.check_equations(setup, statement, &e).unwrap_or(false)
+    }
+
+    /// Verifies proofs made for the same `setup`, e.g. the ones received by a party in keygen
+    ///
+    /// Equations of all the proofs are combined with random exponents, so that powers of `s` and
+    /// `t` are computed once. If the combined check fails, proofs are verified one by one and
+    /// indexes of invalid ones are returned. Accepts the same proofs as [Self::verify], up to
+    /// negligible probability.
+    pub fn batch_verify(
+        setup: &NoSmallFactorSetUp,
+        proofs: &[(&NoSmallFactorProof, &NoSmallFactorStatement, &ProofContext)],
+    ) -> Result<(), Vec<usize>> {
+        let culprits = || -> Vec<usize> {
+            proofs
+                .iter()
+                .enumerate()
+                .filter(|(_, (proof, statement, ctx))| !proof.verify(setup, statement, ctx))
+                .map(|(i, _)| i)
+                .collect()
+        };
+
+        let challenges: Option<Vec<BigInt>> = proofs
+            .iter()
+            .map(|(proof, statement, ctx)| proof.precheck(setup, statement, ctx))
+            .collect();
+        let challenges = match challenges {
+            Some(challenges) => challenges,
+            None => return Err(culprits()),
+        };
 
+        let n_tilde = &setup.n_tilde_;
+        let mut s_exp = BigInt::zero();
+        let mut t_exp = BigInt::zero();
+        let mut lhs = BigInt::one();
+        let mut rhs = BigInt::one();
+        for ((proof, statement, _), e) in proofs.iter().zip(&challenges) {
+            let rho: Vec<BigInt> = (0..3)
+                .map(|_| BigInt::sample(BATCH_RANDOMIZER_BITS))
+                .collect();
+            // s^z1 * t^w1 = A * P^e
+            // s^z2 * t^w2 = B * Q^e
+            // Q^z1 * t^v = T * (s^n0 * t^sigma)^e
+            s_exp = s_exp + &rho[0] * &proof.z1__ + &rho[1] * &proof.z2__
+                - &rho[2] * &statement.n0_ * e;
+            t_exp = t_exp
+                + &rho[0] * &proof.w1__
+                + &rho[1] * &proof.w2__
+                + &rho[2] * (&proof.v__ - &proof.sigma__ * e);
+            let q_exp = &rho[2] * &proof.z1__ - &rho[1] * e;
+            let q_pow = mod_pow_signed(&proof.q__, &q_exp, n_tilde);
+            lhs = match q_pow {
+                Some(q_pow) => BigInt::mod_mul(&lhs, &q_pow, n_tilde),
+                None => return Err(culprits()),
+            };
+            let rho_e = &rho[0] * e;
+            for (base, exp) in [
+                (&proof.a__, &rho[0]),
+                (&proof.p__, &rho_e),
+                (&proof.b__, &rho[1]),
+                (&proof.t__, &rho[2]),
+            ]
+            .iter()
+            {
+                rhs = BigInt::mod_mul(&rhs, &BigInt::mod_pow(base, exp, n_tilde), n_tilde);
+            }
+        }
+        let s_pow = mod_pow_signed(&setup.s_, &s_exp, n_tilde);
+        let t_pow = mod_pow_signed(&setup.t_, &t_exp, n_tilde);
+        match (s_pow, t_pow) {
+            (Some(s_pow), Some(t_pow)) => {
+                let lhs = BigInt::mod_mul(&BigInt::mod_mul(&lhs, &s_pow, n_tilde), &t_pow, n_tilde);
+                if equal_up_to_sign(&lhs, &rhs, n_tilde) {
+                    Ok(())
+                } else {
+                    Err(culprits())
+                }
+            }
+            _ => Err(culprits()),
+        }
+    }
 
-        let mut sha512_hasher = Sha512::new();
-        sha512_hasher.update(&n0.to_bytes());
-        sha512_hasher.update(&self.p__.to_bytes());
-        sha512_hasher.update(&self.q__.to_bytes());
-        sha512_hasher.update(&self.a__.to_bytes());
-        sha512_hasher.update(&self.b__.to_bytes());
-        sha512_hasher.update(&self.t__.to_bytes());
-        sha512_hasher.update(&SALT_STRING);
-        let sha512_digest = sha512_hasher.finalize();
-        let e = BigInt::from_bytes(&sha512_digest);
-        //if (&e & BigInt::one()) == BigInt::zero(){ e = -e;}
+    /// Checks parameters, ranges of commitments and `z1, z2`, and returns the challenge
+    fn precheck(
+        &self,
+        setup: &NoSmallFactorSetUp,
+        statement: &NoSmallFactorStatement,
+        ctx: &ProofContext,
+    ) -> Option<BigInt> {
+        if !setup.is_valid() || !statement.is_valid() {
+            return None;
+        }
+        let commitments = [&self.p__, &self.q__, &self.a__, &self.b__, &self.t__];
+        let in_range = |v: &BigInt| v > &BigInt::zero() && v < &setup.n_tilde_;
+        if !commitments.iter().all(|c| in_range(*c)) || self.sigma__ < BigInt::zero() {
+            return None;
+        }
+        let limit_alpha_beta = statement.limit_alpha_beta();
+        let bounded =
+            |z: &BigInt| z <= &limit_alpha_beta && z >= &(BigInt::zero() - &limit_alpha_beta);
+        if !bounded(&self.z1__) || !bounded(&self.z2__) {
+            return None;
+        }
+        Some(challenge(setup, statement, commitments, ctx))
+    }
 
-        let r = (BigInt::mod_pow(s,n0,n_tilde)*BigInt::mod_pow(t,&self.sigma__,n_tilde)) % n_tilde;
+    /// Returns `None` if one of the negative exponents has no inverse base
+    fn check_equations(
+        &self,
+        setup: &NoSmallFactorSetUp,
+        statement: &NoSmallFactorStatement,
+        e: &BigInt,
+    ) -> Option<bool> {
+        let n_tilde = &setup.n_tilde_;
+        let s = &setup.s_;
+        let t = &setup.t_;
+        let pow = |base: &BigInt, exp: &BigInt| mod_pow_signed(base, exp, n_tilde);
 
-        let mut ok = true;
-        let mut left_num : BigInt;
-        let mut right_num : BigInt;
+        let r = BigInt::mod_mul(
+            &BigInt::mod_pow(s, &statement.n0_, n_tilde),
+            &BigInt::mod_pow(t, &self.sigma__, n_tilde),
+            n_tilde,
+        );
 
         // s^z1 * t^w1 = A * P^e  mod n_tilde
-        left_num = (BigInt::mod_pow(s,&self.z1__,n_tilde) * BigInt::mod_pow(t,&self.w1__,n_tilde)) % n_tilde;
-        right_num = (&self.a__ * BigInt::mod_pow(&self.p__, &e, n_tilde)) % n_tilde;
-        ok = left_num == right_num;
-        if !ok {return false;}
+        let left = BigInt::mod_mul(&pow(s, &self.z1__)?, &pow(t, &self.w1__)?, n_tilde);
+        let right = BigInt::mod_mul(&self.a__, &BigInt::mod_pow(&self.p__, e, n_tilde), n_tilde);
+        if !equal_up_to_sign(&left, &right, n_tilde) {
+            return Some(false);
+        }
 
         // s^z2 * t^w2 = B * Q^e  mod n_tilde
-        left_num = (BigInt::mod_pow(s,&self.z2__,n_tilde) * BigInt::mod_pow(t,&self.w2__,n_tilde)) % n_tilde;
-        right_num = (&self.b__ * BigInt::mod_pow(&self.q__, &e, n_tilde)) % n_tilde;
-        ok = left_num == right_num;
-        if !ok {return false;}
+        let left = BigInt::mod_mul(&pow(s, &self.z2__)?, &pow(t, &self.w2__)?, n_tilde);
+        let right = BigInt::mod_mul(&self.b__, &BigInt::mod_pow(&self.q__, e, n_tilde), n_tilde);
+        if !equal_up_to_sign(&left, &right, n_tilde) {
+            return Some(false);
+        }
 
         // Q^z1 * t^v = T * R^e  mod n_tilde
-        left_num = (BigInt::mod_pow(&self.q__,&self.z1__,n_tilde) * BigInt::mod_pow(t,&self.v__,n_tilde)) % n_tilde;
-        right_num = (&self.t__ * BigInt::mod_pow(&r, &e, n_tilde)) % n_tilde;
-        ok = left_num == right_num;
-        if !ok {return false;}
+        let left = BigInt::mod_mul(&pow(&self.q__, &self.z1__)?, &pow(t, &self.v__)?, n_tilde);
+        let right = BigInt::mod_mul(&self.t__, &BigInt::mod_pow(&r, e, n_tilde), n_tilde);
+        Some(equal_up_to_sign(&left, &right, n_tilde))
+    }
+
+    /// Converts the proof into compact form, returns `None` if the proof doesn't pass range
+    /// checks for given `setup` and `statement`
+    pub fn compact(
+        &self,
+        setup: &NoSmallFactorSetUp,
+        statement: &NoSmallFactorStatement,
+        ctx: &ProofContext,
+    ) -> Option<NoSmallFactorProofK> {
+        Some(NoSmallFactorProofK {
+            e__: self.precheck(setup, statement, ctx)?,
+            p__: self.p__.clone(),
+            q__: self.q__.clone(),
+            sigma__: self.sigma__.clone(),
+            z1__: self.z1__.clone(),
+            z2__: self.z2__.clone(),
+            w1__: self.w1__.clone(),
+            w2__: self.w2__.clone(),
+            v__: self.v__.clone(),
+        })
+    }
+}
 
-        true
+impl NoSmallFactorProofK {
+    /// Returns `None` if witness doesn't factor `n0` or statement is not valid
+    pub fn prove(
+        setup: &NoSmallFactorSetUp,
+        statement: &NoSmallFactorStatement,
+        witness: &NoSmallFactorWitness,
+        ctx: &ProofContext,
+    ) -> Option<NoSmallFactorProofK> {
+        NoSmallFactorProof::prove(setup, statement, witness, ctx)?.compact(setup, statement, ctx)
     }
 
+    /// Recomputes commitments `A, B, T` from the responses and checks that they hash to the
+    /// challenge
+    pub fn verify(
+        &self,
+        setup: &NoSmallFactorSetUp,
+        statement: &NoSmallFactorStatement,
+        ctx: &ProofContext,
+    ) -> bool {
+        self.expand(setup, statement)
+            .and_then(|proof| proof.precheck(setup, statement, ctx))
+            .map_or(false, |e| e == self.e__)
+    }
 
-}
\ No newline at end of file
+    fn expand(
+        &self,
+        setup: &NoSmallFactorSetUp,
+        statement: &NoSmallFactorStatement,
+    ) -> Option<NoSmallFactorProof> {
+        if !setup.is_valid() || !statement.is_valid() || self.e__ < BigInt::zero() {
+            return None;
+        }
+        let n_tilde = &setup.n_tilde_;
+        let s = &setup.s_;
+        let t = &setup.t_;
+        let pow = |base: &BigInt, exp: &BigInt| mod_pow_signed(base, exp, n_tilde);
+        let minus_e = BigInt::zero() - &self.e__;
+
+        // A = s^z1 * t^w1 * P^-e  mod n_tilde
+        let a__ = BigInt::mod_mul(
+            &BigInt::mod_mul(&pow(s, &self.z1__)?, &pow(t, &self.w1__)?, n_tilde),
+            &pow(&self.p__, &minus_e)?,
+            n_tilde,
+        );
+        // B = s^z2 * t^w2 * Q^-e  mod n_tilde
+        let b__ = BigInt::mod_mul(
+            &BigInt::mod_mul(&pow(s, &self.z2__)?, &pow(t, &self.w2__)?, n_tilde),
+            &pow(&self.q__, &minus_e)?,
+            n_tilde,
+        );
+        // T = Q^z1 * t^v * R^-e  mod n_tilde, where R = s^n0 * t^sigma
+        let r = BigInt::mod_mul(
+            &BigInt::mod_pow(s, &statement.n0_, n_tilde),
+            &pow(t, &self.sigma__)?,
+            n_tilde,
+        );
+        let t__ = BigInt::mod_mul(
+            &BigInt::mod_mul(&pow(&self.q__, &self.z1__)?, &pow(t, &self.v__)?, n_tilde),
+            &pow(&r, &minus_e)?,
+            n_tilde,
+        );
+
+        Some(NoSmallFactorProof {
+            p__: self.p__.clone(),
+            q__: self.q__.clone(),
+            a__,
+            b__,
+            t__,
+            sigma__: self.sigma__.clone(),
+            z1__: self.z1__.clone(),
+            z2__: self.z2__.clone(),
+            w1__: self.w1__.clone(),
+            w2__: self.w2__.clone(),
+            v__: self.v__.clone(),
+        })
+    }
+}
+
+/// Derives `varepsilon`-bit challenge from the setup, statement and commitments `P, Q, A, B, T`
+pub(super) fn challenge(
+    setup: &NoSmallFactorSetUp,
+    statement: &NoSmallFactorStatement,
+    commitments: [&BigInt; 5],
+    ctx: &ProofContext,
+) -> BigInt {
+    let hasher = Sha512::new()
+        .chain_bigint(&ctx.tag(NO_SMALL_FACTOR_PROOF))
+        .chain(&statement.salt_)
+        .chain_bigint(&BigInt::from(statement.l_))
+        .chain_bigint(&BigInt::from(statement.varepsilon_))
+        .chain_bigint(&setup.n_tilde_)
+        .chain_bigint(&setup.s_)
+        .chain_bigint(&setup.t_)
+        .chain_bigint(&statement.n0_);
+    let digest = commitments
+        .iter()
+        .fold(hasher, |hasher, c| hasher.chain_bigint(c))
+        .finalize();
+    BigInt::from_bytes(&digest) >> (MAX_VAREPSILON - statement.varepsilon_) as usize
+}
+
+/// Compares squares of `a` and `b` modulo `n`, so that elements of order 2 don't matter
+fn equal_up_to_sign(a: &BigInt, b: &BigInt, n: &BigInt) -> bool {
+    BigInt::mod_mul(a, a, n) == BigInt::mod_mul(b, b, n)
+}
+
+/// `base^exp mod n` for possibly negative `exp`, `None` if `base` is not invertible
+fn mod_pow_signed(base: &BigInt, exp: &BigInt, n: &BigInt) -> Option<BigInt> {
+    if exp < &BigInt::zero() {
+        let base_inv = BigInt::mod_inv(base, n)?;
+        Some(BigInt::mod_pow(&base_inv, &(BigInt::zero() - exp), n))
+    } else {
+        Some(BigInt::mod_pow(base, exp, n))
+    }
+}
@@ -0,0 +1,247 @@
+use crate::no_small_proof::no_small_proof::*;
+use crate::utilities::transcript::ProofContext;
+use crate::utilities::transcript::SessionId;
+use curv::arithmetic::traits::*;
+use curv::BigInt;
+use paillier::{KeyGeneration, Paillier};
+
+fn setup() -> NoSmallFactorSetUp {
+    let (ek_tilde, _) = Paillier::keypair_with_modulus_size(1024).keys();
+    let s = BigInt::mod_pow(
+        &BigInt::sample_below(&ek_tilde.n),
+        &BigInt::from(2),
+        &ek_tilde.n,
+    );
+    let t = BigInt::mod_pow(&s, &BigInt::sample_below(&ek_tilde.n), &ek_tilde.n);
+    NoSmallFactorSetUp {
+        n_tilde_: ek_tilde.n,
+        s_: s,
+        t_: t,
+    }
+}
+
+fn paillier_modulus() -> (BigInt, NoSmallFactorWitness) {
+    let (ek, dk) = Paillier::keypair_with_modulus_size(1024).keys();
+    (ek.n, NoSmallFactorWitness { p_: dk.p, q_: dk.q })
+}
+
+/// Honest prover which negates commitment `A` before deriving the challenge, so that the first
+/// equation holds only up to sign
+fn prove_with_negated_a(
+    setup: &NoSmallFactorSetUp,
+    statement: &NoSmallFactorStatement,
+    witness: &NoSmallFactorWitness,
+    ctx: &ProofContext,
+) -> NoSmallFactorProof {
+    let (n_tilde, s, t) = (&setup.n_tilde_, &setup.s_, &setup.t_);
+    let (p, q) = (&witness.p_, &witness.q_);
+    let l = statement.l_ as usize;
+    let varepsilon = statement.varepsilon_ as usize;
+    let pedersen = |a: &BigInt, b: &BigInt| {
+        BigInt::mod_mul(
+            &BigInt::mod_pow(s, a, n_tilde),
+            &BigInt::mod_pow(t, b, n_tilde),
+            n_tilde,
+        )
+    };
+
+    let limit_alpha_beta = (BigInt::from(2) << (l + varepsilon)) * statement.n0_.sqrt();
+    let limit_mu_nu = (BigInt::from(2) << l) * n_tilde;
+    let limit_sigma = &limit_mu_nu * &statement.n0_;
+    let limit_x_y = &limit_mu_nu << varepsilon;
+    let alpha = BigInt::sample_below(&limit_alpha_beta);
+    let beta = BigInt::sample_below(&limit_alpha_beta);
+    let mu = BigInt::sample_below(&limit_mu_nu);
+    let nu = BigInt::sample_below(&limit_mu_nu);
+    let x = BigInt::sample_below(&limit_x_y);
+    let y = BigInt::sample_below(&limit_x_y);
+    let sigma__ = BigInt::sample_below(&limit_sigma);
+    let r = BigInt::sample_below(&(&limit_sigma << varepsilon));
+
+    let p__ = pedersen(p, &mu);
+    let q__ = pedersen(q, &nu);
+    let a__ = n_tilde - pedersen(&alpha, &x);
+    let b__ = pedersen(&beta, &y);
+    let t__ = BigInt::mod_mul(
+        &BigInt::mod_pow(&q__, &alpha, n_tilde),
+        &BigInt::mod_pow(t, &r, n_tilde),
+        n_tilde,
+    );
+    let e = challenge(setup, statement, [&p__, &q__, &a__, &b__, &t__], ctx);
+
+    NoSmallFactorProof {
+        z1__: alpha + &e * p,
+        z2__: beta + &e * q,
+        w1__: x + &e * &mu,
+        w2__: y + &e * &nu,
+        v__: r + &e * (&sigma__ - &nu * p),
+        p__,
+        q__,
+        a__,
+        b__,
+        t__,
+        sigma__,
+    }
+}
+
+#[test]
+fn test_no_small_factor_proof() {
+    let setup = setup();
+    let (n0, witness) = paillier_modulus();
+    let statement = NoSmallFactorStatement::new(n0);
+    let session = SessionId::random();
+    let ctx = session.context(1, "test");
+
+    let proof = NoSmallFactorProof::prove(&setup, &statement, &witness, &ctx).unwrap();
+    assert!(proof.verify(&setup, &statement, &ctx));
+    assert!(!proof.verify(&setup, &statement, &session.context(2, "test")));
+    assert!(!proof.verify(&setup, &statement.clone().with_salt(b"other"), &ctx));
+    let other_security =
+        NoSmallFactorStatement::with_security(statement.n0_.clone(), DEFAULT_L, 256);
+    assert!(!proof.verify(&setup, &other_security, &ctx));
+
+    let statement_json = serde_json::to_string(&statement).unwrap();
+    let proof_json = serde_json::to_string(&proof).unwrap();
+    let statement: NoSmallFactorStatement = serde_json::from_str(&statement_json).unwrap();
+    let proof: NoSmallFactorProof = serde_json::from_str(&proof_json).unwrap();
+    assert!(proof.verify(&setup, &statement, &ctx));
+}
+
+#[test]
+fn test_custom_security_level() {
+    let setup = setup();
+    let (n0, witness) = paillier_modulus();
+    let statement = NoSmallFactorStatement::with_security(n0, 128, 256).with_salt(b"salt");
+    let ctx = SessionId::random().context(1, "test");
+
+    let proof = NoSmallFactorProof::prove(&setup, &statement, &witness, &ctx).unwrap();
+    assert!(proof.verify(&setup, &statement, &ctx));
+
+    let unsupported = NoSmallFactorStatement::with_security(statement.n0_.clone(), 128, 1024);
+    assert!(NoSmallFactorProof::prove(&setup, &unsupported, &witness, &ctx).is_none());
+}
+
+#[test]
+fn test_malformed_moduli_are_rejected() {
+    let setup = setup();
+    let ctx = SessionId::random().context(1, "test");
+    let (n0, witness) = paillier_modulus();
+
+    // witness doesn't factor the modulus
+    let statement = NoSmallFactorStatement::new(n0);
+    let wrong_witness = NoSmallFactorWitness {
+        p_: witness.p_.clone(),
+        q_: &witness.q_ + BigInt::from(2),
+    };
+    assert!(NoSmallFactorProof::prove(&setup, &statement, &wrong_witness, &ctx).is_none());
+
+    // even modulus
+    let even = NoSmallFactorStatement::new(&witness.p_ * BigInt::from(2));
+    let even_witness = NoSmallFactorWitness {
+        p_: BigInt::from(2),
+        q_: witness.p_.clone(),
+    };
+    assert!(NoSmallFactorProof::prove(&setup, &even, &even_witness, &ctx).is_none());
+    let proof = NoSmallFactorProof::prove(&setup, &statement, &witness, &ctx).unwrap();
+    assert!(!proof.verify(&setup, &even, &ctx));
+
+    // modulus with a small factor, security parameters are lowered so that the bound on factors
+    // is meaningful for 512-bit primes
+    let small_factor = BigInt::from(65537);
+    let statement = NoSmallFactorStatement::with_security(&small_factor * &witness.p_, 64, 128);
+    let small_factor_witness = NoSmallFactorWitness {
+        p_: small_factor,
+        q_: witness.p_.clone(),
+    };
+    let proof = NoSmallFactorProof::prove(&setup, &statement, &small_factor_witness, &ctx).unwrap();
+    assert!(!proof.verify(&setup, &statement, &ctx));
+
+    // proof made for the other modulus
+    let (other_n0, _) = paillier_modulus();
+    let statement = NoSmallFactorStatement::new(witness.p_.clone() * &witness.q_);
+    let proof = NoSmallFactorProof::prove(&setup, &statement, &witness, &ctx).unwrap();
+    assert!(!proof.verify(&setup, &NoSmallFactorStatement::new(other_n0), &ctx));
+}
+
+#[test]
+fn test_compact_proof() {
+    let setup = setup();
+    let (n0, witness) = paillier_modulus();
+    let statement = NoSmallFactorStatement::new(n0);
+    let session = SessionId::random();
+    let ctx = session.context(1, "test");
+
+    let proof = NoSmallFactorProofK::prove(&setup, &statement, &witness, &ctx).unwrap();
+    assert!(proof.verify(&setup, &statement, &ctx));
+    assert!(!proof.verify(&setup, &statement, &session.context(2, "test")));
+
+    let mut tampered = proof.clone();
+    tampered.e__ = &tampered.e__ + BigInt::one();
+    assert!(!tampered.verify(&setup, &statement, &ctx));
+
+    let full = NoSmallFactorProof::prove(&setup, &statement, &witness, &ctx).unwrap();
+    let compact = full.compact(&setup, &statement, &ctx).unwrap();
+    assert!(compact.verify(&setup, &statement, &ctx));
+    assert!(
+        serde_json::to_string(&compact).unwrap().len()
+            < serde_json::to_string(&full).unwrap().len()
+    );
+}
+
+#[test]
+fn test_batch_verify() {
+    let setup = setup();
+    let session = SessionId::random();
+    let statements_and_witnesses: Vec<_> = (0..3)
+        .map(|_| {
+            let (n0, witness) = paillier_modulus();
+            (NoSmallFactorStatement::new(n0), witness)
+        })
+        .collect();
+    let contexts: Vec<_> = (1..=3).map(|i| session.context(i, "test")).collect();
+    let mut proofs: Vec<_> = statements_and_witnesses
+        .iter()
+        .zip(&contexts)
+        .map(|((statement, witness), ctx)| {
+            NoSmallFactorProof::prove(&setup, statement, witness, ctx).unwrap()
+        })
+        .collect();
+
+    let batch = |proofs: &[NoSmallFactorProof]| {
+        let items: Vec<_> = proofs
+            .iter()
+            .zip(&statements_and_witnesses)
+            .zip(&contexts)
+            .map(|((proof, (statement, _)), ctx)| (proof, statement, ctx))
+            .collect();
+        NoSmallFactorProof::batch_verify(&setup, &items)
+    };
+    assert_eq!(batch(&proofs), Ok(()));
+
+    // second proof is made in context of another party
+    let (statement, witness) = &statements_and_witnesses[1];
+    proofs[1] = NoSmallFactorProof::prove(&setup, statement, witness, &contexts[0]).unwrap();
+    assert_eq!(batch(&proofs), Err(vec![1]));
+
+    // third proof has a wrong response
+    proofs[1] = NoSmallFactorProof::prove(&setup, statement, witness, &contexts[1]).unwrap();
+    proofs[2].w1__ = &proofs[2].w1__ + BigInt::one();
+    assert_eq!(batch(&proofs), Err(vec![2]));
+}
+
+#[test]
+fn test_batch_verify_agrees_with_verify_on_negated_commitment() {
+    let setup = setup();
+    let (n0, witness) = paillier_modulus();
+    let statement = NoSmallFactorStatement::new(n0);
+    let ctx = SessionId::random().context(1, "test");
+
+    let proof = prove_with_negated_a(&setup, &statement, &witness, &ctx);
+    let verified = proof.verify(&setup, &statement, &ctx);
+    // random exponents of the batch are even every other time, which used to cancel the sign
+    for _ in 0..16 {
+        let batch_verified =
+            NoSmallFactorProof::batch_verify(&setup, &[(&proof, &statement, &ctx)]).is_ok();
+        assert_eq!(batch_verified, verified);
+    }
+}
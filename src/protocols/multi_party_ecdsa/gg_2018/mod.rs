@@ -16,6 +16,9 @@
 
 pub mod party_i;
 
+/// Label of the protocol in proof contexts, the protocol has no session ids
+pub const PROTOCOL_LABEL: &str = "gg18";
+
 #[cfg(test)]
 mod test;
 
@@ -20,7 +20,9 @@ use crate::protocols::multi_party_ecdsa::gg_2018::party_i::{
     verify, KeyGenBroadcastMessage1, KeyGenDecommitMessage1, Keys, LocalSignature, Parameters,
     PartyPrivate, Phase5ADecom1, Phase5Com1, SharedKeys, SignKeys,
 };
+use crate::protocols::multi_party_ecdsa::gg_2018::PROTOCOL_LABEL;
 use crate::utilities::mta::{MessageA, MessageB};
+use crate::utilities::transcript::ProofContext;
 
 use curv::arithmetic::traits::Converter;
 use curv::cryptographic_primitives::hashing::{Digest, DigestExt};
@@ -192,7 +194,10 @@ fn sign(t: u16, n: u16, ttag: u16, s: Vec<u16>) {
     let m_a_vec: Vec<_> = sign_keys_vec
         .iter()
         .enumerate()
-        .map(|(i, k)| MessageA::a(&k.k_i, &party_keys_vec[usize::from(s[i])].ek, &[]).0)
+        .map(|(i, k)| {
+            let ctx = ProofContext::unbound(i as u16 + 1, PROTOCOL_LABEL);
+            MessageA::a(&k.k_i, &party_keys_vec[usize::from(s[i])].ek, &[], &ctx).0
+        })
         .collect();
 
     // each party i sends responses to m_a_vec she received (one response with input gamma_i and one with w_i)
@@ -212,12 +217,14 @@ fn sign(t: u16, n: u16, ttag: u16, s: Vec<u16>) {
 
         for j in 0..ttag - 1 {
             let ind = if j < i { j } else { j + 1 };
+            let alice_ctx = ProofContext::unbound(ind as u16 + 1, PROTOCOL_LABEL);
 
             let (m_b_gamma, beta_gamma, _, _) = MessageB::b(
                 &key.gamma_i,
                 &party_keys_vec[usize::from(s[ind])].ek,
                 m_a_vec[ind].clone(),
                 &[],
+                &alice_ctx,
             )
             .unwrap();
             let (m_b_w, beta_wi, _, _) = MessageB::b(
@@ -225,6 +232,7 @@ fn sign(t: u16, n: u16, ttag: u16, s: Vec<u16>) {
                 &party_keys_vec[usize::from(s[ind])].ek,
                 m_a_vec[ind].clone(),
                 &[],
+                &alice_ctx,
             )
             .unwrap();
 
@@ -14,8 +14,11 @@
 
     @license GPL-3.0+ <https://github.com/KZen-networks/multi-party-ecdsa/blob/master/LICENSE>
 */
-use crate::protocols::multi_party_ecdsa::gg_2020::ErrorType;
+use crate::protocols::multi_party_ecdsa::gg_2020::{ErrorType, SIGNING_LABEL};
 use crate::utilities::mta::{MessageA, MessageB};
+use crate::utilities::secret::Secret;
+use crate::utilities::transcript::ProofContext;
+use curv::cryptographic_primitives::hashing::{Digest, DigestExt};
 use curv::cryptographic_primitives::proofs::sigma_ec_ddh::ECDDHProof;
 use curv::cryptographic_primitives::proofs::sigma_ec_ddh::ECDDHStatement;
 use curv::cryptographic_primitives::proofs::sigma_ec_ddh::ECDDHWitness;
@@ -28,17 +31,35 @@ use paillier::Paillier;
 use paillier::{EncryptionKey, Randomness, RawCiphertext, RawPlaintext};
 use serde::{Deserialize, Serialize};
 use sha2::Sha256;
+use zeroize::Zeroize;
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct LocalStatePhase5 {
-    pub k: Scalar<Secp256k1>,
-    pub k_randomness: BigInt,
-    pub gamma: Scalar<Secp256k1>,
-    pub beta_randomness: Vec<BigInt>,
+    pub k: Secret<Scalar<Secp256k1>>,
+    pub k_randomness: Secret<BigInt>,
+    pub gamma: Secret<Scalar<Secp256k1>>,
+    pub beta_randomness: Secret<Vec<BigInt>>,
     pub beta_tag: Vec<BigInt>,
     pub encryption_key: EncryptionKey,
 }
 
+impl LocalStatePhase5 {
+    /// Checks that state revealed by one of `n` signers has values for each of the others
+    pub fn is_complete(&self, n: usize) -> bool {
+        self.beta_randomness.expose().len() + 1 == n && self.beta_tag.len() + 1 == n
+    }
+}
+
+impl Zeroize for LocalStatePhase5 {
+    fn zeroize(&mut self) {
+        self.k.zeroize();
+        self.k_randomness.zeroize();
+        self.gamma.zeroize();
+        self.beta_randomness.zeroize();
+        self.beta_tag.zeroize();
+    }
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct GlobalStatePhase5 {
     pub k_vec: Vec<Scalar<Secp256k1>>,
@@ -51,29 +72,42 @@ pub struct GlobalStatePhase5 {
     pub delta_vec: Vec<Scalar<Secp256k1>>,
     pub g_gamma_vec: Vec<Point<Secp256k1>>,
     pub m_a_vec: Vec<MessageA>,
+    /// `m_b_mat[i][j]` is message B sent to Alice `i` by her `j`-th counterparty, as revealed by
+    /// the sender
     pub m_b_mat: Vec<Vec<MessageB>>,
+    /// Same messages as revealed by Alice
+    pub m_b_received_mat: Vec<Vec<MessageB>>,
+    /// `m_b_hash_mat[i][j]` is [m_b_hash] of the same message broadcast by Bob
+    pub m_b_hash_mat: Vec<Vec<BigInt>>,
 }
 
-// TODO: check all parties submitted inputs
-// TODO: if not - abort gracefully with list of parties that did not produce inputs
 impl GlobalStatePhase5 {
+    /// `local_state_vec` must contain states of all the signers, each one
+    /// [complete](LocalStatePhase5::is_complete)
+    ///
+    /// `m_b_received_mat[i]` holds messages B received by party `i` as Alice, `m_b_sent_mat[i]`
+    /// holds messages B sent by party `i` as Bob and `m_b_hash_sent_mat[i]` holds their hashes
+    /// broadcast by party `i`, all in order of counterparties.
+    #[allow(clippy::too_many_arguments)]
     pub fn local_state_to_global_state(
         encryption_key_vec: &[EncryptionKey],
         delta_vec: &[Scalar<Secp256k1>],  //to test against delta_vec
         g_gamma_vec: &[Point<Secp256k1>], // to test against the opened commitment for g_gamma
         m_a_vec: &[MessageA],             // to test against broadcast message A
-        m_b_mat: Vec<Vec<MessageB>>,      // to test against broadcast message B
+        m_b_received_mat: Vec<Vec<MessageB>>,
+        m_b_sent_mat: Vec<Vec<MessageB>>,
+        m_b_hash_sent_mat: Vec<Vec<BigInt>>,
         local_state_vec: &[LocalStatePhase5],
     ) -> Self {
         let len = local_state_vec.len();
         let k_vec = (0..len)
-            .map(|i| local_state_vec[i].k.clone())
+            .map(|i| local_state_vec[i].k.expose().clone())
             .collect::<Vec<Scalar<Secp256k1>>>();
         let k_randomness_vec = (0..len)
-            .map(|i| local_state_vec[i].k_randomness.clone())
+            .map(|i| local_state_vec[i].k_randomness.expose().clone())
             .collect::<Vec<BigInt>>();
         let gamma_vec = (0..len)
-            .map(|i| local_state_vec[i].gamma.clone())
+            .map(|i| local_state_vec[i].gamma.expose().clone())
             .collect::<Vec<Scalar<Secp256k1>>>();
         let beta_randomness_vec = (0..len)
             .map(|i| {
@@ -81,7 +115,7 @@ impl GlobalStatePhase5 {
                     .map(|j| {
                         let ind1 = if j < i { j } else { j + 1 };
                         let ind2 = if j < i { i - 1 } else { i };
-                        local_state_vec[ind1].beta_randomness[ind2].clone()
+                        local_state_vec[ind1].beta_randomness.expose()[ind2].clone()
                     })
                     .collect::<Vec<BigInt>>()
             })
@@ -98,6 +132,8 @@ impl GlobalStatePhase5 {
             })
             .collect::<Vec<Vec<BigInt>>>();
 
+        let m_b_mat = sent_to_alice(&m_b_sent_mat);
+
         //  let encryption_key_vec  = (0..len).map(|i| local_state_vec[i].encryption_key.clone() ).collect::<Vec<EncryptionKey>>();
         GlobalStatePhase5 {
             k_vec,
@@ -110,12 +146,21 @@ impl GlobalStatePhase5 {
             g_gamma_vec: g_gamma_vec.to_vec(),
             m_a_vec: m_a_vec.to_vec(),
             m_b_mat,
+            m_b_received_mat,
+            m_b_hash_mat: sent_to_alice(&m_b_hash_sent_mat),
         }
     }
 
+    /// Blames parties whose revealed values don't match what they broadcast or sent
+    ///
+    /// Bob is blamed if message B he claims to have sent doesn't match the hash he broadcast,
+    /// or his revealed `gamma` and randomness don't reproduce it. Alice is blamed if message B
+    /// she claims to have received doesn't match the hash, as she checked it on receipt.
+    /// `delta` is reconstructed from messages opened by Bob, so Alice's claim doesn't affect it.
     pub fn phase5_blame(&self) -> Result<(), ErrorType> {
         let len = self.delta_vec.len();
-        let mut bad_signers_vec = Vec::new();
+        let (mut bad_signers_vec, false_claims) =
+            check_m_b_hashes(&self.m_b_mat, &self.m_b_received_mat, &self.m_b_hash_mat);
 
         // check commitment to g_gamma
         for i in 0..len {
@@ -126,11 +171,14 @@ impl GlobalStatePhase5 {
 
         let alpha_beta_matrix = (0..len)
             .map(|i| {
+                // ciphertexts are recomputed without range proofs, so the context is not used
+                let alice_ctx = ProofContext::unbound(i as u16 + 1, SIGNING_LABEL);
                 let message_a = MessageA::a_with_predefined_randomness(
                     &self.k_vec[i],
                     &self.encryption_key_vec[i],
                     &self.k_randomness_vec[i],
                     &[],
+                    &alice_ctx,
                 );
 
                 // check message a
@@ -149,6 +197,7 @@ impl GlobalStatePhase5 {
                                 &self.beta_randomness_vec[i][j],
                                 &self.beta_tag_vec[i][j],
                                 &[],
+                                &alice_ctx,
                             )
                             .unwrap();
                             // check message_b
@@ -213,27 +262,37 @@ impl GlobalStatePhase5 {
                 }
             }
         }
+        bad_signers_vec.extend(false_claims);
 
-        bad_signers_vec.sort_unstable();
-        bad_signers_vec.dedup();
-        let err_type = ErrorType {
-            error_type: "phase6_blame".to_string(),
-            bad_actors: bad_signers_vec,
-            data: Vec::new(),
-        };
-        Err(err_type)
+        Err(blame_result("phase5_blame", bad_signers_vec))
     }
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct LocalStatePhase6 {
-    pub k: Scalar<Secp256k1>,
-    pub k_randomness: BigInt,
+    pub k: Secret<Scalar<Secp256k1>>,
+    pub k_randomness: Secret<BigInt>,
     pub miu: Vec<BigInt>, // we need the value before reduction
     pub miu_randomness: Vec<BigInt>,
     pub proof_of_eq_dlog: ECDDHProof<Secp256k1, Sha256>,
 }
 
+impl LocalStatePhase6 {
+    /// Checks that state revealed by one of `n` signers has values for each of the others
+    pub fn is_complete(&self, n: usize) -> bool {
+        self.miu.len() + 1 == n && self.miu_randomness.len() + 1 == n
+    }
+}
+
+impl Zeroize for LocalStatePhase6 {
+    fn zeroize(&mut self) {
+        self.k.zeroize();
+        self.k_randomness.zeroize();
+        self.miu.zeroize();
+        self.miu_randomness.zeroize();
+    }
+}
+
 // It is assumed the second message of MtAwc (ciphertext from b to a) is broadcasted in the original protocol
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct GlobalStatePhase6 {
@@ -246,7 +305,13 @@ pub struct GlobalStatePhase6 {
     pub proof_vec: Vec<ECDDHProof<Secp256k1, Sha256>>,
     pub S_vec: Vec<Point<Secp256k1>>,
     pub m_a_vec: Vec<MessageA>,
+    /// `m_b_mat[i][j]` is message B sent to Alice `i` by her `j`-th counterparty, as revealed by
+    /// the sender
     pub m_b_mat: Vec<Vec<MessageB>>,
+    /// Same messages as revealed by Alice
+    pub m_b_received_mat: Vec<Vec<MessageB>>,
+    /// `m_b_hash_mat[i][j]` is [m_b_hash] of the same message broadcast by Bob
+    pub m_b_hash_mat: Vec<Vec<BigInt>>,
 }
 
 impl GlobalStatePhase6 {
@@ -271,22 +336,29 @@ impl GlobalStatePhase6 {
         ECDDHProof::prove(&w, &delta)
     }
 
-    // TODO: check all parties submitted inputs
-    // TODO: if not - abort gracefully with list of parties that did not produce inputs
+    /// `local_state_vec` must contain states of all the signers, each one
+    /// [complete](LocalStatePhase6::is_complete)
+    ///
+    /// `m_b_received_mat[i]` holds messages B received by party `i` as Alice, `m_b_sent_mat[i]`
+    /// holds messages B sent by party `i` as Bob and `m_b_hash_sent_mat[i]` holds their hashes
+    /// broadcast by party `i`, all in order of counterparties.
+    #[allow(clippy::too_many_arguments)]
     pub fn local_state_to_global_state(
         encryption_key_vec: &[EncryptionKey],
         S_vec: &[Point<Secp256k1>],
         g_w_vec: &[Point<Secp256k1>],
-        m_a_vec: &[MessageA],        // to test against broadcast message A
-        m_b_mat: Vec<Vec<MessageB>>, // to test against broadcast message B
+        m_a_vec: &[MessageA], // to test against broadcast message A
+        m_b_received_mat: Vec<Vec<MessageB>>,
+        m_b_sent_mat: Vec<Vec<MessageB>>,
+        m_b_hash_sent_mat: Vec<Vec<BigInt>>,
         local_state_vec: &[LocalStatePhase6],
     ) -> Self {
         let len = local_state_vec.len();
         let k_vec = (0..len)
-            .map(|i| local_state_vec[i].k.clone())
+            .map(|i| local_state_vec[i].k.expose().clone())
             .collect::<Vec<Scalar<Secp256k1>>>();
         let k_randomness_vec = (0..len)
-            .map(|i| local_state_vec[i].k_randomness.clone())
+            .map(|i| local_state_vec[i].k_randomness.expose().clone())
             .collect::<Vec<BigInt>>();
         let proof_vec = (0..len)
             .map(|i| local_state_vec[i].proof_of_eq_dlog.clone())
@@ -316,13 +388,22 @@ impl GlobalStatePhase6 {
             proof_vec,
             S_vec: S_vec.to_vec(),
             m_a_vec: m_a_vec.to_vec(),
-            m_b_mat,
+            m_b_mat: sent_to_alice(&m_b_sent_mat),
+            m_b_received_mat,
+            m_b_hash_mat: sent_to_alice(&m_b_hash_sent_mat),
         }
     }
 
+    /// Blames parties whose revealed values don't match what they broadcast or received
+    ///
+    /// Alice is blamed if message B she claims to have received doesn't match the hash
+    /// broadcast by Bob, or her revealed `miu` doesn't open it. Bob is blamed if message B he
+    /// claims to have sent doesn't match the hash.
     pub fn phase6_blame(&self, R: &Point<Secp256k1>) -> Result<(), ErrorType> {
         let len = self.k_vec.len();
-        let mut bad_signers_vec = Vec::new();
+        let (mut bad_signers_vec, false_claims) =
+            check_m_b_hashes(&self.m_b_mat, &self.m_b_received_mat, &self.m_b_hash_mat);
+        bad_signers_vec.extend(false_claims);
 
         // check correctness of miu
         for i in 0..len {
@@ -331,7 +412,7 @@ impl GlobalStatePhase6 {
                     &self.encryption_key_vec[i],
                     RawPlaintext::from(self.miu_vec[i][j].clone()),
                     &Randomness::from(self.miu_randomness_vec[i][j].clone()),
-                ) != RawCiphertext::from(self.m_b_mat[i][j].c.clone())
+                ) != RawCiphertext::from(self.m_b_received_mat[i][j].c.clone())
                 {
                     bad_signers_vec.push(i)
                 }
@@ -345,6 +426,7 @@ impl GlobalStatePhase6 {
                 &self.encryption_key_vec[i],
                 &self.k_randomness_vec[i],
                 &[],
+                &ProofContext::unbound(i as u16 + 1, SIGNING_LABEL),
             )
             .c != self.m_a_vec[i].c
             {
@@ -411,14 +493,65 @@ impl GlobalStatePhase6 {
             }
         }
 
-        bad_signers_vec.sort_unstable();
-        bad_signers_vec.dedup();
-        let err_type = ErrorType {
-            error_type: "phase6_blame".to_string(),
-            bad_actors: bad_signers_vec,
-            data: Vec::new(),
-        };
-        Err(err_type)
+        Err(blame_result("phase6_blame", bad_signers_vec))
+    }
+}
+
+/// Hash of message B which Bob broadcasts after sending the message to Alice point-to-point
+///
+/// Binds both of them to the message delivered, so that the blame round can tell which one
+/// lies about it.
+pub fn m_b_hash(m_b: &MessageB) -> BigInt {
+    Sha256::new().chain_bigint(&m_b.c).result_bigint()
+}
+
+/// Rearranges messages B revealed by their senders (or their hashes), `m_b_sent_mat[j]` holds
+/// messages of Bob `j` in order of Alices, into matrix indexed by Alice like the one of received
+/// messages
+fn sent_to_alice<T: Clone>(m_b_sent_mat: &[Vec<T>]) -> Vec<Vec<T>> {
+    let len = m_b_sent_mat.len();
+    (0..len)
+        .map(|i| {
+            (0..len - 1)
+                .map(|j| {
+                    let ind1 = if j < i { j } else { j + 1 };
+                    let ind2 = if j < i { i - 1 } else { i };
+                    m_b_sent_mat[ind1][ind2].clone()
+                })
+                .collect::<Vec<T>>()
+        })
+        .collect::<Vec<Vec<T>>>()
+}
+
+/// Checks revealed messages B against hashes broadcast by Bob, returns Bobs and Alices whose
+/// versions of the message don't match the hash
+fn check_m_b_hashes(
+    m_b_mat: &[Vec<MessageB>],
+    m_b_received_mat: &[Vec<MessageB>],
+    m_b_hash_mat: &[Vec<BigInt>],
+) -> (Vec<usize>, Vec<usize>) {
+    let mut false_senders = Vec::new();
+    let mut false_claims = Vec::new();
+    for (i, hashes) in m_b_hash_mat.iter().enumerate() {
+        for (j, hash) in hashes.iter().enumerate() {
+            if m_b_hash(&m_b_mat[i][j]) != *hash {
+                false_senders.push(if j < i { j } else { j + 1 });
+            }
+            if m_b_hash(&m_b_received_mat[i][j]) != *hash {
+                false_claims.push(i);
+            }
+        }
+    }
+    (false_senders, false_claims)
+}
+
+fn blame_result(error_type: &str, mut bad_signers_vec: Vec<usize>) -> ErrorType {
+    bad_signers_vec.sort_unstable();
+    bad_signers_vec.dedup();
+    ErrorType {
+        error_type: error_type.to_string(),
+        bad_actors: bad_signers_vec,
+        data: Vec::new(),
     }
 }
 
@@ -1,9 +1,13 @@
-use bip32::{ChainCode,KEY_SIZE};
+use bip32::{ChainCode, Prefix, PublicKey, XPub, KEY_SIZE};
 
-use curv::elliptic::curves::{secp256_k1::Secp256k1, Curve, Scalar};
+use curv::elliptic::curves::{secp256_k1::Secp256k1, Curve, Point, Scalar};
 use serde::{Deserialize, Serialize};
+use std::str::FromStr;
 
-use crate::protocols::multi_party_ecdsa::gg_2020::hd_acount::btc_hd;
+use crate::protocols::multi_party_ecdsa::gg_2020::hd_acount::address::{
+    self, AddressError, BtcAddressType,
+};
+use crate::protocols::multi_party_ecdsa::gg_2020::hd_acount::btc_hd::{self, HdError, HdNode, Network};
 use crate::protocols::multi_party_ecdsa::gg_2020::state_machine::keygen::LocalKey;
 
 #[derive(Serialize, Deserialize, Clone,Debug)]
@@ -12,6 +16,67 @@ pub struct raw_share<E: Curve>{
     pub chain_code : ChainCode,
 }
 
+impl raw_share<Secp256k1> {
+    /// Exports extended public key of the account `ac_path` belongs to, with version bytes
+    /// matching its purpose (e.g. `zpub` for BIP84)
+    pub fn export_account_xpub(
+        &self,
+        ac_path: &account_path,
+        network: Network,
+    ) -> Result<String, HdError> {
+        let (_, xpub) = btc_hd::get_hd_xpub(
+            &ac_path.get_account_path_string(),
+            self.local_key_hd.y_sum_s.clone(),
+            self.chain_code,
+        )?;
+        Ok(xpub.to_string(ac_path.purpose.xpub_prefix(network)))
+    }
+
+    /// Exports Base58Check extended public key at non-hardened `path_str`
+    pub fn export_xpub(&self, path_str: &str, network: Network) -> Result<String, HdError> {
+        let (_, xpub) =
+            btc_hd::get_hd_xpub(path_str, self.local_key_hd.y_sum_s.clone(), self.chain_code)?;
+        Ok(xpub.to_string(network.xpub_prefix()))
+    }
+
+    /// Parses extended public key and checks that it was derived from this share at `path_str`
+    /// and is serialized for `network`
+    ///
+    /// Returned [XPub] can be given to a watch-only wallet, see [WatchOnlyAccount::from_xpub].
+    pub fn import_xpub(
+        &self,
+        xpub: &str,
+        path_str: &str,
+        network: Network,
+    ) -> Result<XPub, HdError> {
+        let (imported, actual) = btc_hd::parse_xpub(xpub)?;
+        if actual != network {
+            return Err(HdError::NetworkMismatch {
+                expected: network,
+                actual,
+            });
+        }
+        let (_, expected) =
+            btc_hd::get_hd_xpub(path_str, self.local_key_hd.y_sum_s.clone(), self.chain_code)?;
+
+        let (imported_attrs, expected_attrs) = (imported.attrs(), expected.attrs());
+        if imported.public_key().to_bytes() != expected.public_key().to_bytes()
+            || imported_attrs.chain_code != expected_attrs.chain_code
+            || imported_attrs.depth != expected_attrs.depth
+            || imported_attrs.parent_fingerprint != expected_attrs.parent_fingerprint
+            || imported_attrs.child_number != expected_attrs.child_number
+        {
+            return Err(HdError::XpubMismatch {
+                path: path_str.to_string(),
+            });
+        }
+        Ok(imported)
+    }
+}
+
+
+/// Number of consecutive unused addresses after which discovery stops, as recommended by BIP44
+pub const DEFAULT_GAP_LIMIT: u32 = 20;
 
 #[derive(Clone, Debug)]
 pub struct HD_Account<E: Curve> {
@@ -19,6 +84,21 @@ pub struct HD_Account<E: Curve> {
     pub chain_code : ChainCode,
     pub index : u32,
     pub path : String,
+    /// Cached account nodes, their tweaks are relative to the root key
+    pub watch_only : WatchOnlyAccount,
+}
+
+/// Account which derives and discovers addresses from its extended public key alone
+///
+/// Built either from the account node of [HD_Account], or from an account-level [XPub] (see
+/// [raw_share::export_account_xpub]) by a party that holds no share. In the latter case tweaks
+/// of derived nodes are relative to the account key.
+#[derive(Clone, Debug)]
+pub struct WatchOnlyAccount {
+    /// Cached node at `m/purpose/coin_type/account`
+    pub account_node : HdNode,
+    /// Cached nodes of receive and change chains
+    pub chain_nodes : [HdNode; 2],
 }
 
 #[derive(Clone, Debug)]
@@ -43,64 +123,545 @@ impl account_usage {
     }
 }
 
+/// Purpose level of the path, defines address type and xpub version bytes of the account
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum account_purpose {
+    /// BIP44, legacy P2PKH
+    Bip44,
+    /// BIP49, P2WPKH nested in P2SH
+    Bip49,
+    /// BIP84, native segwit P2WPKH
+    Bip84,
+    /// BIP86, taproot P2TR
+    Bip86,
+}
+impl account_purpose {
+    pub fn get_num(&self) -> u32 {
+        match self {
+            account_purpose::Bip44 => 44,
+            account_purpose::Bip49 => 49,
+            account_purpose::Bip84 => 84,
+            account_purpose::Bip86 => 86,
+        }
+    }
+    pub fn from_num(i: u32) -> Option<Self> {
+        match i {
+            44 => Some(account_purpose::Bip44),
+            49 => Some(account_purpose::Bip49),
+            84 => Some(account_purpose::Bip84),
+            86 => Some(account_purpose::Bip86),
+            _ => None,
+        }
+    }
+    /// Default bitcoin address type of accounts with this purpose
+    pub fn address_type(&self) -> BtcAddressType {
+        match self {
+            account_purpose::Bip44 => BtcAddressType::P2pkh,
+            account_purpose::Bip49 => BtcAddressType::P2shP2wpkh,
+            account_purpose::Bip84 => BtcAddressType::P2wpkh,
+            account_purpose::Bip86 => BtcAddressType::P2tr,
+        }
+    }
+    /// Version bytes of account extended public key: xpub/ypub/zpub, or tpub/upub/vpub on testnet
+    pub fn xpub_prefix(&self, network: Network) -> Prefix {
+        match (self, network) {
+            (account_purpose::Bip44, _) | (account_purpose::Bip86, _) => network.xpub_prefix(),
+            (account_purpose::Bip49, Network::Mainnet) => Prefix::YPUB,
+            (account_purpose::Bip49, Network::Testnet) => btc_hd::UPUB,
+            (account_purpose::Bip84, Network::Mainnet) => Prefix::ZPUB,
+            (account_purpose::Bip84, Network::Testnet) => btc_hd::VPUB,
+        }
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct account_path{
+    pub purpose : account_purpose,
     pub coin_type : u32,
     pub account_index : u32,
     pub usage : account_usage,
-    //pub address_index : u32,
+    pub address_index : u32,
 }
 impl account_path {
-    pub fn init(coin_type:u32,index:u32,usage:account_usage)
+    /// Constructs a BIP44 path, use [account_path::with_purpose] to change the purpose
+    pub fn init(coin_type:u32,index:u32,usage:account_usage,address_index:u32)
     ->Self{
         account_path{
+            purpose : account_purpose::Bip44,
             coin_type : coin_type,
             account_index : index,
             usage : usage,
-            //address_index : index,
+            address_index : address_index,
         }
     }
+    pub fn with_purpose(self, purpose: account_purpose) -> Self {
+        account_path { purpose, ..self }
+    }
+    /// Parses a full path `m/purpose/coin_type/account/change/address_index`, where purpose is
+    /// one of 44, 49, 84 or 86
+    pub fn from_path_str(path_str: &str) -> Result<Self, HdError> {
+        let path = btc_hd::parse_path(path_str)?;
+        let invalid = |position: usize, reason: &'static str| HdError::InvalidPath {
+            path: path_str.to_string(),
+            position,
+            component: path_str.split('/').nth(position).unwrap_or("").to_string(),
+            reason,
+        };
+
+        let indexes: Vec<u32> = path.as_ref().iter().map(|c| c.index()).collect();
+        if indexes.len() != 5 {
+            return Err(invalid(indexes.len().min(5) + 1, "bip44 path must have exactly 5 levels"));
+        }
+        let purpose = account_purpose::from_num(indexes[0])
+            .ok_or_else(|| invalid(1, "must be purpose 44, 49, 84 or 86"))?;
+        let usage = match indexes[3] {
+            0 => account_usage::Receive,
+            1 => account_usage::Change,
+            _ => return Err(invalid(4, "must be 0 (receive) or 1 (change)")),
+        };
+        Ok(account_path::init(indexes[1], indexes[2], usage, indexes[4]).with_purpose(purpose))
+    }
+    /// Address of the child key `pk` derived at this path, format is chosen by `coin_type`
+    /// and `purpose`
+    pub fn address(&self, pk: &Point<Secp256k1>) -> Result<String, AddressError> {
+        address::address(self.coin_type, pk, self.purpose.address_type())
+    }
+    /// Path of the account level `m/purpose/coin_type/account`, which is the level xpubs are
+    /// usually exported at
+    pub fn get_account_path_string(&self) -> String {
+        format!(
+            "m/{}/{}/{}",
+            self.purpose.get_num(),
+            self.coin_type,
+            self.account_index
+        )
+    }
+    /// Full path `m/purpose/coin_type/account/change/address_index`
     pub fn get_path_string_bip44(&self,)
     ->String{
-        String::from("m/44") 
+        String::from("m/")
+        + &self.purpose.get_num().to_string()
         + &String::from("/") 
         + &self.coin_type.to_string()
         + &String::from("/") 
         + &self.account_index.to_string()
         + &String::from("/") 
         + &self.usage.get_num().to_string()
-        // + &String::from("/") 
-        // + &self.address_index.to_string()
+        + &String::from("/") 
+        + &self.address_index.to_string()
+    }
+}
+impl FromStr for account_path {
+    type Err = HdError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        account_path::from_path_str(s)
     }
 }
 impl HD_Account<Secp256k1> {
     pub fn init(
         local_key : &LocalKey<Secp256k1>,
         chain_code : ChainCode,
-        coin_type : u32,
-        index : u32,
-        usage : account_usage)
-    ->Self{
-        let path = account_path::init(coin_type,index,usage);
+        path : &account_path)
+    ->Result<Self, HdError>{
+        let root = HdNode::root(&local_key.y_sum_s, chain_code)?;
+        let account_node = root.derive_path(&path.get_account_path_string())?;
+        let watch_only = WatchOnlyAccount::from_node(account_node)?;
+        let leaf = watch_only.derive_address(&path.usage, path.address_index)?;
 
-        let (tweak_sk, y_sum) = 
-        btc_hd::call_hd_key(&path.get_path_string_bip44(), local_key, chain_code);
-
-        HD_Account{
-            local_key_hd: local_key.update_hd_key(&Scalar::<Secp256k1>::zero(), &tweak_sk, &y_sum),
+        Ok(HD_Account{
+            local_key_hd: local_key.update_hd_key(&Scalar::<Secp256k1>::zero(), &leaf.tweak, &leaf.public_key()),
             chain_code : chain_code,
-            index : index,
+            index : path.account_index,
             path : path.get_path_string_bip44(),
+            watch_only,
+        })
+    }
+}
+
+impl WatchOnlyAccount {
+    /// Watch-only account of an account-level extended public key
+    pub fn from_xpub(xpub: XPub) -> Result<Self, HdError> {
+        Self::from_node(HdNode::from_xpub(xpub))
+    }
+
+    fn from_node(account_node: HdNode) -> Result<Self, HdError> {
+        let chain_nodes = [account_node.derive_child(0)?, account_node.derive_child(1)?];
+        Ok(WatchOnlyAccount {
+            account_node,
+            chain_nodes,
+        })
+    }
+
+    /// Cached node of receive or change chain
+    pub fn chain_node(&self, usage: &account_usage) -> &HdNode {
+        &self.chain_nodes[usage.get_num() as usize]
+    }
+
+    /// Derives node of a single address
+    pub fn derive_address(&self, usage: &account_usage, address_index: u32) -> Result<HdNode, HdError> {
+        self.chain_node(usage).derive_child(address_index)
+    }
+
+    /// Derives nodes of addresses in range `indexes` of receive or change chain
+    pub fn derive_batch(
+        &self,
+        usage: &account_usage,
+        indexes: std::ops::Range<u32>,
+    ) -> Result<Vec<(u32, HdNode)>, HdError> {
+        let chain_node = self.chain_node(usage);
+        indexes
+            .map(|i| chain_node.derive_child(i).map(|node| (i, node)))
+            .collect()
+    }
+
+    /// Iterates over nodes of all addresses of receive or change chain, starting from 0
+    ///
+    /// Indexes that yield an invalid key are skipped, as BIP32 prescribes.
+    pub fn iter_addresses<'a>(
+        &'a self,
+        usage: &account_usage,
+    ) -> impl Iterator<Item = Result<(u32, HdNode), HdError>> + 'a {
+        let chain_node = self.chain_node(usage);
+        valid_children(move |i| chain_node.derive_child(i))
+    }
+
+    /// Discovers used addresses of receive or change chain
+    ///
+    /// Scans addresses in order and stops once `gap_limit` consecutive addresses were reported
+    /// unused by `is_used`. Skipped indexes (see [WatchOnlyAccount::iter_addresses]) don't count
+    /// towards the gap. Returns used addresses only.
+    pub fn discover<F>(
+        &self,
+        usage: &account_usage,
+        gap_limit: u32,
+        is_used: F,
+    ) -> Result<Vec<(u32, HdNode)>, HdError>
+    where
+        F: FnMut(u32, &HdNode) -> bool,
+    {
+        discover_used(self.iter_addresses(usage), gap_limit, is_used)
+    }
+}
+
+/// Children at indexes `[0; 2^31)` derived by `derive`, skipping indexes which result in
+/// [HdError::InvalidTweak]
+fn valid_children<'a, D>(derive: D) -> impl Iterator<Item = Result<(u32, HdNode), HdError>> + 'a
+where
+    D: Fn(u32) -> Result<HdNode, HdError> + 'a,
+{
+    (0..1u32 << 31).filter_map(move |i| match derive(i) {
+        Ok(node) => Some(Ok((i, node))),
+        Err(HdError::InvalidTweak { .. }) => None,
+        Err(err) => Some(Err(err)),
+    })
+}
+
+/// Gap limit scan of `nodes`, see [WatchOnlyAccount::discover]
+fn discover_used<I, F>(
+    nodes: I,
+    gap_limit: u32,
+    mut is_used: F,
+) -> Result<Vec<(u32, HdNode)>, HdError>
+where
+    I: Iterator<Item = Result<(u32, HdNode), HdError>>,
+    F: FnMut(u32, &HdNode) -> bool,
+{
+    let mut used = vec![];
+    let mut gap = 0;
+    for node in nodes {
+        if gap >= gap_limit {
+            break;
+        }
+        let (i, node) = node?;
+        if is_used(i, &node) {
+            gap = 0;
+            used.push((i, node));
+        } else {
+            gap += 1;
         }
     }
-    
-    // pub fn _local_key(
-    //     &self,)
-    // ->LocalKey<Secp256k1>{
-            
-    //     let (tweak_sk, y_sum) = 
-    //     btc_hd::call_hd_key(&self.path, &self.local_key_base, self.chain_code);
-    //     self.local_key_base.update_hd_key(&Scalar::<Secp256k1>::zero(), &tweak_sk, &y_sum)
-    //     //self.local_key.clone()
-    // }
-}
\ No newline at end of file
+    Ok(used)
+}
+
+#[cfg(test)]
+mod test {
+    use bip32::ExtendedKey;
+
+    use super::*;
+    use crate::protocols::multi_party_ecdsa::gg_2020::state_machine::keygen::test::simulate_keygen;
+
+    #[test]
+    fn from_path_str_parses_full_path() {
+        let path = account_path::from_path_str("m/84/0/3/1/17").unwrap();
+        assert_eq!(path.purpose, account_purpose::Bip84);
+        assert_eq!(path.coin_type, 0);
+        assert_eq!(path.account_index, 3);
+        assert!(matches!(path.usage, account_usage::Change));
+        assert_eq!(path.address_index, 17);
+        assert_eq!(path.get_path_string_bip44(), "m/84/0/3/1/17");
+        assert_eq!(path.get_account_path_string(), "m/84/0/3");
+
+        let path: account_path = "m/44/60/0/0/5".parse().unwrap();
+        assert_eq!(path.purpose, account_purpose::Bip44);
+        assert!(matches!(path.usage, account_usage::Receive));
+        assert_eq!(path.get_path_string_bip44(), "m/44/60/0/0/5");
+    }
+
+    #[test]
+    fn from_path_str_rejects_malformed_paths() {
+        let position = |path_str: &str| match account_path::from_path_str(path_str) {
+            Err(HdError::InvalidPath { position, .. }) => position,
+            result => panic!("{}: unexpected result {:?}", path_str, result.map(|_| ())),
+        };
+        assert_eq!(position("m/44/0/0/0"), 5);
+        assert_eq!(position("m/44/0/0/0/0/0"), 6);
+        assert_eq!(position("m/45/0/0/0/0"), 1);
+        assert_eq!(position("m/44/0/0/2/0"), 4);
+        assert_eq!(position("m/44/0/x/0/0"), 3);
+        assert_eq!(position("n/44/0/0/0/0"), 0);
+
+        assert!(matches!(
+            account_path::from_path_str("m/44'/0'/0'/0/0"),
+            Err(HdError::HardenedIndex { position: 1, .. })
+        ));
+    }
+
+    #[test]
+    fn account_purpose_defines_address_type_and_xpub_prefix() {
+        use super::account_purpose::*;
+        let expected = [
+            (Bip44, BtcAddressType::P2pkh, Prefix::XPUB, Prefix::TPUB),
+            (Bip49, BtcAddressType::P2shP2wpkh, Prefix::YPUB, btc_hd::UPUB),
+            (Bip84, BtcAddressType::P2wpkh, Prefix::ZPUB, btc_hd::VPUB),
+            (Bip86, BtcAddressType::P2tr, Prefix::XPUB, Prefix::TPUB),
+        ];
+        for &(purpose, address_type, mainnet, testnet) in &expected {
+            assert_eq!(account_purpose::from_num(purpose.get_num()), Some(purpose));
+            assert_eq!(purpose.address_type(), address_type);
+            assert_eq!(purpose.xpub_prefix(Network::Mainnet), mainnet);
+            assert_eq!(purpose.xpub_prefix(Network::Testnet), testnet);
+            // prefix of every purpose is recognized when the xpub is parsed back
+            assert_eq!(Network::from_xpub_prefix(mainnet), Some(Network::Mainnet));
+            assert_eq!(Network::from_xpub_prefix(testnet), Some(Network::Testnet));
+        }
+        assert_eq!(account_purpose::from_num(0), None);
+        assert_eq!(account_purpose::from_num(48), None);
+
+        let path = account_path::init(0, 0, account_usage::Receive, 0).with_purpose(Bip86);
+        assert_eq!(path.get_path_string_bip44(), "m/86/0/0/0/0");
+    }
+
+    #[test]
+    fn imported_xpub_must_match_share_path_and_network() {
+        let share = simulate_keygen(1, 2).remove(0);
+
+        let exported = share.export_xpub("m/44/0/0", Network::Mainnet).unwrap();
+        let imported = share.import_xpub(&exported, "m/44/0/0", Network::Mainnet).unwrap();
+        let (_, expected) =
+            btc_hd::get_hd_xpub("m/44/0/0", share.local_key_hd.y_sum_s.clone(), share.chain_code)
+                .unwrap();
+        assert_eq!(imported.to_string(Prefix::XPUB), exported);
+        assert_eq!(imported.public_key().to_bytes(), expected.public_key().to_bytes());
+
+        let path = account_path::from_path_str("m/84/1/2/0/0").unwrap();
+        let account_xpub = share.export_account_xpub(&path, Network::Testnet).unwrap();
+        assert!(account_xpub.starts_with("vpub"));
+        share
+            .import_xpub(&account_xpub, &path.get_account_path_string(), Network::Testnet)
+            .unwrap();
+
+        assert_eq!(
+            share
+                .import_xpub(&exported, "m/44/0/0", Network::Testnet)
+                .unwrap_err(),
+            HdError::NetworkMismatch {
+                expected: Network::Testnet,
+                actual: Network::Mainnet,
+            }
+        );
+
+        // same key and chain code, but wrong depth
+        let mut deeper = ExtendedKey::from_str(&exported).unwrap();
+        deeper.attrs.depth += 1;
+        assert!(matches!(
+            share.import_xpub(&deeper.to_string(), "m/44/0/0", Network::Mainnet),
+            Err(HdError::XpubMismatch { .. })
+        ));
+
+        // key of another account
+        let other = share.export_xpub("m/44/0/1", Network::Mainnet).unwrap();
+        assert!(matches!(
+            share.import_xpub(&other, "m/44/0/0", Network::Mainnet),
+            Err(HdError::XpubMismatch { .. })
+        ));
+
+        assert!(matches!(
+            share.import_xpub("xpub-garbage", "m/44/0/0", Network::Mainnet),
+            Err(HdError::InvalidXpub { .. })
+        ));
+    }
+
+    fn random_root() -> HdNode {
+        let pk = Point::generator() * Scalar::<Secp256k1>::random();
+        HdNode::root(&pk, [7u8; 32]).unwrap()
+    }
+
+    fn indexes(nodes: &[(u32, HdNode)]) -> Vec<u32> {
+        nodes.iter().map(|(i, _)| *i).collect()
+    }
+
+    /// Runs gap limit scan over `children` where `used` indexes are used, returns found indexes
+    /// and all the indexes that were checked
+    fn discover_in(
+        children: impl Iterator<Item = Result<(u32, HdNode), HdError>>,
+        gap_limit: u32,
+        used: &[u32],
+    ) -> (Vec<u32>, Vec<u32>) {
+        let mut checked = vec![];
+        let found = discover_used(children, gap_limit, |i, _| {
+            checked.push(i);
+            used.contains(&i)
+        })
+        .unwrap();
+        (indexes(&found), checked)
+    }
+
+    #[test]
+    fn account_derives_addresses_consistently() {
+        let share = simulate_keygen(1, 2).remove(0);
+        let path = account_path::from_path_str("m/84/0/0/0/5").unwrap();
+        let hd_account = HD_Account::init(&share.local_key_hd, share.chain_code, &path).unwrap();
+        let account = &hd_account.watch_only;
+        let y = &share.local_key_hd.y_sum_s;
+
+        let batch = account.derive_batch(&account_usage::Change, 3..7).unwrap();
+        assert_eq!(indexes(&batch), vec![3, 4, 5, 6]);
+        for (i, node) in &batch {
+            let (tweak, xpub) =
+                btc_hd::get_hd_xpub(&format!("m/84/0/0/1/{}", i), y.clone(), share.chain_code)
+                    .unwrap();
+            assert_eq!(node.tweak, tweak);
+            assert_eq!(node.xpub.to_bytes(), xpub.to_bytes());
+            let single = account.derive_address(&account_usage::Change, *i).unwrap();
+            assert_eq!(single.public_key(), node.public_key());
+        }
+
+        let iterated: Vec<_> = account
+            .iter_addresses(&account_usage::Change)
+            .take(7)
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(indexes(&iterated), (0..7).collect::<Vec<_>>());
+        assert_eq!(iterated[5].1.public_key(), batch[2].1.public_key());
+
+        let leaf = account.derive_address(&account_usage::Receive, 5).unwrap();
+        assert_eq!(hd_account.local_key_hd.y_sum_s, leaf.public_key());
+
+        let found = account
+            .discover(&account_usage::Receive, 3, |i, _| i == 1 || i == 4)
+            .unwrap();
+        assert_eq!(indexes(&found), vec![1, 4]);
+    }
+
+    #[test]
+    fn watch_only_account_derives_same_addresses_as_share() {
+        let share = simulate_keygen(1, 2).remove(0);
+        let path = account_path::from_path_str("m/84/0/2/0/0").unwrap();
+        let hd_account = HD_Account::init(&share.local_key_hd, share.chain_code, &path).unwrap();
+
+        // watch-only party only receives account xpub
+        let exported = share.export_account_xpub(&path, Network::Mainnet).unwrap();
+        let (xpub, _) = btc_hd::parse_xpub(&exported).unwrap();
+        let watch_only = WatchOnlyAccount::from_xpub(xpub).unwrap();
+        assert_eq!(
+            watch_only.account_node.public_key(),
+            hd_account.watch_only.account_node.public_key()
+        );
+
+        for usage in &[account_usage::Receive, account_usage::Change] {
+            let expected = hd_account.watch_only.derive_batch(usage, 0..5).unwrap();
+            let actual = watch_only.derive_batch(usage, 0..5).unwrap();
+            for ((i, node), (j, watched)) in expected.iter().zip(&actual) {
+                assert_eq!(i, j);
+                assert_eq!(node.public_key(), watched.public_key());
+                // tweaks differ exactly by tweak of the account key
+                assert_eq!(
+                    node.tweak,
+                    &watched.tweak + &hd_account.watch_only.account_node.tweak
+                );
+            }
+
+            let iterated = watch_only.iter_addresses(usage).nth(3).unwrap().unwrap();
+            assert_eq!(iterated.1.public_key(), actual[3].1.public_key());
+        }
+
+        let expected = hd_account
+            .watch_only
+            .discover(&account_usage::Receive, 2, |i, _| i == 0 || i == 2)
+            .unwrap();
+        let used_keys: Vec<_> = expected.iter().map(|(_, node)| node.public_key()).collect();
+        let found = watch_only
+            .discover(&account_usage::Receive, 2, |_, node| {
+                used_keys.contains(&node.public_key())
+            })
+            .unwrap();
+        assert_eq!(indexes(&found), vec![0, 2]);
+    }
+
+    #[test]
+    fn discover_stops_after_gap_limit_unused_addresses() {
+        let root = random_root();
+        let children = || valid_children(|i| root.derive_child(i));
+
+        // two unused addresses between used ones are within gap limit of 3
+        let (found, checked) = discover_in(children(), 3, &[0, 3]);
+        assert_eq!(found, vec![0, 3]);
+        assert_eq!(checked, (0..=6).collect::<Vec<_>>());
+
+        // three unused addresses reach the limit, address 4 is never checked
+        let (found, checked) = discover_in(children(), 3, &[0, 4]);
+        assert_eq!(found, vec![0]);
+        assert_eq!(checked, vec![0, 1, 2, 3]);
+
+        let (found, checked) = discover_in(children(), 0, &[0]);
+        assert!(found.is_empty() && checked.is_empty());
+    }
+
+    #[test]
+    fn index_with_invalid_tweak_is_skipped() {
+        let root = random_root();
+        let derive = |i: u32| {
+            if i == 2 {
+                Err(HdError::InvalidTweak {
+                    position: 1,
+                    component: i.to_string(),
+                })
+            } else {
+                root.derive_child(i)
+            }
+        };
+
+        let children: Vec<_> = valid_children(derive)
+            .take(4)
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(indexes(&children), vec![0, 1, 3, 4]);
+        assert_eq!(children[2].1.public_key(), root.derive_child(3).unwrap().public_key());
+
+        // skipped index doesn't count towards the gap
+        let (found, checked) = discover_in(valid_children(derive), 3, &[0, 4]);
+        assert_eq!(found, vec![0, 4]);
+        assert_eq!(checked, vec![0, 1, 3, 4, 5, 6, 7]);
+
+        // other errors are still reported
+        let failing = valid_children(|i| {
+            if i == 1 {
+                Err(HdError::DepthOverflow { position: 1 })
+            } else {
+                root.derive_child(i)
+            }
+        });
+        let results: Vec<_> = failing.take(2).collect();
+        assert_eq!(results[1].as_ref().unwrap_err(), &HdError::DepthOverflow { position: 1 });
+    }
+}
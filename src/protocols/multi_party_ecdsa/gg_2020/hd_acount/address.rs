@@ -0,0 +1,301 @@
+//! Bitcoin and Ethereum addresses of derived child keys
+
+use curv::arithmetic::Converter;
+use curv::elliptic::curves::{secp256_k1::Secp256k1, Point, Scalar};
+use curv::BigInt;
+use ripemd::Ripemd160;
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+
+use crate::protocols::multi_party_ecdsa::gg_2020::hd_acount::btc_hd::Network;
+
+/// SLIP-44 coin type of Bitcoin
+pub const COIN_TYPE_BITCOIN: u32 = 0;
+/// SLIP-44 coin type shared by all Bitcoin testnets
+pub const COIN_TYPE_TESTNET: u32 = 1;
+/// SLIP-44 coin type of Ethereum
+pub const COIN_TYPE_ETHEREUM: u32 = 60;
+
+const BECH32_CHARSET: &[u8; 32] = b"qpzry9x8gf2tvdw0s3jn54khce6mua7l";
+const BECH32_CONST: u32 = 1;
+const BECH32M_CONST: u32 = 0x2bc8_30a3;
+
+/// Bitcoin address type
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BtcAddressType {
+    /// Legacy pay-to-pubkey-hash, `1...`
+    P2pkh,
+    /// Segwit v0 wrapped into pay-to-script-hash, `3...`
+    P2shP2wpkh,
+    /// Native segwit v0, `bc1q...`
+    P2wpkh,
+    /// Taproot key path spend, `bc1p...`
+    P2tr,
+}
+
+#[derive(Debug, Error, Clone, PartialEq, Eq)]
+pub enum AddressError {
+    #[error("coin type {0} is not supported")]
+    UnsupportedCoinType(u32),
+    #[error("public key is a point at infinity")]
+    PointAtInfinity,
+}
+
+/// Address of `pk` for SLIP-44 `coin_type`
+///
+/// `btc_type` is ignored for Ethereum.
+pub fn address(
+    coin_type: u32,
+    pk: &Point<Secp256k1>,
+    btc_type: BtcAddressType,
+) -> Result<String, AddressError> {
+    match coin_type {
+        COIN_TYPE_BITCOIN => btc_address(pk, btc_type, Network::Mainnet),
+        COIN_TYPE_TESTNET => btc_address(pk, btc_type, Network::Testnet),
+        COIN_TYPE_ETHEREUM => eth_address(pk),
+        _ => Err(AddressError::UnsupportedCoinType(coin_type)),
+    }
+}
+
+pub fn btc_address(
+    pk: &Point<Secp256k1>,
+    btc_type: BtcAddressType,
+    network: Network,
+) -> Result<String, AddressError> {
+    match btc_type {
+        BtcAddressType::P2pkh => p2pkh(pk, network),
+        BtcAddressType::P2shP2wpkh => p2sh_p2wpkh(pk, network),
+        BtcAddressType::P2wpkh => p2wpkh(pk, network),
+        BtcAddressType::P2tr => p2tr(pk, network),
+    }
+}
+
+pub fn p2pkh(pk: &Point<Secp256k1>, network: Network) -> Result<String, AddressError> {
+    let version = match network {
+        Network::Mainnet => 0x00,
+        Network::Testnet => 0x6f,
+    };
+    Ok(base58check(version, &hash160(&compressed(pk)?)))
+}
+
+pub fn p2sh_p2wpkh(pk: &Point<Secp256k1>, network: Network) -> Result<String, AddressError> {
+    let version = match network {
+        Network::Mainnet => 0x05,
+        Network::Testnet => 0xc4,
+    };
+    // redeem script is `OP_0 <20-byte pubkey hash>`
+    let mut redeem_script = vec![0x00, 0x14];
+    redeem_script.extend_from_slice(&hash160(&compressed(pk)?));
+    Ok(base58check(version, &hash160(&redeem_script)))
+}
+
+pub fn p2wpkh(pk: &Point<Secp256k1>, network: Network) -> Result<String, AddressError> {
+    Ok(segwit_address(
+        hrp(network),
+        0,
+        &hash160(&compressed(pk)?),
+    ))
+}
+
+/// Taproot address with no script path, as specified in BIP86
+pub fn p2tr(pk: &Point<Secp256k1>, network: Network) -> Result<String, AddressError> {
+    let pk_bytes = compressed(pk)?;
+    let x_only = &pk_bytes[1..];
+    // internal key is the point with even y and the same x
+    let mut even_pk_bytes = [0u8; 33];
+    even_pk_bytes[0] = 0x02;
+    even_pk_bytes[1..].copy_from_slice(x_only);
+    let internal_key = Point::<Secp256k1>::from_bytes(&even_pk_bytes)
+        .map_err(|_| AddressError::PointAtInfinity)?;
+
+    let tweak = tagged_hash(b"TapTweak", x_only);
+    let tweak = Scalar::<Secp256k1>::from_bigint(&BigInt::from_bytes(&tweak));
+    let output_key = internal_key + Point::generator() * tweak;
+    let output_key_bytes = compressed(&output_key)?;
+    Ok(segwit_address(hrp(network), 1, &output_key_bytes[1..]))
+}
+
+/// EIP-55 checksummed Ethereum address
+pub fn eth_address(pk: &Point<Secp256k1>) -> Result<String, AddressError> {
+    use sha3::{Digest, Keccak256};
+
+    if pk.is_zero() {
+        return Err(AddressError::PointAtInfinity);
+    }
+    let uncompressed = pk.to_bytes(false);
+    let digest = Keccak256::digest(&uncompressed[1..]);
+    let address = hex::encode(&digest[12..]);
+
+    let checksum = Keccak256::digest(address.as_bytes());
+    let checksummed: String = address
+        .chars()
+        .enumerate()
+        .map(|(i, c)| {
+            let nibble = (checksum[i / 2] >> (4 * (1 - i % 2))) & 0x0f;
+            if nibble >= 8 {
+                c.to_ascii_uppercase()
+            } else {
+                c
+            }
+        })
+        .collect();
+    Ok(format!("0x{}", checksummed))
+}
+
+fn compressed(pk: &Point<Secp256k1>) -> Result<Vec<u8>, AddressError> {
+    if pk.is_zero() {
+        return Err(AddressError::PointAtInfinity);
+    }
+    Ok(pk.to_bytes(true).to_vec())
+}
+
+fn hrp(network: Network) -> &'static str {
+    match network {
+        Network::Mainnet => "bc",
+        Network::Testnet => "tb",
+    }
+}
+
+fn hash160(data: &[u8]) -> Vec<u8> {
+    use ripemd::Digest as _;
+    Ripemd160::digest(&Sha256::digest(data)).to_vec()
+}
+
+fn tagged_hash(tag: &[u8], data: &[u8]) -> Vec<u8> {
+    let tag_hash = Sha256::digest(tag);
+    let mut hasher = Sha256::new();
+    hasher.update(&tag_hash);
+    hasher.update(&tag_hash);
+    hasher.update(data);
+    hasher.finalize().to_vec()
+}
+
+pub(crate) fn base58check(version: u8, payload: &[u8]) -> String {
+    let mut data = vec![version];
+    data.extend_from_slice(payload);
+    let checksum = Sha256::digest(&Sha256::digest(&data));
+    data.extend_from_slice(&checksum[..4]);
+    bs58::encode(data).into_string()
+}
+
+fn segwit_address(hrp: &str, witness_version: u8, program: &[u8]) -> String {
+    let mut data = vec![witness_version];
+    data.extend(convert_8_to_5_bits(program));
+    let constant = if witness_version == 0 {
+        BECH32_CONST
+    } else {
+        BECH32M_CONST
+    };
+
+    let mut values = bech32_hrp_expand(hrp);
+    values.extend_from_slice(&data);
+    values.extend_from_slice(&[0u8; 6]);
+    let polymod = bech32_polymod(&values) ^ constant;
+    data.extend((0..6).map(|i| ((polymod >> (5 * (5 - i))) & 31) as u8));
+
+    let mut address = String::from(hrp);
+    address.push('1');
+    address.extend(data.iter().map(|&d| BECH32_CHARSET[usize::from(d)] as char));
+    address
+}
+
+fn bech32_polymod(values: &[u8]) -> u32 {
+    const GENERATOR: [u32; 5] = [0x3b6a_57b2, 0x2650_8e6d, 0x1ea1_19fa, 0x3d42_33dd, 0x2a14_62b3];
+    let mut chk: u32 = 1;
+    for &value in values {
+        let top = chk >> 25;
+        chk = ((chk & 0x01ff_ffff) << 5) ^ u32::from(value);
+        for (i, g) in GENERATOR.iter().enumerate() {
+            if (top >> i) & 1 == 1 {
+                chk ^= g;
+            }
+        }
+    }
+    chk
+}
+
+fn bech32_hrp_expand(hrp: &str) -> Vec<u8> {
+    let mut expanded: Vec<u8> = hrp.bytes().map(|c| c >> 5).collect();
+    expanded.push(0);
+    expanded.extend(hrp.bytes().map(|c| c & 31));
+    expanded
+}
+
+fn convert_8_to_5_bits(data: &[u8]) -> Vec<u8> {
+    let mut acc: u32 = 0;
+    let mut bits = 0;
+    let mut result = Vec::with_capacity((data.len() * 8 + 4) / 5);
+    for &byte in data {
+        acc = (acc << 8) | u32::from(byte);
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            result.push(((acc >> bits) & 31) as u8);
+        }
+    }
+    if bits > 0 {
+        result.push(((acc << (5 - bits)) & 31) as u8);
+    }
+    result
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn generator() -> Point<Secp256k1> {
+        Point::generator().to_point()
+    }
+
+    #[test]
+    fn bitcoin_addresses_of_generator() {
+        let g = generator();
+        assert_eq!(
+            p2pkh(&g, Network::Mainnet).unwrap(),
+            "1BgGZ9tcN4rm9KBzDn7KprQz87SZ26SAMH"
+        );
+        assert_eq!(
+            p2sh_p2wpkh(&g, Network::Mainnet).unwrap(),
+            "3JvL6Ymt8MVWiCNHC7oWU6nLeHNJKLZGLN"
+        );
+        assert_eq!(
+            p2wpkh(&g, Network::Mainnet).unwrap(),
+            "bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4"
+        );
+    }
+
+    #[test]
+    fn bech32m_encoding() {
+        let x = hex::decode("79be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798")
+            .unwrap();
+        assert_eq!(
+            segwit_address("bc", 1, &x),
+            "bc1p0xlxvlhemja6c4dqv22uapctqupfhlxm9h8z3k2e72q4k9hcz7vqzk5jj0"
+        );
+    }
+
+    #[test]
+    fn p2tr_matches_bip86_test_vector() {
+        let internal_key = Point::<Secp256k1>::from_bytes(
+            &hex::decode("02cc8a4bc64d897bddc5fbc2f670f7a8ba0b386779106cf1223c6fc5d7cd6fc115")
+                .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(
+            p2tr(&internal_key, Network::Mainnet).unwrap(),
+            "bc1p5cyxnuxmeuwuvkwfem96lqzszd02n6xdcjrs20cac6yqjjwudpxqkedrcr"
+        );
+    }
+
+    #[test]
+    fn eip55_address_of_generator() {
+        assert_eq!(
+            address(COIN_TYPE_ETHEREUM, &generator(), BtcAddressType::P2pkh).unwrap(),
+            "0x7E5F4552091A69125d5DfCb7b8C2659029395Bdf"
+        );
+        assert_eq!(
+            address(2, &generator(), BtcAddressType::P2pkh),
+            Err(AddressError::UnsupportedCoinType(2))
+        );
+    }
+}
@@ -1,33 +1,144 @@
-
 use bip32::{
-    ChainCode, ChildNumber, DerivationPath, Error, ExtendedKey, ExtendedKeyAttrs, Prefix,
-    PrivateKey, PublicKey, Result, XPrv, XPub, KEY_SIZE,
+    ChainCode, ChildNumber, DerivationPath, ExtendedKey, ExtendedKeyAttrs, Prefix, PublicKey,
+    XPub, KEY_SIZE,
 };
-use curv::elliptic::curves::{secp256_k1::Secp256k1, Point, Scalar};
+use curv::arithmetic::Converter;
+use curv::elliptic::curves::{secp256_k1::Secp256k1, Curve, Point, Scalar};
+use curv::BigInt;
 use hmac::{Hmac, Mac, NewMac};
-use std::{
-    convert::{TryFrom, TryInto},
-    str::FromStr,
-};
+use std::convert::{TryFrom, TryInto};
+use std::str::FromStr;
+use thiserror::Error;
+
 use crate::protocols::multi_party_ecdsa::gg_2020 as gg20;
 use gg20::state_machine::keygen::LocalKey;
 
 pub type HmacSha512 = Hmac<sha2::Sha512>;
 
+/// Error of non-hardened BIP32 derivation
+///
+/// `position` is the index of the failed component in the path, so that `m` is at
+/// position 0 and the first child number is at position 1.
+#[derive(Debug, Error, Clone, PartialEq, Eq)]
+pub enum HdError {
+    /// Path is malformed
+    #[error("invalid path {path:?}: component {position} ({component:?}) {reason}")]
+    InvalidPath {
+        path: String,
+        position: usize,
+        component: String,
+        reason: &'static str,
+    },
+    /// Path contains a hardened child number, which can't be derived from a public key
+    #[error("hardened index {component:?} at component {position} is not supported")]
+    HardenedIndex { position: usize, component: String },
+    /// Path is deeper than 255 levels
+    #[error("maximum derivation depth exceeded at component {position}")]
+    DepthOverflow { position: usize },
+    /// Derived `IL` is not less than curve order, or child key is a point at infinity
+    #[error("invalid tweak at component {position} ({component:?})")]
+    InvalidTweak { position: usize, component: String },
+    /// BIP32 is only defined over secp256k1
+    #[error("unsupported curve {curve}, only secp256k1 is supported")]
+    UnsupportedCurve { curve: &'static str },
+    /// Extended public key can't be parsed
+    #[error("invalid extended public key: {reason}")]
+    InvalidXpub { reason: &'static str },
+    /// Private key can't be imported
+    #[error("invalid private key: {reason}")]
+    InvalidPrivateKey { reason: &'static str },
+    /// Extended public key wasn't derived from our share at given path
+    #[error("extended public key doesn't match the share at {path:?}")]
+    XpubMismatch { path: String },
+    /// Extended public key is serialized for another network
+    #[error("extended public key is for {actual:?}, expected {expected:?}")]
+    NetworkMismatch { expected: Network, actual: Network },
+}
+
+/// Version bytes of BIP49 testnet extended public keys
+pub const UPUB: Prefix = Prefix::from_parts_unchecked("upub", 0x044a_5262);
+/// Version bytes of BIP84 testnet extended public keys
+pub const VPUB: Prefix = Prefix::from_parts_unchecked("vpub", 0x045f_1cf6);
+
+/// Network which defines version bytes of serialized extended keys
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Network {
+    /// `xpub` prefix
+    Mainnet,
+    /// `tpub` prefix
+    Testnet,
+}
+
+impl Network {
+    pub fn xpub_prefix(&self) -> Prefix {
+        match self {
+            Network::Mainnet => Prefix::XPUB,
+            Network::Testnet => Prefix::TPUB,
+        }
+    }
+    /// Recognizes xpub/ypub/zpub as mainnet and tpub/upub/vpub as testnet
+    pub fn from_xpub_prefix(prefix: Prefix) -> Option<Self> {
+        if [Prefix::XPUB, Prefix::YPUB, Prefix::ZPUB].contains(&prefix) {
+            Some(Network::Mainnet)
+        } else if [Prefix::TPUB, UPUB, VPUB].contains(&prefix) {
+            Some(Network::Testnet)
+        } else {
+            None
+        }
+    }
+}
+
+/// Parses a non-hardened derivation path like `m/44/0/0/0`
+pub fn parse_path(path_str: &str) -> Result<DerivationPath, HdError> {
+    let invalid = |position: usize, component: &str, reason: &'static str| HdError::InvalidPath {
+        path: path_str.to_string(),
+        position,
+        component: component.to_string(),
+        reason,
+    };
+
+    let mut components = path_str.split('/');
+    match components.next() {
+        Some("m") => (),
+        Some(component) => return Err(invalid(0, component, "must be `m`")),
+        None => return Err(invalid(0, "", "must be `m`")),
+    }
+
+    let mut path = DerivationPath::default();
+    for (position, component) in (1..).zip(components) {
+        let (index, hardened) = match component.strip_suffix(|c| c == '\'' || c == 'h' || c == 'H')
+        {
+            Some(index) => (index, true),
+            None => (component, false),
+        };
+        let index: u32 = index
+            .parse()
+            .map_err(|_| invalid(position, component, "is not a number"))?;
+        if hardened {
+            return Err(HdError::HardenedIndex {
+                position,
+                component: component.to_string(),
+            });
+        }
+        let child_number = ChildNumber::new(index, false)
+            .map_err(|_| invalid(position, component, "is out of range [0; 2^31)"))?;
+        if position > usize::from(u8::MAX) {
+            return Err(HdError::DepthOverflow { position });
+        }
+        path.push(child_number);
+    }
+    Ok(path)
+}
+
 pub fn call_hd_key<C>(
     path_str: &str,
     locakl_share: &LocalKey<C>,
     chain_code: ChainCode,
-) -> (Scalar<C>, Point<C>) 
+) -> Result<(Scalar<C>, Point<C>), HdError>
 where
-    C: curv::elliptic::curves::Curve,{
-    let (tweak_sk, child_pk) =
-        if let Ok((tweak_sk, child_pk)) = get_hd_key(path_str, locakl_share.y_sum_s.clone(), chain_code) {
-            (tweak_sk, child_pk)
-        } else {
-            todo!()
-        };
-    (tweak_sk, child_pk.clone())
+    C: Curve,
+{
+    get_hd_key(path_str, locakl_share.y_sum_s.clone(), chain_code)
 }
 
 // input: path_str (&str), public_key (Point<Secp256k1>)
@@ -36,70 +147,338 @@ pub fn get_hd_key<C>(
     path_str: &str,
     par_pk: Point<C>,
     chain_code: ChainCode,
-) -> Result<(Scalar<C>, Point<C>)> 
+) -> Result<(Scalar<C>, Point<C>), HdError>
 where
-    C: curv::elliptic::curves::Curve,{
-    let path = DerivationPath::from_str(path_str).unwrap();
-    let mut ex_pk = ExtendedKey {
-        prefix: Prefix::XPUB,
-        attrs: ExtendedKeyAttrs {
-            parent_fingerprint: [0u8; 4],
-            child_number: ChildNumber(0u32),
-            chain_code,
-            depth: 0u8,
-        },
-        key_bytes: <&[u8] as TryInto<[u8; 33]>>::try_into(par_pk.to_bytes(true).as_ref()).unwrap(),
+    C: Curve,
+{
+    let (total_tweak, xpub) = get_hd_xpub(path_str, par_pk, chain_code)?;
+    let child_pk = Point::<C>::from_bytes(&xpub.public_key().to_bytes()).map_err(|_| {
+        HdError::InvalidTweak {
+            position: usize::from(xpub.attrs().depth),
+            component: xpub.attrs().child_number.to_string(),
+        }
+    })?;
+    Ok((total_tweak, child_pk))
+}
+
+/// Derives extended public key at `path_str` from master public key and chain code
+///
+/// Returns accumulated tweak along with the child [XPub] which has correct depth, parent
+/// fingerprint and child number.
+pub fn get_hd_xpub<C>(
+    path_str: &str,
+    par_pk: Point<C>,
+    chain_code: ChainCode,
+) -> Result<(Scalar<C>, XPub), HdError>
+where
+    C: Curve,
+{
+    if C::CURVE_NAME != Secp256k1::CURVE_NAME {
+        return Err(HdError::UnsupportedCurve {
+            curve: C::CURVE_NAME,
+        });
+    }
+    let path = parse_path(path_str)?;
+    let master = master_xpub(&par_pk, chain_code)?;
+    derive_xpub_path(master, &path)
+}
+
+/// Derives a child of `xpub` at path `path_str` relative to it (e.g. `m/0/17`)
+///
+/// Doesn't require any share, so it can be used by watch-only wallets.
+pub fn derive_xpub(xpub: &XPub, path_str: &str) -> Result<XPub, HdError> {
+    let path = parse_path(path_str)?;
+    derive_xpub_path::<Secp256k1>(xpub.clone(), &path).map(|(_, xpub)| xpub)
+}
+
+/// Node of the derivation tree: extended public key along with the tweak accumulated since
+/// the root
+///
+/// Deriving children of a cached node only costs one HMAC per child instead of redoing the
+/// whole path from the root.
+#[derive(Clone, Debug)]
+pub struct HdNode {
+    pub tweak: Scalar<Secp256k1>,
+    pub xpub: XPub,
+}
+
+impl HdNode {
+    /// Root node of the shared public key
+    pub fn root(pk: &Point<Secp256k1>, chain_code: ChainCode) -> Result<Self, HdError> {
+        Ok(HdNode {
+            tweak: Scalar::zero(),
+            xpub: master_xpub(pk, chain_code)?,
+        })
+    }
+
+    /// Node of an extended public key obtained elsewhere, e.g. an account xpub given to a
+    /// watch-only wallet
+    ///
+    /// Tweaks of its descendants are relative to `xpub`, not to the root key.
+    pub fn from_xpub(xpub: XPub) -> Self {
+        HdNode {
+            tweak: Scalar::zero(),
+            xpub,
+        }
+    }
+
+    /// Derives a descendant at path `path_str` relative to this node (e.g. `m/0/17`)
+    pub fn derive_path(&self, path_str: &str) -> Result<Self, HdError> {
+        let path = parse_path(path_str)?;
+        self.derive(&path)
+    }
+
+    /// Derives non-hardened child with given `index`
+    pub fn derive_child(&self, index: u32) -> Result<Self, HdError> {
+        let child_number = ChildNumber::new(index, false).map_err(|_| HdError::InvalidPath {
+            path: format!("m/{}", index),
+            position: 1,
+            component: index.to_string(),
+            reason: "is out of range [0; 2^31)",
+        })?;
+        let mut path = DerivationPath::default();
+        path.push(child_number);
+        self.derive(&path)
+    }
+
+    /// Public key of the node
+    pub fn public_key(&self) -> Point<Secp256k1> {
+        Point::from_bytes(&self.xpub.public_key().to_bytes())
+            .expect("xpub always holds a valid point")
+    }
+
+    fn derive(&self, path: &DerivationPath) -> Result<Self, HdError> {
+        let (tweak, xpub) = derive_xpub_path::<Secp256k1>(self.xpub.clone(), path)?;
+        Ok(HdNode {
+            tweak: &self.tweak + tweak,
+            xpub,
+        })
+    }
+}
+
+/// Parses Base58Check extended public key, returns it along with its network
+pub fn parse_xpub(xpub: &str) -> Result<(XPub, Network), HdError> {
+    let key = ExtendedKey::from_str(xpub).map_err(|_| HdError::InvalidXpub {
+        reason: "malformed base58check encoding",
+    })?;
+    let network = Network::from_xpub_prefix(key.prefix).ok_or(HdError::InvalidXpub {
+        reason: "unknown version bytes",
+    })?;
+    let xpub = XPub::try_from(key).map_err(|_| HdError::InvalidXpub {
+        reason: "invalid public key",
+    })?;
+    Ok((xpub, network))
+}
+
+/// Parses Base58Check extended private key, returns its secret key and chain code
+pub fn parse_xprv(xprv: &str) -> Result<(Scalar<Secp256k1>, ChainCode), HdError> {
+    let key = ExtendedKey::from_str(xprv).map_err(|_| HdError::InvalidPrivateKey {
+        reason: "malformed base58check encoding",
+    })?;
+    if !key.prefix.is_private() || key.key_bytes[0] != 0 {
+        return Err(HdError::InvalidPrivateKey {
+            reason: "not an extended private key",
+        });
+    }
+    Ok((secret_key(&key.key_bytes[1..])?, key.attrs.chain_code))
+}
+
+/// Master secret key and chain code generated from a seed (e.g. BIP39 seed), as specified in
+/// BIP32
+pub fn master_key_from_seed(seed: &[u8]) -> Result<(Scalar<Secp256k1>, ChainCode), HdError> {
+    let mut hmac =
+        HmacSha512::new_from_slice(b"Bitcoin seed").expect("HMAC accepts keys of any size");
+    hmac.update(seed);
+    let result = hmac.finalize().into_bytes();
+    let (secret, chain_code) = result.split_at(KEY_SIZE);
+    Ok((
+        secret_key(secret)?,
+        chain_code.try_into().expect("chain code is 32 bytes"),
+    ))
+}
+
+fn secret_key(bytes: &[u8]) -> Result<Scalar<Secp256k1>, HdError> {
+    let n = BigInt::from_bytes(bytes);
+    if n == BigInt::from(0) || &n >= Scalar::<Secp256k1>::group_order() {
+        return Err(HdError::InvalidPrivateKey {
+            reason: "secret key is not in range [1; q-1]",
+        });
+    }
+    Ok(Scalar::from_bigint(&n))
+}
+
+fn master_xpub<C: Curve>(par_pk: &Point<C>, chain_code: ChainCode) -> Result<XPub, HdError> {
+    // the only way a secp256k1 point can't be converted is the point at infinity
+    let infinity = || HdError::InvalidTweak {
+        position: 0,
+        component: "m".to_string(),
     };
-    let mut pk = XPub::try_from(ex_pk).unwrap();
-    let scalar_one = XPrv::try_from(ExtendedKey {
-        prefix: Prefix::XPRV,
+    let key_bytes: [u8; 33] = par_pk
+        .to_bytes(true)
+        .as_ref()
+        .try_into()
+        .map_err(|_| infinity())?;
+    XPub::try_from(ExtendedKey {
+        prefix: Prefix::XPUB,
         attrs: ExtendedKeyAttrs {
             parent_fingerprint: [0u8; 4],
             child_number: ChildNumber(0u32),
             chain_code,
             depth: 0u8,
         },
-        key_bytes: [
-            0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
-            0, 0, 0, 1,
-        ],
+        key_bytes,
     })
-    .unwrap();
-    let mut total_tweak = scalar_one.private_key().clone();
-    for ccnum in path.as_ref() {
-        let depth = pk.attrs().depth.checked_add(1).ok_or(Error::Depth)?;
-
-        let mut hmac =
-            HmacSha512::new_from_slice(&pk.attrs().chain_code).map_err(|_| Error::Crypto)?;
-        if ccnum.is_hardened() {
-            // Cannot derive child public keys for hardened `ChildNumber`s
-            return Err(Error::ChildNumber);
-        } else {
-            hmac.update(&pk.public_key().to_bytes());
-        }
+    .map_err(|_| infinity())
+}
+
+fn derive_xpub_path<C: Curve>(
+    mut pk: XPub,
+    path: &DerivationPath,
+) -> Result<(Scalar<C>, XPub), HdError> {
+    let invalid_tweak = |position: usize, component: String| HdError::InvalidTweak {
+        position,
+        component,
+    };
+
+    let mut total_tweak = Scalar::<C>::zero();
+    for (position, ccnum) in (1..).zip(path.as_ref()) {
+        let component = ccnum.to_string();
+        let depth = pk
+            .attrs()
+            .depth
+            .checked_add(1)
+            .ok_or(HdError::DepthOverflow { position })?;
+
+        let mut hmac = HmacSha512::new_from_slice(&pk.attrs().chain_code)
+            .expect("HMAC accepts keys of any size");
+        hmac.update(&pk.public_key().to_bytes());
         hmac.update(&ccnum.to_bytes());
 
         let result = hmac.finalize().into_bytes();
         let (tweak, chain_code) = result.split_at(KEY_SIZE);
-        let public_key = pk.public_key().derive_child(tweak.try_into()?)?;
-        let binding = total_tweak.derive_child(tweak.try_into()?)?;
-        total_tweak = binding;
+        let tweak: [u8; KEY_SIZE] = tweak.try_into().expect("tweak is KEY_SIZE bytes long");
 
-        ex_pk = ExtendedKey {
+        let tweak_bn = BigInt::from_bytes(&tweak);
+        if &tweak_bn >= Scalar::<C>::group_order() {
+            return Err(invalid_tweak(position, component));
+        }
+        let public_key = pk
+            .public_key()
+            .derive_child(tweak)
+            .map_err(|_| invalid_tweak(position, component.clone()))?;
+        total_tweak = total_tweak + Scalar::<C>::from_bigint(&tweak_bn);
+
+        let key_bytes: [u8; 33] = public_key.to_bytes();
+        pk = XPub::try_from(ExtendedKey {
             prefix: Prefix::XPUB,
             attrs: ExtendedKeyAttrs {
                 parent_fingerprint: pk.public_key().fingerprint(),
                 child_number: *ccnum,
-                chain_code: chain_code.try_into()?,
+                chain_code: chain_code.try_into().expect("chain code is KEY_SIZE bytes long"),
                 depth,
             },
-            key_bytes: <&[u8] as TryInto<[u8; 33]>>::try_into(&public_key.to_bytes()).unwrap(),
-        };
-        pk = XPub::try_from(ex_pk).unwrap();
+            key_bytes,
+        })
+        .map_err(|_| invalid_tweak(position, component))?;
+    }
+
+    Ok((total_tweak, pk))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // BIP32 test vector 2
+    const MASTER_XPUB: &str = "xpub661MyMwAqRbcFW31YEwpkMuc5THy2PSt5bDMsktWQcFF8syAmRUapSCGu8ED9W6oDMSgv6Zz8idoc4a6mr8BDzTJY47LJhkJ8UB7WEGuduB";
+    const CHILD_0_XPUB: &str = "xpub69H7F5d8KSRgmmdJg2KhpAK8SR3DjMwAdkxj3ZuxV27CprR9LgpeyGmXUbC6wb7ERfvrnKZjXoUmmDznezpbZb7ap6r1D3tgFxHmwMkQTPH";
+
+    #[test]
+    fn derive_xpub_matches_bip32_test_vector() {
+        let (master, network) = parse_xpub(MASTER_XPUB).unwrap();
+        assert_eq!(network, Network::Mainnet);
+        let child = derive_xpub(&master, "m/0").unwrap();
+        assert_eq!(child.to_string(Prefix::XPUB), CHILD_0_XPUB);
     }
 
-    let tweak_sk =
-        Scalar::<C>::from_bytes(&total_tweak.to_bytes()).unwrap() - Scalar::from(1u32);
-    let child_pk = Point::<C>::from_bytes(&pk.public_key().to_bytes()).unwrap();
-    Ok((tweak_sk, child_pk))
+    #[test]
+    fn master_key_from_seed_matches_bip32_test_vector() {
+        let seed = hex::decode(
+            "fffcf9f6f3f0edeae7e4e1dedbd8d5d2cfccc9c6c3c0bdbab7b4b1aeaba8a5a29f9c999693908d8a87\
+             84817e7b7875726f6c696663605d5a5754514e4b484542",
+        )
+        .unwrap();
+        let (secret, chain_code) = master_key_from_seed(&seed).unwrap();
+        let (master, _) = parse_xpub(MASTER_XPUB).unwrap();
+        assert_eq!(
+            (Point::generator() * secret).to_bytes(true).to_vec(),
+            master.public_key().to_bytes().to_vec()
+        );
+        assert_eq!(chain_code, master.attrs().chain_code);
+
+        assert!(matches!(
+            parse_xprv(MASTER_XPUB),
+            Err(HdError::InvalidPrivateKey { .. })
+        ));
+    }
+
+    #[test]
+    fn xpub_is_consistent_with_tweak() {
+        let master_sk = Scalar::<Secp256k1>::random();
+        let master_pk = Point::generator() * &master_sk;
+        let chain_code = [3u8; 32];
+
+        let (tweak, xpub) = get_hd_xpub("m/44/60/0/0/17", master_pk.clone(), chain_code).unwrap();
+        let child_pk = Point::<Secp256k1>::from_bytes(&xpub.public_key().to_bytes()).unwrap();
+        assert_eq!(child_pk, master_pk.clone() + Point::generator() * tweak);
+        assert_eq!(xpub.attrs().depth, 5);
+        assert_eq!(xpub.attrs().child_number, ChildNumber(17));
+
+        let (_, account) = get_hd_xpub("m/44/60/0", master_pk, chain_code).unwrap();
+        let watch_only = derive_xpub(&account, "m/0/17").unwrap();
+        assert_eq!(
+            watch_only.to_string(Prefix::TPUB),
+            xpub.to_string(Prefix::TPUB)
+        );
+    }
+
+    #[test]
+    fn cached_nodes_match_derivation_from_root() {
+        let master_pk = Point::generator() * Scalar::<Secp256k1>::random();
+        let chain_code = [5u8; 32];
+
+        let account = HdNode::root(&master_pk, chain_code)
+            .unwrap()
+            .derive_path("m/84/0/0")
+            .unwrap();
+        let address = account.derive_child(1).unwrap().derive_child(9).unwrap();
+
+        let (tweak, child_pk) = get_hd_key("m/84/0/0/1/9", master_pk, chain_code).unwrap();
+        assert_eq!(address.tweak, tweak);
+        assert_eq!(address.public_key(), child_pk);
+    }
+
+    #[test]
+    fn parse_path_reports_failed_component() {
+        assert_eq!(parse_path("m/44/60/0/0").unwrap().as_ref().len(), 4);
+        assert_eq!(
+            parse_path("m/44/60'/0"),
+            Err(HdError::HardenedIndex {
+                position: 2,
+                component: "60'".to_string()
+            })
+        );
+        assert!(matches!(
+            parse_path("m/44/x/0"),
+            Err(HdError::InvalidPath { position: 2, .. })
+        ));
+        assert!(matches!(
+            parse_path("44/0"),
+            Err(HdError::InvalidPath { position: 0, .. })
+        ));
+        assert!(matches!(
+            parse_path("m/2147483648"),
+            Err(HdError::InvalidPath { position: 1, .. })
+        ));
+    }
 }
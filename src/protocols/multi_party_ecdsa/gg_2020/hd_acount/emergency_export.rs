@@ -0,0 +1,213 @@
+//! Emergency reconstruction of the full private key from `t+1` shares
+//!
+//! Only available with `emergency-export` feature. Reconstructed key is no longer protected by
+//! threshold setup, so it's meant for supervised disaster-recovery drills on an offline
+//! machine. Nothing in this module touches the network.
+
+use bip32::{ChainCode, ChildNumber, ExtendedKey, ExtendedKeyAttrs, Prefix};
+use bip39::{Language, Mnemonic};
+use curv::elliptic::curves::{secp256_k1::Secp256k1, Point, Scalar};
+use std::convert::TryInto;
+use thiserror::Error;
+
+use crate::protocols::multi_party_ecdsa::gg_2018::VerifiableSS;
+use crate::protocols::multi_party_ecdsa::gg_2020::hd_acount::account_manage::raw_share;
+use crate::protocols::multi_party_ecdsa::gg_2020::hd_acount::address;
+use crate::protocols::multi_party_ecdsa::gg_2020::hd_acount::btc_hd::Network;
+use crate::protocols::multi_party_ecdsa::gg_2020::state_machine::keygen::InvalidLocalKey;
+use crate::utilities::secret::Secret;
+
+#[derive(Debug, Error)]
+pub enum ExportError {
+    #[error("at least {required} shares are required, {given} given")]
+    NotEnoughShares { required: usize, given: usize },
+    #[error("share of party {party} is invalid: {source}")]
+    InvalidShare {
+        party: u16,
+        #[source]
+        source: InvalidLocalKey,
+    },
+    #[error("share of party {party} belongs to a different key")]
+    ShareMismatch { party: u16 },
+    #[error("share of party {party} is given twice")]
+    DuplicateShare { party: u16 },
+    #[error("reconstructed private key doesn't match the public key")]
+    PublicKeyMismatch,
+}
+
+/// Full private key reconstructed from shares along with its chain code
+pub struct ReconstructedKey {
+    secret: Secret<Scalar<Secp256k1>>,
+    chain_code: ChainCode,
+}
+
+impl ReconstructedKey {
+    /// Reconstructs the private key from at least `t+1` shares of the same key
+    ///
+    /// Every share is validated against its `vss_scheme` and `y_sum_s`, and all the shares must
+    /// agree on the public key, VSS scheme and chain code.
+    pub fn reconstruct(shares: &[raw_share<Secp256k1>]) -> Result<Self, ExportError> {
+        let first = match shares.first() {
+            Some(share) => &share.local_key_hd,
+            None => {
+                return Err(ExportError::NotEnoughShares {
+                    required: 2,
+                    given: 0,
+                })
+            }
+        };
+        let required = usize::from(first.t) + 1;
+        if shares.len() < required {
+            return Err(ExportError::NotEnoughShares {
+                required,
+                given: shares.len(),
+            });
+        }
+
+        let mut indices = Vec::with_capacity(shares.len());
+        for share in shares {
+            let key = &share.local_key_hd;
+            key.validate().map_err(|source| ExportError::InvalidShare {
+                party: key.i,
+                source,
+            })?;
+            if key.y_sum_s != first.y_sum_s
+                || key.t != first.t
+                || key.n != first.n
+                || key.vss_scheme.commitments != first.vss_scheme.commitments
+                || share.chain_code != shares[0].chain_code
+            {
+                return Err(ExportError::ShareMismatch { party: key.i });
+            }
+            if indices.contains(&(key.i - 1)) {
+                return Err(ExportError::DuplicateShare { party: key.i });
+            }
+            indices.push(key.i - 1);
+        }
+
+        let secret = shares
+            .iter()
+            .map(|share| {
+                let key = &share.local_key_hd;
+                let li = VerifiableSS::<Secp256k1>::map_share_to_new_params(
+                    &key.vss_scheme.parameters,
+                    key.i - 1,
+                    &indices,
+                );
+                li * key.keys_linear.x_i.expose()
+            })
+            .fold(Scalar::zero(), |acc, x| acc + x);
+        if Point::generator() * &secret != first.y_sum_s {
+            return Err(ExportError::PublicKeyMismatch);
+        }
+
+        Ok(ReconstructedKey {
+            secret: Secret::new(secret),
+            chain_code: shares[0].chain_code,
+        })
+    }
+
+    pub fn public_key(&self) -> Point<Secp256k1> {
+        Point::generator() * self.secret.expose()
+    }
+
+    /// Hex-encoded 32 bytes private key
+    pub fn to_hex(&self) -> String {
+        hex::encode(self.secret_bytes())
+    }
+
+    /// Wallet import format of the private key with compressed public key
+    pub fn to_wif(&self, network: Network) -> String {
+        let version = match network {
+            Network::Mainnet => 0x80,
+            Network::Testnet => 0xef,
+        };
+        let mut payload = self.secret_bytes().to_vec();
+        payload.push(0x01);
+        address::base58check(version, &payload)
+    }
+
+    /// BIP32 master extended private key with the stored chain code
+    pub fn to_xprv(&self, network: Network) -> String {
+        let prefix = match network {
+            Network::Mainnet => Prefix::XPRV,
+            Network::Testnet => Prefix::TPRV,
+        };
+        let mut key_bytes = [0u8; 33];
+        key_bytes[1..].copy_from_slice(&self.secret_bytes());
+        ExtendedKey {
+            prefix,
+            attrs: ExtendedKeyAttrs {
+                depth: 0,
+                parent_fingerprint: [0u8; 4],
+                child_number: ChildNumber(0),
+                chain_code: self.chain_code,
+            },
+            key_bytes,
+        }
+        .to_string()
+    }
+
+    /// 24 words BIP39 mnemonic which uses the private key as entropy
+    ///
+    /// Note that it's not a wallet seed phrase: the private key is restored with
+    /// [Mnemonic::entropy], not by deriving a BIP32 master key from the mnemonic seed.
+    pub fn to_mnemonic(&self) -> String {
+        Mnemonic::from_entropy(&self.secret_bytes(), Language::English)
+            .expect("32 bytes is a valid entropy length")
+            .into_phrase()
+    }
+
+    fn secret_bytes(&self) -> [u8; 32] {
+        let bytes = self.secret.expose().to_bytes();
+        bytes[..].try_into().expect("scalar is 32 bytes")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::protocols::multi_party_ecdsa::gg_2020::state_machine::keygen::test::simulate_keygen;
+
+    #[test]
+    fn exports_match_known_encodings() {
+        let key = ReconstructedKey {
+            secret: Secret::new(Scalar::from_bigint(&curv::BigInt::from(1))),
+            chain_code: [0u8; 32],
+        };
+        assert_eq!(
+            key.to_hex(),
+            "0000000000000000000000000000000000000000000000000000000000000001"
+        );
+        assert_eq!(
+            key.to_wif(Network::Mainnet),
+            "KwDiBf89QgGbjEhKnhXJuH7LrciVrZi3qYjgd9M7rFU73sVHnoWn"
+        );
+        let (secret, chain_code) =
+            crate::protocols::multi_party_ecdsa::gg_2020::hd_acount::btc_hd::parse_xprv(
+                &key.to_xprv(Network::Mainnet),
+            )
+            .unwrap();
+        assert_eq!(&secret, key.secret.expose());
+        assert_eq!(chain_code, key.chain_code);
+        let mnemonic = Mnemonic::from_phrase(&key.to_mnemonic(), Language::English).unwrap();
+        assert_eq!(mnemonic.entropy(), &key.secret_bytes()[..]);
+    }
+
+    #[test]
+    fn reconstructs_key_from_threshold_shares() {
+        let shares = simulate_keygen(1, 3);
+        let key = ReconstructedKey::reconstruct(&[shares[0].clone(), shares[2].clone()]).unwrap();
+        assert_eq!(key.public_key(), shares[0].local_key_hd.y_sum_s);
+        assert_eq!(key.chain_code, shares[0].chain_code);
+
+        assert!(matches!(
+            ReconstructedKey::reconstruct(&shares[..1]),
+            Err(ExportError::NotEnoughShares { .. })
+        ));
+        assert!(matches!(
+            ReconstructedKey::reconstruct(&[shares[1].clone(), shares[1].clone()]),
+            Err(ExportError::DuplicateShare { party: 2 })
+        ));
+    }
+}
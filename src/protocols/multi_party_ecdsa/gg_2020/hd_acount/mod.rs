@@ -1,2 +1,6 @@
 pub mod btc_hd;
-pub mod account_manage;
\ No newline at end of file
+pub mod account_manage;
+pub mod address;
+pub mod sealed_share;
+#[cfg(feature = "emergency-export")]
+pub mod emergency_export;
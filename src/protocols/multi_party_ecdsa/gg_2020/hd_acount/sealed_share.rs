@@ -0,0 +1,420 @@
+//! Password-protected container for long-term storage of [raw_share]
+//!
+//! Sealed share is a JSON document:
+//!
+//! ```text
+//! {
+//!   "format": "gg20-hd-sealed-share",
+//!   "version": 1,
+//!   "header": {
+//!     "key_id": "<hex, first 8 bytes of SHA-256 of compressed public key>",
+//!     "public_key": "<hex, compressed public key>",
+//!     "party_index": 1,
+//!     "threshold": 1,
+//!     "parties": 3,
+//!     "chain_code_fingerprint": "<hex, first 4 bytes of SHA-256 of chain code>"
+//!   },
+//!   "kdf": { "algorithm": "scrypt", "log_n": 17, "r": 8, "p": 1, "salt": "<hex, 32 bytes>" },
+//!   "cipher": { "algorithm": "aes-256-gcm", "nonce": "<hex, 12 bytes>" },
+//!   "ciphertext": "<hex>"
+//! }
+//! ```
+//!
+//! AES-256-GCM key is derived from the password with scrypt. Plaintext is JSON-serialized
+//! [raw_share], and JSON-serialized `header`, `kdf` and `cipher` objects are authenticated as
+//! associated data, so the header can be read without the password but can't be altered.
+//! Version is bumped whenever any of the above changes, older versions remain readable.
+
+use aes_gcm::aead::{Aead, NewAead, Payload};
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use curv::elliptic::curves::secp256_k1::Secp256k1;
+use rand_core::{OsRng, RngCore};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+use zeroize::Zeroize;
+
+use crate::protocols::multi_party_ecdsa::gg_2020::hd_acount::account_manage::raw_share;
+use crate::protocols::multi_party_ecdsa::gg_2020::state_machine::keygen::InvalidLocalKey;
+
+/// Value of `format` field of sealed share
+pub const SEALED_SHARE_FORMAT: &str = "gg20-hd-sealed-share";
+/// Latest version of sealed share format
+pub const SEALED_SHARE_VERSION: u32 = 1;
+
+const KDF_SCRYPT: &str = "scrypt";
+const CIPHER_AES_256_GCM: &str = "aes-256-gcm";
+const SALT_LEN: usize = 32;
+const NONCE_LEN: usize = 12;
+/// Sealed shares requiring more than 4 GiB of memory (`128 * r * 2^log_n` bytes) to open are
+/// rejected
+const MAX_LOG_N: u8 = 22;
+const MAX_MEMORY: u64 = 1 << 32;
+/// Bounds time of opening along with memory, as every lane repeats the whole computation
+const MAX_P: u32 = 16;
+
+/// Public information about sealed share, readable without the password
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SealedShareHeader {
+    pub key_id: String,
+    pub public_key: String,
+    pub party_index: u16,
+    pub threshold: u16,
+    pub parties: u16,
+    pub chain_code_fingerprint: String,
+}
+
+impl SealedShareHeader {
+    fn of(share: &raw_share<Secp256k1>) -> Self {
+        let local_key = &share.local_key_hd;
+        let public_key = local_key.y_sum_s.to_bytes(true);
+        let public_key: &[u8] = public_key.as_ref();
+        SealedShareHeader {
+            key_id: hex::encode(&Sha256::digest(public_key)[..8]),
+            public_key: hex::encode(public_key),
+            party_index: local_key.i,
+            threshold: local_key.t,
+            parties: local_key.n,
+            chain_code_fingerprint: hex::encode(&Sha256::digest(&share.chain_code)[..4]),
+        }
+    }
+}
+
+/// scrypt parameters of sealed share
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KdfParams {
+    pub algorithm: String,
+    pub log_n: u8,
+    pub r: u32,
+    pub p: u32,
+    pub salt: String,
+}
+
+/// Cipher parameters of sealed share
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CipherParams {
+    pub algorithm: String,
+    pub nonce: String,
+}
+
+/// scrypt cost, see [raw_share::seal_with_cost]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct KdfCost {
+    pub log_n: u8,
+    pub r: u32,
+    pub p: u32,
+}
+
+impl KdfCost {
+    /// Checks that memory and time needed to derive the key are within limits
+    fn check(&self) -> Result<(), SealError> {
+        let too_high = self.log_n > MAX_LOG_N
+            || (128 * u64::from(self.r)) << self.log_n > MAX_MEMORY
+            || self.p > MAX_P;
+        if too_high {
+            return Err(SealError::KdfCostTooHigh {
+                log_n: self.log_n,
+                r: self.r,
+                p: self.p,
+            });
+        }
+        Ok(())
+    }
+}
+
+impl Default for KdfCost {
+    /// `N = 2^17, r = 8, p = 1`, i.e. 128 MiB of memory
+    fn default() -> Self {
+        KdfCost {
+            log_n: 17,
+            r: 8,
+            p: 1,
+        }
+    }
+}
+
+/// Encrypted [raw_share] along with its public header
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SealedShare {
+    pub format: String,
+    pub version: u32,
+    pub header: SealedShareHeader,
+    pub kdf: KdfParams,
+    pub cipher: CipherParams,
+    pub ciphertext: String,
+}
+
+impl SealedShare {
+    /// Parses sealed share, doesn't require the password
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, SealError> {
+        let sealed: SealedShare = serde_json::from_slice(bytes).map_err(SealError::Serialize)?;
+        if sealed.format != SEALED_SHARE_FORMAT {
+            return Err(SealError::UnknownFormat);
+        }
+        if sealed.version == 0 || sealed.version > SEALED_SHARE_VERSION {
+            return Err(SealError::UnsupportedVersion {
+                version: sealed.version,
+            });
+        }
+        Ok(sealed)
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        serde_json::to_vec_pretty(self).expect("serialization of sealed share never fails")
+    }
+
+    /// Decrypts the share, fails if password is wrong or the container was altered
+    pub fn open(&self, password: &str) -> Result<raw_share<Secp256k1>, SealError> {
+        if self.kdf.algorithm != KDF_SCRYPT || self.cipher.algorithm != CIPHER_AES_256_GCM {
+            return Err(SealError::UnsupportedAlgorithm);
+        }
+        let cost = KdfCost {
+            log_n: self.kdf.log_n,
+            r: self.kdf.r,
+            p: self.kdf.p,
+        };
+        cost.check()?;
+        let salt = decode_hex(&self.kdf.salt, Some(SALT_LEN))?;
+        let nonce = decode_hex(&self.cipher.nonce, Some(NONCE_LEN))?;
+        let ciphertext = decode_hex(&self.ciphertext, None)?;
+
+        let mut key = derive_key(password, &salt, cost)?;
+        let aad = associated_data(&self.header, &self.kdf, &self.cipher);
+        let plaintext = Aes256Gcm::new(Key::from_slice(&key)).decrypt(
+            Nonce::from_slice(&nonce),
+            Payload {
+                msg: &ciphertext,
+                aad: &aad,
+            },
+        );
+        key.zeroize();
+        let mut plaintext = plaintext.map_err(|_| SealError::Decrypt)?;
+
+        let share = serde_json::from_slice::<raw_share<Secp256k1>>(&plaintext);
+        plaintext.zeroize();
+        let share = share.map_err(SealError::Serialize)?;
+        if SealedShareHeader::of(&share) != self.header {
+            return Err(SealError::HeaderMismatch);
+        }
+        share
+            .local_key_hd
+            .validate()
+            .map_err(SealError::InvalidShare)?;
+        Ok(share)
+    }
+}
+
+impl raw_share<Secp256k1> {
+    /// Encrypts the share with `password`, using default scrypt cost
+    pub fn seal(&self, password: &str) -> Result<SealedShare, SealError> {
+        self.seal_with_cost(password, KdfCost::default())
+    }
+
+    /// Encrypts the share with `password`, using given scrypt cost
+    pub fn seal_with_cost(&self, password: &str, cost: KdfCost) -> Result<SealedShare, SealError> {
+        cost.check()?;
+        let mut salt = [0u8; SALT_LEN];
+        OsRng.fill_bytes(&mut salt);
+        let mut nonce = [0u8; NONCE_LEN];
+        OsRng.fill_bytes(&mut nonce);
+
+        let header = SealedShareHeader::of(self);
+        let kdf = KdfParams {
+            algorithm: KDF_SCRYPT.to_string(),
+            log_n: cost.log_n,
+            r: cost.r,
+            p: cost.p,
+            salt: hex::encode(salt),
+        };
+        let cipher = CipherParams {
+            algorithm: CIPHER_AES_256_GCM.to_string(),
+            nonce: hex::encode(nonce),
+        };
+
+        let mut key = derive_key(password, &salt, cost)?;
+        let mut plaintext = serde_json::to_vec(self).map_err(SealError::Serialize)?;
+        let aad = associated_data(&header, &kdf, &cipher);
+        let ciphertext = Aes256Gcm::new(Key::from_slice(&key)).encrypt(
+            Nonce::from_slice(&nonce),
+            Payload {
+                msg: &plaintext,
+                aad: &aad,
+            },
+        );
+        key.zeroize();
+        plaintext.zeroize();
+
+        Ok(SealedShare {
+            format: SEALED_SHARE_FORMAT.to_string(),
+            version: SEALED_SHARE_VERSION,
+            header,
+            kdf,
+            cipher,
+            ciphertext: hex::encode(ciphertext.map_err(|_| SealError::Encrypt)?),
+        })
+    }
+
+    /// Decrypts sealed share serialized by [SealedShare::to_bytes]
+    pub fn open(sealed: &[u8], password: &str) -> Result<Self, SealError> {
+        SealedShare::from_bytes(sealed)?.open(password)
+    }
+
+    /// Reads a share which is either sealed or stored as plain JSON
+    ///
+    /// Returns [SealError::PasswordRequired] if the share is sealed and `password` is `None`.
+    pub fn from_bytes(bytes: &[u8], password: Option<&str>) -> Result<Self, SealError> {
+        match (SealedShare::from_bytes(bytes), password) {
+            (Ok(sealed), Some(password)) => sealed.open(password),
+            (Ok(_), None) => Err(SealError::PasswordRequired),
+            (Err(SealError::Serialize(_)), _) | (Err(SealError::UnknownFormat), _) => {
+                serde_json::from_slice(bytes).map_err(SealError::Serialize)
+            }
+            (Err(err), _) => Err(err),
+        }
+    }
+}
+
+fn derive_key(password: &str, salt: &[u8], cost: KdfCost) -> Result<[u8; 32], SealError> {
+    let params =
+        scrypt::Params::new(cost.log_n, cost.r, cost.p).map_err(|_| SealError::InvalidKdfParams)?;
+    let mut key = [0u8; 32];
+    scrypt::scrypt(password.as_bytes(), salt, &params, &mut key)
+        .map_err(|_| SealError::InvalidKdfParams)?;
+    Ok(key)
+}
+
+fn associated_data(header: &SealedShareHeader, kdf: &KdfParams, cipher: &CipherParams) -> Vec<u8> {
+    serde_json::to_vec(&(header, kdf, cipher)).expect("serialization of header never fails")
+}
+
+fn decode_hex(value: &str, expected_len: Option<usize>) -> Result<Vec<u8>, SealError> {
+    let bytes = hex::decode(value).map_err(|_| SealError::InvalidEncoding)?;
+    match expected_len {
+        Some(len) if bytes.len() != len => Err(SealError::InvalidEncoding),
+        _ => Ok(bytes),
+    }
+}
+
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum SealError {
+    #[error("not a sealed share")]
+    UnknownFormat,
+    #[error("sealed share version {version} is not supported")]
+    UnsupportedVersion { version: u32 },
+    #[error("unsupported kdf or cipher")]
+    UnsupportedAlgorithm,
+    #[error("invalid kdf parameters")]
+    InvalidKdfParams,
+    /// Opening would take more than 4 GiB of memory or more than 16 parallel lanes
+    #[error("kdf cost N=2^{log_n}, r={r}, p={p} is too high")]
+    KdfCostTooHigh { log_n: u8, r: u32, p: u32 },
+    #[error("invalid hex encoding or length of a field")]
+    InvalidEncoding,
+    #[error("share is sealed, password is required")]
+    PasswordRequired,
+    #[error("cannot encrypt share")]
+    Encrypt,
+    /// Password is wrong or the container was altered
+    #[error("cannot decrypt share: wrong password or corrupted container")]
+    Decrypt,
+    #[error("header doesn't match sealed share")]
+    HeaderMismatch,
+    /// Decrypted share is inconsistent
+    #[error("invalid share: {0}")]
+    InvalidShare(#[source] InvalidLocalKey),
+    #[error("serialization error: {0}")]
+    Serialize(#[source] serde_json::Error),
+}
+
+#[cfg(test)]
+mod test {
+    use curv::elliptic::curves::Scalar;
+
+    use super::*;
+    use crate::utilities::secret::Secret;
+    use crate::protocols::multi_party_ecdsa::gg_2020::state_machine::keygen::test::simulate_keygen;
+
+    const TEST_COST: KdfCost = KdfCost {
+        log_n: 10,
+        r: 8,
+        p: 1,
+    };
+
+    #[test]
+    fn sealed_share_opens_with_the_right_password_only() {
+        let share = simulate_keygen(1, 2).remove(0);
+        let sealed = share.seal_with_cost("correct horse", TEST_COST).unwrap();
+        assert_eq!(sealed.header.party_index, 1);
+        assert_eq!((sealed.header.threshold, sealed.header.parties), (1, 2));
+
+        let bytes = sealed.to_bytes();
+        let opened = raw_share::open(&bytes, "correct horse").unwrap();
+        assert_eq!(opened.local_key_hd.y_sum_s, share.local_key_hd.y_sum_s);
+        assert_eq!(
+            opened.local_key_hd.keys_linear.x_i,
+            share.local_key_hd.keys_linear.x_i
+        );
+        assert_eq!(opened.chain_code, share.chain_code);
+
+        assert!(matches!(
+            raw_share::open(&bytes, "battery staple"),
+            Err(SealError::Decrypt)
+        ));
+        assert!(matches!(
+            raw_share::from_bytes(&bytes, None),
+            Err(SealError::PasswordRequired)
+        ));
+        let plain = serde_json::to_vec(&share).unwrap();
+        assert_eq!(
+            raw_share::from_bytes(&plain, None).unwrap().chain_code,
+            share.chain_code
+        );
+    }
+
+    #[test]
+    fn altered_header_is_rejected() {
+        let share = simulate_keygen(1, 2).remove(1);
+        let mut sealed = share.seal_with_cost("password", TEST_COST).unwrap();
+        sealed.header.party_index = 1;
+        assert!(matches!(sealed.open("password"), Err(SealError::Decrypt)));
+    }
+
+    #[test]
+    fn excessive_kdf_cost_is_rejected() {
+        let share = simulate_keygen(1, 2).remove(0);
+        let sealed = share.seal_with_cost("password", TEST_COST).unwrap();
+
+        let too_costly = [
+            KdfCost { log_n: 23, ..TEST_COST },
+            // 128 * 2^16 * 2^17 bytes = 1 TiB of memory, even though log_n is within limit
+            KdfCost { r: 1 << 16, ..KdfCost::default() },
+            KdfCost { p: 1 << 20, ..TEST_COST },
+        ];
+        for &cost in &too_costly {
+            let mut altered = sealed.clone();
+            altered.kdf.log_n = cost.log_n;
+            altered.kdf.r = cost.r;
+            altered.kdf.p = cost.p;
+            assert!(matches!(
+                altered.open("password"),
+                Err(SealError::KdfCostTooHigh { .. })
+            ));
+            assert!(matches!(
+                share.seal_with_cost("password", cost),
+                Err(SealError::KdfCostTooHigh { .. })
+            ));
+        }
+    }
+
+    #[test]
+    fn inconsistent_share_is_rejected() {
+        let mut share = simulate_keygen(1, 2).remove(0);
+        share.local_key_hd.keys_linear.x_i = Secret::new(Scalar::random());
+        let sealed = share.seal_with_cost("password", TEST_COST).unwrap();
+        assert!(matches!(
+            sealed.open("password"),
+            Err(SealError::InvalidShare(_))
+        ));
+    }
+}
@@ -16,13 +16,27 @@
 
 pub mod blame;
 pub mod party_i;
+pub mod prime_pool;
 pub mod state_machine;
 pub mod hd_acount;
 
+/// Labels of the protocols in proof contexts, see [ProofContext](
+/// crate::utilities::transcript::ProofContext)
+pub const KEYGEN_LABEL: &str = "gg20-keygen";
+pub const SIGNING_LABEL: &str = "gg20-signing";
+pub const REFRESH_LABEL: &str = "gg20-refresh";
+pub const RESHARE_LABEL: &str = "gg20-reshare";
+pub const RECOVERY_LABEL: &str = "gg20-recovery";
+
 
 #[derive(Clone, Debug)]
 pub struct ErrorType {
     pub error_type: String,
+    /// Parties that caused the error, as 0-based positions among protocol participants
+    ///
+    /// Position `j` stands for the party with index `j + 1` in protocol messages, for signing
+    /// it is the position in `s_l`, not the index from keygen. Every protocol of this module
+    /// reports bad actors this way.
     pub bad_actors: Vec<usize>,
     pub data: Vec<u8>,
 }
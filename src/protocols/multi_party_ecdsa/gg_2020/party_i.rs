@@ -40,19 +40,33 @@ use paillier::{
 };
 
 use serde::{Deserialize, Serialize};
+use zeroize::Zeroize;
 use zk_paillier::zkproofs::NiCorrectKeyProof;
-use zk_paillier::zkproofs::{CompositeDLogProof, DLogStatement};
+use zk_paillier::zkproofs::DLogStatement;
 use crate::no_small_proof::no_small_proof::{NoSmallFactorSetUp,NoSmallFactorWitness,NoSmallFactorStatement,NoSmallFactorProof};
 
-use crate::protocols::multi_party_ecdsa::gg_2020::ErrorType;
+use crate::protocols::multi_party_ecdsa::gg_2020::{ErrorType, KEYGEN_LABEL};
+use crate::utilities::secret::Secret;
+use crate::utilities::transcript::{ProofContext, SessionId};
+use crate::utilities::zk_composite_dlog::CompositeDLogProof;
+use crate::utilities::zk_homo_elgamal::HomoElGamalProof;
+use crate::utilities::zk_paillier_blum_modulus::{
+    PaillierBlumModulusProof, PaillierBlumModulusStatement, PaillierBlumModulusWitness,
+};
 use crate::utilities::zk_pdl_with_slack::{PDLwSlackProof, PDLwSlackStatement, PDLwSlackWitness};
+use crate::utilities::zk_ring_pedersen::{
+    RingPedersenProof, RingPedersenStatement, RingPedersenWitness,
+};
 use curv::cryptographic_primitives::proofs::sigma_valid_pedersen::PedersenProof;
 
 use std::convert::TryInto;
 
 const SECURITY: usize = 256;
-const PAILLIER_MIN_BIT_LENGTH: usize = 2047;
-const PAILLIER_MAX_BIT_LENGTH: usize = 2048;
+/// Minimal accepted bit length of Paillier modulus and `N_tilde`
+pub const PAILLIER_MIN_BIT_LENGTH: usize = 2047;
+/// Maximal accepted bit length of Paillier modulus and `N_tilde`, parties may generate
+/// moduli larger than the default 2048 bits
+pub const PAILLIER_MAX_BIT_LENGTH: usize = 4096;
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Parameters {
@@ -62,35 +76,209 @@ pub struct Parameters {
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct Keys<E: Curve = Secp256k1> {
-    pub u_i: Scalar<E>,
+    pub u_i: Secret<Scalar<E>>,
     pub y_i: Point<E>,
-    pub dk: DecryptionKey,
+    pub dk: Secret<DecryptionKey>,
     pub ek: EncryptionKey,
     pub party_index: usize,
     pub N_tilde: BigInt,
     pub h1: BigInt,
     pub h2: BigInt,
-    pub xhi: BigInt,
-    pub xhi_inv: BigInt,
+    pub xhi: Secret<BigInt>,
+    pub xhi_inv: Secret<BigInt>,
+}
+
+impl<E: Curve> Zeroize for Keys<E> {
+    fn zeroize(&mut self) {
+        self.u_i.zeroize();
+        self.dk.zeroize();
+        self.xhi.zeroize();
+        self.xhi_inv.zeroize();
+    }
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct PartyPrivate {
-    u_i: Scalar<Secp256k1>,
-    x_i: Scalar<Secp256k1>,
-    dk: DecryptionKey,
+    u_i: Secret<Scalar<Secp256k1>>,
+    x_i: Secret<Scalar<Secp256k1>>,
+    dk: Secret<DecryptionKey>,
+}
+
+impl Zeroize for PartyPrivate {
+    fn zeroize(&mut self) {
+        self.u_i.zeroize();
+        self.x_i.zeroize();
+        self.dk.zeroize();
+    }
+}
+
+/// Proofs of Paillier key and `h1, h2, N_tilde` correctness sent in keygen round 1
+///
+/// * `Legacy`: [NiCorrectKeyProof] of Paillier key and two [CompositeDLogProof]s of `h1, h2`
+/// * `Cggmp`: Paillier-Blum modulus proof Πmod and ring-Pedersen parameters proof Πprm of
+///   CGGMP21. Paillier key must be generated from Blum primes (`p ≡ q ≡ 3 mod 4`).
+/// * `Both`: all of the above
+///
+/// Every party requires the proofs of its own setting from the others, so the setting must be
+/// the same for all the parties.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum AuxInfoProofs {
+    Legacy,
+    Cggmp,
+    Both,
 }
 
+impl Default for AuxInfoProofs {
+    fn default() -> Self {
+        AuxInfoProofs::Legacy
+    }
+}
+
+impl AuxInfoProofs {
+    pub fn legacy(self) -> bool {
+        matches!(self, AuxInfoProofs::Legacy | AuxInfoProofs::Both)
+    }
+
+    pub fn cggmp(self) -> bool {
+        matches!(self, AuxInfoProofs::Cggmp | AuxInfoProofs::Both)
+    }
+}
+
+/// Commitment to party's `y_i`, its Paillier key and `h1, h2, N_tilde`, and proofs of their
+/// correctness required by [AuxInfoProofs]
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct KeyGenBroadcastMessage1 {
     pub e: EncryptionKey,
     pub dlog_statement: DLogStatement,
     pub com: BigInt,
+    pub correct_key_proof: Option<NiCorrectKeyProof>,
+    pub composite_dlog_proof_base_h1: Option<CompositeDLogProof>,
+    pub composite_dlog_proof_base_h2: Option<CompositeDLogProof>,
+    #[serde(default)]
+    pub paillier_blum_modulus_proof: Option<PaillierBlumModulusProof>,
+    #[serde(default)]
+    pub ring_pedersen_proof: Option<RingPedersenProof>,
+}
+
+impl KeyGenBroadcastMessage1 {
+    /// Checks that `decom` opens the commitment to `y_i`
+    pub fn verify_decommitment(&self, decom: &KeyGenDecommitMessage1) -> bool {
+        HashCommitment::<Sha256>::create_commitment_with_user_defined_randomness(
+            &BigInt::from_bytes(&decom.y_i.to_bytes(true)),
+            &decom.blind_factor,
+        ) == self.com
+    }
+
+    /// Checks Paillier modulus size and the proofs of Paillier key correctness required by
+    /// `proofs`, `prover_ctx` is the context of the sender
+    pub fn verify_paillier_key(&self, proofs: AuxInfoProofs, prover_ctx: &ProofContext) -> bool {
+        let n = &self.e.n;
+        if n.bit_length() < PAILLIER_MIN_BIT_LENGTH || n.bit_length() > PAILLIER_MAX_BIT_LENGTH {
+            return false;
+        }
+        let legacy_valid = || {
+            self.correct_key_proof
+                .as_ref()
+                .map(|proof| {
+                    proof
+                        .verify(&self.e, &correct_key_salt(prover_ctx))
+                        .is_ok()
+                })
+                .unwrap_or(false)
+        };
+        let cggmp_valid = || {
+            self.paillier_blum_modulus_proof
+                .as_ref()
+                .map(|proof| {
+                    let statement = PaillierBlumModulusStatement { N: n.clone() };
+                    proof.verify(&statement, prover_ctx).is_ok()
+                })
+                .unwrap_or(false)
+        };
+        (!proofs.legacy() || legacy_valid()) && (!proofs.cggmp() || cggmp_valid())
+    }
+
+    /// Checks `N_tilde` size and the proofs of `h1, h2` generation required by `proofs`,
+    /// `prover_ctx` is the context of the sender
+    pub fn verify_n_tilde(&self, proofs: AuxInfoProofs, prover_ctx: &ProofContext) -> bool {
+        let n_tilde = &self.dlog_statement.N;
+        if n_tilde.bit_length() < PAILLIER_MIN_BIT_LENGTH
+            || n_tilde.bit_length() > PAILLIER_MAX_BIT_LENGTH
+        {
+            return false;
+        }
+        let legacy_valid = || {
+            let dlog_statement_base_h2 = DLogStatement {
+                N: n_tilde.clone(),
+                g: self.dlog_statement.ni.clone(),
+                ni: self.dlog_statement.g.clone(),
+            };
+            let base_h1_valid = self
+                .composite_dlog_proof_base_h1
+                .as_ref()
+                .map(|proof| proof.verify(&self.dlog_statement, prover_ctx).is_ok())
+                .unwrap_or(false);
+            let base_h2_valid = self
+                .composite_dlog_proof_base_h2
+                .as_ref()
+                .map(|proof| proof.verify(&dlog_statement_base_h2, prover_ctx).is_ok())
+                .unwrap_or(false);
+            base_h1_valid && base_h2_valid
+        };
+        let cggmp_valid = || {
+            self.ring_pedersen_proof
+                .as_ref()
+                .and_then(|proof| {
+                    let statement = RingPedersenStatement::from_dlog_statement(&self.dlog_statement)?;
+                    Some(proof.verify(&statement, prover_ctx).is_ok())
+                })
+                .unwrap_or(false)
+        };
+        (!proofs.legacy() || legacy_valid()) && (!proofs.cggmp() || cggmp_valid())
+    }
+}
+
+/// Party's Paillier encryption key and `h1, h2, N_tilde` parameters along with proofs of their
+/// correctness
+///
+/// Same as the public part of [KeyGenBroadcastMessage1], used when these keys are replaced
+/// outside of keygen (e.g. at key refresh).
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct AuxInfoMessage {
+    pub e: EncryptionKey,
+    pub dlog_statement: DLogStatement,
     pub correct_key_proof: NiCorrectKeyProof,
     pub composite_dlog_proof_base_h1: CompositeDLogProof,
     pub composite_dlog_proof_base_h2: CompositeDLogProof,
 }
 
+impl AuxInfoMessage {
+    /// Verifies Paillier key correctness, modulus sizes and proofs of `h1, h2` generation,
+    /// `prover_ctx` is the context of the sender
+    pub fn verify(&self, prover_ctx: &ProofContext) -> bool {
+        let dlog_statement_base_h2 = DLogStatement {
+            N: self.dlog_statement.N.clone(),
+            g: self.dlog_statement.ni.clone(),
+            ni: self.dlog_statement.g.clone(),
+        };
+        self.correct_key_proof
+            .verify(&self.e, &correct_key_salt(prover_ctx))
+            .is_ok()
+            && self.e.n.bit_length() >= PAILLIER_MIN_BIT_LENGTH
+            && self.e.n.bit_length() <= PAILLIER_MAX_BIT_LENGTH
+            && self.dlog_statement.N.bit_length() >= PAILLIER_MIN_BIT_LENGTH
+            && self.dlog_statement.N.bit_length() <= PAILLIER_MAX_BIT_LENGTH
+            && self
+                .composite_dlog_proof_base_h1
+                .verify(&self.dlog_statement, prover_ctx)
+                .is_ok()
+            && self
+                .composite_dlog_proof_base_h2
+                .verify(&dlog_statement_base_h2, prover_ctx)
+                .is_ok()
+    }
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct KeyGenDecommitMessage1 {
     pub blind_factor: BigInt,
@@ -100,18 +288,32 @@ pub struct KeyGenDecommitMessage1 {
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct SharedKeys<E: Curve = Secp256k1> {
     pub y: Point<E>,
-    pub x_i: Scalar<E>,
+    pub x_i: Secret<Scalar<E>>,
+}
+
+impl<E: Curve> Zeroize for SharedKeys<E> {
+    fn zeroize(&mut self) {
+        self.x_i.zeroize();
+    }
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct SignKeys {
-    pub w_i: Scalar<Secp256k1>,
+    pub w_i: Secret<Scalar<Secp256k1>>,
     pub g_w_i: Point<Secp256k1>,
-    pub k_i: Scalar<Secp256k1>,
-    pub gamma_i: Scalar<Secp256k1>,
+    pub k_i: Secret<Scalar<Secp256k1>>,
+    pub gamma_i: Secret<Scalar<Secp256k1>>,
     pub g_gamma_i: Point<Secp256k1>,
 }
 
+impl Zeroize for SignKeys {
+    fn zeroize(&mut self) {
+        self.w_i.zeroize();
+        self.k_i.zeroize();
+        self.gamma_i.zeroize();
+    }
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct SignBroadcastPhase1 {
     pub com: BigInt,
@@ -142,7 +344,20 @@ pub struct SignatureRecid {
 pub fn generate_h1_h2_N_tilde() -> (BigInt, BigInt, BigInt, BigInt, BigInt) {
     // note, should be safe primes:
     // let (ek_tilde, dk_tilde) = Paillier::keypair_safe_primes().keys();;
-    let (ek_tilde, dk_tilde) = Paillier::keypair().keys();
+    generate_h1_h2_N_tilde_with(false, 2048)
+}
+
+/// Same as [generate_h1_h2_N_tilde], with `N_tilde` of `bit_length` bits which is a product of
+/// safe primes if `safe_primes` is set
+pub fn generate_h1_h2_N_tilde_with(
+    safe_primes: bool,
+    bit_length: usize,
+) -> (BigInt, BigInt, BigInt, BigInt, BigInt) {
+    let (ek_tilde, dk_tilde) = if safe_primes {
+        Paillier::keypair_safe_primes_with_modulus_size(bit_length).keys()
+    } else {
+        Paillier::keypair_with_modulus_size(bit_length).keys()
+    };
     let one = BigInt::one();
     let phi = (&dk_tilde.p - &one) * (&dk_tilde.q - &one);
     let h1 = BigInt::sample_below(&ek_tilde.n);
@@ -160,26 +375,107 @@ pub fn generate_h1_h2_N_tilde() -> (BigInt, BigInt, BigInt, BigInt, BigInt) {
     (ek_tilde.n, h1, h2, xhi, xhi_inv)
 }
 
+fn is_blum_prime(p: &BigInt) -> bool {
+    p % BigInt::from(4) == BigInt::from(3)
+}
+
+/// Salt of `NiCorrectKeyProof`, binds the proof to context `ctx` of the prover
+fn correct_key_salt(ctx: &ProofContext) -> Vec<u8> {
+    ctx.tag("ni-correct-key").to_bytes()
+}
+
+/// Pre-generated Paillier key and `h1, h2, N_tilde` parameters of a party
+///
+/// Prime generation dominates keygen latency, so these can be generated in advance and given
+/// to keygen via `KeygenConfig`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PrecomputedAux {
+    pub ek: EncryptionKey,
+    pub dk: Secret<DecryptionKey>,
+    pub N_tilde: BigInt,
+    pub h1: BigInt,
+    pub h2: BigInt,
+    pub xhi: Secret<BigInt>,
+    pub xhi_inv: Secret<BigInt>,
+}
+
+impl PrecomputedAux {
+    /// Generates Paillier key and `N_tilde` of `bit_length` bits, from safe primes if
+    /// `safe_primes` is set
+    pub fn generate(safe_primes: bool, bit_length: usize) -> Self {
+        let (ek, dk) = if safe_primes {
+            Paillier::keypair_safe_primes_with_modulus_size(bit_length).keys()
+        } else {
+            Paillier::keypair_with_modulus_size(bit_length).keys()
+        };
+        let (N_tilde, h1, h2, xhi, xhi_inv) = generate_h1_h2_N_tilde_with(safe_primes, bit_length);
+        PrecomputedAux {
+            ek,
+            dk: Secret::new(dk),
+            N_tilde,
+            h1,
+            h2,
+            xhi: Secret::new(xhi),
+            xhi_inv: Secret::new(xhi_inv),
+        }
+    }
+
+    /// Generates Paillier key from Blum primes and `N_tilde` of `bit_length` bits, as required
+    /// by [AuxInfoProofs::Cggmp]
+    pub fn generate_blum(bit_length: usize) -> Self {
+        let (ek, dk) = loop {
+            let (ek, dk) = Paillier::keypair_with_modulus_size(bit_length).keys();
+            if is_blum_prime(&dk.p) && is_blum_prime(&dk.q) {
+                break (ek, dk);
+            }
+        };
+        let (N_tilde, h1, h2, xhi, xhi_inv) = generate_h1_h2_N_tilde_with(false, bit_length);
+        PrecomputedAux {
+            ek,
+            dk: Secret::new(dk),
+            N_tilde,
+            h1,
+            h2,
+            xhi: Secret::new(xhi),
+            xhi_inv: Secret::new(xhi_inv),
+        }
+    }
+
+    /// Checks that Paillier modulus is a product of Blum primes
+    pub fn has_blum_modulus(&self) -> bool {
+        let dk = self.dk.expose();
+        is_blum_prime(&dk.p) && is_blum_prime(&dk.q)
+    }
+
+    /// Checks that Paillier key pair matches and that moduli sizes are acceptable
+    pub fn is_valid(&self) -> bool {
+        let acceptable = |n: &BigInt| {
+            n.bit_length() >= PAILLIER_MIN_BIT_LENGTH && n.bit_length() <= PAILLIER_MAX_BIT_LENGTH
+        };
+        let dk = self.dk.expose();
+        &dk.p * &dk.q == self.ek.n && acceptable(&self.ek.n) && acceptable(&self.N_tilde)
+    }
+}
+
 impl Keys {
     pub fn create(index: usize) -> Self {
         let u = Scalar::<Secp256k1>::random();
         //let u = Scalar::<Secp256k1>::from_bigint(&BigInt::from_str_radix("53148706113055400461778566684754052970592521397982588090730450887828980789535",10).unwrap());
-        println!("u {}",u.to_bigint());
         let y = Point::generator() * &u;
         let (ek, dk) = Paillier::keypair().keys();
         let (N_tilde, h1, h2, xhi, xhi_inv) = generate_h1_h2_N_tilde();
 
         Self {
-            u_i: u,
+            u_i: Secret::new(u),
             y_i: y,
-            dk,
+            dk: Secret::new(dk),
             ek,
             party_index: index,
             N_tilde,
             h1,
             h2,
-            xhi,
-            xhi_inv,
+            xhi: Secret::new(xhi),
+            xhi_inv: Secret::new(xhi_inv),
         }
     }
 
@@ -192,16 +488,16 @@ impl Keys {
         let (N_tilde, h1, h2, xhi, xhi_inv) = generate_h1_h2_N_tilde();
 
         Self {
-            u_i: u,
+            u_i: Secret::new(u),
             y_i: y,
-            dk,
+            dk: Secret::new(dk),
             ek,
             party_index: index,
             N_tilde,
             h1,
             h2,
-            xhi,
-            xhi_inv,
+            xhi: Secret::new(xhi),
+            xhi_inv: Secret::new(xhi_inv),
         }
     }
     
@@ -213,42 +509,60 @@ impl Keys {
         let (N_tilde, h1, h2, xhi, xhi_inv) = generate_h1_h2_N_tilde();
 
         Self {
-            u_i: u,
+            u_i: Secret::new(u),
             y_i: y,
-            dk,
+            dk: Secret::new(dk),
             ek,
             party_index: index,
             N_tilde,
             h1,
             h2,
-            xhi,
-            xhi_inv,
+            xhi: Secret::new(xhi),
+            xhi_inv: Secret::new(xhi_inv),
+        }
+    }
+    /// Creates party keys with given secret `u` and pre-generated Paillier key and
+    /// `h1, h2, N_tilde` parameters
+    pub fn create_with_aux(u: Scalar<Secp256k1>, index: usize, aux: PrecomputedAux) -> Self {
+        let y = Point::generator() * &u;
+        Self {
+            u_i: Secret::new(u),
+            y_i: y,
+            dk: aux.dk,
+            ek: aux.ek,
+            party_index: index,
+            N_tilde: aux.N_tilde,
+            h1: aux.h1,
+            h2: aux.h2,
+            xhi: aux.xhi,
+            xhi_inv: aux.xhi_inv,
         }
     }
+
     pub fn create_from(u: Scalar<Secp256k1>, index: usize) -> Self {
         let y = Point::generator() * &u;
         let (ek, dk) = Paillier::keypair().keys();
         let (N_tilde, h1, h2, xhi, xhi_inv) = generate_h1_h2_N_tilde();
 
         Self {
-            u_i: u,
+            u_i: Secret::new(u),
             y_i: y,
-            dk,
+            dk: Secret::new(dk),
             ek,
             party_index: index,
             N_tilde,
             h1,
             h2,
-            xhi,
-            xhi_inv,
+            xhi: Secret::new(xhi),
+            xhi_inv: Secret::new(xhi_inv),
         }
     }
 
-    pub fn phase1_broadcast_phase3_proof_of_correct_key_proof_of_correct_h1h2(
-        &self,
-    ) -> (KeyGenBroadcastMessage1, KeyGenDecommitMessage1) {
-        let blind_factor = BigInt::sample(SECURITY);
-        let correct_key_proof = NiCorrectKeyProof::proof(&self.dk, None);
+    /// Proves correctness of party's Paillier key and `h1, h2, N_tilde` parameters in context
+    /// `ctx` of this party
+    pub fn aux_info_message(&self, ctx: &ProofContext) -> AuxInfoMessage {
+        let salt = correct_key_salt(ctx);
+        let correct_key_proof = NiCorrectKeyProof::proof(self.dk.expose(), Some(&salt[..]));
 
         let dlog_statement_base_h1 = DLogStatement {
             N: self.N_tilde.clone(),
@@ -262,9 +576,116 @@ impl Keys {
         };
 
         let composite_dlog_proof_base_h1 =
-            CompositeDLogProof::prove(&dlog_statement_base_h1, &self.xhi);
+            CompositeDLogProof::prove(&dlog_statement_base_h1, self.xhi.expose(), ctx);
         let composite_dlog_proof_base_h2 =
-            CompositeDLogProof::prove(&dlog_statement_base_h2, &self.xhi_inv);
+            CompositeDLogProof::prove(&dlog_statement_base_h2, self.xhi_inv.expose(), ctx);
+
+        AuxInfoMessage {
+            e: self.ek.clone(),
+            dlog_statement: dlog_statement_base_h1,
+            correct_key_proof,
+            composite_dlog_proof_base_h1,
+            composite_dlog_proof_base_h2,
+        }
+    }
+
+    /// Proves that party's Paillier modulus is a product of two Blum primes, returns `None` if
+    /// it's not
+    pub fn paillier_blum_modulus_proof(
+        &self,
+        ctx: &ProofContext,
+    ) -> Option<PaillierBlumModulusProof> {
+        let statement = PaillierBlumModulusStatement {
+            N: self.ek.n.clone(),
+        };
+        let witness = PaillierBlumModulusWitness {
+            p: self.dk.expose().p.clone(),
+            q: self.dk.expose().q.clone(),
+        };
+        PaillierBlumModulusProof::prove(&witness, &statement, ctx)
+    }
+
+    /// Proves that `h2` is generated by `h1` modulo `N_tilde`, returns `None` if `h2` is not
+    /// invertible
+    pub fn ring_pedersen_proof(&self, ctx: &ProofContext) -> Option<RingPedersenProof> {
+        let statement = RingPedersenStatement::from_dlog_statement(&DLogStatement {
+            N: self.N_tilde.clone(),
+            g: self.h1.clone(),
+            ni: self.h2.clone(),
+        })?;
+        let witness = RingPedersenWitness {
+            lambda: self.xhi.expose().clone(),
+        };
+        Some(RingPedersenProof::prove(&witness, &statement, ctx))
+    }
+
+    /// Proves to the owner of `recipient` parameters that party's Paillier modulus has no small
+    /// factors, `ctx` is the context of this party
+    pub fn nsf_proof_for(
+        &self,
+        recipient: &DLogStatement,
+        ctx: &ProofContext,
+    ) -> Option<NoSmallFactorProof> {
+        let nsf_setup = NoSmallFactorSetUp {
+            n_tilde_: recipient.N.clone(),
+            s_: recipient.g.clone(),
+            t_: recipient.ni.clone(),
+        };
+        let nsf_statement = NoSmallFactorStatement::new(self.ek.n.clone());
+        let nsf_witness = NoSmallFactorWitness {
+            p_: self.dk.expose().p.clone(),
+            q_: self.dk.expose().q.clone(),
+        };
+        NoSmallFactorProof::prove(&nsf_setup, &nsf_statement, &nsf_witness, ctx)
+    }
+
+    /// Verifies that Paillier modulus `n0` of another party has no small factors, the proof
+    /// must be made for our `h1, h2, N_tilde` in context `prover_ctx` of that party
+    pub fn verify_nsf_proof(
+        &self,
+        n0: &BigInt,
+        proof: &NoSmallFactorProof,
+        prover_ctx: &ProofContext,
+    ) -> bool {
+        let nsf_setup = NoSmallFactorSetUp {
+            n_tilde_: self.N_tilde.clone(),
+            s_: self.h1.clone(),
+            t_: self.h2.clone(),
+        };
+        let nsf_statement = NoSmallFactorStatement::new(n0.clone());
+        proof.verify(&nsf_setup, &nsf_statement, prover_ctx)
+    }
+
+    /// Commits to `y_i` and proves correctness of Paillier key and `h1, h2, N_tilde` with
+    /// `proofs` in context `ctx` of this party
+    ///
+    /// Returns `None` if CGGMP proofs are requested, but Paillier modulus is not a product of
+    /// Blum primes.
+    pub fn phase1_broadcast_phase3_proof_of_correct_key_proof_of_correct_h1h2(
+        &self,
+        proofs: AuxInfoProofs,
+        ctx: &ProofContext,
+    ) -> Option<(KeyGenBroadcastMessage1, KeyGenDecommitMessage1)> {
+        let blind_factor = BigInt::sample(SECURITY);
+        let (correct_key_proof, composite_dlog_proof_base_h1, composite_dlog_proof_base_h2) =
+            if proofs.legacy() {
+                let aux_info = self.aux_info_message(ctx);
+                (
+                    Some(aux_info.correct_key_proof),
+                    Some(aux_info.composite_dlog_proof_base_h1),
+                    Some(aux_info.composite_dlog_proof_base_h2),
+                )
+            } else {
+                (None, None, None)
+            };
+        let (paillier_blum_modulus_proof, ring_pedersen_proof) = if proofs.cggmp() {
+            (
+                Some(self.paillier_blum_modulus_proof(ctx)?),
+                Some(self.ring_pedersen_proof(ctx)?),
+            )
+        } else {
+            (None, None)
+        };
 
         let com = HashCommitment::<Sha256>::create_commitment_with_user_defined_randomness(
             &BigInt::from_bytes(self.y_i.to_bytes(true).as_ref()),
@@ -272,17 +693,23 @@ impl Keys {
         );
         let bcm1 = KeyGenBroadcastMessage1 {
             e: self.ek.clone(),
-            dlog_statement: dlog_statement_base_h1,
+            dlog_statement: DLogStatement {
+                N: self.N_tilde.clone(),
+                g: self.h1.clone(),
+                ni: self.h2.clone(),
+            },
             com,
             correct_key_proof,
             composite_dlog_proof_base_h1,
             composite_dlog_proof_base_h2,
+            paillier_blum_modulus_proof,
+            ring_pedersen_proof,
         };
         let decom1 = KeyGenDecommitMessage1 {
             blind_factor,
             y_i: self.y_i.clone(),
         };
-        (bcm1, decom1)
+        Some((bcm1, decom1))
     }
 
     pub fn phase1_verify_com_phase3_verify_correct_key_verify_dlog_phase2_distribute_nsf_proof(
@@ -290,6 +717,8 @@ impl Keys {
         params: &Parameters,
         decom_vec: &[KeyGenDecommitMessage1],
         bc1_vec: &[KeyGenBroadcastMessage1],
+        proofs: AuxInfoProofs,
+        session_id: &SessionId,
     ) -> Result<(VerifiableSS<Secp256k1>, Vec<Scalar<Secp256k1>>, Vec<NoSmallFactorProof>, usize), ErrorType> {
         let mut bad_actors_vec = Vec::new();
         // test length:
@@ -298,78 +727,10 @@ impl Keys {
         // test paillier correct key, h1,h2 correct generation and test decommitments
         let correct_key_correct_decom_all = (0..bc1_vec.len())
             .map(|i| {
-                let dlog_statement_base_h2 = DLogStatement {
-                    N: bc1_vec[i].dlog_statement.N.clone(),
-                    g: bc1_vec[i].dlog_statement.ni.clone(),
-                    ni: bc1_vec[i].dlog_statement.g.clone(),
-                };
-                let test_res_1 =
-                    HashCommitment::<Sha256>::create_commitment_with_user_defined_randomness(
-                        &BigInt::from_bytes(&decom_vec[i].y_i.to_bytes(true)),
-                        &decom_vec[i].blind_factor,
-                    ) == bc1_vec[i].com;
-                //log::info!("MP-ECDSA : Round 2 : test_res_1 {:?}", test_res_1);
-
-                if !test_res_1 {
-                    // Do a dumb test to search if the right decommit exists
-                    for j in 0..bc1_vec.len() {
-                        if i != j {
-                            let test_res_1_1 =
-                                HashCommitment::<Sha256>::create_commitment_with_user_defined_randomness(
-                                    &BigInt::from_bytes(&decom_vec[j].y_i.to_bytes(true)),
-                                    &decom_vec[j].blind_factor,
-                                ) == bc1_vec[i].com;
-                            if test_res_1_1 {
-                                // log::info!("MP-ECDSA : Round 2 RETEST : test_res_1_1 {:?}", test_res_1_1);
-                                // log::info!("MP-ECDSA : Round 2 RETEST : i {:?}", i);
-                                // log::info!("MP-ECDSA : Round 2 RETEST : j {:?}", j);
-                                // log::info!("MP-ECDSA : Round 2 RETEST : decom_vec[i] {:?}", decom_vec[i]);
-                                // log::info!("MP-ECDSA : Round 2 RETEST : decom_vec[j] {:?}", decom_vec[j]);
-                                // log::info!("MP-ECDSA : Round 2 RETEST : bc1_vec[i] {:?}", bc1_vec[i]);
-                                // log::info!("MP-ECDSA : Round 2 RETEST : bc1_vec[j] {:?}", bc1_vec[j]);
-                            }
-                        }
-                    }
-                }
-
-                let test_res_2 = bc1_vec[i]
-                    .correct_key_proof
-                    .verify(&bc1_vec[i].e, zk_paillier::zkproofs::SALT_STRING)
-                    .is_ok();
-                //log::info!("MP-ECDSA : Round 2 : test_res_2 {:?}", test_res_2);
-
-                let test_res_3 = bc1_vec[i].e.n.bit_length() >= PAILLIER_MIN_BIT_LENGTH;
-                //log::info!("MP-ECDSA : Round 2 : test_res_3 {:?}", test_res_3);
-
-                let test_res_4 = bc1_vec[i].e.n.bit_length() <= PAILLIER_MAX_BIT_LENGTH;
-                //log::info!("MP-ECDSA : Round 2 : test_res_4 {:?}", test_res_4);
-
-                let test_res_5 =
-                    bc1_vec[i].dlog_statement.N.bit_length() >= PAILLIER_MIN_BIT_LENGTH;
-                //log::info!("MP-ECDSA : Round 2 : test_res_5 {:?}", test_res_5);
-
-                let test_res_6 =
-                    bc1_vec[i].dlog_statement.N.bit_length() <= PAILLIER_MAX_BIT_LENGTH;
-                //log::info!("MP-ECDSA : Round 2 : test_res_6 {:?}", test_res_6);
-                let test_res_7 = bc1_vec[i]
-                    .composite_dlog_proof_base_h1
-                    .verify(&bc1_vec[i].dlog_statement)
-                    .is_ok();
-                //log::info!("MP-ECDSA : Round 2 : test_res_7 {:?}", test_res_7);
-                let test_res_8 = bc1_vec[i]
-                    .composite_dlog_proof_base_h2
-                    .verify(&dlog_statement_base_h2)
-                    .is_ok();
-                //log::info!("MP-ECDSA : Round 2 : test_res_8 {:?}", test_res_8);
-
-                let test_res = test_res_1
-                    && test_res_2
-                    && test_res_3
-                    && test_res_4
-                    && test_res_5
-                    && test_res_6
-                    && test_res_7
-                    && test_res_8;
+                let prover_ctx = session_id.context(i as u16 + 1, KEYGEN_LABEL);
+                let test_res = bc1_vec[i].verify_decommitment(&decom_vec[i])
+                    && bc1_vec[i].verify_paillier_key(proofs, &prover_ctx)
+                    && bc1_vec[i].verify_n_tilde(proofs, &prover_ctx);
 
                 if !test_res {
                     bad_actors_vec.push(i);
@@ -380,25 +741,10 @@ impl Keys {
             })
             .all(|x| x);
 
-        let no_small_factor_proof = (0..bc1_vec.len())
-            .map(|i|{
-                let nsf_setup = NoSmallFactorSetUp{
-                    n_tilde_ : bc1_vec[i].dlog_statement.N.clone(),
-                    s_ : bc1_vec[i].dlog_statement.g.clone(),
-                    t_ : bc1_vec[i].dlog_statement.ni.clone()
-                };
-                let nsf_statement = NoSmallFactorStatement{
-                    n0_ : self.ek.n.clone(),
-                    l_ : 256,
-                    varepsilon_ : 512
-                };
-                let nsf_witness = NoSmallFactorWitness{
-                    p_: self.dk.p.clone(),
-                    q_: self.dk.q.clone()
-                };
-                NoSmallFactorProof::prove(&nsf_setup, &nsf_statement, &nsf_witness)
-            })
-            .filter_map(|proof| proof)
+        let ctx = session_id.context(self.party_index as u16, KEYGEN_LABEL);
+        let no_small_factor_proof = bc1_vec
+            .iter()
+            .filter_map(|bc1| self.nsf_proof_for(&bc1.dlog_statement, &ctx))
             .collect();
 
         let err_type = ErrorType {
@@ -408,7 +754,7 @@ impl Keys {
         };
 
         let (vss_scheme, secret_shares) =
-            VerifiableSS::share(params.threshold, params.share_count, &self.u_i);
+            VerifiableSS::share(params.threshold, params.share_count, self.u_i.expose());
         if correct_key_correct_decom_all {
             Ok((vss_scheme, secret_shares.to_vec(), no_small_factor_proof,self.party_index))
         } else {
@@ -426,6 +772,7 @@ impl Keys {
         nsf_proofs:&[NoSmallFactorProof],
         bc1_vec:&[KeyGenBroadcastMessage1],
         index: usize,
+        session_id: &SessionId,
     ) -> Result<(SharedKeys, DLogProof<Secp256k1, Sha256>), ErrorType> {
         let mut bad_actors_vec = Vec::new();
         //let mut bad_actors_vec_nsf = Vec::new();
@@ -449,27 +796,31 @@ impl Keys {
             })
             .all(|x| x);
 
-        let correct_nsf_verify = (0..y_vec.len())
-            .map(|i| {
-                let nsf_setup = NoSmallFactorSetUp{
-                    n_tilde_: self.N_tilde.clone(),
-                    s_ : self.h1.clone(),
-                    t_ : self.h2.clone()
-                };
-                let nsf_statement = NoSmallFactorStatement{
-                    n0_ : bc1_vec[i].e.n.clone(),
-                    l_ : 256,
-                    varepsilon_ : 512
-                };
-                let res = nsf_proofs[i].verify(&nsf_setup, &nsf_statement);
-                if !res {
-                    bad_actors_vec.push(i);
-                    false
-                } else {
-                    true
-                }
-            })
-            .all(|x| x);
+        let nsf_setup = NoSmallFactorSetUp {
+            n_tilde_: self.N_tilde.clone(),
+            s_: self.h1.clone(),
+            t_: self.h2.clone(),
+        };
+        let nsf_statements: Vec<_> = bc1_vec
+            .iter()
+            .map(|bc1| NoSmallFactorStatement::new(bc1.e.n.clone()))
+            .collect();
+        let prover_ctxs: Vec<_> = (0..nsf_proofs.len())
+            .map(|i| session_id.context(i as u16 + 1, KEYGEN_LABEL))
+            .collect();
+        let nsf_batch: Vec<_> = nsf_proofs
+            .iter()
+            .zip(&nsf_statements)
+            .zip(&prover_ctxs)
+            .map(|((proof, statement), ctx)| (proof, statement, ctx))
+            .collect();
+        let correct_nsf_verify = match NoSmallFactorProof::batch_verify(&nsf_setup, &nsf_batch) {
+            Ok(()) => true,
+            Err(bad_actors) => {
+                bad_actors_vec.extend(bad_actors);
+                false
+            }
+        };
         
         //let bad_actors_vec = bad_actors_map["bad_ss"].append(bad_actors_map["bad_nsf"].clone());
 
@@ -480,7 +831,6 @@ impl Keys {
         };
 
         if correct_ss_verify && correct_nsf_verify{
-            println!("success verified nsf & ss");
             let (head, tail) = y_vec.split_at(1);
             let y = tail.iter().fold(head[0].clone(), |acc, x| acc + x);
 
@@ -488,16 +838,20 @@ impl Keys {
                 .iter()
                 .fold(Scalar::<Secp256k1>::zero(), |acc, x| acc + x);
             let dlog_proof = DLogProof::prove(&x_i);
-            Ok((SharedKeys { y, x_i }, dlog_proof))
+            Ok((
+                SharedKeys {
+                    y,
+                    x_i: Secret::new(x_i),
+                },
+                dlog_proof,
+            ))
         } else {
             Err(err_type)
         }
     }
 
-    pub fn get_commitments_to_xi(
-        vss_scheme_vec: &[VerifiableSS<Secp256k1>],
-    ) -> Vec<Point<Secp256k1>> {
-        let len = vss_scheme_vec.len();
+    /// Sums up VSS schemes of all the parties into VSS scheme of the shared secret
+    pub fn get_global_vss(vss_scheme_vec: &[VerifiableSS<Secp256k1>]) -> VerifiableSS<Secp256k1> {
         let (head, tail) = vss_scheme_vec.split_at(1);
         let mut global_coefficients = head[0].commitments.clone();
         for vss in tail {
@@ -506,11 +860,18 @@ impl Keys {
             }
         }
 
-        let global_vss = VerifiableSS {
+        VerifiableSS {
             parameters: vss_scheme_vec[0].parameters.clone(),
             commitments: global_coefficients,
             proof: vss_scheme_vec[0].proof.clone(),
-        };
+        }
+    }
+
+    pub fn get_commitments_to_xi(
+        vss_scheme_vec: &[VerifiableSS<Secp256k1>],
+    ) -> Vec<Point<Secp256k1>> {
+        let len = vss_scheme_vec.len();
+        let global_vss = Self::get_global_vss(vss_scheme_vec);
         (1..=len)
             .map(|i| global_vss.get_point_commitment(i.try_into().unwrap()))
             .collect::<Vec<Point<Secp256k1>>>()
@@ -579,53 +940,53 @@ impl PartyPrivate {
 
     pub fn y_i(&self) -> Point<Secp256k1> {
         let g = Point::generator();
-        g * &self.u_i
+        g * self.u_i.expose()
     }
 
     pub fn decrypt(&self, ciphertext: BigInt) -> RawPlaintext {
-        Paillier::decrypt(&self.dk, &RawCiphertext::from(ciphertext))
+        Paillier::decrypt(self.dk.expose(), &RawCiphertext::from(ciphertext))
     }
 
     pub fn refresh_private_key(&self, factor: &Scalar<Secp256k1>, index: usize) -> Keys {
-        let u: Scalar<Secp256k1> = &self.u_i + factor;
+        let u: Scalar<Secp256k1> = self.u_i.expose() + factor;
         let y = Point::generator() * &u;
         let (ek, dk) = Paillier::keypair().keys();
 
         let (N_tilde, h1, h2, xhi, xhi_inv) = generate_h1_h2_N_tilde();
 
         Keys {
-            u_i: u,
+            u_i: Secret::new(u),
             y_i: y,
-            dk,
+            dk: Secret::new(dk),
             ek,
             party_index: index,
             N_tilde,
             h1,
             h2,
-            xhi,
-            xhi_inv,
+            xhi: Secret::new(xhi),
+            xhi_inv: Secret::new(xhi_inv),
         }
     }
 
     // we recommend using safe primes if the code is used in production
     pub fn refresh_private_key_safe_prime(&self, factor: &Scalar<Secp256k1>, index: usize) -> Keys {
-        let u: Scalar<Secp256k1> = &self.u_i + factor;
+        let u: Scalar<Secp256k1> = self.u_i.expose() + factor;
         let y = Point::generator() * &u;
         let (ek, dk) = Paillier::keypair_safe_primes().keys();
 
         let (N_tilde, h1, h2, xhi, xhi_inv) = generate_h1_h2_N_tilde();
 
         Keys {
-            u_i: u,
+            u_i: Secret::new(u),
             y_i: y,
-            dk,
+            dk: Secret::new(dk),
             ek,
             party_index: index,
             N_tilde,
             h1,
             h2,
-            xhi,
-            xhi_inv,
+            xhi: Secret::new(xhi),
+            xhi_inv: Secret::new(xhi_inv),
         }
     }
 
@@ -637,7 +998,13 @@ impl PartyPrivate {
         pub_ke_y: &Point<Secp256k1>,
         g: &Point<Secp256k1>,
     ) -> (Witness, Helgamalsegmented) {
-        Msegmentation::to_encrypted_segments(&self.u_i, &segment_size, num_of_segments, pub_ke_y, g)
+        Msegmentation::to_encrypted_segments(
+            self.u_i.expose(),
+            &segment_size,
+            num_of_segments,
+            pub_ke_y,
+            g,
+        )
     }
 
     pub fn update_private_key(
@@ -646,8 +1013,8 @@ impl PartyPrivate {
         factor_x_i: &Scalar<Secp256k1>,
     ) -> Self {
         PartyPrivate {
-            u_i: &self.u_i + factor_u_i,
-            x_i: &self.x_i + factor_x_i,
+            u_i: Secret::new(self.u_i.expose() + factor_u_i),
+            x_i: Secret::new(self.x_i.expose() + factor_x_i),
             dk: self.dk.clone(),
         }
     }
@@ -692,10 +1059,10 @@ impl SignKeys {
         let g_gamma_i = g * &gamma_i;
         let k_i = Scalar::<Secp256k1>::random();
         Self {
-            w_i,
+            w_i: Secret::new(w_i),
             g_w_i,
-            k_i,
-            gamma_i,
+            k_i: Secret::new(k_i),
+            gamma_i: Secret::new(gamma_i),
             g_gamma_i,
         }
     }
@@ -703,7 +1070,7 @@ impl SignKeys {
     pub fn phase1_broadcast(&self) -> (SignBroadcastPhase1, SignDecommitPhase1) {
         let blind_factor = BigInt::sample(SECURITY);
         let g = Point::generator();
-        let g_gamma_i = g * &self.gamma_i;
+        let g_gamma_i = g * self.gamma_i.expose();
         let com = HashCommitment::<Sha256>::create_commitment_with_user_defined_randomness(
             &BigInt::from_bytes(g_gamma_i.to_bytes(true).as_ref()),
             &blind_factor,
@@ -726,7 +1093,7 @@ impl SignKeys {
         let vec_len = alpha_vec.len();
         assert_eq!(alpha_vec.len(), beta_vec.len());
         // assert_eq!(alpha_vec.len(), self.s.len() - 1);
-        let ki_gamma_i = &self.k_i * &self.gamma_i;
+        let ki_gamma_i = self.k_i.expose() * self.gamma_i.expose();
 
         (0..vec_len)
             .map(|i| &alpha_vec[i] + &beta_vec[i])
@@ -741,7 +1108,7 @@ impl SignKeys {
         let vec_len = miu_vec.len();
         assert_eq!(miu_vec.len(), ni_vec.len());
         //assert_eq!(miu_vec.len(), self.s.len() - 1);
-        let ki_w_i = &self.k_i * &self.w_i;
+        let ki_w_i = self.k_i.expose() * self.w_i.expose();
         (0..vec_len)
             .map(|i| &miu_vec[i] + &ni_vec[i])
             .fold(ki_w_i, |acc, x| acc + x)
@@ -827,6 +1194,7 @@ impl LocalSignature {
         k_i: &Scalar<Secp256k1>,
         k_enc_randomness: &BigInt,
         dlog_statement: &DLogStatement,
+        ctx: &ProofContext,
     ) -> PDLwSlackProof {
         // Generate PDL with slack statement, witness and proof
         let pdl_w_slack_statement = PDLwSlackStatement {
@@ -844,7 +1212,7 @@ impl LocalSignature {
             r: k_enc_randomness.clone(),
         };
 
-        PDLwSlackProof::prove(&pdl_w_slack_witness, &pdl_w_slack_statement)
+        PDLwSlackProof::prove(&pdl_w_slack_witness, &pdl_w_slack_statement, ctx)
     }
 
     pub fn phase5_verify_pdl(
@@ -856,6 +1224,7 @@ impl LocalSignature {
         dlog_statement: &[DLogStatement],
         s: &[usize],
         i: usize,
+        prover_ctx: &ProofContext,
     ) -> Result<(), ErrorType> {
         let mut bad_actors_vec = Vec::new();
 
@@ -875,7 +1244,8 @@ impl LocalSignature {
                         h2: dlog_statement[s[ind]].ni.clone(),
                         N_tilde: dlog_statement[s[ind]].N.clone(),
                     };
-                    let ver_res = pdl_w_slack_proof_vec[j].verify(&pdl_w_slack_statement);
+                    let ver_res =
+                        pdl_w_slack_proof_vec[j].verify(&pdl_w_slack_statement, prover_ctx);
                     if ver_res.is_err() {
                         bad_actors_vec.push(i);
                         false
@@ -912,7 +1282,8 @@ impl LocalSignature {
         T: &Point<Secp256k1>,
         sigma: &Scalar<Secp256k1>,
         l: &Scalar<Secp256k1>,
-    ) -> (Point<Secp256k1>, HomoELGamalProof<Secp256k1, Sha256>) {
+        ctx: &ProofContext,
+    ) -> (Point<Secp256k1>, HomoElGamalProof) {
         let S = R * sigma;
         let delta = HomoElGamalStatement {
             G: R.clone(),
@@ -925,16 +1296,17 @@ impl LocalSignature {
             x: l.clone(),
             r: sigma.clone(),
         };
-        let proof = HomoELGamalProof::prove(&witness, &delta);
+        let proof = HomoElGamalProof::prove(&witness, &delta, ctx);
 
         (S, proof)
     }
 
     pub fn phase6_verify_proof(
         S_vec: &[Point<Secp256k1>],
-        proof_vec: &[HomoELGamalProof<Secp256k1, Sha256>],
+        proof_vec: &[HomoElGamalProof],
         R_vec: &[Point<Secp256k1>],
         T_vec: &[Point<Secp256k1>],
+        prover_ctx: &[ProofContext],
     ) -> Result<(), ErrorType> {
         let mut bad_actors_vec = Vec::new();
         let mut verify_proofs = true;
@@ -946,7 +1318,7 @@ impl LocalSignature {
                 D: T_vec[i].clone(),
                 E: S_vec[i].clone(),
             };
-            if proof_vec[i].verify(&delta).is_err() {
+            if proof_vec[i].verify(&delta, &prover_ctx[i]).is_err() {
                 verify_proofs = false;
                 bad_actors_vec.push(i);
             };
@@ -0,0 +1,613 @@
+//! Background pool of pre-generated Paillier keys and `h1, h2, N_tilde` parameters
+//!
+//! Prime generation takes most of keygen time (tens of seconds for safe primes). [PrimePool]
+//! generates [PrecomputedAux] on worker threads ahead of time, keeps them on disk encrypted
+//! with AES-256-GCM, and hands every one of them out exactly once to
+//! [Keygen](super::state_machine::keygen::Keygen), [Refresh](super::state_machine::refresh::Refresh)
+//! or [Reshare](super::state_machine::reshare::Reshare).
+//!
+//! Every entry is stored in a separate file in pool directory. An entry is claimed by atomically
+//! renaming its file before it's handed out, and removed from disk afterwards, so it can't be
+//! handed out again even if the process is restarted or another pool is opened on the same
+//! directory.
+
+use std::collections::VecDeque;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread::{self, JoinHandle};
+
+use aes_gcm::aead::{Aead, NewAead};
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use rand_core::{OsRng, RngCore};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use zeroize::Zeroize;
+
+use crate::protocols::multi_party_ecdsa::gg_2020::party_i::PrecomputedAux;
+use crate::protocols::multi_party_ecdsa::gg_2020::state_machine::keygen::KeygenConfig;
+
+const ENTRY_EXTENSION: &str = "aux";
+const CLAIMED_EXTENSION: &str = "claimed";
+
+/// Parameters of [PrimePool]
+#[derive(Clone, Debug)]
+pub struct PoolConfig {
+    /// Number of entries the pool keeps in stock
+    pub target: usize,
+    /// Number of worker threads generating entries
+    pub workers: usize,
+    /// Whether entries are generated from safe primes
+    pub safe_primes: bool,
+    /// Whether Paillier keys are generated from Blum primes, as required by
+    /// [AuxInfoProofs::Cggmp](super::party_i::AuxInfoProofs::Cggmp). Safe primes are Blum primes
+    /// as well, so it only matters if `safe_primes` is not set.
+    pub blum_primes: bool,
+    /// Bit length of Paillier modulus and `N_tilde`
+    pub modulus_bits: usize,
+}
+
+impl Default for PoolConfig {
+    fn default() -> Self {
+        PoolConfig {
+            target: 8,
+            workers: 2,
+            safe_primes: true,
+            blum_primes: false,
+            modulus_bits: 2048,
+        }
+    }
+}
+
+/// Supply status of [PrimePool]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PoolStatus {
+    /// Entries ready to be handed out
+    pub available: usize,
+    /// Entries being generated right now
+    pub generating: usize,
+    /// Number of entries the pool keeps in stock
+    pub target: usize,
+    /// Entries generated since the pool was opened
+    pub generated: u64,
+    /// Entries handed out since the pool was opened
+    pub handed_out: u64,
+    /// Error which stopped the workers, no entries are generated anymore once it's set
+    pub error: Option<String>,
+}
+
+/// Pool of pre-generated Paillier keys and `h1, h2, N_tilde` parameters
+///
+/// Worker threads are stopped when the pool is dropped, entries which are not handed out
+/// remain on disk and are loaded next time the pool is opened.
+pub struct PrimePool {
+    shared: Arc<Shared>,
+    workers: Vec<JoinHandle<()>>,
+}
+
+struct Shared {
+    dir: PathBuf,
+    cipher: Aes256Gcm,
+    config: PoolConfig,
+    state: Mutex<State>,
+    /// Notifies workers that stock went below target or the pool is closing
+    need_more: Condvar,
+    /// Notifies consumers that a new entry is available
+    available: Condvar,
+}
+
+struct State {
+    entries: VecDeque<PathBuf>,
+    generating: usize,
+    generated: u64,
+    handed_out: u64,
+    closing: bool,
+    error: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SealedEntry {
+    nonce: Vec<u8>,
+    ciphertext: Vec<u8>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct PoolEntry {
+    safe_primes: bool,
+    /// Missing in entries stored before Blum primes were supported
+    #[serde(default)]
+    blum_primes: bool,
+    modulus_bits: usize,
+    aux: PrecomputedAux,
+}
+
+impl PoolEntry {
+    fn generate(config: &PoolConfig) -> Self {
+        let aux = if !config.safe_primes && config.blum_primes {
+            PrecomputedAux::generate_blum(config.modulus_bits)
+        } else {
+            PrecomputedAux::generate(config.safe_primes, config.modulus_bits)
+        };
+        PoolEntry {
+            safe_primes: config.safe_primes,
+            blum_primes: config.blum_primes,
+            modulus_bits: config.modulus_bits,
+            aux,
+        }
+    }
+
+    /// Whether entry was generated with parameters of `config`
+    fn fits(&self, config: &PoolConfig) -> bool {
+        // any Blum entry fits a config which doesn't ask for Blum primes
+        self.safe_primes == config.safe_primes
+            && self.modulus_bits == config.modulus_bits
+            && (config.safe_primes || self.blum_primes || !config.blum_primes)
+    }
+}
+
+impl PrimePool {
+    /// Opens a pool stored in directory `dir` and starts worker threads
+    ///
+    /// Entries are encrypted with `key`. Existing entries generated with other parameters than
+    /// `config` are left on disk and not handed out. Fails if directory can't be created or
+    /// read, or if an existing entry can't be decrypted with `key`.
+    pub fn open(dir: impl AsRef<Path>, key: &[u8; 32], config: PoolConfig) -> Result<Self> {
+        if config.workers == 0 {
+            return Err(PoolError::InvalidConfig);
+        }
+        let probe = KeygenConfig {
+            safe_primes: config.safe_primes,
+            modulus_bits: config.modulus_bits,
+            ..Default::default()
+        };
+        if !probe.is_valid() {
+            return Err(PoolError::InvalidConfig);
+        }
+
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir).map_err(PoolError::Io)?;
+
+        let shared = Arc::new(Shared {
+            dir,
+            cipher: Aes256Gcm::new(Key::from_slice(key)),
+            config,
+            state: Mutex::new(State {
+                entries: VecDeque::new(),
+                generating: 0,
+                generated: 0,
+                handed_out: 0,
+                closing: false,
+                error: None,
+            }),
+            need_more: Condvar::new(),
+            available: Condvar::new(),
+        });
+
+        let mut entries = vec![];
+        for dir_entry in fs::read_dir(&shared.dir).map_err(PoolError::Io)? {
+            let path = dir_entry.map_err(PoolError::Io)?.path();
+            if path.extension().map_or(true, |ext| ext != ENTRY_EXTENSION) {
+                continue;
+            }
+            let entry = match shared.read_entry(&path) {
+                Ok(entry) => entry,
+                // claimed by another pool in the meantime
+                Err(PoolError::Io(err)) if err.kind() == io::ErrorKind::NotFound => continue,
+                Err(err) => return Err(err),
+            };
+            if entry.fits(&shared.config) {
+                entries.push(path);
+            }
+        }
+        entries.sort();
+        shared.lock().entries.extend(entries);
+
+        let workers = (0..shared.config.workers)
+            .map(|_| {
+                let shared = shared.clone();
+                thread::spawn(move || shared.work())
+            })
+            .collect();
+
+        Ok(PrimePool { shared, workers })
+    }
+
+    /// Takes an entry from the pool, returns `None` if the pool is empty
+    pub fn take(&self) -> Result<Option<PrecomputedAux>> {
+        loop {
+            let path = {
+                let mut state = self.shared.lock();
+                let path = state.entries.pop_front();
+                if path.is_some() {
+                    self.shared.need_more.notify_one();
+                }
+                path
+            };
+            let path = match path {
+                Some(path) => path,
+                None => return Ok(None),
+            };
+            if let Some(aux) = self.shared.hand_out(&path)? {
+                return Ok(Some(aux));
+            }
+        }
+    }
+
+    /// Takes an entry from the pool, waits until one is generated if the pool is empty
+    pub fn take_blocking(&self) -> Result<PrecomputedAux> {
+        loop {
+            let path = {
+                let mut state = self.shared.lock();
+                loop {
+                    if let Some(path) = state.entries.pop_front() {
+                        break path;
+                    }
+                    if state.closing {
+                        return Err(PoolError::Closed);
+                    }
+                    state = self
+                        .shared
+                        .available
+                        .wait(state)
+                        .unwrap_or_else(|e| e.into_inner());
+                }
+            };
+            self.shared.need_more.notify_one();
+            if let Some(aux) = self.shared.hand_out(&path)? {
+                return Ok(aux);
+            }
+        }
+    }
+
+    /// Takes an entry from the pool and wraps it into [KeygenConfig]
+    pub fn keygen_config(&self) -> Result<KeygenConfig> {
+        self.take_blocking().map(KeygenConfig::with_precomputed)
+    }
+
+    /// Returns supply status of the pool
+    pub fn status(&self) -> PoolStatus {
+        let state = self.shared.lock();
+        PoolStatus {
+            available: state.entries.len(),
+            generating: state.generating,
+            target: self.shared.config.target,
+            generated: state.generated,
+            handed_out: state.handed_out,
+            error: state.error.clone(),
+        }
+    }
+}
+
+impl Drop for PrimePool {
+    fn drop(&mut self) {
+        self.shared.lock().closing = true;
+        self.shared.need_more.notify_all();
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+impl Shared {
+    fn lock(&self) -> MutexGuard<State> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn work(&self) {
+        loop {
+            {
+                let mut state = self.lock();
+                while !state.closing && state.entries.len() + state.generating >= self.config.target
+                {
+                    state = self
+                        .need_more
+                        .wait(state)
+                        .unwrap_or_else(|e| e.into_inner());
+                }
+                if state.closing {
+                    return;
+                }
+                state.generating += 1;
+            }
+
+            let entry = PoolEntry::generate(&self.config);
+            let written = self.write_entry(&entry);
+
+            let mut state = self.lock();
+            state.generating -= 1;
+            match written {
+                Ok(path) => {
+                    state.entries.push_back(path);
+                    state.generated += 1;
+                    self.available.notify_one();
+                }
+                Err(err) => {
+                    log::error!("prime pool: cannot store generated entry: {}", err);
+                    state.error = Some(err.to_string());
+                    state.closing = true;
+                    self.need_more.notify_all();
+                    self.available.notify_all();
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Claims entry at `path` and removes it from disk, returns `None` if it was already claimed
+    /// by another pool opened on the same directory
+    fn hand_out(&self, path: &Path) -> Result<Option<PrecomputedAux>> {
+        // Rename is atomic, so only one of the pools racing for the entry succeeds
+        let claimed = self.new_path(CLAIMED_EXTENSION);
+        match fs::rename(path, &claimed) {
+            Ok(()) => (),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(PoolError::Io(err)),
+        }
+        let entry = self.read_entry(&claimed)?;
+        fs::remove_file(&claimed).map_err(PoolError::Io)?;
+        self.lock().handed_out += 1;
+        Ok(Some(entry.aux))
+    }
+
+    /// Random file name in pool directory
+    fn new_path(&self, extension: &str) -> PathBuf {
+        let mut id = [0u8; 16];
+        OsRng.fill_bytes(&mut id);
+        self.dir.join(hex::encode(id)).with_extension(extension)
+    }
+
+    fn write_entry(&self, entry: &PoolEntry) -> Result<PathBuf> {
+        let mut plaintext = serde_json::to_vec(entry).map_err(PoolError::Serialize)?;
+        let mut nonce = [0u8; 12];
+        OsRng.fill_bytes(&mut nonce);
+        let ciphertext = self
+            .cipher
+            .encrypt(Nonce::from_slice(&nonce), plaintext.as_slice());
+        plaintext.zeroize();
+        let ciphertext = ciphertext.map_err(|_| PoolError::Encrypt)?;
+        let sealed = serde_json::to_vec(&SealedEntry {
+            nonce: nonce.to_vec(),
+            ciphertext,
+        })
+        .map_err(PoolError::Serialize)?;
+
+        let path = self.new_path(ENTRY_EXTENSION);
+        // Entry becomes visible under its final name only when it's completely written
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, sealed).map_err(PoolError::Io)?;
+        fs::rename(&tmp, &path).map_err(PoolError::Io)?;
+        Ok(path)
+    }
+
+    fn read_entry(&self, path: &Path) -> Result<PoolEntry> {
+        let sealed = fs::read(path).map_err(PoolError::Io)?;
+        let sealed: SealedEntry = serde_json::from_slice(&sealed).map_err(PoolError::Serialize)?;
+        if sealed.nonce.len() != 12 {
+            return Err(PoolError::Decrypt {
+                path: path.to_path_buf(),
+            });
+        }
+        let mut plaintext = self
+            .cipher
+            .decrypt(
+                Nonce::from_slice(&sealed.nonce),
+                sealed.ciphertext.as_slice(),
+            )
+            .map_err(|_| PoolError::Decrypt {
+                path: path.to_path_buf(),
+            })?;
+        let entry = serde_json::from_slice(&plaintext);
+        plaintext.zeroize();
+        entry.map_err(PoolError::Serialize)
+    }
+}
+
+type Result<T> = std::result::Result<T, PoolError>;
+
+/// Error type of [PrimePool]
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum PoolError {
+    /// Pool must have at least one worker, and modulus size must be in range `[2048; 4096]`
+    #[error("invalid pool config")]
+    InvalidConfig,
+    #[error("i/o error: {0}")]
+    Io(#[source] io::Error),
+    #[error("serialization error: {0}")]
+    Serialize(#[source] serde_json::Error),
+    #[error("cannot encrypt entry")]
+    Encrypt,
+    /// Workers were stopped because a generated entry couldn't be stored, see
+    /// [PoolStatus::error]
+    #[error("pool is closed")]
+    Closed,
+    /// Entry is corrupted or encrypted with another key
+    #[error("cannot decrypt entry {path:?}")]
+    Decrypt { path: PathBuf },
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::protocols::multi_party_ecdsa::gg_2020::party_i::AuxInfoProofs;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let mut id = [0u8; 8];
+        OsRng.fill_bytes(&mut id);
+        std::env::temp_dir().join(format!("prime_pool_{}_{}", name, hex::encode(id)))
+    }
+
+    fn config() -> PoolConfig {
+        PoolConfig {
+            target: 2,
+            workers: 2,
+            safe_primes: false,
+            blum_primes: false,
+            modulus_bits: 2048,
+        }
+    }
+
+    #[test]
+    fn entries_are_handed_out_once_and_persisted() {
+        let dir = temp_dir("persist");
+        let key = [42u8; 32];
+
+        let pool = PrimePool::open(&dir, &key, config()).unwrap();
+        let aux = pool.take_blocking().unwrap();
+        assert!(aux.is_valid());
+        while pool.status().available < 2 {
+            std::thread::sleep(std::time::Duration::from_millis(100));
+        }
+        assert_eq!(pool.status().handed_out, 1);
+        drop(pool);
+
+        // Remaining entries survive restart, handed out one is gone
+        let pool = PrimePool::open(
+            &dir,
+            &key,
+            PoolConfig {
+                workers: 1,
+                target: 0,
+                ..config()
+            },
+        )
+        .unwrap();
+        assert_eq!(pool.status().available, 2);
+        let first = pool.take().unwrap().unwrap();
+        let second = pool.take().unwrap().unwrap();
+        assert_ne!(first.ek.n, second.ek.n);
+        assert_ne!(first.ek.n, aux.ek.n);
+        assert!(pool.take().unwrap().is_none());
+        drop(pool);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn pools_sharing_directory_dont_hand_out_same_entry() {
+        let dir = temp_dir("shared");
+        let key = [7u8; 32];
+
+        let pool = PrimePool::open(&dir, &key, config()).unwrap();
+        while pool.status().available < 2 {
+            std::thread::sleep(std::time::Duration::from_millis(100));
+        }
+        drop(pool);
+
+        let no_workers = PoolConfig {
+            workers: 1,
+            target: 0,
+            ..config()
+        };
+        let first = PrimePool::open(&dir, &key, no_workers.clone()).unwrap();
+        let second = PrimePool::open(&dir, &key, no_workers).unwrap();
+        assert_eq!(first.status().available, 2);
+        assert_eq!(second.status().available, 2);
+
+        let a = first.take().unwrap().unwrap();
+        let b = second.take().unwrap().unwrap();
+        assert_ne!(a.ek.n, b.ek.n);
+        // both entries are claimed, stale paths are skipped
+        assert!(first.take().unwrap().is_none());
+        assert!(second.take().unwrap().is_none());
+        assert_eq!(first.status().handed_out + second.status().handed_out, 2);
+        drop((first, second));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn blum_entries_fit_cggmp_keygen() {
+        let dir = temp_dir("blum");
+        let key = [9u8; 32];
+        let blum = PoolConfig {
+            target: 1,
+            workers: 1,
+            blum_primes: true,
+            ..config()
+        };
+
+        let pool = PrimePool::open(&dir, &key, blum.clone()).unwrap();
+        let aux = pool.take_blocking().unwrap();
+        assert!(aux.has_blum_modulus());
+        let keygen_config = KeygenConfig {
+            aux_proofs: AuxInfoProofs::Cggmp,
+            ..KeygenConfig::with_precomputed(aux)
+        };
+        assert!(keygen_config.is_valid());
+        while pool.status().available < 1 {
+            std::thread::sleep(std::time::Duration::from_millis(100));
+        }
+        drop(pool);
+
+        // Blum entry can be handed out to a pool which doesn't need Blum primes, but not
+        // the other way around
+        let no_workers = PoolConfig {
+            target: 0,
+            ..blum
+        };
+        let pool = PrimePool::open(
+            &dir,
+            &key,
+            PoolConfig {
+                blum_primes: false,
+                ..no_workers.clone()
+            },
+        )
+        .unwrap();
+        assert_eq!(pool.status().available, 1);
+        drop(pool);
+        let entry = PoolEntry::generate(&config());
+        assert!(!entry.fits(&no_workers));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn status_reports_error_which_stopped_workers() {
+        let dir = temp_dir("error");
+        let pool = PrimePool::open(
+            &dir,
+            &[3u8; 32],
+            PoolConfig {
+                target: 1,
+                workers: 1,
+                ..config()
+            },
+        )
+        .unwrap();
+        // generated entry can't be written anymore
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert!(matches!(pool.take_blocking(), Err(PoolError::Closed)));
+        let status = pool.status();
+        assert!(status.error.is_some());
+        assert_eq!(status.generated, 0);
+    }
+
+    #[test]
+    fn entries_cannot_be_opened_with_another_key() {
+        let dir = temp_dir("wrong_key");
+        let pool = PrimePool::open(
+            &dir,
+            &[1u8; 32],
+            PoolConfig {
+                target: 1,
+                ..config()
+            },
+        )
+        .unwrap();
+        pool.take_blocking().unwrap();
+        while pool.status().available < 1 {
+            std::thread::sleep(std::time::Duration::from_millis(100));
+        }
+        drop(pool);
+
+        assert!(matches!(
+            PrimePool::open(&dir, &[2u8; 32], config()),
+            Err(PoolError::Decrypt { .. })
+        ));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
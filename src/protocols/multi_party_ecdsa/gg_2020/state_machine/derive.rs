@@ -1,4 +1,9 @@
-//! High-level keygen protocol implementation
+//! High-level HD derivation protocol implementation
+//!
+//! Parties holding [raw shares](raw_share) agree on an [account path](account_path), derive the
+//! child public key locally, and cross-check each other's results. Successfully completed
+//! protocol produces a [LocalKey] of the child key that can be used in further
+//! [signing](super::sign) protocol.
 
 use std::fmt;
 use std::mem::replace;
@@ -11,154 +16,118 @@ use round_based::containers::{
 };
 use round_based::{IsCritical, Msg, StateMachine};
 use serde::{Deserialize, Serialize};
-use sha2::Sha256;
 use thiserror::Error;
 
-use crate::protocols::multi_party_ecdsa::gg_2020::hd_acount::account_manage::{raw_share,account_usage,account_path};
+use crate::protocols::multi_party_ecdsa::gg_2020::hd_acount::account_manage::{
+    account_path, raw_share,
+};
+use crate::protocols::multi_party_ecdsa::gg_2020::hd_acount::btc_hd::{self, HdError};
+use crate::protocols::multi_party_ecdsa::gg_2020::state_machine::keygen::LocalKey;
 
 mod rounds;
 
-use rounds::{Round0};
+use private::InternalError;
+pub use rounds::{DeriveBroadcast, ProceedError};
+use rounds::{Round0, Round1};
 
-/// Keygen protocol state machine
+/// Derive protocol state machine
 ///
-/// Successfully completed keygen protocol produces [LocalKey] that can be used in further
-/// [signing](super::sign) protocol.
+/// Successfully completed derive protocol produces [LocalKey] of the child key at agreed
+/// `account_path`.
 pub struct Derive {
-    round: OfflineR,
+    round: R,
+
+    msgs1: Option<Store<BroadcastMsgs<DeriveBroadcast>>>,
+
+    msgs_queue: Vec<Msg<ProtocolMessage>>,
+
+    party_i: u16,
+    party_n: u16,
 }
 
 impl Derive {
-    /// Construct a party of offline stage of threshold signing protocol
-    ///
-    /// Once offline stage is finished, parties can do one-round threshold signing (i.e. they only
-    /// need to exchange a single set of messages).
+    /// Constructs a party of derive protocol
     ///
-    /// Takes party index `i` (in range `[1; n]`), list `s_l` of parties' indexes from keygen protocol
-    /// (`s_l[i]` must be an index of party `i` that was used by this party in keygen protocol), and
-    /// party local secret share `local_key`.
+    /// Takes party index `i` (in range `[1; n]`), total number of parties `n` taking part in
+    /// derivation, path of the account to derive and party's `raw_share`. All the parties must
+    /// be given the same `ac_path`, otherwise protocol will fail.
     ///
-    /// Returns error if given arguments are contradicting.
-    pub fn new<C>(i: u16, index: u16, account_type: account_usage, raw_share: raw_share<C>) -> Result<Self>
-    where
-    C: curv::elliptic::curves::Curve,{
-        let path = account_path::init(0, index, account_type);
-        Ok(Self {
-            round: OfflineR::R0(Round0 { ac_path:path, raw_share:raw_share }),
-        })
+    /// Returns error if:
+    /// * `n` is less than 2, returns [Error::TooFewParties]
+    /// * `i` is not in range `[1; n]`, returns [Error::InvalidPartyIndex]
+    /// * `ac_path` is not a valid non-hardened derivation path, returns [Error::InvalidPath]
+    pub fn new(
+        i: u16,
+        n: u16,
+        ac_path: account_path,
+        raw_share: raw_share<Secp256k1>,
+    ) -> Result<Self> {
+        if n < 2 {
+            return Err(Error::TooFewParties);
+        }
+        if i == 0 || i > n {
+            return Err(Error::InvalidPartyIndex);
+        }
+        btc_hd::parse_path(&ac_path.get_path_string_bip44()).map_err(Error::InvalidPath)?;
+        let mut state = Self {
+            round: R::Round0(Round0 {
+                party_i: i,
+                ac_path,
+                raw_share,
+            }),
+
+            msgs1: Some(Round1::expects_messages(i, n)),
+
+            msgs_queue: vec![],
+            party_i: i,
+            party_n: n,
+        };
+
+        state.proceed_round(false)?;
+        Ok(state)
     }
 
-    // fn proceed_state(&mut self, may_block: bool) -> Result<()> {
-    //     self.proceed_round(may_block)?;
-    //     self.proceed_decommit_round(may_block)
-    // }
+    fn gmap_queue<'a, T, F>(&'a mut self, mut f: F) -> impl Push<Msg<T>> + 'a
+    where
+        F: FnMut(T) -> M + 'a,
+    {
+        (&mut self.msgs_queue).gmap(move |m: Msg<T>| m.map_body(|m| ProtocolMessage(f(m))))
+    }
 
+    /// Proceeds round state if it received enough messages and if it's cheap to compute or
+    /// `may_block == true`
     fn proceed_round(&mut self, may_block: bool) -> Result<()> {
+        let store1_wants_more = self.msgs1.as_ref().map(|s| s.wants_more()).unwrap_or(false);
 
-        let next_state: OfflineR;
-        let try_again: bool = match replace(&mut self.round, OfflineR::Gone) {
-            OfflineR::R0(round) if !round.is_expensive() || may_block => {
+        let next_state: R;
+        let try_again: bool = match replace(&mut self.round, R::Gone) {
+            R::Round0(round) if !round.is_expensive() || may_block => {
                 next_state = round
-                    .proceed(&mut self.msgs_queue)
-                    .map(OfflineR::R1)
+                    .proceed(self.gmap_queue(M::Round1))
+                    .map(R::Round1)
                     .map_err(Error::ProceedRound)?;
                 true
             }
-            s @ OfflineR::R0(_) => {
+            s @ R::Round0(_) => {
                 next_state = s;
                 false
             }
-            OfflineR::R1(round) if !store1_wants_more && (!round.is_expensive() || may_block) => {
+            R::Round1(round) if !store1_wants_more && (!round.is_expensive() || may_block) => {
                 let store = self.msgs1.take().ok_or(InternalError::StoreGone)?;
                 let msgs = store
                     .finish()
-                    .map_err(InternalError::RetrieveMessagesFromStore)?;
-                next_state = round
-                    .proceed(msgs, &mut self.msgs_queue)
-                    .map(OfflineR::R2)
-                    .map_err(Error::ProceedRound)?;
-                true
-            }
-            s @ OfflineR::R1(_) => {
-                next_state = s;
-                false
-            }
-            OfflineR::R2(round) if !store2_wants_more && (!round.is_expensive() || may_block) => {
-                let store = self.msgs2.take().ok_or(InternalError::StoreGone)?;
-                let msgs = store
-                    .finish()
-                    .map_err(InternalError::RetrieveMessagesFromStore)?;
-                next_state = round
-                    .proceed(msgs, &mut self.msgs_queue)
-                    .map(OfflineR::R3)
-                    .map_err(Error::ProceedRound)?;
-                true
-            }
-            s @ OfflineR::R2(_) => {
-                next_state = s;
-                false
-            }
-            OfflineR::R3(round) if !store3_wants_more && (!round.is_expensive() || may_block) => {
-                let store = self.msgs3.take().ok_or(InternalError::StoreGone)?;
-                let msgs = store
-                    .finish()
-                    .map_err(InternalError::RetrieveMessagesFromStore)?;
-                next_state = round
-                    .proceed(msgs, &mut self.msgs_queue)
-                    .map(OfflineR::R4)
-                    .map_err(Error::ProceedRound)?;
-                true
-            }
-            s @ OfflineR::R3(_) => {
-                next_state = s;
-                false
-            }
-            OfflineR::R4(round) if !store4_wants_more && (!round.is_expensive() || may_block) => {
-                let store = self.msgs4.take().ok_or(InternalError::StoreGone)?;
-                let msgs = store
-                    .finish()
-                    .map_err(InternalError::RetrieveMessagesFromStore)?;
-                next_state = round
-                    .proceed(msgs, &mut self.msgs_queue)
-                    .map(OfflineR::R5)
-                    .map_err(Error::ProceedRound)?;
-                false
-            }
-            s @ OfflineR::R4(_) => {
-                next_state = s;
-                false
-            }
-            OfflineR::R5(round) if !store5_wants_more && (!round.is_expensive() || may_block) => {
-                let store = self.msgs5.take().ok_or(InternalError::StoreGone)?;
-                let msgs = store
-                    .finish()
-                    .map_err(InternalError::RetrieveMessagesFromStore)?;
-                next_state = round
-                    .proceed(msgs, &mut self.msgs_queue)
-                    .map(OfflineR::R6)
-                    .map_err(Error::ProceedRound)?;
-                false
-            }
-            s @ OfflineR::R5(_) => {
-                next_state = s;
-                false
-            }
-            OfflineR::R6(round) if !store6_wants_more && (!round.is_expensive() || may_block) => {
-                let store = self.msgs6.take().ok_or(InternalError::StoreGone)?;
-                let msgs = store
-                    .finish()
-                    .map_err(InternalError::RetrieveMessagesFromStore)?;
+                    .map_err(InternalError::RetrieveRoundMessages)?;
                 next_state = round
                     .proceed(msgs)
-                    .map(OfflineR::Finished)
+                    .map(R::Final)
                     .map_err(Error::ProceedRound)?;
-                false
+                true
             }
-            s @ OfflineR::R6(_) => {
+            s @ R::Round1(_) => {
                 next_state = s;
                 false
             }
-            s @ OfflineR::Finished(_) | s @ OfflineR::Gone => {
+            s @ R::Final(_) | s @ R::Gone => {
                 next_state = s;
                 false
             }
@@ -173,16 +142,16 @@ impl Derive {
     }
 }
 
-impl StateMachine for OfflineStage {
-    type MessageBody = OfflineProtocolMessage;
+impl StateMachine for Derive {
+    type MessageBody = ProtocolMessage;
     type Err = Error;
-    type Output = CompletedOfflineStage;
+    type Output = LocalKey<Secp256k1>;
 
-    fn handle_incoming(&mut self, msg: Msg<Self::MessageBody>) -> Result<(), Self::Err> {
+    fn handle_incoming(&mut self, msg: Msg<Self::MessageBody>) -> Result<()> {
         let current_round = self.current_round();
 
         match msg.body {
-            OfflineProtocolMessage(OfflineM::M1(m)) => {
+            ProtocolMessage(M::Round1(m)) => {
                 let store = self
                     .msgs1
                     .as_mut()
@@ -192,127 +161,32 @@ impl StateMachine for OfflineStage {
                     })?;
                 store
                     .push_msg(Msg {
-                        round:1,
-                        sender: msg.sender,
-                        receiver: msg.receiver,
-                        body: m,
-                    })
-                    .map_err(Error::HandleMessage)?;
-            }
-            OfflineProtocolMessage(OfflineM::M2(m)) => {
-                let store = self
-                    .msgs2
-                    .as_mut()
-                    .ok_or(Error::ReceivedOutOfOrderMessage {
-                        current_round,
-                        msg_round: 2,
-                    })?;
-                store
-                    .push_msg(Msg {
-                        round:2,
-                        sender: msg.sender,
-                        receiver: msg.receiver,
-                        body: m,
-                    })
-                    .map_err(Error::HandleMessage)?;
-            }
-            OfflineProtocolMessage(OfflineM::M3(m)) => {
-                let store = self
-                    .msgs3
-                    .as_mut()
-                    .ok_or(Error::ReceivedOutOfOrderMessage {
-                        current_round,
-                        msg_round: 2,
-                    })?;
-                store
-                    .push_msg(Msg {
-                        round:3,
-                        sender: msg.sender,
-                        receiver: msg.receiver,
-                        body: m,
-                    })
-                    .map_err(Error::HandleMessage)?;
-            }
-            OfflineProtocolMessage(OfflineM::M4(m)) => {
-                let store = self
-                    .msgs4
-                    .as_mut()
-                    .ok_or(Error::ReceivedOutOfOrderMessage {
-                        current_round,
-                        msg_round: 2,
-                    })?;
-                store
-                    .push_msg(Msg {
-                        round:4,
-                        sender: msg.sender,
-                        receiver: msg.receiver,
-                        body: m,
-                    })
-                    .map_err(Error::HandleMessage)?;
-            }
-            OfflineProtocolMessage(OfflineM::M5(m)) => {
-                let store = self
-                    .msgs5
-                    .as_mut()
-                    .ok_or(Error::ReceivedOutOfOrderMessage {
-                        current_round,
-                        msg_round: 2,
-                    })?;
-                store
-                    .push_msg(Msg {
-                        round:5,
-                        sender: msg.sender,
-                        receiver: msg.receiver,
-                        body: m,
-                    })
-                    .map_err(Error::HandleMessage)?;
-            }
-            OfflineProtocolMessage(OfflineM::M6(m)) => {
-                let store = self
-                    .msgs6
-                    .as_mut()
-                    .ok_or(Error::ReceivedOutOfOrderMessage {
-                        current_round,
-                        msg_round: 2,
-                    })?;
-                store
-                    .push_msg(Msg {
-                        round:6,
+                        round: current_round,
                         sender: msg.sender,
                         receiver: msg.receiver,
                         body: m,
                     })
                     .map_err(Error::HandleMessage)?;
+                self.proceed_round(false)
             }
         }
-        self.proceed_round(false)
     }
 
     fn message_queue(&mut self) -> &mut Vec<Msg<Self::MessageBody>> {
-        &mut self.msgs_queue.0
+        &mut self.msgs_queue
     }
 
     fn wants_to_proceed(&self) -> bool {
         let store1_wants_more = self.msgs1.as_ref().map(|s| s.wants_more()).unwrap_or(false);
-        let store2_wants_more = self.msgs2.as_ref().map(|s| s.wants_more()).unwrap_or(false);
-        let store3_wants_more = self.msgs3.as_ref().map(|s| s.wants_more()).unwrap_or(false);
-        let store4_wants_more = self.msgs4.as_ref().map(|s| s.wants_more()).unwrap_or(false);
-        let store5_wants_more = self.msgs5.as_ref().map(|s| s.wants_more()).unwrap_or(false);
-        let store6_wants_more = self.msgs6.as_ref().map(|s| s.wants_more()).unwrap_or(false);
 
         match &self.round {
-            OfflineR::R0(_) => true,
-            OfflineR::R1(_) => !store1_wants_more,
-            OfflineR::R2(_) => !store2_wants_more,
-            OfflineR::R3(_) => !store3_wants_more,
-            OfflineR::R4(_) => !store4_wants_more,
-            OfflineR::R5(_) => !store5_wants_more,
-            OfflineR::R6(_) => !store6_wants_more,
-            OfflineR::Finished(_) | OfflineR::Gone => false,
+            R::Round0(_) => true,
+            R::Round1(_) => !store1_wants_more,
+            R::Final(_) | R::Gone => false,
         }
     }
 
-    fn proceed(&mut self) -> Result<(), Self::Err> {
+    fn proceed(&mut self) -> Result<()> {
         self.proceed_round(true)
     }
 
@@ -325,37 +199,32 @@ impl StateMachine for OfflineStage {
     }
 
     fn is_finished(&self) -> bool {
-        matches!(&self.round, OfflineR::Finished(_))
+        matches!(self.round, R::Final(_))
     }
 
-    fn pick_output(&mut self) -> Option<Result<Self::Output, Self::Err>> {
+    fn pick_output(&mut self) -> Option<Result<Self::Output>> {
         match self.round {
-            OfflineR::Finished(_) => (),
-            OfflineR::Gone => return Some(Err(Error::DoublePickOutput)),
+            R::Final(_) => (),
+            R::Gone => return Some(Err(Error::DoublePickOutput)),
             _ => return None,
         }
 
-        match replace(&mut self.round, OfflineR::Gone) {
-            OfflineR::Finished(result) => Some(Ok(result)),
+        match replace(&mut self.round, R::Gone) {
+            R::Final(result) => Some(Ok(result)),
             _ => unreachable!("guaranteed by match expression above"),
         }
     }
 
     fn current_round(&self) -> u16 {
         match &self.round {
-            OfflineR::R0(_) => 0,
-            OfflineR::R1(_) => 1,
-            OfflineR::R2(_) => 2,
-            OfflineR::R3(_) => 3,
-            OfflineR::R4(_) => 4,
-            OfflineR::R5(_) => 5,
-            OfflineR::R6(_) => 6,
-            OfflineR::Finished(_) | OfflineR::Gone => 7,
+            R::Round0(_) => 0,
+            R::Round1(_) => 1,
+            R::Final(_) | R::Gone => 2,
         }
     }
 
     fn total_rounds(&self) -> Option<u16> {
-        Some(6)
+        Some(1)
     }
 
     fn party_ind(&self) -> u16 {
@@ -367,160 +236,212 @@ impl StateMachine for OfflineStage {
     }
 }
 
-impl super::traits::RoundBlame for OfflineStage {
-    /// RoundBlame returns number of unwilling parties and a vector of their party indexes.
+impl super::traits::RoundBlame for Derive {
+    /// Returns number of unwilling parties and a vector of their party indexes.
     fn round_blame(&self) -> (u16, Vec<u16>) {
         let store1_blame = self.msgs1.as_ref().map(|s| s.blame()).unwrap_or_default();
-        let store2_blame = self.msgs2.as_ref().map(|s| s.blame()).unwrap_or_default();
-        let store3_blame = self.msgs3.as_ref().map(|s| s.blame()).unwrap_or_default();
-        let store4_blame = self.msgs4.as_ref().map(|s| s.blame()).unwrap_or_default();
-        let store5_blame = self.msgs5.as_ref().map(|s| s.blame()).unwrap_or_default();
-        let store6_blame = self.msgs6.as_ref().map(|s| s.blame()).unwrap_or_default();
 
         let default = (0, vec![]);
         match &self.round {
-            OfflineR::R0(_) => default,
-            OfflineR::R1(_) => store1_blame,
-            OfflineR::R2(_) => store2_blame,
-            OfflineR::R3(_) => store3_blame,
-            OfflineR::R4(_) => store4_blame,
-            OfflineR::R5(_) => store5_blame,
-            OfflineR::R6(_) => store6_blame,
-            OfflineR::Finished(_) => store6_blame,
-            OfflineR::Gone => default,
+            R::Round0(_) => default,
+            R::Round1(_) => store1_blame,
+            R::Final(_) | R::Gone => default,
         }
     }
 }
 
-#[allow(clippy::large_enum_variant)]
+impl fmt::Debug for Derive {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let current_round = match &self.round {
+            R::Round0(_) => "0",
+            R::Round1(_) => "1",
+            R::Final(_) => "[Final]",
+            R::Gone => "[Gone]",
+        };
+        let msgs1 = match self.msgs1.as_ref() {
+            Some(msgs) => format!("[{}/{}]", msgs.messages_received(), msgs.messages_total()),
+            None => "[None]".into(),
+        };
+        write!(
+            f,
+            "{{Derive at round={} msgs1={} queue=[len={}]}}",
+            current_round,
+            msgs1,
+            self.msgs_queue.len()
+        )
+    }
+}
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
-pub struct OfflineProtocolMessage(OfflineM);
+// Rounds
 
-impl crate::MessageRoundID for OfflineProtocolMessage {
-    fn round_id(&self) -> u16 {
-        match &self.0 {
-            OfflineM::M1(_) => 1,
-            OfflineM::M2(_) => 2,
-            OfflineM::M3(_) => 3,
-            OfflineM::M4(_) => 4,
-            OfflineM::M5(_) => 5,
-            OfflineM::M6(_) => 6,
-        }
-    }
+enum R {
+    Round0(Round0),
+    Round1(Round1),
+    Final(LocalKey<Secp256k1>),
+    Gone,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
-#[allow(clippy::large_enum_variant)]
-enum OfflineM {
-    M1((MessageA, SignBroadcastPhase1)),
-    M2((GammaI, WI)),
-    M3((DeltaI, TI, TIProof)),
-    M4(SignDecommitPhase1),
-    M5((RDash, Vec<PDLwSlackProof>)),
-    M6((SI, HEGProof)),
+// Messages
+
+/// Protocol message which parties send on wire
+///
+/// Hides actual messages structure so it could be changed without breaking semver policy.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProtocolMessage(M);
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+enum M {
+    Round1(DeriveBroadcast),
 }
 
-struct MsgQueue(Vec<Msg<OfflineProtocolMessage>>);
-
-macro_rules! make_pushable {
-    ($($constructor:ident $t:ty),*$(,)?) => {
-        $(
-        impl Push<Msg<$t>> for MsgQueue {
-            fn push(&mut self, m: Msg<$t>) {
-                Vec::push(&mut self.0, Msg{
-                    round: m.round,
-                    sender: m.sender,
-                    receiver: m.receiver,
-                    body: OfflineProtocolMessage(OfflineM::$constructor(m.body))
-                })
-            }
+impl crate::MessageRoundID for ProtocolMessage {
+    fn round_id(&self) -> u16 {
+        match self.0 {
+            M::Round1(_) => 1,
         }
-        )*
-    };
+    }
 }
 
-type Result<T, E = Error> = std::result::Result<T, E>;
+// Error
+
+type Result<T> = std::result::Result<T, Error>;
 
+/// Error type of derive protocol
 #[derive(Debug, Error)]
+#[non_exhaustive]
 pub enum Error {
+    /// Round proceeding resulted in error
+    #[error("proceed round: {0}")]
+    ProceedRound(#[source] ProceedError),
+
     /// Too few parties (`n < 2`)
-    #[error("at least 2 parties are required for signing")]
+    #[error("at least 2 parties are required for derivation")]
     TooFewParties,
-    /// Too many parties. `n` must fit into `u16`, so only `n < u16::MAX` values are supported.
-    #[error("too many parties: n={n}, n must be less than 2^16")]
-    TooManyParties { n: usize },
     /// Party index `i` is not in range `[1; n]`
     #[error("party index is not in range [1; n]")]
     InvalidPartyIndex,
-    /// List `s_l` is invalid. Either it contains duplicates (`exist i j. i != j && s_l[i] = s_l[j]`),
-    /// or contains index that is not in the range `[1; keygen_n]`, `keygen_n` — number of parties
-    /// participated in DKG (`exist i. s_l[i] = 0 || s_l[i] > keygen_n`).
-    #[error("invalid s_l")]
-    InvalidSl,
-
-    /// Round proceeding resulted in protocol error
-    #[error("proceeding round: {0}")]
-    ProceedRound(rounds::Error),
+    /// Account path can't be derived
+    #[error("invalid account path: {0}")]
+    InvalidPath(#[source] HdError),
 
+    /// Received message didn't pass pre-validation
+    #[error("received message didn't pass pre-validation: {0}")]
+    HandleMessage(#[source] StoreErr),
     /// Received message which we didn't expect to receive now (e.g. message from previous round)
     #[error(
         "didn't expect to receive message from round {msg_round} (being at round {current_round})"
     )]
     ReceivedOutOfOrderMessage { current_round: u16, msg_round: u16 },
-    /// Received message didn't pass pre-validation
-    #[error("received message didn't pass pre-validation: {0}")]
-    HandleMessage(#[source] StoreErr),
-
-    /// [OfflineStage::pick_output] called twice
+    /// [Derive::pick_output] called twice
     #[error("pick_output called twice")]
     DoublePickOutput,
 
-    /// A bug in protocol implementation
-    #[error("offline stage protocol bug: {0}")]
-    Bug(InternalError),
+    /// Some internal assertions were failed, which is a bug
+    #[doc(hidden)]
+    #[error("internal error: {0:?}")]
+    InternalError(InternalError),
 }
 
-#[derive(Debug, Error)]
-pub enum InternalError {
-    #[error("store gone")]
-    StoreGone,
-    #[error("store reported that it's collected all the messages it needed, but refused to give received messages")]
-    RetrieveMessagesFromStore(StoreErr),
-    #[error("decommit round expected to be in NotStarted state")]
-    DecommitRoundWasntInInitialState,
+impl IsCritical for Error {
+    fn is_critical(&self) -> bool {
+        match self {
+            Error::ProceedRound(e) => e.is_critical(),
+            Error::HandleMessage(e) => !matches!(
+                e,
+                StoreErr::MsgOverwrite | StoreErr::NotForMe | StoreErr::WantsMoreMessages
+            ),
+            Error::ReceivedOutOfOrderMessage { .. } => false,
+            Error::DoublePickOutput
+            | Error::TooFewParties
+            | Error::InvalidPartyIndex
+            | Error::InvalidPath(_)
+            | Error::InternalError(_) => true,
+        }
+    }
 }
 
 impl From<InternalError> for Error {
     fn from(err: InternalError) -> Self {
-        Error::Bug(err)
+        Self::InternalError(err)
     }
 }
 
-impl IsCritical for Error {
-    fn is_critical(&self) -> bool {
-        match self {
-            Error::TooFewParties => true,
-            Error::TooManyParties { .. } => true,
-            Error::InvalidPartyIndex => true,
-            Error::InvalidSl => true,
-            Error::ProceedRound(_) => true,
-            Error::ReceivedOutOfOrderMessage { .. } => false,
-            Error::HandleMessage(_) => false,
-            Error::DoublePickOutput => true,
-            Error::Bug(_) => true,
-        }
+mod private {
+    #[derive(Debug)]
+    #[non_exhaustive]
+    pub enum InternalError {
+        /// [Messages store](super::MessageStore) reported that it received all messages it wanted to receive,
+        /// but refused to return message container
+        RetrieveRoundMessages(super::StoreErr),
+        #[doc(hidden)]
+        StoreGone,
     }
 }
 
-enum OfflineR {
-    R0(Round0),
-    Gone,
-}
-#[derive(Debug, Error)]
-pub enum SignError {
-    #[error("signing message locally: {0}")]
-    LocalSigning(rounds::Error),
-    #[error("couldn't complete signing: {0}")]
-    CompleteSigning(rounds::Error),
+#[cfg(test)]
+mod test {
+    use curv::BigInt;
+    use round_based::dev::Simulation;
+
+    use super::*;
+    use crate::protocols::multi_party_ecdsa::gg_2020::hd_acount::account_manage::account_usage;
+    use crate::protocols::multi_party_ecdsa::gg_2020::party_i::verify;
+    use crate::protocols::multi_party_ecdsa::gg_2020::state_machine::keygen::test::simulate_keygen;
+    use crate::protocols::multi_party_ecdsa::gg_2020::state_machine::sign::{
+        OfflineStage, SignManual,
+    };
+    use crate::utilities::transcript::SessionId;
+
+    fn simulate_derive(
+        raw_shares: Vec<raw_share<Secp256k1>>,
+        ac_path: account_path,
+    ) -> Vec<LocalKey<Secp256k1>> {
+        let n = raw_shares.len() as u16;
+        let mut simulation = Simulation::new();
+        for (i, raw_share) in (1..).zip(raw_shares) {
+            simulation.add_party(Derive::new(i, n, ac_path.clone(), raw_share).unwrap());
+        }
+
+        simulation.run().unwrap()
+    }
+
+    #[test]
+    fn simulate_derive_t1_n3() {
+        let raw_shares = simulate_keygen(1, 3);
+        let ac_path = account_path::init(60, 0, account_usage::Receive, 17);
+        let child_keys = simulate_derive(raw_shares.clone(), ac_path.clone());
+
+        let (_, child_pk) = btc_hd::call_hd_key(
+            &ac_path.get_path_string_bip44(),
+            &raw_shares[0].local_key_hd,
+            raw_shares[0].chain_code,
+        )
+        .unwrap();
+        for key in &child_keys {
+            key.validate().unwrap();
+            assert_eq!(key.y_sum_s, child_pk);
+        }
+
+        // derived shares of parties 1 and 3 sign on behalf of the child key
+        let s_l = vec![1, 3];
+        let session_id = SessionId::random();
+        let mut simulation = Simulation::new();
+        for (i, &j) in (1..).zip(&s_l) {
+            let child_key = child_keys[usize::from(j - 1)].clone();
+            simulation.add_party(OfflineStage::new(i, s_l.clone(), child_key, session_id).unwrap());
+        }
+        let completed = simulation.run().unwrap();
+
+        let message = BigInt::from(17);
+        let (signers, partial_sigs): (Vec<_>, Vec<_>) = completed
+            .into_iter()
+            .map(|offline| SignManual::new(message.clone(), offline).unwrap())
+            .unzip();
+        for (i, signer) in signers.into_iter().enumerate() {
+            let mut sigs = partial_sigs.clone();
+            sigs.remove(i);
+            let signature = signer.complete(&sigs).unwrap();
+            assert!(verify(&signature, &child_pk, &message).is_ok());
+        }
+    }
 }
@@ -1,58 +1,121 @@
-#![allow(non_snake_case)]
 use curv::elliptic::curves::{secp256_k1::Secp256k1, Point, Scalar};
+use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
-
 use round_based::containers::push::Push;
-use round_based::Msg;
-
+use round_based::containers::{self, BroadcastMsgs, Store};
+use round_based::{IsCritical, Msg};
 
 use crate::protocols::multi_party_ecdsa::gg_2020 as gg20;
-use gg20::hd_acount::account_manage::{raw_share,account_path};
-use gg20::hd_acount;
+use gg20::hd_acount::account_manage::{account_path, raw_share};
+use gg20::hd_acount::btc_hd::{self, HdError};
+use gg20::state_machine::keygen::LocalKey;
 use gg20::ErrorType;
 
-type Result<T, E = Error> = std::result::Result<T, E>;
-
-
+/// Child public key derived by a party, broadcasted so that parties can cross-check
+/// that they all derived the same account
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct DeriveBroadcast {
+    pub path: String,
+    pub child_pk: Point<Secp256k1>,
+}
 
-pub struct Round0<C> {
-    /// List of parties' indexes from keygen protocol
-    ///
-    /// I.e. `s_l[i]` must be an index of party `i` that was used by this party in keygen protocol.
-    // s_l.len()` equals to `n` (number of parties involved in signing)
+pub struct Round0 {
+    pub party_i: u16,
     pub ac_path: account_path,
-    pub raw_share: raw_share<C>,
+    pub raw_share: raw_share<Secp256k1>,
 }
 
-
-
-
-impl Round0<Secp256k1> {
-    pub fn proceed<O,C>(self) -> Result<(Scalar<C>,Point<C>)>
+impl Round0 {
+    pub fn proceed<O>(self, mut output: O) -> Result<Round1>
     where
-        C: curv::elliptic::curves::Curve,
+        O: Push<Msg<DeriveBroadcast>>,
     {
         let path = self.ac_path.get_path_string_bip44();
+        let (tweak_sk, child_pk) = btc_hd::call_hd_key(
+            path.as_str(),
+            &self.raw_share.local_key_hd,
+            self.raw_share.chain_code,
+        )
+        .map_err(ProceedError::Round0DeriveChildKey)?;
+
+        let bc = DeriveBroadcast {
+            path,
+            child_pk: child_pk.clone(),
+        };
+        output.push(Msg {
+            round: 1,
+            sender: self.party_i,
+            receiver: None,
+            body: bc.clone(),
+        });
+        Ok(Round1 {
+            local_key: self.raw_share.local_key_hd,
+            own_bc: bc,
+            tweak_sk,
+            child_pk,
+        })
+    }
+    pub fn is_expensive(&self) -> bool {
+        false
+    }
+}
 
-        let (tweak_sk, y_sum) = 
-        hd_acount::btc_hd::call_hd_key(
-            path.as_str(), 
-            self.raw_share.local_key_hd, 
-            self.raw_share.chain_code);
-
+pub struct Round1 {
+    local_key: LocalKey<Secp256k1>,
+    own_bc: DeriveBroadcast,
+    tweak_sk: Scalar<Secp256k1>,
+    child_pk: Point<Secp256k1>,
+}
 
-        Ok((tweak_sk,y_sum))
+impl Round1 {
+    pub fn proceed(self, input: BroadcastMsgs<DeriveBroadcast>) -> Result<LocalKey<Secp256k1>> {
+        let received = input.into_vec_including_me(self.own_bc.clone());
+
+        let bad_actors: Vec<usize> = received
+            .iter()
+            .enumerate()
+            .filter(|(_, bc)| bc.path != self.own_bc.path || bc.child_pk != self.child_pk)
+            .map(|(i, _)| i)
+            .collect();
+        if !bad_actors.is_empty() {
+            return Err(ProceedError::Round1ChildKeyMismatch(ErrorType {
+                error_type: "derived child key mismatch".to_string(),
+                bad_actors,
+                data: Vec::new(),
+            }));
+        }
+
+        Ok(self
+            .local_key
+            .update_hd_key(&Scalar::<Secp256k1>::zero(), &self.tweak_sk, &self.child_pk))
     }
-
     pub fn is_expensive(&self) -> bool {
-        true
+        false
+    }
+    pub fn expects_messages(i: u16, n: u16) -> Store<BroadcastMsgs<DeriveBroadcast>> {
+        containers::BroadcastMsgsStore::new(i, n)
     }
-  
 }
 
+// Errors
+
+type Result<T> = std::result::Result<T, ProceedError>;
+
+/// Proceeding protocol error
+///
+/// Subset of [derive errors](enum@super::Error) that can occur at protocol proceeding (i.e. after
+/// every message was received and pre-validated).
 #[derive(Debug, Error)]
-pub enum Error {
-    #[error("round 0: {0:?}")]
-    Round0(ErrorType),
+pub enum ProceedError {
+    #[error("round 0: derive child key: {0}")]
+    Round0DeriveChildKey(#[source] HdError),
+    #[error("round 1: derived child key mismatch: {0:?}")]
+    Round1ChildKeyMismatch(ErrorType),
+}
+
+impl IsCritical for ProceedError {
+    fn is_critical(&self) -> bool {
+        true
+    }
 }
@@ -5,7 +5,7 @@ use std::mem::replace;
 use std::time::Duration;
 
 use curv::cryptographic_primitives::proofs::sigma_dlog::DLogProof;
-use curv::elliptic::curves::secp256_k1::Secp256k1;
+use curv::elliptic::curves::{secp256_k1::Secp256k1, Point};
 use round_based::containers::{
     push::{Push, PushExt},
     *,
@@ -18,22 +18,36 @@ use thiserror::Error;
 use crate::protocols::multi_party_ecdsa::gg_2018::VerifiableSS;
 use crate::no_small_proof::no_small_proof::NoSmallFactorProof;
 use crate::protocols::multi_party_ecdsa::gg_2020;
+use crate::protocols::multi_party_ecdsa::gg_2020::hd_acount::account_manage::raw_share;
+use crate::utilities::transcript::SessionId;
 
+mod abort;
 mod rounds;
+mod storage;
 
+pub use abort::{Culprit, KeygenAbort, KeygenCheck, KeygenEvidence};
 use private::InternalError;
-pub use rounds::{LocalKey, Old_LocalKey, ProceedError};
+pub use rounds::{
+    ChainCodeCommitment, ChainCodeDecommitment, ImportedSecret, InvalidLocalKey, KeyImport,
+    KeygenConfig, LocalKey, LocalKey_HD, Old_LocalKey, ProceedError,
+};
 use rounds::{Round0, Round1, Round2, Round3, Round4};
+pub use storage::{LocalKeyStorageError, LOCAL_KEY_VERSION};
 
 /// Keygen protocol state machine
 ///
-/// Successfully completed keygen protocol produces [LocalKey] that can be used in further
-/// [signing](super::sign) protocol.
+/// Successfully completed keygen protocol produces [raw_share] which holds [LocalKey] that can
+/// be used in further [signing](super::sign) protocol, and a chain code jointly generated by
+/// all the parties which is used in HD derivation.
 pub struct Keygen {
     round: R,
 
-    msgs1: Option<Store<BroadcastMsgs<gg_2020::party_i::KeyGenBroadcastMessage1>>>,
-    msgs2: Option<Store<BroadcastMsgs<gg_2020::party_i::KeyGenDecommitMessage1>>>,
+    msgs1: Option<
+        Store<BroadcastMsgs<(gg_2020::party_i::KeyGenBroadcastMessage1, ChainCodeCommitment)>>,
+    >,
+    msgs2: Option<
+        Store<BroadcastMsgs<(gg_2020::party_i::KeyGenDecommitMessage1, ChainCodeDecommitment)>>,
+    >,
     msgs3: Option<Store<P2PMsgs<(VerifiableSS<Secp256k1>, Vec<u8>,NoSmallFactorProof)>>>,
     msgs4: Option<Store<BroadcastMsgs<DLogProof<Secp256k1, Sha256>>>>,
 
@@ -48,13 +62,85 @@ impl Keygen {
     ///
     /// Takes party index `i` (in range `[1; n]`), threshold value `t`, and total number of
     /// parties `n`. Party index identifies this party in the protocol, so it must be guaranteed
-    /// to be unique.
+    /// to be unique. All the parties must be given the same `session_id` which is unique for
+    /// this keygen, it's bound into every zero-knowledge proof sent by the parties.
     ///
     /// Returns error if:
     /// * `n` is less than 2, returns [Error::TooFewParties]
     /// * `t` is not in range `[1; n-1]`, returns [Error::InvalidThreshold]
     /// * `i` is not in range `[1; n]`, returns [Error::InvalidPartyIndex]
-    pub fn new(i: u16, t: u16, n: u16) -> Result<Self> {
+    pub fn new(i: u16, t: u16, n: u16, session_id: SessionId) -> Result<Self> {
+        Self::new_with_config(i, t, n, KeygenConfig::default(), session_id)
+    }
+
+    /// Constructs a party of keygen protocol with given Paillier and `N_tilde` parameters
+    ///
+    /// `config` may carry pre-generated Paillier key and `h1, h2, N_tilde`, so that keygen
+    /// doesn't spend time on prime generation.
+    ///
+    /// Returns the same errors as [Keygen::new], and [Error::InvalidConfig] if `config` is
+    /// not valid.
+    pub fn new_with_config(
+        i: u16,
+        t: u16,
+        n: u16,
+        config: KeygenConfig,
+        session_id: SessionId,
+    ) -> Result<Self> {
+        Self::new_with_round0(Round0 {
+            party_i: i,
+            t,
+            n,
+            import: None,
+            secret: None,
+            config,
+            session_id,
+        })
+    }
+
+    /// Constructs a party of keygen protocol which imports an existing key
+    ///
+    /// Party `import.importer` must be given the imported `secret`, the other parties contribute
+    /// zero and must be given `None`. Resulting [LocalKey]s share the imported key, i.e. their
+    /// `y_sum_s` equals `import.public_key`. If `import.with_chain_code` is set, resulting
+    /// [raw_share]s keep importer's chain code (e.g. of imported xprv), otherwise chain code
+    /// is generated jointly as usual.
+    ///
+    /// Returns the same errors as [Keygen::new_with_config], and [Error::InvalidImport] if
+    /// `import` doesn't match `secret` or importer's index is not in range `[1; n]`.
+    pub fn new_import(
+        i: u16,
+        t: u16,
+        n: u16,
+        import: KeyImport,
+        secret: Option<ImportedSecret>,
+        config: KeygenConfig,
+        session_id: SessionId,
+    ) -> Result<Self> {
+        if import.importer == 0 || import.importer > n {
+            return Err(Error::InvalidImport);
+        }
+        match &secret {
+            Some(secret)
+                if i == import.importer
+                    && Point::generator() * secret.secret.expose() == import.public_key
+                    && secret.chain_code.is_some() == import.with_chain_code => {}
+            None if i != import.importer => {}
+            _ => return Err(Error::InvalidImport),
+        }
+        Self::new_with_round0(Round0 {
+            party_i: i,
+            t,
+            n,
+            import: Some(import),
+            secret,
+            config,
+            session_id,
+        })
+    }
+
+    fn new_with_round0(round0: Round0) -> Result<Self> {
+        let (i, t, n) = (round0.party_i, round0.t, round0.n);
         if n < 2 {
             return Err(Error::TooFewParties);
         }
@@ -64,8 +150,11 @@ impl Keygen {
         if i == 0 || i > n {
             return Err(Error::InvalidPartyIndex);
         }
+        if !round0.config.is_valid() {
+            return Err(Error::InvalidConfig);
+        }
         let mut state = Self {
-            round: R::Round0(Round0 { party_i: i, t, n }),
+            round: R::Round0(round0),
 
             msgs1: Some(Round1::expects_messages(i, n)),
             msgs2: Some(Round2::expects_messages(i, n)),
@@ -102,7 +191,7 @@ impl Keygen {
                 next_state = round
                     .proceed(self.gmap_queue(M::Round1))
                     .map(R::Round1)
-                    .map_err(Error::ProceedRound)?;
+                    .map_err(Error::from)?;
                 true
             }
             s @ R::Round0(_) => {
@@ -117,7 +206,7 @@ impl Keygen {
                 next_state = round
                     .proceed(msgs, self.gmap_queue(M::Round2))
                     .map(R::Round2)
-                    .map_err(Error::ProceedRound)?;
+                    .map_err(Error::from)?;
                 true
             }
             s @ R::Round1(_) => {
@@ -132,7 +221,7 @@ impl Keygen {
                 next_state = round
                     .proceed(msgs, self.gmap_queue(M::Round3))
                     .map(R::Round3)
-                    .map_err(Error::ProceedRound)?;
+                    .map_err(Error::from)?;
                 true
             }
             s @ R::Round2(_) => {
@@ -147,7 +236,7 @@ impl Keygen {
                 next_state = round
                     .proceed(msgs, self.gmap_queue(M::Round4))
                     .map(R::Round4)
-                    .map_err(Error::ProceedRound)?;
+                    .map_err(Error::from)?;
                 true
             }
             s @ R::Round3(_) => {
@@ -162,7 +251,7 @@ impl Keygen {
                 next_state = round
                     .proceed(msgs)
                     .map(R::Final)
-                    .map_err(Error::ProceedRound)?;
+                    .map_err(Error::from)?;
                 true
             }
             s @ R::Round4(_) => {
@@ -187,7 +276,7 @@ impl Keygen {
 impl StateMachine for Keygen {
     type MessageBody = ProtocolMessage;
     type Err = Error;
-    type Output = LocalKey<Secp256k1>;
+    type Output = raw_share<Secp256k1>;
 
     fn handle_incoming(&mut self, msg: Msg<Self::MessageBody>) -> Result<()> {
         let current_round = self.current_round();
@@ -409,7 +498,7 @@ enum R {
     Round2(Round2),
     Round3(Round3),
     Round4(Round4),
-    Final(LocalKey<Secp256k1>),
+    Final(raw_share<Secp256k1>),
     Gone,
 }
 
@@ -423,8 +512,8 @@ pub struct ProtocolMessage(M);
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 enum M {
-    Round1(gg_2020::party_i::KeyGenBroadcastMessage1),
-    Round2(gg_2020::party_i::KeyGenDecommitMessage1),
+    Round1((gg_2020::party_i::KeyGenBroadcastMessage1, ChainCodeCommitment)),
+    Round2((gg_2020::party_i::KeyGenDecommitMessage1, ChainCodeDecommitment)),
     Round3((VerifiableSS<Secp256k1>, Vec<u8>,NoSmallFactorProof)),
     Round4(DLogProof<Secp256k1, Sha256>),
 }
@@ -451,6 +540,10 @@ pub enum Error {
     /// Round proceeding resulted in error
     #[error("proceed round: {0}")]
     ProceedRound(#[source] ProceedError),
+    /// Some parties misbehaved, [KeygenAbort] names them and carries evidence which can be
+    /// checked by anyone via [KeygenAbort::verify]
+    #[error("keygen aborted: {0}")]
+    Aborted(KeygenAbort),
 
     /// Too few parties (`n < 2`)
     #[error("at least 2 parties are required for keygen")]
@@ -461,6 +554,13 @@ pub enum Error {
     /// Party index `i` is not in range `[1; n]`
     #[error("party index is not in range [1; n]")]
     InvalidPartyIndex,
+    /// Imported secret doesn't match [KeyImport], see [Keygen::new_import]
+    #[error("imported secret doesn't match import parameters")]
+    InvalidImport,
+    /// Paillier key size is not in range `[2048; 4096]` or precomputed Paillier key doesn't
+    /// match, see [KeygenConfig]
+    #[error("invalid keygen config")]
+    InvalidConfig,
 
     /// Received message didn't pass pre-validation
     #[error("received message didn't pass pre-validation: {0}")]
@@ -492,14 +592,26 @@ impl IsCritical for Error {
             ),
             Error::ReceivedOutOfOrderMessage { .. } => false,
             Error::DoublePickOutput
+            | Error::Aborted(_)
             | Error::TooFewParties
             | Error::InvalidThreshold
             | Error::InvalidPartyIndex
+            | Error::InvalidImport
+            | Error::InvalidConfig
             | Error::InternalError(_) => true,
         }
     }
 }
 
+impl From<ProceedError> for Error {
+    fn from(err: ProceedError) -> Self {
+        match err {
+            ProceedError::Abort(abort) => Self::Aborted(abort),
+            err => Self::ProceedRound(err),
+        }
+    }
+}
+
 impl From<InternalError> for Error {
     fn from(err: InternalError) -> Self {
         Self::InternalError(err)
@@ -520,19 +632,32 @@ mod private {
 
 #[cfg(test)]
 pub mod test {
+    use curv::arithmetic::Converter;
+    use curv::elliptic::curves::Scalar;
+    use curv::BigInt;
+    use paillier::{Encrypt, Paillier, RawPlaintext};
     use round_based::dev::Simulation;
 
     use super::*;
+    use crate::protocols::multi_party_ecdsa::gg_2020::party_i::{
+        AuxInfoProofs, Keys, PrecomputedAux,
+    };
+    use crate::protocols::multi_party_ecdsa::gg_2020::state_machine::test::run_with_tamper;
+    use crate::protocols::multi_party_ecdsa::gg_2020::KEYGEN_LABEL;
 
-    pub fn simulate_keygen(t: u16, n: u16) -> Vec<LocalKey<Secp256k1>> {
+    pub fn simulate_keygen(t: u16, n: u16) -> Vec<raw_share<Secp256k1>> {
         let mut simulation = Simulation::new();
         simulation.enable_benchmarks(true);
 
+        let session_id = SessionId::random();
         for i in 1..=n {
-            simulation.add_party(Keygen::new(i, t, n).unwrap());
+            simulation.add_party(Keygen::new(i, t, n, session_id).unwrap());
         }
 
         let keys = simulation.run().unwrap();
+        for key in &keys {
+            key.local_key_hd.validate().unwrap();
+        }
 
         println!("Benchmark results:");
         println!("{:#?}", simulation.benchmark_results().unwrap());
@@ -545,6 +670,272 @@ pub mod test {
         simulate_keygen(1, 2);
     }
 
+    #[test]
+    fn simulate_keygen_agrees_on_chain_code() {
+        let shares = simulate_keygen(1, 3);
+        assert!(shares
+            .iter()
+            .all(|s| s.chain_code == shares[0].chain_code));
+        assert_ne!(shares[0].chain_code, [0u8; 32]);
+    }
+
+    #[test]
+    fn simulate_keygen_importing_private_key() {
+        let secret = ImportedSecret {
+            chain_code: Some([7u8; 32]),
+            ..ImportedSecret::from_private_key(Scalar::random())
+        };
+        let import = secret.key_import(2);
+
+        let session_id = SessionId::random();
+        let mut simulation = Simulation::new();
+        for i in 1..=3 {
+            let secret = if i == import.importer {
+                Some(secret.clone())
+            } else {
+                None
+            };
+            simulation.add_party(Keygen::new_import(
+                i,
+                1,
+                3,
+                import.clone(),
+                secret,
+                KeygenConfig::default(),
+                session_id,
+            )
+            .unwrap());
+        }
+        let shares = simulation.run().unwrap();
+
+        for share in &shares {
+            share.local_key_hd.validate().unwrap();
+            assert_eq!(share.local_key_hd.y_sum_s, import.public_key);
+            assert_eq!(Some(share.chain_code), secret.chain_code);
+        }
+    }
+
+    #[test]
+    fn simulate_keygen_with_precomputed_aux() {
+        let precomputed: Vec<_> = (0..3)
+            .map(|_| PrecomputedAux::generate(false, 2048))
+            .collect();
+
+        let session_id = SessionId::random();
+        let mut simulation = Simulation::new();
+        for (i, aux) in (1..=3).zip(&precomputed) {
+            let config = KeygenConfig::with_precomputed(aux.clone());
+            simulation.add_party(Keygen::new_with_config(i, 1, 3, config, session_id).unwrap());
+        }
+        let shares = simulation.run().unwrap();
+
+        for (share, aux) in shares.iter().zip(&precomputed) {
+            share.local_key_hd.validate().unwrap();
+            assert_eq!(share.local_key_hd.paillier_dk.expose().p, aux.dk.expose().p);
+            assert_eq!(share.local_key_hd.h1_h2_n_tilde_vec[0].N, precomputed[0].N_tilde);
+        }
+    }
+
+    #[test]
+    fn simulate_keygen_with_cggmp_aux_proofs() {
+        let session_id = SessionId::random();
+        let mut simulation = Simulation::new();
+        for i in 1..=3 {
+            let config = KeygenConfig {
+                aux_proofs: AuxInfoProofs::Both,
+                ..KeygenConfig::with_precomputed(PrecomputedAux::generate_blum(2048))
+            };
+            simulation.add_party(Keygen::new_with_config(i, 1, 3, config, session_id).unwrap());
+        }
+        let shares = simulation.run().unwrap();
+
+        for share in &shares {
+            share.local_key_hd.validate().unwrap();
+        }
+    }
+
+    #[test]
+    fn keygen_fails_if_aux_proofs_are_missing() {
+        let session_id = SessionId::random();
+        let mut simulation = Simulation::new();
+        for i in 1..=3 {
+            // party 3 sends only legacy proofs while the others require CGGMP ones
+            let aux_proofs = if i == 3 {
+                AuxInfoProofs::Legacy
+            } else {
+                AuxInfoProofs::Cggmp
+            };
+            let config = KeygenConfig {
+                aux_proofs,
+                ..KeygenConfig::with_precomputed(PrecomputedAux::generate_blum(2048))
+            };
+            simulation.add_party(Keygen::new_with_config(i, 1, 3, config, session_id).unwrap());
+        }
+        assert!(simulation.run().is_err());
+    }
+
+    #[test]
+    fn keygen_aborts_with_verifiable_evidence_of_bad_share() {
+        let session_id = SessionId::random();
+        let parties: Vec<_> = (1..=3)
+            .map(|i| Keygen::new(i, 1, 3, session_id).unwrap())
+            .collect();
+
+        // party 3 sends party 1 encryption of a share which doesn't match its VSS scheme
+        let mut ek_1 = None;
+        let results = run_with_tamper(parties, |msg| {
+            let (sender, receiver) = (msg.sender, msg.receiver);
+            match &mut msg.body.0 {
+                M::Round1((bc1, _)) if sender == 1 => ek_1 = Some(bc1.e.clone()),
+                M::Round3((_, encrypted_share, _)) if sender == 3 && receiver == Some(1) => {
+                    let ek = ek_1.as_ref().unwrap();
+                    let c = Paillier::encrypt(ek, RawPlaintext::from(BigInt::from(42)));
+                    *encrypted_share = c.0.to_bytes();
+                }
+                _ => (),
+            }
+        });
+
+        let abort = match &results[0] {
+            Some(Err(Error::Aborted(abort))) => abort.clone(),
+            _ => panic!("party 1 must abort"),
+        };
+        assert_eq!(abort.accuser, 1);
+        assert_eq!(abort.culprit_indexes(), vec![3]);
+        assert_eq!(abort.culprits[0].check(), KeygenCheck::Vss);
+        assert!(abort.verify());
+
+        // evidence is checked by a third party after being sent over the wire
+        let abort: KeygenAbort =
+            serde_json::from_slice(&serde_json::to_vec(&abort).unwrap()).unwrap();
+        assert!(abort.verify());
+
+        // share and randomness recovered by the accuser must open the ciphertext
+        let tamper_opening = |f: &dyn Fn(&mut BigInt, &mut BigInt)| {
+            let mut tampered = abort.clone();
+            match &mut tampered.culprits[0].evidence {
+                KeygenEvidence::Vss {
+                    share, randomness, ..
+                } => f(share, randomness),
+                _ => unreachable!(),
+            }
+            tampered.verify()
+        };
+        assert!(!tamper_opening(&|share, _| *share = &*share + BigInt::from(1)));
+        assert!(!tamper_opening(&|_, randomness| *randomness = BigInt::from(1)));
+
+        // the same opening doesn't prove anything if VSS scheme evaluates to 42 at point 1
+        let mut honest = abort;
+        match &mut honest.culprits[0].evidence {
+            KeygenEvidence::Vss { y_i, vss, .. } => {
+                let g_42 = Point::generator() * Scalar::from_bigint(&BigInt::from(42));
+                vss.commitments = vec![y_i.clone(), g_42 - &*y_i];
+            }
+            _ => unreachable!(),
+        }
+        assert!(!honest.verify());
+    }
+
+    #[test]
+    fn keygen_abort_evidence_is_verifiable() {
+        let session_id = SessionId::random();
+        let keys = Keys::create(2);
+        let (bc1, decom1) = keys
+            .phase1_broadcast_phase3_proof_of_correct_key_proof_of_correct_h1h2(
+                AuxInfoProofs::Legacy,
+                &session_id.context(2, KEYGEN_LABEL),
+            )
+            .unwrap();
+        let abort = |evidence| KeygenAbort {
+            session_id,
+            accuser: 1,
+            culprits: vec![Culprit { party: 2, evidence }],
+        };
+
+        // honest messages don't prove anything
+        let honest = abort(KeygenEvidence::Commitment {
+            bc1: bc1.clone(),
+            decom1: decom1.clone(),
+        });
+        assert!(!honest.verify());
+        let honest = abort(KeygenEvidence::PaillierKey {
+            bc1: bc1.clone(),
+            aux_proofs: AuxInfoProofs::Legacy,
+        });
+        assert!(!honest.verify());
+
+        let mut bad_decom1 = decom1;
+        bad_decom1.blind_factor = &bad_decom1.blind_factor + BigInt::from(1);
+        let bad_commitment = abort(KeygenEvidence::Commitment {
+            bc1: bc1.clone(),
+            decom1: bad_decom1,
+        });
+        assert!(bad_commitment.verify());
+        assert_eq!(bad_commitment.culprit_indexes(), vec![2]);
+        assert_eq!(bad_commitment.culprits[0].check(), KeygenCheck::Commitment);
+
+        let mut bad_bc1 = bc1.clone();
+        bad_bc1.correct_key_proof = None;
+        assert!(abort(KeygenEvidence::PaillierKey {
+            bc1: bad_bc1,
+            aux_proofs: AuxInfoProofs::Legacy,
+        })
+        .verify());
+
+        assert!(!abort(KeygenEvidence::NTilde {
+            bc1,
+            aux_proofs: AuxInfoProofs::Legacy,
+        })
+        .verify());
+
+        // valid proof of knowledge doesn't prove anything, mismatch with VSS schemes received by
+        // the accuser is not accepted as they could be forged by the accuser
+        let proof = DLogProof::<Secp256k1, Sha256>::prove(&Scalar::random());
+        assert!(!abort(KeygenEvidence::DLogProof {
+            proof: proof.clone()
+        })
+        .verify());
+        let (forged_vss, _) = VerifiableSS::share(1, 2, &Scalar::random());
+        let forged = abort(KeygenEvidence::PublicKeyMismatch {
+            proof: proof.clone(),
+            vss_vec: vec![forged_vss.clone(), forged_vss],
+        });
+        assert!(forged.culprits[0].verify(&session_id, 1));
+        assert!(!forged.verify());
+
+        let mut bad_proof = proof;
+        let one = Scalar::from_bigint(&BigInt::from(1));
+        bad_proof.challenge_response = &bad_proof.challenge_response + one;
+        assert!(abort(KeygenEvidence::DLogProof { proof: bad_proof }).verify());
+
+        // abort without culprits proves nothing
+        let mut empty = bad_commitment;
+        empty.culprits.clear();
+        assert!(!empty.verify());
+    }
+
+    #[test]
+    fn rejects_invalid_keygen_config() {
+        let config = KeygenConfig {
+            modulus_bits: 1024,
+            ..Default::default()
+        };
+        assert!(matches!(
+            Keygen::new_with_config(1, 1, 3, config, SessionId::random()),
+            Err(Error::InvalidConfig)
+        ));
+    }
+
+    #[test]
+    fn keygen_fails_if_session_ids_differ() {
+        let session_id = SessionId::random();
+        let mut simulation = Simulation::new();
+        simulation.add_party(Keygen::new(1, 1, 3, session_id).unwrap());
+        simulation.add_party(Keygen::new(2, 1, 3, session_id).unwrap());
+        simulation.add_party(Keygen::new(3, 1, 3, SessionId::random()).unwrap());
+        assert!(simulation.run().is_err());
+    }
+
     #[test]
     fn simulate_keygen_t1_n3() {
         simulate_keygen(1, 3);
@@ -0,0 +1,293 @@
+//! Identifiable abort of keygen
+//!
+//! When a party detects misbehaviour it aborts with [KeygenAbort] carrying the offending messages
+//! of each culprit along with public data needed to check them. Anyone knowing the session id can
+//! re-verify the evidence with [KeygenAbort::verify] offline, without the secrets of the accuser.
+//! Evidence only proves that the messages fail the check: the verifier must still make sure that
+//! the messages were indeed sent by the culprits, e.g. by checking signatures of the transport.
+//! Evidence of every check but [KeygenCheck::PublicKeyMismatch] consists of the culprit's own
+//! messages and public data broadcasted in keygen.
+
+use std::fmt;
+
+use curv::arithmetic::traits::*;
+use curv::cryptographic_primitives::proofs::sigma_dlog::DLogProof;
+use curv::elliptic::curves::{secp256_k1::Secp256k1, Point, Scalar};
+use curv::BigInt;
+use paillier::{
+    Decrypt, DecryptionKey, EncryptWithChosenRandomness, EncryptionKey, Paillier, Randomness,
+    RawCiphertext, RawPlaintext,
+};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use zk_paillier::zkproofs::DLogStatement;
+
+use super::rounds::{ChainCodeCommitment, ChainCodeDecommitment, KeyImport};
+use crate::no_small_proof::no_small_proof::{
+    NoSmallFactorProof, NoSmallFactorSetUp, NoSmallFactorStatement,
+};
+use crate::protocols::multi_party_ecdsa::gg_2018::VerifiableSS;
+use crate::protocols::multi_party_ecdsa::gg_2020::party_i::{
+    AuxInfoProofs, KeyGenBroadcastMessage1, KeyGenDecommitMessage1, Keys,
+};
+use crate::protocols::multi_party_ecdsa::gg_2020::KEYGEN_LABEL;
+use crate::utilities::transcript::SessionId;
+
+/// Keygen aborted because some parties misbehaved
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct KeygenAbort {
+    /// Session id of the aborted keygen
+    pub session_id: SessionId,
+    /// Index of the party which detected misbehaviour, in range `[1; n]`
+    pub accuser: u16,
+    /// Misbehaving parties, each one with its offending message
+    pub culprits: Vec<Culprit>,
+}
+
+/// Party which failed a check along with the evidence
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Culprit {
+    /// Index of the party, in range `[1; n]`
+    pub party: u16,
+    pub evidence: KeygenEvidence,
+}
+
+/// Check of keygen which a party failed
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[non_exhaustive]
+pub enum KeygenCheck {
+    /// Decommitment of `y_i` doesn't match the commitment
+    Commitment,
+    /// Decommitment of chain code contribution doesn't match the commitment
+    ChainCodeCommitment,
+    /// Contribution to the imported key doesn't match [KeyImport]
+    ImportContribution,
+    /// Paillier key is malformed or its proofs are not valid
+    PaillierKey,
+    /// `h1, h2, N_tilde` are malformed or their proofs are not valid
+    NTilde,
+    /// Secret share sent to the accuser doesn't match VSS commitments
+    Vss,
+    /// Proof that Paillier modulus has no small factors is not valid
+    NoSmallFactorProof,
+    /// Proof of knowledge of `x_i` is not valid
+    DLogProof,
+    /// Public key in the proof of knowledge of `x_i` doesn't match VSS commitments
+    PublicKeyMismatch,
+}
+
+/// Offending message of a culprit along with public data needed to check it
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[non_exhaustive]
+pub enum KeygenEvidence {
+    /// Round 1 and round 2 messages of the culprit
+    Commitment {
+        bc1: KeyGenBroadcastMessage1,
+        decom1: KeyGenDecommitMessage1,
+    },
+    /// Chain code commitment and decommitment of the culprit
+    ChainCodeCommitment {
+        com: ChainCodeCommitment,
+        decom: ChainCodeDecommitment,
+    },
+    /// Import parameters and round 2 message of the culprit
+    ImportContribution {
+        import: KeyImport,
+        decom1: KeyGenDecommitMessage1,
+    },
+    /// Round 1 message of the culprit and the proofs required in keygen
+    PaillierKey {
+        bc1: KeyGenBroadcastMessage1,
+        aux_proofs: AuxInfoProofs,
+    },
+    /// Round 1 message of the culprit and the proofs required in keygen
+    NTilde {
+        bc1: KeyGenBroadcastMessage1,
+        aux_proofs: AuxInfoProofs,
+    },
+    /// Secret share sent by the culprit, opened by the accuser
+    ///
+    /// `y_i` is decommitted by the culprit in round 2, `accuser_ek` is broadcasted by the accuser
+    /// in round 1. `share` and `randomness` open `encrypted_share` under `accuser_ek`.
+    Vss {
+        y_i: Point<Secp256k1>,
+        vss: VerifiableSS<Secp256k1>,
+        accuser_ek: EncryptionKey,
+        encrypted_share: BigInt,
+        share: BigInt,
+        randomness: BigInt,
+    },
+    /// Proof sent by the culprit, `n0` is culprit's Paillier modulus and `accuser_dlog_statement`
+    /// is `h1, h2, N_tilde` of the accuser, both broadcasted in round 1
+    NoSmallFactorProof {
+        n0: BigInt,
+        accuser_dlog_statement: DLogStatement,
+        proof: NoSmallFactorProof,
+    },
+    /// Proof broadcasted by the culprit in round 4
+    DLogProof { proof: DLogProof<Secp256k1, Sha256> },
+    /// Valid proof broadcasted by the culprit in round 4 and VSS schemes received by the accuser
+    ///
+    /// Unlike the other evidence, it relies on messages of other parties: VSS schemes are sent
+    /// point-to-point in round 3, so the accuser could put any scheme as its own or claim that
+    /// another party sent it a different one. Verifier must check every entry `vss_vec[k]`
+    /// against transport signature of party `k + 1`, and the accuser's own entry against the
+    /// round 3 message the accuser sent to the culprit. Otherwise this evidence doesn't prove
+    /// anything, which is why [KeygenAbort::verify] doesn't accept it.
+    PublicKeyMismatch {
+        proof: DLogProof<Secp256k1, Sha256>,
+        vss_vec: Vec<VerifiableSS<Secp256k1>>,
+    },
+}
+
+impl KeygenAbort {
+    /// Indexes of the misbehaving parties
+    pub fn culprit_indexes(&self) -> Vec<u16> {
+        self.culprits.iter().map(|culprit| culprit.party).collect()
+    }
+
+    /// Re-verifies the evidence, returns `true` if every culprit indeed failed its check
+    ///
+    /// Evidence of [KeygenCheck::PublicKeyMismatch] is not self-contained, so abort carrying it
+    /// is never accepted, see [KeygenEvidence::PublicKeyMismatch] for how to check it.
+    pub fn verify(&self) -> bool {
+        !self.culprits.is_empty()
+            && self.culprits.iter().all(|culprit| {
+                culprit.check() != KeygenCheck::PublicKeyMismatch
+                    && culprit.verify(&self.session_id, self.accuser)
+            })
+    }
+}
+
+impl fmt::Display for KeygenAbort {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "party {} blames", self.accuser)?;
+        for (i, culprit) in self.culprits.iter().enumerate() {
+            let sep = if i == 0 { " " } else { ", " };
+            write!(f, "{}party {} ({:?})", sep, culprit.party, culprit.check())?;
+        }
+        Ok(())
+    }
+}
+
+impl Culprit {
+    pub fn check(&self) -> KeygenCheck {
+        self.evidence.check()
+    }
+
+    /// Checks that the evidence shows misbehaviour of the culprit
+    ///
+    /// For [KeygenEvidence::PublicKeyMismatch] result is only meaningful if `vss_vec` is
+    /// authenticated as described there.
+    pub fn verify(&self, session_id: &SessionId, accuser: u16) -> bool {
+        let party = self.party;
+        let prover_ctx = session_id.context(party, KEYGEN_LABEL);
+        match &self.evidence {
+            KeygenEvidence::Commitment { bc1, decom1 } => !bc1.verify_decommitment(decom1),
+            KeygenEvidence::ChainCodeCommitment { com, decom } => decom.commit().com != com.com,
+            KeygenEvidence::ImportContribution { import, decom1 } => {
+                if party == import.importer {
+                    decom1.y_i != import.public_key
+                } else {
+                    !decom1.y_i.is_zero()
+                }
+            }
+            KeygenEvidence::PaillierKey { bc1, aux_proofs } => {
+                !bc1.verify_paillier_key(*aux_proofs, &prover_ctx)
+            }
+            KeygenEvidence::NTilde { bc1, aux_proofs } => {
+                !bc1.verify_n_tilde(*aux_proofs, &prover_ctx)
+            }
+            KeygenEvidence::Vss {
+                y_i,
+                vss,
+                accuser_ek,
+                encrypted_share,
+                share,
+                randomness,
+            } => {
+                let in_range = |v: &BigInt| v >= &BigInt::zero() && v < &accuser_ek.n;
+                if !in_range(share) || !in_range(randomness) {
+                    return false;
+                }
+                let opened = Paillier::encrypt_with_chosen_randomness(
+                    accuser_ek,
+                    RawPlaintext::from(share.clone()),
+                    &Randomness::from(randomness.clone()),
+                );
+                let share = Scalar::<Secp256k1>::from_bigint(share);
+                *opened.0 == *encrypted_share
+                    && (vss.validate_share(&share, accuser).is_err() || &vss.commitments[0] != y_i)
+            }
+            KeygenEvidence::NoSmallFactorProof {
+                n0,
+                accuser_dlog_statement,
+                proof,
+            } => {
+                let setup = NoSmallFactorSetUp {
+                    n_tilde_: accuser_dlog_statement.N.clone(),
+                    s_: accuser_dlog_statement.g.clone(),
+                    t_: accuser_dlog_statement.ni.clone(),
+                };
+                let statement = NoSmallFactorStatement::new(n0.clone());
+                !proof.verify(&setup, &statement, &prover_ctx)
+            }
+            KeygenEvidence::DLogProof { proof } => DLogProof::verify(proof).is_err(),
+            KeygenEvidence::PublicKeyMismatch { proof, vss_vec } => {
+                let i = usize::from(party);
+                if i == 0 || i > vss_vec.len() || DLogProof::verify(proof).is_err() {
+                    return false;
+                }
+                Keys::get_commitments_to_xi(vss_vec)[i - 1] != proof.pk
+            }
+        }
+    }
+}
+
+impl KeygenEvidence {
+    pub fn check(&self) -> KeygenCheck {
+        match self {
+            KeygenEvidence::Commitment { .. } => KeygenCheck::Commitment,
+            KeygenEvidence::ChainCodeCommitment { .. } => KeygenCheck::ChainCodeCommitment,
+            KeygenEvidence::ImportContribution { .. } => KeygenCheck::ImportContribution,
+            KeygenEvidence::PaillierKey { .. } => KeygenCheck::PaillierKey,
+            KeygenEvidence::NTilde { .. } => KeygenCheck::NTilde,
+            KeygenEvidence::Vss { .. } => KeygenCheck::Vss,
+            KeygenEvidence::NoSmallFactorProof { .. } => KeygenCheck::NoSmallFactorProof,
+            KeygenEvidence::DLogProof { .. } => KeygenCheck::DLogProof,
+            KeygenEvidence::PublicKeyMismatch { .. } => KeygenCheck::PublicKeyMismatch,
+        }
+    }
+
+    /// Evidence of a bad share: opens `encrypted_share` received by the accuser with its
+    /// decryption key
+    pub(super) fn vss(
+        y_i: Point<Secp256k1>,
+        vss: VerifiableSS<Secp256k1>,
+        accuser_ek: EncryptionKey,
+        accuser_dk: &DecryptionKey,
+        encrypted_share: BigInt,
+    ) -> Self {
+        let n = &accuser_ek.n;
+        let c = BigInt::modulus(&encrypted_share, n);
+        // c = (1 + n)^m * r^n mod n^2, so r = (c mod n)^(n^-1 mod phi(n)) mod n
+        let phi = (&accuser_dk.p - BigInt::one()) * (&accuser_dk.q - BigInt::one());
+        let randomness = BigInt::mod_inv(n, &phi)
+            .map(|n_inv| BigInt::mod_pow(&c, &n_inv, n))
+            .unwrap_or_else(BigInt::zero);
+        let share = paillier_decrypt(accuser_dk, &encrypted_share);
+        KeygenEvidence::Vss {
+            y_i,
+            vss,
+            accuser_ek,
+            encrypted_share,
+            share,
+            randomness,
+        }
+    }
+}
+
+fn paillier_decrypt(dk: &DecryptionKey, c: &BigInt) -> BigInt {
+    let m: RawPlaintext = Paillier::decrypt(dk, RawCiphertext::from(c.clone()));
+    m.0.into_owned()
+}
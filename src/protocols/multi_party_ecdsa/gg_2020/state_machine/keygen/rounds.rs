@@ -1,11 +1,16 @@
-use curv::arithmetic::Converter;
+use bip32::ChainCode;
+use curv::arithmetic::{Converter, Samplable, Zero};
+use curv::cryptographic_primitives::commitments::hash_commitment::HashCommitment;
+use curv::cryptographic_primitives::commitments::traits::Commitment;
 use curv::cryptographic_primitives::proofs::sigma_dlog::DLogProof;
 use curv::elliptic::curves::{secp256_k1::Secp256k1, Curve, Point, Scalar};
 use curv::BigInt;
-use sha2::{Sha256};
+use rand_core::{OsRng, RngCore};
+use sha2::{Digest, Sha256};
 
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
+use zeroize::Zeroize;
 
 use paillier::{Paillier, Decrypt, Encrypt,EncryptionKey, RawCiphertext, RawPlaintext};
 use round_based::containers::push::Push;
@@ -13,45 +18,260 @@ use round_based::containers::{self, BroadcastMsgs, MessageStore, P2PMsgs, P2PMsg
 use round_based::{IsCritical, Msg};
 use zk_paillier::zkproofs::DLogStatement;
 
-use bip39::{Language, Mnemonic};
+use bip39::{Language, Mnemonic, Seed};
 
 use crate::no_small_proof::no_small_proof::NoSmallFactorProof;
+use crate::protocols::multi_party_ecdsa::gg_2020::hd_acount::account_manage::raw_share;
+use crate::protocols::multi_party_ecdsa::gg_2020::hd_acount::btc_hd::{self, HdError};
 use crate::protocols::multi_party_ecdsa::gg_2018::VerifiableSS;
 use crate::protocols::multi_party_ecdsa::gg_2020::party_i::{
-    KeyGenBroadcastMessage1, KeyGenDecommitMessage1, Keys, SharedKeys,
+    AuxInfoProofs, KeyGenBroadcastMessage1, KeyGenDecommitMessage1, Keys, PrecomputedAux,
+    SharedKeys, PAILLIER_MAX_BIT_LENGTH,
 };
-use crate::protocols::multi_party_ecdsa::gg_2020::{self, ErrorType};
+use crate::protocols::multi_party_ecdsa::gg_2020::{self, KEYGEN_LABEL};
+use crate::utilities::secret::Secret;
+use crate::utilities::transcript::SessionId;
+
+use super::abort::{Culprit, KeygenAbort, KeygenEvidence};
 
 pub struct Round0 {
     pub party_i: u16,
     pub t: u16,
     pub n: u16,
+    pub import: Option<KeyImport>,
+    pub secret: Option<ImportedSecret>,
+    pub config: KeygenConfig,
+    pub session_id: SessionId,
+}
+
+/// Default bit length of Paillier modulus and `N_tilde`
+pub const DEFAULT_MODULUS_BITS: usize = 2048;
+
+/// Local parameters of party's Paillier key and `h1, h2, N_tilde`, they don't need to be the
+/// same for all the parties, except for `aux_proofs`
+#[derive(Clone)]
+pub struct KeygenConfig {
+    /// Whether Paillier modulus and `N_tilde` are generated from safe primes. Safe primes are
+    /// considerably slower to generate.
+    pub safe_primes: bool,
+    /// Bit length of Paillier modulus and `N_tilde`, must be in range `[2048; 4096]`
+    pub modulus_bits: usize,
+    /// Pre-generated Paillier key and `h1, h2, N_tilde`. If set, `safe_primes` and
+    /// `modulus_bits` are ignored and keygen doesn't generate any primes.
+    pub precomputed: Option<PrecomputedAux>,
+    /// Proofs of Paillier key and `h1, h2, N_tilde` correctness sent and required from the
+    /// other parties, must be the same for all the parties. CGGMP proofs need Paillier key
+    /// generated from Blum primes, which keygen takes care of unless `precomputed` is set.
+    pub aux_proofs: AuxInfoProofs,
+}
+
+impl Default for KeygenConfig {
+    fn default() -> Self {
+        KeygenConfig {
+            safe_primes: false,
+            modulus_bits: DEFAULT_MODULUS_BITS,
+            precomputed: None,
+            aux_proofs: AuxInfoProofs::default(),
+        }
+    }
+}
+
+impl KeygenConfig {
+    /// Config using pre-generated Paillier key and `h1, h2, N_tilde`
+    pub fn with_precomputed(precomputed: PrecomputedAux) -> Self {
+        KeygenConfig {
+            precomputed: Some(precomputed),
+            ..Default::default()
+        }
+    }
+
+    /// Checks that modulus size is in acceptable range and precomputed material is consistent
+    pub fn is_valid(&self) -> bool {
+        match &self.precomputed {
+            Some(precomputed) => {
+                precomputed.is_valid()
+                    && (!self.aux_proofs.cggmp() || precomputed.has_blum_modulus())
+            }
+            None => {
+                self.modulus_bits >= DEFAULT_MODULUS_BITS
+                    && self.modulus_bits <= PAILLIER_MAX_BIT_LENGTH
+            }
+        }
+    }
+
+    /// Paillier key and `h1, h2, N_tilde` to be used in keygen, or `None` if the default ones
+    /// should be generated
+    fn aux(&self) -> Option<PrecomputedAux> {
+        match &self.precomputed {
+            Some(precomputed) => Some(precomputed.clone()),
+            // safe primes are Blum primes as well
+            None if self.aux_proofs.cggmp() && !self.safe_primes => {
+                Some(PrecomputedAux::generate_blum(self.modulus_bits))
+            }
+            None if self.safe_primes || self.modulus_bits != DEFAULT_MODULUS_BITS => Some(
+                PrecomputedAux::generate(self.safe_primes, self.modulus_bits),
+            ),
+            None => None,
+        }
+    }
+}
+
+/// Public parameters of keygen importing an existing key, must be the same for all the parties
+///
+/// Importer contributes the imported secret, the other parties contribute sharings of zero,
+/// which is checked by everyone against decommitted `y_i`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct KeyImport {
+    /// Index of the party contributing the imported secret
+    pub importer: u16,
+    /// Public key of the imported secret
+    pub public_key: Point<Secp256k1>,
+    /// Whether importer's chain code is used instead of jointly generated one
+    pub with_chain_code: bool,
+}
+
+/// Existing private key, and optionally chain code, known to the importer
+#[derive(Clone)]
+pub struct ImportedSecret {
+    pub secret: Secret<Scalar<Secp256k1>>,
+    pub chain_code: Option<ChainCode>,
+}
+
+impl ImportedSecret {
+    /// Single-sig private key, chain code will be generated by the parties
+    pub fn from_private_key(secret: Scalar<Secp256k1>) -> Self {
+        ImportedSecret {
+            secret: Secret::new(secret),
+            chain_code: None,
+        }
+    }
+
+    /// Base58Check extended private key, its chain code is kept
+    pub fn from_xprv(xprv: &str) -> std::result::Result<Self, HdError> {
+        let (secret, chain_code) = btc_hd::parse_xprv(xprv)?;
+        Ok(ImportedSecret {
+            secret: Secret::new(secret),
+            chain_code: Some(chain_code),
+        })
+    }
+
+    /// BIP32 master key of a BIP39 mnemonic, its chain code is kept
+    pub fn from_mnemonic(phrase: &str, passphrase: &str) -> std::result::Result<Self, HdError> {
+        let mnemonic = Mnemonic::from_phrase(phrase, Language::English).map_err(|_| {
+            HdError::InvalidPrivateKey {
+                reason: "invalid mnemonic",
+            }
+        })?;
+        let seed = Seed::new(&mnemonic, passphrase);
+        let (secret, chain_code) = btc_hd::master_key_from_seed(seed.as_bytes())?;
+        Ok(ImportedSecret {
+            secret: Secret::new(secret),
+            chain_code: Some(chain_code),
+        })
+    }
+
+    /// Public parameters to be given to all the parties when `importer` imports this secret
+    pub fn key_import(&self, importer: u16) -> KeyImport {
+        KeyImport {
+            importer,
+            public_key: Point::generator() * self.secret.expose(),
+            with_chain_code: self.chain_code.is_some(),
+        }
+    }
+}
+
+/// Commitment to party's random contribution to the chain code, sent along with
+/// [KeyGenBroadcastMessage1]
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ChainCodeCommitment {
+    pub com: BigInt,
+}
+
+/// Party's contribution to the chain code, sent along with [KeyGenDecommitMessage1]
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ChainCodeDecommitment {
+    pub contribution: ChainCode,
+    pub blind_factor: BigInt,
+}
+
+impl ChainCodeDecommitment {
+    fn random() -> Self {
+        let mut contribution = ChainCode::default();
+        OsRng.fill_bytes(&mut contribution);
+        ChainCodeDecommitment {
+            contribution,
+            blind_factor: BigInt::sample(256),
+        }
+    }
+
+    pub(super) fn commit(&self) -> ChainCodeCommitment {
+        ChainCodeCommitment {
+            com: HashCommitment::<Sha256>::create_commitment_with_user_defined_randomness(
+                &BigInt::from_bytes(&self.contribution),
+                &self.blind_factor,
+            ),
+        }
+    }
+
+    /// Chain code is a hash of contributions of all the parties, ordered by party index
+    fn chain_code(decommitments: &[ChainCodeDecommitment]) -> ChainCode {
+        let mut hasher = Sha256::new();
+        for decom in decommitments {
+            hasher.update(&decom.contribution);
+        }
+        hasher.finalize().into()
+    }
 }
 
 impl Round0 {
     pub fn proceed<O>(self, mut output: O) -> Result<Round1>
     where
-        O: Push<Msg<gg_2020::party_i::KeyGenBroadcastMessage1>>,
+        O: Push<Msg<(KeyGenBroadcastMessage1, ChainCodeCommitment)>>,
     {
-        let party_keys = Keys::create(self.party_i as usize);
-        println!("party_keys");
-        let mnemonic = Mnemonic::from_entropy(&party_keys.u_i.to_bytes(), Language::English).unwrap(); // 24-word mnemonic
-        let phrase: &str = mnemonic.phrase();
-        println!("phrase {}",&phrase);
+        let u = match (&self.import, &self.secret) {
+            (Some(_), Some(secret)) => Some(secret.secret.expose().clone()),
+            (Some(_), None) => Some(Scalar::zero()),
+            (None, _) => None,
+        };
+        let party_keys = match (u, self.config.aux()) {
+            (u, Some(aux)) => {
+                Keys::create_with_aux(u.unwrap_or_else(Scalar::random), self.party_i as usize, aux)
+            }
+            (Some(u), None) => Keys::create_from(u, self.party_i as usize),
+            (None, None) => Keys::create(self.party_i as usize),
+        };
 
-        let (bc1, decom1) =
-            party_keys.phase1_broadcast_phase3_proof_of_correct_key_proof_of_correct_h1h2();
+        let aux_proofs = self.config.aux_proofs;
+        let (bc1, decom1) = party_keys
+            .phase1_broadcast_phase3_proof_of_correct_key_proof_of_correct_h1h2(
+                aux_proofs,
+                &self.session_id.context(self.party_i, KEYGEN_LABEL),
+            )
+            .ok_or(ProceedError::Round1ProveAuxInfo)?;
+        let cc_decom = match self.secret.and_then(|secret| secret.chain_code) {
+            Some(chain_code) => ChainCodeDecommitment {
+                contribution: chain_code,
+                blind_factor: BigInt::sample(256),
+            },
+            None => ChainCodeDecommitment::random(),
+        };
+        let cc_com = cc_decom.commit();
 
         output.push(Msg {
             round: 1,
             sender: self.party_i,
             receiver: None,
-            body: bc1.clone(),
+            body: (bc1.clone(), cc_com.clone()),
         });
         Ok(Round1 {
             keys: party_keys,
             bc1,
             decom1,
+            cc_com,
+            cc_decom,
+            import: self.import,
+            aux_proofs,
+            session_id: self.session_id,
             party_i: self.party_i,
             t: self.t,
             n: self.n,
@@ -66,6 +286,11 @@ pub struct Round1 {
     keys: Keys,
     bc1: KeyGenBroadcastMessage1,
     decom1: KeyGenDecommitMessage1,
+    cc_com: ChainCodeCommitment,
+    cc_decom: ChainCodeDecommitment,
+    import: Option<KeyImport>,
+    aux_proofs: AuxInfoProofs,
+    session_id: SessionId,
     party_i: u16,
     t: u16,
     n: u16,
@@ -74,22 +299,31 @@ pub struct Round1 {
 impl Round1 {
     pub fn proceed<O>(
         self,
-        input: BroadcastMsgs<KeyGenBroadcastMessage1>,
+        input: BroadcastMsgs<(KeyGenBroadcastMessage1, ChainCodeCommitment)>,
         mut output: O,
     ) -> Result<Round2>
     where
-        O: Push<Msg<gg_2020::party_i::KeyGenDecommitMessage1>>,
+        O: Push<Msg<(KeyGenDecommitMessage1, ChainCodeDecommitment)>>,
     {
         output.push(Msg {
             round: 2,
             sender: self.party_i,
             receiver: None,
-            body: self.decom1.clone(),
+            body: (self.decom1.clone(), self.cc_decom.clone()),
         });
+        let (received_comm, received_cc_comm) = input
+            .into_vec_including_me((self.bc1, self.cc_com))
+            .into_iter()
+            .unzip();
         Ok(Round2 {
             keys: self.keys,
-            received_comm: input.into_vec_including_me(self.bc1),
+            received_comm,
+            received_cc_comm,
             decom: self.decom1,
+            cc_decom: self.cc_decom,
+            import: self.import,
+            aux_proofs: self.aux_proofs,
+            session_id: self.session_id,
 
             party_i: self.party_i,
             t: self.t,
@@ -99,7 +333,10 @@ impl Round1 {
     pub fn is_expensive(&self) -> bool {
         false
     }
-    pub fn expects_messages(i: u16, n: u16) -> Store<BroadcastMsgs<KeyGenBroadcastMessage1>> {
+    pub fn expects_messages(
+        i: u16,
+        n: u16,
+    ) -> Store<BroadcastMsgs<(KeyGenBroadcastMessage1, ChainCodeCommitment)>> {
         containers::BroadcastMsgsStore::new(i, n)
     }
 }
@@ -107,7 +344,12 @@ impl Round1 {
 pub struct Round2 {
     keys: gg_2020::party_i::Keys,
     received_comm: Vec<KeyGenBroadcastMessage1>,
+    received_cc_comm: Vec<ChainCodeCommitment>,
     decom: KeyGenDecommitMessage1,
+    cc_decom: ChainCodeDecommitment,
+    import: Option<KeyImport>,
+    aux_proofs: AuxInfoProofs,
+    session_id: SessionId,
 
     party_i: u16,
     t: u16,
@@ -117,7 +359,7 @@ pub struct Round2 {
 impl Round2 {
     pub fn proceed<O>(
         self,
-        input: BroadcastMsgs<KeyGenDecommitMessage1>,
+        input: BroadcastMsgs<(KeyGenDecommitMessage1, ChainCodeDecommitment)>,
         mut output: O,
     ) -> Result<Round3>
     where
@@ -127,17 +369,92 @@ impl Round2 {
             threshold: self.t,
             share_count: self.n,
         };
-        let received_decom = input.into_vec_including_me(self.decom);
+        let (received_decom, received_cc_decom): (Vec<_>, Vec<_>) = input
+            .into_vec_including_me((self.decom, self.cc_decom))
+            .into_iter()
+            .unzip();
 
+        let culprits: Vec<Culprit> = received_cc_decom
+            .iter()
+            .zip(&self.received_cc_comm)
+            .enumerate()
+            .filter(|(_, (decom, comm))| decom.commit().com != comm.com)
+            .map(|(i, (decom, comm))| Culprit {
+                party: i as u16 + 1,
+                evidence: KeygenEvidence::ChainCodeCommitment {
+                    com: comm.clone(),
+                    decom: decom.clone(),
+                },
+            })
+            .collect();
+        if !culprits.is_empty() {
+            return Err(abort(self.session_id, self.party_i, culprits));
+        }
+        let chain_code = match &self.import {
+            Some(import) if import.with_chain_code => {
+                received_cc_decom[usize::from(import.importer - 1)].contribution
+            }
+            _ => ChainCodeDecommitment::chain_code(&received_cc_decom),
+        };
+
+        if let Some(import) = &self.import {
+            let culprits: Vec<Culprit> = received_decom
+                .iter()
+                .enumerate()
+                .filter(|(j, decom)| {
+                    if *j + 1 == usize::from(import.importer) {
+                        decom.y_i != import.public_key
+                    } else {
+                        !decom.y_i.is_zero()
+                    }
+                })
+                .map(|(j, decom)| Culprit {
+                    party: j as u16 + 1,
+                    evidence: KeygenEvidence::ImportContribution {
+                        import: import.clone(),
+                        decom1: decom.clone(),
+                    },
+                })
+                .collect();
+            if !culprits.is_empty() {
+                return Err(abort(self.session_id, self.party_i, culprits));
+            }
+        }
+
+        let (session_id, party_i, aux_proofs, received_comm) =
+            (self.session_id, self.party_i, self.aux_proofs, &self.received_comm);
         let vss_result = self
             .keys
             .phase1_verify_com_phase3_verify_correct_key_verify_dlog_phase2_distribute_nsf_proof(
                 &params,
                 &received_decom,
                 &self.received_comm,
+                self.aux_proofs,
+                &self.session_id,
             )
-
-            .map_err(ProceedError::Round2VerifyCommitments)?;
+            .map_err(|err| {
+                let culprits = err
+                    .bad_actors
+                    .iter()
+                    .map(|&i| {
+                        let bc1 = received_comm[i].clone();
+                        let decom1 = received_decom[i].clone();
+                        let prover_ctx = session_id.context(i as u16 + 1, KEYGEN_LABEL);
+                        let evidence = if !bc1.verify_decommitment(&decom1) {
+                            KeygenEvidence::Commitment { bc1, decom1 }
+                        } else if !bc1.verify_paillier_key(aux_proofs, &prover_ctx) {
+                            KeygenEvidence::PaillierKey { bc1, aux_proofs }
+                        } else {
+                            KeygenEvidence::NTilde { bc1, aux_proofs }
+                        };
+                        Culprit {
+                            party: i as u16 + 1,
+                            evidence,
+                        }
+                    })
+                    .collect();
+                abort(session_id, party_i, culprits)
+            })?;
         for (i, share) in vss_result.1.iter().enumerate() {
             if i + 1 == usize::from(self.party_i) {
                 continue;
@@ -163,6 +480,8 @@ impl Round2 {
             own_vss: vss_result.0.clone(),
             own_share: vss_result.1[usize::from(self.party_i - 1)].clone(),
             own_nsf_proof : vss_result.2[usize::from(self.party_i - 1)].clone(),
+            chain_code,
+            session_id: self.session_id,
 
             party_i: self.party_i,
             t: self.t,
@@ -172,7 +491,10 @@ impl Round2 {
     pub fn is_expensive(&self) -> bool {
         true
     }
-    pub fn expects_messages(i: u16, n: u16) -> Store<BroadcastMsgs<KeyGenDecommitMessage1>> {
+    pub fn expects_messages(
+        i: u16,
+        n: u16,
+    ) -> Store<BroadcastMsgs<(KeyGenDecommitMessage1, ChainCodeDecommitment)>> {
         containers::BroadcastMsgsStore::new(i, n)
     }
 }
@@ -186,6 +508,8 @@ pub struct Round3 {
     own_vss: VerifiableSS<Secp256k1>,
     own_share: Scalar<Secp256k1>,
     own_nsf_proof : NoSmallFactorProof,
+    chain_code: ChainCode,
+    session_id: SessionId,
 
     party_i: u16,
     t: u16,
@@ -206,13 +530,15 @@ impl Round3 {
             share_count: self.n,
         };
 
+        let mut encrypted_shares = vec![BigInt::zero(); usize::from(self.n)];
         let input: P2PMsgs<(VerifiableSS<Secp256k1>, Scalar<Secp256k1>,NoSmallFactorProof)> = {
             let encrypted_input = input.into_iter_indexed();
             let mut decrypted_input = P2PMsgsStore::new(self.party_i, self.n);
             for (i, (vss, encrypted_share,nsf_proofs)) in encrypted_input {
                 let v = BigInt::from_bytes(&encrypted_share);
+                encrypted_shares[usize::from(i - 1)] = v.clone();
                 let c = RawCiphertext::from(v);
-                let raw_share: RawPlaintext<'_> = Paillier::decrypt(&self.keys.dk, c);
+                let raw_share: RawPlaintext<'_> = Paillier::decrypt(self.keys.dk.expose(), c);
                 let share = Scalar::from_bigint(&raw_share.0.into_owned());
                 let _ = decrypted_input.push_msg(Msg {
                     round: 4,
@@ -231,6 +557,8 @@ impl Round3 {
         let party_shares: Vec<_> = vec_including_me.iter().map(|(_, b, _)| b.clone()).collect();
         let nsf_proofs: Vec<_> = vec_including_me.iter().map(|(_, _, c)| c.clone()).collect();
 
+        let (session_id, party_i, keys, y_vec, bc_vec) =
+            (self.session_id, self.party_i, &self.keys, &self.y_vec, &self.bc_vec);
         let (shared_keys, dlog_proof) = self
         .keys
         .phase2_verify_vss_construct_keypair_phase3_pok_dlog_nsf_verify(
@@ -241,8 +569,42 @@ impl Round3 {
             nsf_proofs.as_slice(),
             &self.bc_vec,
             self.party_i.into(),
+            &self.session_id,
         )
-        .map_err(ProceedError::Round3VerifyVssConstruct)?;
+        .map_err(|err| {
+            let mut bad_actors = err.bad_actors;
+            bad_actors.sort_unstable();
+            bad_actors.dedup();
+            let me = usize::from(party_i - 1);
+            let culprits = bad_actors
+                .into_iter()
+                .map(|i| {
+                    let vss = &vss_schemes[i];
+                    let evidence = if vss.validate_share(&party_shares[i], party_i).is_err()
+                        || vss.commitments[0] != y_vec[i]
+                    {
+                        KeygenEvidence::vss(
+                            y_vec[i].clone(),
+                            vss.clone(),
+                            keys.ek.clone(),
+                            keys.dk.expose(),
+                            encrypted_shares[i].clone(),
+                        )
+                    } else {
+                        KeygenEvidence::NoSmallFactorProof {
+                            n0: bc_vec[i].e.n.clone(),
+                            accuser_dlog_statement: bc_vec[me].dlog_statement.clone(),
+                            proof: nsf_proofs[i].clone(),
+                        }
+                    };
+                    Culprit {
+                        party: i as u16 + 1,
+                        evidence,
+                    }
+                })
+                .collect();
+            abort(session_id, party_i, culprits)
+        })?;
         output.push(Msg {
             round: 4,
             sender: self.party_i,
@@ -257,6 +619,8 @@ impl Round3 {
             shared_keys,
             own_dlog_proof: dlog_proof,
             vss_vec: vss_schemes,
+            chain_code: self.chain_code,
+            session_id: self.session_id,
 
             party_i: self.party_i,
             t: self.t,
@@ -278,6 +642,8 @@ pub struct Round4 {
     shared_keys: gg_2020::party_i::SharedKeys<Secp256k1>,
     own_dlog_proof: DLogProof<Secp256k1, Sha256>,
     vss_vec: Vec<VerifiableSS<Secp256k1>>,
+    chain_code: ChainCode,
+    session_id: SessionId,
 
     party_i: u16,
     t: u16,
@@ -288,7 +654,7 @@ impl Round4 {
     pub fn proceed(
         self,
         input: BroadcastMsgs<DLogProof<Secp256k1, Sha256>>,
-    ) -> Result<LocalKey<Secp256k1>> {
+    ) -> Result<raw_share<Secp256k1>> {
         let params = gg_2020::party_i::Parameters {
             threshold: self.t,
             share_count: self.n,
@@ -301,7 +667,28 @@ impl Round4 {
             &self.y_vec,
             &self.vss_vec,
         )
-        .map_err(ProceedError::Round4VerifyDLogProof)?;
+        .map_err(|err| {
+            let culprits = err
+                .bad_actors
+                .into_iter()
+                .map(|i| {
+                    let proof = dlog_proofs[i].clone();
+                    let evidence = if DLogProof::verify(&proof).is_err() {
+                        KeygenEvidence::DLogProof { proof }
+                    } else {
+                        KeygenEvidence::PublicKeyMismatch {
+                            proof,
+                            vss_vec: self.vss_vec.clone(),
+                        }
+                    };
+                    Culprit {
+                        party: i as u16 + 1,
+                        evidence,
+                    }
+                })
+                .collect();
+            abort(self.session_id, self.party_i, culprits)
+        })?;
         let pk_vec = (0..params.share_count as usize)
             .map(|i| dlog_proofs[i].pk.clone())
             .collect::<Vec<Point<Secp256k1>>>();
@@ -328,14 +715,18 @@ impl Round4 {
             y_sum_s: y_sum.clone(),
             h1_h2_n_tilde_vec,
 
-            vss_scheme: self.vss_vec[usize::from(self.party_i - 1)].clone(),
+            vss_scheme: Keys::get_global_vss(&self.vss_vec),
 
             i: self.party_i,
             t: self.t,
             n: self.n,
+            needs_refresh: false,
         };
-        
-        Ok(local_key)
+
+        Ok(raw_share {
+            local_key_hd: local_key,
+            chain_code: self.chain_code,
+        })
     }
     pub fn is_expensive(&self) -> bool {
         true
@@ -349,21 +740,43 @@ impl Round4 {
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct LocalKey<E: Curve> {
     pub raw_key : Keys<Secp256k1>,
-    pub paillier_dk: paillier::DecryptionKey,
+    pub paillier_dk: Secret<paillier::DecryptionKey>,
     pub pk_vec: Vec<Point<E>>,
     pub keys_linear: gg_2020::party_i::SharedKeys<E>,
     pub paillier_key_vec: Vec<EncryptionKey>,
     pub y_sum_s: Point<E>,
     pub h1_h2_n_tilde_vec: Vec<DLogStatement>,
+    /// VSS scheme of the shared secret, i.e. sum of VSS schemes of all the parties
+    ///
+    /// Keys stored in format older than version 3 hold party's own VSS scheme here instead, see
+    /// [LocalKey::restore_global_vss]
     pub vss_scheme: VerifiableSS<E>,
     pub i: u16,
     pub t: u16,
     pub n: u16,
+    /// Set for keys migrated from [Old_LocalKey], which didn't store party's `u_i` and
+    /// trapdoors of `N_tilde`: `raw_key.u_i`, `raw_key.xhi` and `raw_key.xhi_inv` are zero and
+    /// must not be used
+    ///
+    /// Such key can sign, but refresh keeps `raw_key` unless it generates new aux info, so
+    /// [Refresh](super::super::refresh::Refresh) refuses it unless new Paillier key and
+    /// `h1, h2, N_tilde` are generated. Refreshed key has the flag unset.
+    #[serde(default)]
+    pub needs_refresh: bool,
+}
+
+impl<E: Curve> Zeroize for LocalKey<E> {
+    fn zeroize(&mut self) {
+        self.raw_key.zeroize();
+        self.paillier_dk.zeroize();
+        self.keys_linear.zeroize();
+    }
 }
 
+/// [LocalKey] in the legacy format which didn't include `raw_key`
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct Old_LocalKey<E: Curve> {
-    pub paillier_dk: paillier::DecryptionKey,
+    pub paillier_dk: Secret<paillier::DecryptionKey>,
     pub pk_vec: Vec<Point<E>>,
     pub keys_linear: gg_2020::party_i::SharedKeys<E>,
     pub paillier_key_vec: Vec<EncryptionKey>,
@@ -375,9 +788,55 @@ pub struct Old_LocalKey<E: Curve> {
     pub n: u16,
 }
 impl Old_LocalKey<Secp256k1> {
-    pub fn to_LocalKey(&self) -> LocalKey<Secp256k1>{
-        LocalKey {
-            raw_key : Keys::<Secp256k1>::create(1),
+    /// Converts the key to [LocalKey], rebuilding `raw_key` from the stored data
+    ///
+    /// Paillier key and `N_tilde, h1, h2` are taken from `paillier_dk` and party's own entries
+    /// of `paillier_key_vec` and `h1_h2_n_tilde_vec`. Party's `u_i` and trapdoors of `N_tilde`
+    /// were never stored, so they are set to zero and the key is marked with
+    /// [LocalKey::needs_refresh]. Stored `vss_scheme` is party's own VSS scheme, VSS scheme of
+    /// the shared secret is rebuilt with [LocalKey::restore_global_vss].
+    ///
+    /// Fails if stored Paillier key pair doesn't match or resulting key doesn't pass
+    /// [LocalKey::validate].
+    pub fn to_LocalKey(&self) -> std::result::Result<LocalKey<Secp256k1>, InvalidLocalKey> {
+        if self.i == 0 || self.i > self.n {
+            return Err(InvalidLocalKey::InvalidPartyIndex {
+                i: self.i,
+                n: self.n,
+            });
+        }
+        let own = usize::from(self.i) - 1;
+        let wrong_length = |field, actual| InvalidLocalKey::WrongLength {
+            field,
+            expected: usize::from(self.n),
+            actual,
+        };
+        let ek = self
+            .paillier_key_vec
+            .get(own)
+            .ok_or_else(|| wrong_length("paillier_key_vec", self.paillier_key_vec.len()))?;
+        let dlog_statement = self
+            .h1_h2_n_tilde_vec
+            .get(own)
+            .ok_or_else(|| wrong_length("h1_h2_n_tilde_vec", self.h1_h2_n_tilde_vec.len()))?;
+        let dk = self.paillier_dk.expose();
+        if &dk.p * &dk.q != ek.n {
+            return Err(InvalidLocalKey::PaillierKeyMismatch);
+        }
+
+        let mut local_key = LocalKey {
+            raw_key: Keys {
+                u_i: Secret::new(Scalar::zero()),
+                y_i: Point::zero(),
+                dk: self.paillier_dk.clone(),
+                ek: ek.clone(),
+                party_index: usize::from(self.i),
+                N_tilde: dlog_statement.N.clone(),
+                h1: dlog_statement.g.clone(),
+                h2: dlog_statement.ni.clone(),
+                xhi: Secret::new(BigInt::zero()),
+                xhi_inv: Secret::new(BigInt::zero()),
+            },
             paillier_dk: self.paillier_dk.clone(),
             pk_vec : self.pk_vec.clone(),
 
@@ -394,7 +853,10 @@ impl Old_LocalKey<Secp256k1> {
             i: self.i,
             t: self.t,
             n: self.n,
-        }
+            needs_refresh: true,
+        };
+        local_key.restore_global_vss()?;
+        Ok(local_key)
     }
 }
 impl LocalKey<Secp256k1> {
@@ -404,7 +866,7 @@ impl LocalKey<Secp256k1> {
     }
 
     pub fn decrypt(&self, ciphertext: BigInt) -> RawPlaintext {
-        Paillier::decrypt(&self.paillier_dk, &RawCiphertext::from(ciphertext))
+        Paillier::decrypt(self.paillier_dk.expose(), &RawCiphertext::from(ciphertext))
     }
 
     pub fn update_private_key(
@@ -414,7 +876,7 @@ impl LocalKey<Secp256k1> {
     ) -> Self {
         LocalKey {
             raw_key : Keys {
-                u_i: self.raw_key.u_i.clone() + factor_u_i,
+                u_i: Secret::new(self.raw_key.u_i.expose() + factor_u_i),
                 y_i: self.raw_key.y_i.clone(),
                 dk: self.raw_key.dk.clone(),
                 ek: self.raw_key.ek.clone(),
@@ -431,7 +893,7 @@ impl LocalKey<Secp256k1> {
 
             keys_linear: SharedKeys{
                 y : self.keys_linear.y.clone(),
-                x_i : self.keys_linear.x_i.clone() + factor_x_i
+                x_i : Secret::new(self.keys_linear.x_i.expose() + factor_x_i)
             },
             paillier_key_vec : self.paillier_key_vec.clone(),
             y_sum_s: self.y_sum_s.clone(),
@@ -442,25 +904,32 @@ impl LocalKey<Secp256k1> {
             i: self.i,
             t: self.t,
             n: self.n,
+            needs_refresh: self.needs_refresh,
         }
             
     }
+    /// Shifts the shared secret by `factor_x_i`, i.e. derives a child key with given tweak
+    ///
+    /// Every party must apply the same tweak. `y_sum` is the expected child public key.
     pub fn update_hd_key(
         &self,
         factor_u_i: &Scalar<Secp256k1>,
         factor_x_i: &Scalar<Secp256k1>,
         y_sum : &Point<Secp256k1>,
     ) -> Self {
-        //Point::generator()
-        // let a = Point::generator() * factor_x_i;
-        // let c = self.pk_vec[0]+a;
-
-        let (vss_scheme, secret_shares) =
-        VerifiableSS::share(1, 3, &(self.raw_key.u_i.clone() + factor_u_i));
+        // x_i + tweak are shares of x + tweak since lagrange coefficients sum up to 1, so
+        // only the constant term commitment of the VSS scheme changes
+        let mut commitments = self.vss_scheme.commitments.clone();
+        commitments[0] = &commitments[0] + Point::generator() * factor_x_i;
+        let vss_scheme = VerifiableSS {
+            parameters: self.vss_scheme.parameters.clone(),
+            commitments,
+            proof: self.vss_scheme.proof.clone(),
+        };
         LocalKey {
 
             raw_key : Keys {
-                u_i: self.raw_key.u_i.clone() + factor_u_i,
+                u_i: Secret::new(self.raw_key.u_i.expose() + factor_u_i),
                 y_i: self.raw_key.y_i.clone(),
                 dk: self.raw_key.dk.clone(),
                 ek: self.raw_key.ek.clone(),
@@ -477,27 +946,180 @@ impl LocalKey<Secp256k1> {
 
             keys_linear: SharedKeys{
                 y : y_sum.clone(),
-                x_i : self.keys_linear.x_i.clone() + factor_x_i
+                x_i : Secret::new(self.keys_linear.x_i.expose() + factor_x_i)
             },
             paillier_key_vec : self.paillier_key_vec.clone(),
             y_sum_s: y_sum.clone(),
             h1_h2_n_tilde_vec : self.h1_h2_n_tilde_vec.clone(),
 
-            vss_scheme : vss_scheme.clone(),
+            vss_scheme,
 
             i: self.i,
             t: self.t,
             n: self.n,
+            needs_refresh: self.needs_refresh,
         }
             
     }
+
+    /// Replaces `vss_scheme` with VSS scheme of the shared secret rebuilt from `pk_vec`
+    ///
+    /// Keys written before format version 3 store party's own VSS scheme in `vss_scheme`.
+    /// Commitments to coefficients of the shared polynomial are interpolated in the exponent
+    /// from public keys of parties `1..=t+1`, parameters are kept. Remaining public keys are
+    /// checked against the result by [LocalKey::validate], which is called at the end.
+    pub fn restore_global_vss(&mut self) -> std::result::Result<(), InvalidLocalKey> {
+        if self.n < 2 || self.t == 0 || self.t >= self.n {
+            return Err(InvalidLocalKey::InvalidThreshold {
+                t: self.t,
+                n: self.n,
+            });
+        }
+        if self.pk_vec.len() != usize::from(self.n) {
+            return Err(InvalidLocalKey::WrongLength {
+                field: "pk_vec",
+                expected: usize::from(self.n),
+                actual: self.pk_vec.len(),
+            });
+        }
+        let scalar = |v: usize| Scalar::<Secp256k1>::from_bigint(&BigInt::from(v as u64));
+        let points = usize::from(self.t) + 1;
+        let mut commitments = vec![Point::<Secp256k1>::zero(); points];
+        for j in 1..=points {
+            // coefficients of l_j(x) = prod_{m != j} (x - m) / (j - m), lowest degree first
+            let mut basis = vec![scalar(1)];
+            let mut denominator = scalar(1);
+            for m in (1..=points).filter(|&m| m != j) {
+                let mut next = vec![Scalar::<Secp256k1>::zero(); basis.len() + 1];
+                for (k, coefficient) in basis.iter().enumerate() {
+                    next[k + 1] = &next[k + 1] + coefficient;
+                    next[k] = &next[k] - coefficient * scalar(m);
+                }
+                basis = next;
+                denominator = denominator * (scalar(j) - scalar(m));
+            }
+            let denominator_inv = denominator.invert().expect("indexes are distinct");
+            for (commitment, coefficient) in commitments.iter_mut().zip(&basis) {
+                *commitment = &*commitment + &self.pk_vec[j - 1] * (coefficient * &denominator_inv);
+            }
+        }
+        self.vss_scheme = VerifiableSS {
+            parameters: self.vss_scheme.parameters.clone(),
+            commitments,
+            proof: self.vss_scheme.proof.clone(),
+        };
+        self.validate()
+    }
+
+    /// Checks that public parts of the key are consistent with each other and with the
+    /// party's secret share
+    pub fn validate(&self) -> std::result::Result<(), InvalidLocalKey> {
+        if self.n < 2 || self.t == 0 || self.t >= self.n {
+            return Err(InvalidLocalKey::InvalidThreshold {
+                t: self.t,
+                n: self.n,
+            });
+        }
+        if self.i == 0 || self.i > self.n {
+            return Err(InvalidLocalKey::InvalidPartyIndex {
+                i: self.i,
+                n: self.n,
+            });
+        }
+        let n = usize::from(self.n);
+        let lengths = [
+            ("pk_vec", self.pk_vec.len()),
+            ("paillier_key_vec", self.paillier_key_vec.len()),
+            ("h1_h2_n_tilde_vec", self.h1_h2_n_tilde_vec.len()),
+        ];
+        for &(field, len) in lengths.iter() {
+            if len != n {
+                return Err(InvalidLocalKey::WrongLength {
+                    field,
+                    expected: n,
+                    actual: len,
+                });
+            }
+        }
+        let parameters = &self.vss_scheme.parameters;
+        if parameters.threshold != self.t
+            || parameters.share_count != self.n
+            || self.vss_scheme.commitments.len() != usize::from(self.t) + 1
+        {
+            return Err(InvalidLocalKey::VssParametersMismatch);
+        }
+
+        if self.vss_scheme.commitments[0] != self.y_sum_s || self.keys_linear.y != self.y_sum_s {
+            return Err(InvalidLocalKey::PublicKeyMismatch);
+        }
+        for (j, pk) in (1..).zip(&self.pk_vec) {
+            if self.vss_scheme.get_point_commitment(j) != *pk {
+                return Err(InvalidLocalKey::PartyPublicKeyMismatch { party: j });
+            }
+        }
+        let y_i = Point::generator() * self.keys_linear.x_i.expose();
+        if y_i != self.pk_vec[usize::from(self.i) - 1] {
+            return Err(InvalidLocalKey::SecretShareMismatch);
+        }
+        Ok(())
+    }
+}
+
+/// Error returned by [LocalKey::validate]
+#[derive(Debug, Error)]
+pub enum InvalidLocalKey {
+    #[error("threshold {t} is not in range [1; n-1] (n = {n})")]
+    InvalidThreshold { t: u16, n: u16 },
+    #[error("party index {i} is not in range [1; {n}]")]
+    InvalidPartyIndex { i: u16, n: u16 },
+    #[error("{field} has {actual} elements, expected {expected}")]
+    WrongLength {
+        field: &'static str,
+        expected: usize,
+        actual: usize,
+    },
+    #[error("vss scheme parameters don't match t and n")]
+    VssParametersMismatch,
+    #[error("y_sum_s doesn't match vss scheme")]
+    PublicKeyMismatch,
+    #[error("public key of party {party} doesn't match vss scheme")]
+    PartyPublicKeyMismatch { party: u16 },
+    #[error("secret share doesn't match party's public key")]
+    SecretShareMismatch,
+    #[error("paillier decryption key doesn't match party's encryption key")]
+    PaillierKeyMismatch,
 }
+/// Root [LocalKey] along with BIP32 tweak of a child key
+///
+/// Allows signing on behalf of the child key without making a tweaked copy of the root key,
+/// see `OfflineStage::new_with_tweak`.
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct LocalKey_HD<E: Curve> {
     pub local_key : LocalKey<E>,
     pub tweak_sk : Scalar<E>,
     pub y_sum : Point<E>,
 }
+
+impl LocalKey_HD<Secp256k1> {
+    /// Derives a child key at non-hardened path `path_str`
+    pub fn derive(
+        local_key: LocalKey<Secp256k1>,
+        path_str: &str,
+        chain_code: ChainCode,
+    ) -> std::result::Result<Self, HdError> {
+        let (tweak_sk, y_sum) = btc_hd::call_hd_key(path_str, &local_key, chain_code)?;
+        Ok(LocalKey_HD {
+            local_key,
+            tweak_sk,
+            y_sum,
+        })
+    }
+
+    /// Checks that `y_sum` is the root public key shifted by `tweak_sk`
+    pub fn is_consistent(&self) -> bool {
+        self.y_sum == &self.local_key.y_sum_s + Point::generator() * &self.tweak_sk
+    }
+}
 // Errors
 
 type Result<T> = std::result::Result<T, ProceedError>;
@@ -508,12 +1130,20 @@ type Result<T> = std::result::Result<T, ProceedError>;
 /// every message was received and pre-validated).
 #[derive(Debug, Error)]
 pub enum ProceedError {
-    #[error("round 2: verify commitments: {0:?}")]
-    Round2VerifyCommitments(ErrorType),
-    #[error("round 3: verify vss construction: {0:?}")]
-    Round3VerifyVssConstruct(ErrorType),
-    #[error("round 4: verify dlog proof: {0:?}")]
-    Round4VerifyDLogProof(ErrorType),
+    #[error("round 1: paillier modulus is not a product of blum primes")]
+    Round1ProveAuxInfo,
+    /// Some parties misbehaved, converted into [Error::Aborted](super::Error::Aborted) by
+    /// [Keygen](super::Keygen)
+    #[error("{0}")]
+    Abort(KeygenAbort),
+}
+
+fn abort(session_id: SessionId, accuser: u16, culprits: Vec<Culprit>) -> ProceedError {
+    ProceedError::Abort(KeygenAbort {
+        session_id,
+        accuser,
+        culprits,
+    })
 }
 
 impl IsCritical for ProceedError {
@@ -0,0 +1,210 @@
+//! Versioned on-disk format of [LocalKey]
+//!
+//! Key is stored as `{"version": 3, "local_key": {...}}`. Files written before the format was
+//! versioned are plain JSON of the key and are recognized by their fields:
+//!
+//! * version 1 is [Old_LocalKey], which has no `raw_key` and stores party's own VSS scheme; it is
+//!   migrated with [Old_LocalKey::to_LocalKey], resulting key has
+//!   [needs_refresh](LocalKey::needs_refresh) set
+//! * version 2 is [LocalKey] whose `vss_scheme` is party's own VSS scheme; it is migrated with
+//!   [LocalKey::restore_global_vss]
+//! * version 3 is current [LocalKey], `vss_scheme` is VSS scheme of the shared secret
+//!
+//! Every loaded key is checked with [LocalKey::validate], so inconsistent keys are never
+//! returned.
+
+use curv::elliptic::curves::secp256_k1::Secp256k1;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use thiserror::Error;
+
+use super::rounds::{InvalidLocalKey, LocalKey, Old_LocalKey};
+
+/// Version of [LocalKey] format written by [LocalKey::to_versioned_json]
+pub const LOCAL_KEY_VERSION: u64 = 3;
+
+#[derive(Serialize)]
+struct VersionedLocalKeyRef<'a> {
+    version: u64,
+    local_key: &'a LocalKey<Secp256k1>,
+}
+
+#[derive(Deserialize)]
+struct VersionedLocalKey {
+    version: u64,
+    local_key: Value,
+}
+
+impl LocalKey<Secp256k1> {
+    /// Serializes the key in the latest format version
+    pub fn to_versioned_json(&self) -> Result<Vec<u8>, LocalKeyStorageError> {
+        serde_json::to_vec_pretty(&VersionedLocalKeyRef {
+            version: LOCAL_KEY_VERSION,
+            local_key: self,
+        })
+        .map_err(LocalKeyStorageError::Serialize)
+    }
+
+    /// Loads the key stored in any known format version, migrating it to current [LocalKey]
+    pub fn from_versioned_json(bytes: &[u8]) -> Result<Self, LocalKeyStorageError> {
+        let value: Value =
+            serde_json::from_slice(bytes).map_err(LocalKeyStorageError::Serialize)?;
+        let (version, local_key) = if value.get("version").is_some() {
+            let versioned: VersionedLocalKey =
+                serde_json::from_value(value).map_err(LocalKeyStorageError::Serialize)?;
+            (versioned.version, versioned.local_key)
+        } else if value.get("raw_key").is_some() {
+            (2, value)
+        } else {
+            (1, value)
+        };
+
+        let local_key = match version {
+            1 => serde_json::from_value::<Old_LocalKey<Secp256k1>>(local_key)
+                .map_err(LocalKeyStorageError::Serialize)?
+                .to_LocalKey()
+                .map_err(LocalKeyStorageError::Migrate)?,
+            2 => {
+                let mut local_key: LocalKey<Secp256k1> =
+                    serde_json::from_value(local_key).map_err(LocalKeyStorageError::Serialize)?;
+                local_key
+                    .restore_global_vss()
+                    .map_err(LocalKeyStorageError::Migrate)?;
+                local_key
+            }
+            3 => {
+                let local_key: LocalKey<Secp256k1> =
+                    serde_json::from_value(local_key).map_err(LocalKeyStorageError::Serialize)?;
+                local_key
+                    .validate()
+                    .map_err(LocalKeyStorageError::InvalidKey)?;
+                local_key
+            }
+            version => return Err(LocalKeyStorageError::UnsupportedVersion { version }),
+        };
+        Ok(local_key)
+    }
+}
+
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum LocalKeyStorageError {
+    #[error("malformed local key")]
+    Serialize(#[source] serde_json::Error),
+    #[error("local key version {version} is not supported")]
+    UnsupportedVersion { version: u64 },
+    #[error("cannot migrate local key to the latest version")]
+    Migrate(#[source] InvalidLocalKey),
+    #[error("local key is invalid")]
+    InvalidKey(#[source] InvalidLocalKey),
+}
+
+#[cfg(test)]
+mod test {
+    use curv::arithmetic::Zero;
+    use curv::elliptic::curves::{Point, Scalar};
+    use curv::BigInt;
+
+    use super::*;
+    use crate::protocols::multi_party_ecdsa::gg_2018::VerifiableSS;
+    use crate::protocols::multi_party_ecdsa::gg_2020::state_machine::keygen::test::simulate_keygen;
+
+    /// Serializes the key the way it was stored before format version 3: `vss_scheme` is
+    /// replaced with a VSS scheme of party's own secret `u_i`, like the one it dealt in keygen
+    fn own_vss_json(local_key: &LocalKey<Secp256k1>) -> Vec<u8> {
+        let (own_vss, _) =
+            VerifiableSS::share(local_key.t, local_key.n, local_key.raw_key.u_i.expose());
+        let mut value = serde_json::to_value(local_key).unwrap();
+        value["vss_scheme"] = serde_json::to_value(&own_vss).unwrap();
+        serde_json::to_vec(&value).unwrap()
+    }
+
+    fn legacy_json(local_key: &LocalKey<Secp256k1>) -> Vec<u8> {
+        let mut value: Value = serde_json::from_slice(&own_vss_json(local_key)).unwrap();
+        value.as_object_mut().unwrap().remove("raw_key");
+        serde_json::to_vec(&value).unwrap()
+    }
+
+    #[test]
+    fn local_key_is_loaded_from_every_version() {
+        let local_key = simulate_keygen(1, 3).remove(0).local_key_hd;
+
+        let versioned = local_key.to_versioned_json().unwrap();
+        let loaded = LocalKey::from_versioned_json(&versioned).unwrap();
+        assert_eq!(loaded.keys_linear.x_i, local_key.keys_linear.x_i);
+        assert_eq!(loaded.raw_key.N_tilde, local_key.raw_key.N_tilde);
+
+        let unversioned = serde_json::to_vec(&local_key).unwrap();
+        let loaded = LocalKey::from_versioned_json(&unversioned).unwrap();
+        assert_eq!(loaded.raw_key.u_i, local_key.raw_key.u_i);
+
+        let migrated = LocalKey::from_versioned_json(&legacy_json(&local_key)).unwrap();
+        let own = usize::from(local_key.i) - 1;
+        assert_eq!(migrated.raw_key.ek.n, local_key.paillier_key_vec[own].n);
+        assert_eq!(
+            migrated.raw_key.dk.expose().p,
+            local_key.paillier_dk.expose().p
+        );
+        assert_eq!(migrated.raw_key.N_tilde, local_key.h1_h2_n_tilde_vec[own].N);
+        assert_eq!(migrated.raw_key.h1, local_key.h1_h2_n_tilde_vec[own].g);
+        assert_eq!(migrated.raw_key.h2, local_key.h1_h2_n_tilde_vec[own].ni);
+        assert_eq!(migrated.vss_scheme.commitments, local_key.vss_scheme.commitments);
+        assert!(migrated.needs_refresh);
+        assert_eq!(migrated.raw_key.u_i.expose(), &Scalar::<Secp256k1>::zero());
+        assert_eq!(migrated.raw_key.xhi.expose(), &BigInt::zero());
+        assert!(!loaded.needs_refresh);
+
+        let resaved = migrated.to_versioned_json().unwrap();
+        let loaded = LocalKey::from_versioned_json(&resaved).unwrap();
+        assert!(loaded.needs_refresh);
+    }
+
+    #[test]
+    fn own_vss_is_migrated_to_global_vss() {
+        for &(t, n) in [(1, 3), (2, 4)].iter() {
+            for share in simulate_keygen(t, n) {
+                let local_key = share.local_key_hd;
+                let loaded = LocalKey::from_versioned_json(&own_vss_json(&local_key)).unwrap();
+                assert_eq!(loaded.vss_scheme.commitments, local_key.vss_scheme.commitments);
+                assert_eq!(loaded.raw_key.u_i, local_key.raw_key.u_i);
+            }
+        }
+    }
+
+    #[test]
+    fn own_vss_with_wrong_public_keys_is_rejected() {
+        let mut local_key = simulate_keygen(2, 4).remove(0).local_key_hd;
+        local_key.pk_vec[3] = &local_key.pk_vec[3] + Point::generator();
+
+        let result = LocalKey::from_versioned_json(&own_vss_json(&local_key));
+        assert!(matches!(
+            result,
+            Err(LocalKeyStorageError::Migrate(
+                InvalidLocalKey::PartyPublicKeyMismatch { party: 4 }
+            ))
+        ));
+    }
+
+    #[test]
+    fn inconsistent_legacy_key_is_rejected() {
+        let mut shares = simulate_keygen(1, 3);
+        let other = shares.remove(1).local_key_hd;
+        let mut local_key = shares.remove(0).local_key_hd;
+        local_key.paillier_dk = other.paillier_dk.clone();
+
+        let result = LocalKey::from_versioned_json(&legacy_json(&local_key));
+        assert!(matches!(
+            result,
+            Err(LocalKeyStorageError::Migrate(
+                InvalidLocalKey::PaillierKeyMismatch
+            ))
+        ));
+
+        let future = serde_json::json!({ "version": LOCAL_KEY_VERSION + 1, "local_key": {} });
+        let result = LocalKey::from_versioned_json(&serde_json::to_vec(&future).unwrap());
+        assert!(matches!(
+            result,
+            Err(LocalKeyStorageError::UnsupportedVersion { .. })
+        ));
+    }
+}
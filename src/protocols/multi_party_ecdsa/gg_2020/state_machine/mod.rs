@@ -1,4 +1,10 @@
+pub mod derive;
 pub mod keygen;
+pub mod recovery;
+pub mod refresh;
+pub mod reshare;
 pub mod sign;
 pub mod traits;
-//pub mod derive;
\ No newline at end of file
+
+#[cfg(test)]
+mod test;
@@ -0,0 +1,782 @@
+//! High-level share recovery protocol implementation
+//!
+//! At least `t+1` helpers rebuild secret share `x_i` of a party which lost it, without changing
+//! the public key. Each helper sends its share weighted by lagrange coefficient at point `i`
+//! and blinded by pairwise masks, so the recovering party learns nothing but `x_i`, while every
+//! contribution is checked against `pk_vec` and commitments to the masks. The recovering party
+//! generates new Paillier key and `h1, h2, N_tilde` parameters; helpers update them in their
+//! keys, other parties do it with [update_recovered_party] from [RecoveredPartyUpdate]
+//! published by the recovering party.
+
+use std::fmt;
+use std::mem::replace;
+use std::time::Duration;
+
+use curv::elliptic::curves::secp256_k1::Secp256k1;
+use round_based::containers::{
+    push::{Push, PushExt},
+    *,
+};
+use round_based::{IsCritical, Msg, StateMachine};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::no_small_proof::no_small_proof::NoSmallFactorProof;
+use crate::protocols::multi_party_ecdsa::gg_2020::hd_acount::account_manage::raw_share;
+use crate::protocols::multi_party_ecdsa::gg_2020::party_i::AuxInfoMessage;
+use crate::protocols::multi_party_ecdsa::gg_2020::RECOVERY_LABEL;
+use crate::protocols::multi_party_ecdsa::gg_2020::state_machine::keygen::{
+    InvalidLocalKey, LocalKey,
+};
+
+mod rounds;
+
+use private::InternalError;
+pub use rounds::{
+    BlindedContribution, HelperBroadcast, ProceedError, RecoveryBroadcast1, RecoveryMessage2,
+    RecoveryParams,
+};
+use rounds::{Round0, Round1, Round2, Round3};
+
+/// Share recovery protocol state machine
+///
+/// Successfully completed recovery protocol produces [raw_share] with recovered share for the
+/// recovering party, and helper's [raw_share] with updated Paillier key and `h1, h2, N_tilde`
+/// parameters of the recovering party for helpers.
+pub struct Recovery {
+    round: R,
+
+    msgs1: Option<Store<BroadcastMsgs<RecoveryBroadcast1>>>,
+    msgs2: Option<Store<P2PMsgs<RecoveryMessage2>>>,
+    msgs3: Option<Store<BroadcastMsgs<Option<BlindedContribution>>>>,
+
+    msgs_queue: Vec<Msg<ProtocolMessage>>,
+
+    party_i: u16,
+    party_n: u16,
+}
+
+impl Recovery {
+    /// Constructs a party of recovery protocol
+    ///
+    /// Takes party index `i` (in range `[1; s_l.len() + 1]`) and `params`, which must be the
+    /// same for all the parties. Parties `1..=s_l.len()` are helpers and must be given their
+    /// `share`, party `s_l.len() + 1` is the recovering party.
+    ///
+    /// Returns error if:
+    /// * `s_l` has less than 2 parties, returns [Error::TooFewParties]
+    /// * `i` is not in range `[1; s_l.len() + 1]`, returns [Error::InvalidPartyIndex]
+    /// * `s_l` has duplicated or zero indexes, or `recovering` is zero or in `s_l`, returns
+    ///   [Error::InvalidParams]
+    /// * `share` is given to the recovering party or missing for a helper, returns
+    ///   [Error::InvalidRole]
+    /// * helper's `share` is inconsistent, returns [Error::InvalidLocalKey]
+    /// * helper's `share` is not share `s_l[i-1]` of `public_key`, or `s_l` has less than `t+1`
+    ///   parties or indexes greater than `n`, returns [Error::ShareMismatch]
+    pub fn new(
+        i: u16,
+        params: RecoveryParams,
+        share: Option<raw_share<Secp256k1>>,
+    ) -> Result<Self> {
+        if params.s_l.len() < 2 {
+            return Err(Error::TooFewParties);
+        }
+        let n = params.parties();
+        if i == 0 || i > n {
+            return Err(Error::InvalidPartyIndex);
+        }
+        let mut s_l = params.s_l.clone();
+        s_l.sort_unstable();
+        s_l.dedup();
+        if s_l.len() != params.s_l.len()
+            || s_l.contains(&0)
+            || params.recovering == 0
+            || s_l.contains(&params.recovering)
+        {
+            return Err(Error::InvalidParams);
+        }
+        match (&share, i < n) {
+            (Some(share), true) => {
+                let local_key = &share.local_key_hd;
+                local_key.validate().map_err(Error::InvalidLocalKey)?;
+                if params.s_l[usize::from(i - 1)] != local_key.i
+                    || s_l.len() <= usize::from(local_key.t)
+                    || s_l.iter().any(|&j| j > local_key.n)
+                    || params.recovering > local_key.n
+                    || local_key.y_sum_s != params.public_key
+                {
+                    return Err(Error::ShareMismatch);
+                }
+            }
+            (None, false) => (),
+            _ => return Err(Error::InvalidRole),
+        }
+
+        let mut state = Self {
+            round: R::Round0(Round0 {
+                party_i: i,
+                params,
+                share,
+            }),
+
+            msgs1: Some(Round1::expects_messages(i, n)),
+            msgs2: Some(Round2::expects_messages(i, n)),
+            msgs3: Some(Round3::expects_messages(i, n)),
+
+            msgs_queue: vec![],
+            party_i: i,
+            party_n: n,
+        };
+
+        state.proceed_round(false)?;
+        Ok(state)
+    }
+
+    fn gmap_queue<'a, T, F>(&'a mut self, mut f: F) -> impl Push<Msg<T>> + 'a
+    where
+        F: FnMut(T) -> M + 'a,
+    {
+        (&mut self.msgs_queue).gmap(move |m: Msg<T>| m.map_body(|m| ProtocolMessage(f(m))))
+    }
+
+    /// Proceeds round state if it received enough messages and if it's cheap to compute or
+    /// `may_block == true`
+    fn proceed_round(&mut self, may_block: bool) -> Result<()> {
+        let store1_wants_more = self.msgs1.as_ref().map(|s| s.wants_more()).unwrap_or(false);
+        let store2_wants_more = self.msgs2.as_ref().map(|s| s.wants_more()).unwrap_or(false);
+        let store3_wants_more = self.msgs3.as_ref().map(|s| s.wants_more()).unwrap_or(false);
+
+        let next_state: R;
+        let try_again: bool = match replace(&mut self.round, R::Gone) {
+            R::Round0(round) if !round.is_expensive() || may_block => {
+                next_state = round
+                    .proceed(self.gmap_queue(M::Round1))
+                    .map(R::Round1)
+                    .map_err(Error::ProceedRound)?;
+                true
+            }
+            s @ R::Round0(_) => {
+                next_state = s;
+                false
+            }
+            R::Round1(round) if !store1_wants_more && (!round.is_expensive() || may_block) => {
+                let store = self.msgs1.take().ok_or(InternalError::StoreGone)?;
+                let msgs = store
+                    .finish()
+                    .map_err(InternalError::RetrieveRoundMessages)?;
+                next_state = round
+                    .proceed(msgs, self.gmap_queue(M::Round2))
+                    .map(R::Round2)
+                    .map_err(Error::ProceedRound)?;
+                true
+            }
+            s @ R::Round1(_) => {
+                next_state = s;
+                false
+            }
+            R::Round2(round) if !store2_wants_more && (!round.is_expensive() || may_block) => {
+                let store = self.msgs2.take().ok_or(InternalError::StoreGone)?;
+                let msgs = store
+                    .finish()
+                    .map_err(InternalError::RetrieveRoundMessages)?;
+                next_state = round
+                    .proceed(msgs, self.gmap_queue(M::Round3))
+                    .map(R::Round3)
+                    .map_err(Error::ProceedRound)?;
+                true
+            }
+            s @ R::Round2(_) => {
+                next_state = s;
+                false
+            }
+            R::Round3(round) if !store3_wants_more && (!round.is_expensive() || may_block) => {
+                let store = self.msgs3.take().ok_or(InternalError::StoreGone)?;
+                let msgs = store
+                    .finish()
+                    .map_err(InternalError::RetrieveRoundMessages)?;
+                next_state = round
+                    .proceed(msgs)
+                    .map(R::Final)
+                    .map_err(Error::ProceedRound)?;
+                true
+            }
+            s @ R::Round3(_) => {
+                next_state = s;
+                false
+            }
+            s @ R::Final(_) | s @ R::Gone => {
+                next_state = s;
+                false
+            }
+        };
+
+        self.round = next_state;
+        if try_again {
+            self.proceed_round(may_block)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl StateMachine for Recovery {
+    type MessageBody = ProtocolMessage;
+    type Err = Error;
+    type Output = raw_share<Secp256k1>;
+
+    fn handle_incoming(&mut self, msg: Msg<Self::MessageBody>) -> Result<()> {
+        let current_round = self.current_round();
+
+        match msg.body {
+            ProtocolMessage(M::Round1(m)) => {
+                let store = self
+                    .msgs1
+                    .as_mut()
+                    .ok_or(Error::ReceivedOutOfOrderMessage {
+                        current_round,
+                        msg_round: 1,
+                    })?;
+                store
+                    .push_msg(Msg {
+                        round: current_round,
+                        sender: msg.sender,
+                        receiver: msg.receiver,
+                        body: m,
+                    })
+                    .map_err(Error::HandleMessage)?;
+                self.proceed_round(false)
+            }
+            ProtocolMessage(M::Round2(m)) => {
+                let store = self
+                    .msgs2
+                    .as_mut()
+                    .ok_or(Error::ReceivedOutOfOrderMessage {
+                        current_round,
+                        msg_round: 2,
+                    })?;
+                store
+                    .push_msg(Msg {
+                        round: current_round,
+                        sender: msg.sender,
+                        receiver: msg.receiver,
+                        body: m,
+                    })
+                    .map_err(Error::HandleMessage)?;
+                self.proceed_round(false)
+            }
+            ProtocolMessage(M::Round3(m)) => {
+                let store = self
+                    .msgs3
+                    .as_mut()
+                    .ok_or(Error::ReceivedOutOfOrderMessage {
+                        current_round,
+                        msg_round: 3,
+                    })?;
+                store
+                    .push_msg(Msg {
+                        round: current_round,
+                        sender: msg.sender,
+                        receiver: msg.receiver,
+                        body: m,
+                    })
+                    .map_err(Error::HandleMessage)?;
+                self.proceed_round(false)
+            }
+        }
+    }
+
+    fn message_queue(&mut self) -> &mut Vec<Msg<Self::MessageBody>> {
+        &mut self.msgs_queue
+    }
+
+    fn wants_to_proceed(&self) -> bool {
+        let store1_wants_more = self.msgs1.as_ref().map(|s| s.wants_more()).unwrap_or(false);
+        let store2_wants_more = self.msgs2.as_ref().map(|s| s.wants_more()).unwrap_or(false);
+        let store3_wants_more = self.msgs3.as_ref().map(|s| s.wants_more()).unwrap_or(false);
+
+        match &self.round {
+            R::Round0(_) => true,
+            R::Round1(_) => !store1_wants_more,
+            R::Round2(_) => !store2_wants_more,
+            R::Round3(_) => !store3_wants_more,
+            R::Final(_) | R::Gone => false,
+        }
+    }
+
+    fn proceed(&mut self) -> Result<()> {
+        self.proceed_round(true)
+    }
+
+    fn round_timeout(&self) -> Option<Duration> {
+        None
+    }
+
+    fn round_timeout_reached(&mut self) -> Self::Err {
+        panic!("no timeout was set")
+    }
+
+    fn is_finished(&self) -> bool {
+        matches!(self.round, R::Final(_))
+    }
+
+    fn pick_output(&mut self) -> Option<Result<Self::Output>> {
+        match self.round {
+            R::Final(_) => (),
+            R::Gone => return Some(Err(Error::DoublePickOutput)),
+            _ => return None,
+        }
+
+        match replace(&mut self.round, R::Gone) {
+            R::Final(result) => Some(Ok(result)),
+            _ => unreachable!("guaranteed by match expression above"),
+        }
+    }
+
+    fn current_round(&self) -> u16 {
+        match &self.round {
+            R::Round0(_) => 0,
+            R::Round1(_) => 1,
+            R::Round2(_) => 2,
+            R::Round3(_) => 3,
+            R::Final(_) | R::Gone => 4,
+        }
+    }
+
+    fn total_rounds(&self) -> Option<u16> {
+        Some(3)
+    }
+
+    fn party_ind(&self) -> u16 {
+        self.party_i
+    }
+
+    fn parties(&self) -> u16 {
+        self.party_n
+    }
+}
+
+impl super::traits::RoundBlame for Recovery {
+    /// Returns number of unwilling parties and a vector of their party indexes.
+    fn round_blame(&self) -> (u16, Vec<u16>) {
+        let store1_blame = self.msgs1.as_ref().map(|s| s.blame()).unwrap_or_default();
+        let store2_blame = self.msgs2.as_ref().map(|s| s.blame()).unwrap_or_default();
+        let store3_blame = self.msgs3.as_ref().map(|s| s.blame()).unwrap_or_default();
+
+        let default = (0, vec![]);
+        match &self.round {
+            R::Round0(_) => default,
+            R::Round1(_) => store1_blame,
+            R::Round2(_) => store2_blame,
+            R::Round3(_) => store3_blame,
+            R::Final(_) | R::Gone => default,
+        }
+    }
+}
+
+impl fmt::Debug for Recovery {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let current_round = match &self.round {
+            R::Round0(_) => "0",
+            R::Round1(_) => "1",
+            R::Round2(_) => "2",
+            R::Round3(_) => "3",
+            R::Final(_) => "[Final]",
+            R::Gone => "[Gone]",
+        };
+        let msgs1 = match self.msgs1.as_ref() {
+            Some(msgs) => format!("[{}/{}]", msgs.messages_received(), msgs.messages_total()),
+            None => "[None]".into(),
+        };
+        let msgs2 = match self.msgs2.as_ref() {
+            Some(msgs) => format!("[{}/{}]", msgs.messages_received(), msgs.messages_total()),
+            None => "[None]".into(),
+        };
+        let msgs3 = match self.msgs3.as_ref() {
+            Some(msgs) => format!("[{}/{}]", msgs.messages_received(), msgs.messages_total()),
+            None => "[None]".into(),
+        };
+        write!(
+            f,
+            "{{Recovery at round={} msgs1={} msgs2={} msgs3={} queue=[len={}]}}",
+            current_round,
+            msgs1,
+            msgs2,
+            msgs3,
+            self.msgs_queue.len()
+        )
+    }
+}
+
+// Rounds
+
+enum R {
+    Round0(Round0),
+    Round1(Round1),
+    Round2(Round2),
+    Round3(Round3),
+    Final(raw_share<Secp256k1>),
+    Gone,
+}
+
+// Messages
+
+/// Protocol message which parties send on wire
+///
+/// Hides actual messages structure so it could be changed without breaking semver policy.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProtocolMessage(M);
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+enum M {
+    Round1(RecoveryBroadcast1),
+    Round2(RecoveryMessage2),
+    Round3(Option<BlindedContribution>),
+}
+
+impl crate::MessageRoundID for ProtocolMessage {
+    fn round_id(&self) -> u16 {
+        match self.0 {
+            M::Round1(_) => 1,
+            M::Round2(_) => 2,
+            M::Round3(_) => 3,
+        }
+    }
+}
+
+// Error
+
+type Result<T> = std::result::Result<T, Error>;
+
+/// Error type of recovery protocol
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum Error {
+    /// Round proceeding resulted in error
+    #[error("proceed round: {0}")]
+    ProceedRound(#[source] ProceedError),
+
+    /// Less than 2 helpers
+    #[error("at least t+1 helpers are required")]
+    TooFewParties,
+    /// Party index `i` is not in range `[1; s_l.len() + 1]`
+    #[error("party index is not in range [1; s_l.len() + 1]")]
+    InvalidPartyIndex,
+    /// `s_l` has duplicated or zero indexes, or recovering party is zero or in `s_l`
+    #[error("invalid helper or recovering party indexes")]
+    InvalidParams,
+    /// Share is given to the recovering party, or not given to a helper
+    #[error("share must be given to helpers only")]
+    InvalidRole,
+    /// Helper's share doesn't match [RecoveryParams]
+    #[error("share doesn't match recovery parameters")]
+    ShareMismatch,
+    /// Helper's key is inconsistent
+    #[error("invalid local key: {0}")]
+    InvalidLocalKey(#[source] InvalidLocalKey),
+    /// Recovering party's Paillier key or `h1, h2, N_tilde` parameters are invalid
+    #[error("invalid aux info of recovered party")]
+    InvalidAuxInfo,
+    /// Recovering party's Paillier key isn't proven to have no small factors for our
+    /// `h1, h2, N_tilde` parameters
+    #[error("invalid no small factor proof of recovered party")]
+    InvalidNoSmallFactorProof,
+    /// Couldn't prove that Paillier key of the recovered party has no small factors
+    #[error("couldn't prove that Paillier key has no small factors")]
+    ProveNoSmallFactor,
+
+    /// Received message didn't pass pre-validation
+    #[error("received message didn't pass pre-validation: {0}")]
+    HandleMessage(#[source] StoreErr),
+    /// Received message which we didn't expect to receive now (e.g. message from previous round)
+    #[error(
+        "didn't expect to receive message from round {msg_round} (being at round {current_round})"
+    )]
+    ReceivedOutOfOrderMessage { current_round: u16, msg_round: u16 },
+    /// [Recovery::pick_output] called twice
+    #[error("pick_output called twice")]
+    DoublePickOutput,
+
+    /// Some internal assertions were failed, which is a bug
+    #[doc(hidden)]
+    #[error("internal error: {0:?}")]
+    InternalError(InternalError),
+}
+
+impl IsCritical for Error {
+    fn is_critical(&self) -> bool {
+        match self {
+            Error::ProceedRound(e) => e.is_critical(),
+            Error::HandleMessage(e) => !matches!(
+                e,
+                StoreErr::MsgOverwrite | StoreErr::NotForMe | StoreErr::WantsMoreMessages
+            ),
+            Error::ReceivedOutOfOrderMessage { .. } => false,
+            Error::DoublePickOutput
+            | Error::TooFewParties
+            | Error::InvalidPartyIndex
+            | Error::InvalidParams
+            | Error::InvalidRole
+            | Error::ShareMismatch
+            | Error::InvalidLocalKey(_)
+            | Error::InvalidAuxInfo
+            | Error::InvalidNoSmallFactorProof
+            | Error::ProveNoSmallFactor
+            | Error::InternalError(_) => true,
+        }
+    }
+}
+
+impl From<InternalError> for Error {
+    fn from(err: InternalError) -> Self {
+        Self::InternalError(err)
+    }
+}
+
+mod private {
+    #[derive(Debug)]
+    #[non_exhaustive]
+    pub enum InternalError {
+        /// [Messages store](super::MessageStore) reported that it received all messages it wanted to receive,
+        /// but refused to return message container
+        RetrieveRoundMessages(super::StoreErr),
+        #[doc(hidden)]
+        StoreGone,
+    }
+}
+
+/// Paillier key and `h1, h2, N_tilde` parameters of the party which recovered its share,
+/// published by it for parties that didn't take part in recovery, see [update_recovered_party]
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RecoveredPartyUpdate {
+    pub aux: AuxInfoMessage,
+    /// `nsf_proofs[j-1]` proves that Paillier modulus has no small factors for
+    /// `h1, h2, N_tilde` parameters of party `j`
+    pub nsf_proofs: Vec<NoSmallFactorProof>,
+}
+
+impl RecoveredPartyUpdate {
+    /// Makes the update from key `recovered` output to the recovering party by recovery with
+    /// `params`
+    pub fn new(recovered: &LocalKey<Secp256k1>, params: &RecoveryParams) -> Result<Self> {
+        if recovered.i != params.recovering {
+            return Err(Error::InvalidPartyIndex);
+        }
+        // The recovering party comes after the helpers in recovery
+        let ctx = params.session_id.context(params.parties(), RECOVERY_LABEL);
+        let nsf_proofs = recovered
+            .h1_h2_n_tilde_vec
+            .iter()
+            .map(|statement| recovered.raw_key.nsf_proof_for(statement, &ctx))
+            .collect::<Option<_>>()
+            .ok_or(Error::ProveNoSmallFactor)?;
+        Ok(Self {
+            aux: recovered.raw_key.aux_info_message(&ctx),
+            nsf_proofs,
+        })
+    }
+}
+
+/// Replaces Paillier key and `h1, h2, N_tilde` parameters of party `params.recovering` which
+/// recovered its share, for parties that didn't take part in recovery
+///
+/// `aux` and `nsf_proof` are taken from [RecoveredPartyUpdate] published by the recovering
+/// party in recovery with `params`, `nsf_proof` is the one made for `local_key.i`.
+pub fn update_recovered_party(
+    local_key: &mut LocalKey<Secp256k1>,
+    params: &RecoveryParams,
+    aux: &AuxInfoMessage,
+    nsf_proof: &NoSmallFactorProof,
+) -> Result<()> {
+    let i = params.recovering;
+    if i == 0 || i > local_key.n {
+        return Err(Error::InvalidPartyIndex);
+    }
+    // The recovering party comes after the helpers in recovery
+    let prover_ctx = params.session_id.context(params.parties(), RECOVERY_LABEL);
+    if !aux.verify(&prover_ctx) {
+        return Err(Error::InvalidAuxInfo);
+    }
+    if !local_key
+        .raw_key
+        .verify_nsf_proof(&aux.e.n, nsf_proof, &prover_ctx)
+    {
+        return Err(Error::InvalidNoSmallFactorProof);
+    }
+    local_key.paillier_key_vec[usize::from(i - 1)] = aux.e.clone();
+    local_key.h1_h2_n_tilde_vec[usize::from(i - 1)] = aux.dlog_statement.clone();
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use curv::arithmetic::Converter;
+    use curv::elliptic::curves::Scalar;
+    use paillier::{Encrypt, Paillier, RawPlaintext};
+    use round_based::dev::Simulation;
+
+    use super::*;
+    use crate::protocols::multi_party_ecdsa::gg_2020::party_i::Keys;
+    use crate::protocols::multi_party_ecdsa::gg_2020::state_machine::keygen::test::simulate_keygen;
+    use crate::protocols::multi_party_ecdsa::gg_2020::state_machine::test::run_with_tamper;
+    use crate::protocols::multi_party_ecdsa::gg_2020::REFRESH_LABEL;
+    use crate::utilities::transcript::SessionId;
+
+    fn recovery_params(
+        shares: &[raw_share<Secp256k1>],
+        s_l: Vec<u16>,
+        recovering: u16,
+    ) -> RecoveryParams {
+        RecoveryParams {
+            s_l,
+            recovering,
+            public_key: shares[0].local_key_hd.y_sum_s.clone(),
+            session_id: SessionId::random(),
+        }
+    }
+
+    fn simulate_recovery(
+        shares: &[raw_share<Secp256k1>],
+        params: &RecoveryParams,
+    ) -> Vec<raw_share<Secp256k1>> {
+        let mut simulation = Simulation::new();
+        for (i, &j) in (1..).zip(&params.s_l) {
+            let share = shares[usize::from(j - 1)].clone();
+            simulation.add_party(Recovery::new(i, params.clone(), Some(share)).unwrap());
+        }
+        simulation.add_party(Recovery::new(params.parties(), params.clone(), None).unwrap());
+
+        simulation.run().unwrap()
+    }
+
+    #[test]
+    fn simulate_recovery_t1_n3() {
+        let shares = simulate_keygen(1, 3);
+        let outputs = simulate_recovery(&shares, &recovery_params(&shares, vec![1, 3], 2));
+
+        let recovered = &outputs[2];
+        recovered.local_key_hd.validate().unwrap();
+        assert_eq!(
+            recovered.local_key_hd.keys_linear.x_i,
+            shares[1].local_key_hd.keys_linear.x_i
+        );
+        assert_eq!(recovered.chain_code, shares[1].chain_code);
+
+        let new_ek = &recovered.local_key_hd.paillier_key_vec[1];
+        assert_ne!(new_ek.n, shares[1].local_key_hd.paillier_key_vec[1].n);
+        for helper in &outputs[..2] {
+            helper.local_key_hd.validate().unwrap();
+            assert_eq!(helper.local_key_hd.paillier_key_vec[1].n, new_ek.n);
+        }
+    }
+
+    #[test]
+    fn party_not_taking_part_updates_recovered_party() {
+        let shares = simulate_keygen(1, 4);
+        let params = recovery_params(&shares, vec![1, 3], 2);
+        let outputs = simulate_recovery(&shares, &params);
+
+        let update = RecoveredPartyUpdate::new(&outputs[2].local_key_hd, &params).unwrap();
+        let mut local_key = shares[3].local_key_hd.clone();
+        update_recovered_party(&mut local_key, &params, &update.aux, &update.nsf_proofs[3])
+            .unwrap();
+        local_key.validate().unwrap();
+        assert_eq!(
+            local_key.paillier_key_vec[1].n,
+            outputs[0].local_key_hd.paillier_key_vec[1].n
+        );
+
+        // proof made for another party doesn't convince us
+        let mut local_key = shares[3].local_key_hd.clone();
+        assert!(matches!(
+            update_recovered_party(&mut local_key, &params, &update.aux, &update.nsf_proofs[0]),
+            Err(Error::InvalidNoSmallFactorProof)
+        ));
+    }
+
+    #[test]
+    fn recovery_rejects_invalid_indexes_for_every_role() {
+        let shares = simulate_keygen(1, 3);
+        for (s_l, recovering) in [(vec![1, 1], 2), (vec![0, 1], 2), (vec![1, 3], 0)] {
+            let params = recovery_params(&shares, s_l, recovering);
+            assert!(matches!(
+                Recovery::new(params.parties(), params.clone(), None),
+                Err(Error::InvalidParams)
+            ));
+            assert!(matches!(
+                Recovery::new(1, params, Some(shares[0].clone())),
+                Err(Error::InvalidParams)
+            ));
+        }
+    }
+
+    #[test]
+    fn update_recovered_party_accepts_only_aux_info_of_this_recovery() {
+        let shares = simulate_keygen(1, 3);
+        let params = recovery_params(&shares, vec![2, 3], 1);
+        let keys = Keys::create(1);
+        let prover = params.parties();
+        let ctx = params.session_id.context(prover, RECOVERY_LABEL);
+        let aux = keys.aux_info_message(&ctx);
+        let statement = &shares[1].local_key_hd.h1_h2_n_tilde_vec[1];
+        let nsf_proof = keys.nsf_proof_for(statement, &ctx).unwrap();
+
+        let other_session = RecoveryParams {
+            session_id: SessionId::random(),
+            ..params.clone()
+        };
+        let mut local_key = shares[1].local_key_hd.clone();
+        assert!(matches!(
+            update_recovered_party(&mut local_key, &other_session, &aux, &nsf_proof),
+            Err(Error::InvalidAuxInfo)
+        ));
+        let other_label = keys.aux_info_message(&params.session_id.context(prover, REFRESH_LABEL));
+        assert!(matches!(
+            update_recovered_party(&mut local_key, &params, &other_label, &nsf_proof),
+            Err(Error::InvalidAuxInfo)
+        ));
+
+        update_recovered_party(&mut local_key, &params, &aux, &nsf_proof).unwrap();
+        assert_eq!(local_key.paillier_key_vec[0].n, keys.ek.n);
+    }
+
+    #[test]
+    fn recovery_blames_helper_with_bad_blinded_contribution() {
+        let shares = simulate_keygen(1, 3);
+        let params = RecoveryParams {
+            s_l: vec![1, 3],
+            recovering: 2,
+            public_key: shares[0].local_key_hd.y_sum_s.clone(),
+            session_id: SessionId::random(),
+        };
+        let parties = vec![
+            Recovery::new(1, params.clone(), Some(shares[0].clone())).unwrap(),
+            Recovery::new(2, params.clone(), Some(shares[2].clone())).unwrap(),
+            Recovery::new(3, params.clone(), None).unwrap(),
+        ];
+
+        // helper 2 replaces its contribution with encryption of a random scalar
+        let mut ek = None;
+        let results = run_with_tamper(parties, |msg| match (msg.sender, &mut msg.body) {
+            (3, ProtocolMessage(M::Round1(RecoveryBroadcast1::Recovering(aux)))) => {
+                ek = Some(aux.e.clone())
+            }
+            (2, ProtocolMessage(M::Round3(Some(contribution)))) => {
+                let ek = ek.as_ref().unwrap();
+                let forged = Scalar::<Secp256k1>::random().to_bigint();
+                contribution.encrypted_contribution =
+                    Paillier::encrypt(ek, RawPlaintext::from(forged)).0.to_bytes();
+            }
+            _ => (),
+        });
+
+        // helpers don't check each other's contributions, recovering party does
+        assert!(matches!(results[0], Some(Ok(_))));
+        assert!(matches!(results[1], Some(Ok(_))));
+        match &results[2] {
+            Some(Err(Error::ProceedRound(ProceedError::Round3VerifyContributions(err)))) => {
+                assert_eq!(err.bad_actors, vec![1])
+            }
+            result => panic!("unexpected result: {:?}", result.as_ref().map(|r| r.is_ok())),
+        }
+    }
+}
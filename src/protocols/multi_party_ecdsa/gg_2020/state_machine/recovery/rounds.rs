@@ -0,0 +1,673 @@
+use bip32::ChainCode;
+use curv::arithmetic::Converter;
+use curv::elliptic::curves::{secp256_k1::Secp256k1, Point, Scalar};
+use curv::BigInt;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use paillier::{Decrypt, Encrypt, EncryptionKey, Paillier, RawCiphertext, RawPlaintext};
+use round_based::containers::push::Push;
+use round_based::containers::{self, BroadcastMsgs, P2PMsgs, Store};
+use round_based::{IsCritical, Msg};
+use zk_paillier::zkproofs::DLogStatement;
+
+use crate::no_small_proof::no_small_proof::NoSmallFactorProof;
+use crate::protocols::multi_party_ecdsa::gg_2018::VerifiableSS;
+use crate::protocols::multi_party_ecdsa::gg_2020 as gg20;
+use gg20::hd_acount::account_manage::raw_share;
+use gg20::party_i::{AuxInfoMessage, Keys, SharedKeys};
+use gg20::state_machine::keygen::{InvalidLocalKey, LocalKey};
+use crate::utilities::secret::Secret;
+use crate::utilities::transcript::SessionId;
+use gg20::{ErrorType, RECOVERY_LABEL};
+
+/// Helpers and recovering party taking part in share recovery
+///
+/// Must be the same for all the parties. Parties `1..=s_l.len()` of recovery protocol are
+/// helpers, and party `s_l.len() + 1` is the recovering party.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RecoveryParams {
+    /// Keygen indexes of helpers, at least `t+1` of them
+    pub s_l: Vec<u16>,
+    /// Keygen index of the party whose share is recovered
+    pub recovering: u16,
+    /// Public key whose share is recovered
+    pub public_key: Point<Secp256k1>,
+    /// Session id unique for this recovery, bound into proofs of the parties
+    pub session_id: SessionId,
+}
+
+impl RecoveryParams {
+    /// Total number of parties taking part in recovery
+    pub fn parties(&self) -> u16 {
+        self.s_l.len() as u16 + 1
+    }
+}
+
+/// Broadcast of a helper: public part of its [LocalKey] and commitments `ρ·G` to masks it
+/// sends to other helpers
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct HelperBroadcast {
+    pub vss_scheme: VerifiableSS<Secp256k1>,
+    pub paillier_key_vec: Vec<EncryptionKey>,
+    pub h1_h2_n_tilde_vec: Vec<DLogStatement>,
+    /// Commitments to masks for other helpers, ordered by helper index, sender is skipped
+    pub mask_commitments: Vec<Point<Secp256k1>>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum RecoveryBroadcast1 {
+    Helper(HelperBroadcast),
+    /// Recovering party's new Paillier key and `h1, h2, N_tilde` parameters
+    Recovering(AuxInfoMessage),
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum RecoveryMessage2 {
+    /// Mask encrypted under recipient helper's Paillier key
+    Mask(Vec<u8>),
+    /// Chain code encrypted under recovering party's new Paillier key
+    ChainCode(Vec<u8>),
+    /// Proof that recovering party's new Paillier modulus has no small factors, made for
+    /// recipient helper's `h1, h2, N_tilde`
+    NoSmallFactor(NoSmallFactorProof),
+}
+
+/// Helper's lagrange-weighted share blinded by masks, encrypted under recovering party's new
+/// Paillier key
+///
+/// Masks cancel out in the sum of all the contributions, while each contribution can be checked
+/// against `pk_vec` and mask commitments.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct BlindedContribution {
+    pub encrypted_contribution: Vec<u8>,
+}
+
+struct PublicInfo {
+    vss_scheme: VerifiableSS<Secp256k1>,
+    paillier_key_vec: Vec<EncryptionKey>,
+    h1_h2_n_tilde_vec: Vec<DLogStatement>,
+}
+
+enum Role {
+    Helper {
+        local_key: LocalKey<Secp256k1>,
+        chain_code: ChainCode,
+        /// Masks for other helpers indexed by helper index, own slot is zero and never sent
+        masks: Vec<Scalar<Secp256k1>>,
+    },
+    Recovering {
+        keys: Keys,
+    },
+}
+
+pub struct Round0 {
+    pub party_i: u16,
+    pub params: RecoveryParams,
+    pub share: Option<raw_share<Secp256k1>>,
+}
+
+impl Round0 {
+    pub fn proceed<O>(self, mut output: O) -> Result<Round1>
+    where
+        O: Push<Msg<RecoveryBroadcast1>>,
+    {
+        let (role, bc) = match self.share {
+            Some(share) => {
+                let me = usize::from(self.party_i - 1);
+                let masks: Vec<Scalar<Secp256k1>> = (0..self.params.s_l.len())
+                    .map(|k| {
+                        if k == me {
+                            Scalar::zero()
+                        } else {
+                            Scalar::random()
+                        }
+                    })
+                    .collect();
+                let local_key = share.local_key_hd;
+                let bc = RecoveryBroadcast1::Helper(HelperBroadcast {
+                    vss_scheme: local_key.vss_scheme.clone(),
+                    paillier_key_vec: local_key.paillier_key_vec.clone(),
+                    h1_h2_n_tilde_vec: local_key.h1_h2_n_tilde_vec.clone(),
+                    mask_commitments: masks
+                        .iter()
+                        .enumerate()
+                        .filter(|(k, _)| *k != me)
+                        .map(|(_, mask)| Point::generator() * mask)
+                        .collect(),
+                });
+                let role = Role::Helper {
+                    local_key,
+                    chain_code: share.chain_code,
+                    masks,
+                };
+                (role, bc)
+            }
+            None => {
+                let keys = Keys::create(usize::from(self.params.recovering));
+                let ctx = self.params.session_id.context(self.party_i, RECOVERY_LABEL);
+                let bc = RecoveryBroadcast1::Recovering(keys.aux_info_message(&ctx));
+                (Role::Recovering { keys }, bc)
+            }
+        };
+
+        output.push(Msg {
+            round: 1,
+            sender: self.party_i,
+            receiver: None,
+            body: bc.clone(),
+        });
+        Ok(Round1 {
+            party_i: self.party_i,
+            params: self.params,
+            role,
+            own_bc: bc,
+        })
+    }
+    pub fn is_expensive(&self) -> bool {
+        // recovering party generates Paillier key and safe primes for N_tilde
+        self.share.is_none()
+    }
+}
+
+pub struct Round1 {
+    party_i: u16,
+    params: RecoveryParams,
+    role: Role,
+    own_bc: RecoveryBroadcast1,
+}
+
+impl Round1 {
+    pub fn proceed<O>(
+        self,
+        input: BroadcastMsgs<RecoveryBroadcast1>,
+        mut output: O,
+    ) -> Result<Round2>
+    where
+        O: Push<Msg<RecoveryMessage2>>,
+    {
+        let params = self.params;
+        let h = params.s_l.len();
+        let received = input.into_vec_including_me(self.own_bc);
+
+        let bad_actors: Vec<usize> = received
+            .iter()
+            .enumerate()
+            .filter(|(j, bc)| match bc {
+                RecoveryBroadcast1::Helper(bc) => *j >= h || bc.mask_commitments.len() != h - 1,
+                RecoveryBroadcast1::Recovering(aux) => {
+                    *j < h
+                        || !aux.verify(&params.session_id.context(*j as u16 + 1, RECOVERY_LABEL))
+                }
+            })
+            .map(|(j, _)| j)
+            .collect();
+        if !bad_actors.is_empty() {
+            return Err(ProceedError::Round1VerifyBroadcast(ErrorType {
+                error_type: "unexpected role, mask commitments or invalid aux info".to_string(),
+                bad_actors,
+                data: Vec::new(),
+            }));
+        }
+        let helper_bcs: Vec<HelperBroadcast> = received[..h]
+            .iter()
+            .map(|bc| match bc {
+                RecoveryBroadcast1::Helper(bc) => bc.clone(),
+                RecoveryBroadcast1::Recovering(_) => unreachable!("checked above"),
+            })
+            .collect();
+        let aux = match &received[h] {
+            RecoveryBroadcast1::Recovering(aux) => aux.clone(),
+            RecoveryBroadcast1::Helper(_) => unreachable!("checked above"),
+        };
+
+        // helpers trust their own key, recovering party takes public info most helpers agree on
+        let summaries: Vec<_> = helper_bcs.iter().map(public_info_summary).collect();
+        let reference_index = match &self.role {
+            Role::Helper { .. } => usize::from(self.party_i - 1),
+            Role::Recovering { .. } => majority(&summaries),
+        };
+        let bad_actors: Vec<usize> = summaries
+            .iter()
+            .enumerate()
+            .filter(|(_, s)| **s != summaries[reference_index])
+            .map(|(j, _)| j)
+            .collect();
+        if !bad_actors.is_empty() {
+            return Err(ProceedError::Round1VerifyPublicInfo(ErrorType {
+                error_type: "public info mismatch".to_string(),
+                bad_actors,
+                data: Vec::new(),
+            }));
+        }
+        let reference_bc = &helper_bcs[reference_index];
+        let public_info = PublicInfo {
+            vss_scheme: reference_bc.vss_scheme.clone(),
+            paillier_key_vec: reference_bc.paillier_key_vec.clone(),
+            h1_h2_n_tilde_vec: reference_bc.h1_h2_n_tilde_vec.clone(),
+        };
+        let parameters = &public_info.vss_scheme.parameters;
+        let n = usize::from(parameters.share_count);
+        if public_info.vss_scheme.commitments.first() != Some(&params.public_key)
+            || usize::from(parameters.threshold) >= h
+            || params.recovering > parameters.share_count
+            || params.s_l.iter().any(|&j| j > parameters.share_count)
+            || public_info.paillier_key_vec.len() != n
+            || public_info.h1_h2_n_tilde_vec.len() != n
+        {
+            return Err(ProceedError::Round1VerifyPublicKey);
+        }
+
+        match &self.role {
+            Role::Helper {
+                chain_code, masks, ..
+            } => {
+                let me = usize::from(self.party_i - 1);
+                for (k, mask) in masks.iter().enumerate() {
+                    if k == me {
+                        continue;
+                    }
+                    let ek = &public_info.paillier_key_vec[usize::from(params.s_l[k] - 1)];
+                    output.push(Msg {
+                        round: 2,
+                        sender: self.party_i,
+                        receiver: Some(k as u16 + 1),
+                        body: RecoveryMessage2::Mask(encrypt(ek, mask.to_bigint())),
+                    });
+                }
+                output.push(Msg {
+                    round: 2,
+                    sender: self.party_i,
+                    receiver: Some(h as u16 + 1),
+                    body: RecoveryMessage2::ChainCode(encrypt(
+                        &aux.e,
+                        BigInt::from_bytes(chain_code),
+                    )),
+                });
+            }
+            Role::Recovering { keys } => {
+                for (k, &j) in params.s_l.iter().enumerate() {
+                    let statement = &public_info.h1_h2_n_tilde_vec[usize::from(j - 1)];
+                    let proof = keys
+                        .nsf_proof_for(
+                            statement,
+                            &params.session_id.context(self.party_i, RECOVERY_LABEL),
+                        )
+                        .ok_or(ProceedError::Round1ProveNoSmallFactor)?;
+                    output.push(Msg {
+                        round: 2,
+                        sender: self.party_i,
+                        receiver: Some(k as u16 + 1),
+                        body: RecoveryMessage2::NoSmallFactor(proof),
+                    });
+                }
+            }
+        }
+
+        Ok(Round2 {
+            party_i: self.party_i,
+            params,
+            role: self.role,
+            mask_commitments: helper_bcs.into_iter().map(|bc| bc.mask_commitments).collect(),
+            aux,
+            public_info,
+        })
+    }
+    pub fn is_expensive(&self) -> bool {
+        true
+    }
+    pub fn expects_messages(i: u16, n: u16) -> Store<BroadcastMsgs<RecoveryBroadcast1>> {
+        containers::BroadcastMsgsStore::new(i, n)
+    }
+}
+
+pub struct Round2 {
+    party_i: u16,
+    params: RecoveryParams,
+    role: Role,
+    mask_commitments: Vec<Vec<Point<Secp256k1>>>,
+    aux: AuxInfoMessage,
+    public_info: PublicInfo,
+}
+
+impl Round2 {
+    pub fn proceed<O>(self, input: P2PMsgs<RecoveryMessage2>, mut output: O) -> Result<Round3>
+    where
+        O: Push<Msg<Option<BlindedContribution>>>,
+    {
+        let h = self.params.s_l.len();
+        let mut bad_actors = vec![];
+        let mut chain_code = None;
+
+        match &self.role {
+            Role::Helper {
+                local_key, masks, ..
+            } => {
+                let me = usize::from(self.party_i - 1);
+                let mut contribution = lagrange_coefficient_at(
+                    self.params.recovering,
+                    me,
+                    &self.params.s_l,
+                ) * local_key.keys_linear.x_i.expose();
+                for mask in masks {
+                    contribution = contribution + mask;
+                }
+                for (j, msg) in input.into_iter_indexed() {
+                    let sender = usize::from(j - 1);
+                    let is_valid = match msg {
+                        RecoveryMessage2::Mask(encrypted_mask) if sender < h => {
+                            let mask = Scalar::from_bigint(&decrypt(
+                                local_key.paillier_dk.expose(),
+                                &encrypted_mask,
+                            ));
+                            let is_valid = Point::generator() * &mask
+                                == self.mask_commitments[sender][mask_index(sender, me)];
+                            contribution = contribution - mask;
+                            is_valid
+                        }
+                        RecoveryMessage2::NoSmallFactor(proof) if sender == h => local_key
+                            .raw_key
+                            .verify_nsf_proof(
+                                &self.aux.e.n,
+                                &proof,
+                                &self.params.session_id.context(h as u16 + 1, RECOVERY_LABEL),
+                            ),
+                        _ => false,
+                    };
+                    if !is_valid {
+                        bad_actors.push(sender);
+                    }
+                }
+                if bad_actors.is_empty() {
+                    output.push(Msg {
+                        round: 3,
+                        sender: self.party_i,
+                        receiver: None,
+                        body: Some(BlindedContribution {
+                            encrypted_contribution: encrypt(
+                                &self.aux.e,
+                                contribution.to_bigint(),
+                            ),
+                        }),
+                    });
+                }
+            }
+            Role::Recovering { keys } => {
+                let mut chain_codes = vec![];
+                for (j, msg) in input.into_iter_indexed() {
+                    match msg {
+                        RecoveryMessage2::ChainCode(encrypted_chain_code) => chain_codes.push(
+                            chain_code_from_bigint(&decrypt(
+                                keys.dk.expose(),
+                                &encrypted_chain_code,
+                            )),
+                        ),
+                        _ => {
+                            chain_codes.push(None);
+                            bad_actors.push(usize::from(j - 1));
+                        }
+                    }
+                }
+                if bad_actors.is_empty() {
+                    let reference = chain_codes[majority(&chain_codes)];
+                    bad_actors.extend(
+                        chain_codes
+                            .iter()
+                            .enumerate()
+                            .filter(|(_, c)| c.is_none() || **c != reference)
+                            .map(|(j, _)| j),
+                    );
+                    chain_code = reference;
+                }
+                output.push(Msg {
+                    round: 3,
+                    sender: self.party_i,
+                    receiver: None,
+                    body: None,
+                });
+            }
+        }
+        if !bad_actors.is_empty() {
+            return Err(ProceedError::Round2VerifyMessages(ErrorType {
+                error_type: "invalid mask, chain code or nsf proof".to_string(),
+                bad_actors,
+                data: Vec::new(),
+            }));
+        }
+
+        Ok(Round3 {
+            params: self.params,
+            role: self.role,
+            mask_commitments: self.mask_commitments,
+            aux: self.aux,
+            public_info: self.public_info,
+            chain_code,
+        })
+    }
+    pub fn is_expensive(&self) -> bool {
+        true
+    }
+    pub fn expects_messages(i: u16, n: u16) -> Store<P2PMsgs<RecoveryMessage2>> {
+        containers::P2PMsgsStore::new(i, n)
+    }
+}
+
+pub struct Round3 {
+    params: RecoveryParams,
+    role: Role,
+    mask_commitments: Vec<Vec<Point<Secp256k1>>>,
+    aux: AuxInfoMessage,
+    public_info: PublicInfo,
+    chain_code: Option<ChainCode>,
+}
+
+impl Round3 {
+    pub fn proceed(
+        self,
+        input: BroadcastMsgs<Option<BlindedContribution>>,
+    ) -> Result<raw_share<Secp256k1>> {
+        let i = self.params.recovering;
+        let recovering = usize::from(i - 1);
+        let mut public_info = self.public_info;
+        public_info.paillier_key_vec[recovering] = self.aux.e.clone();
+        public_info.h1_h2_n_tilde_vec[recovering] = self.aux.dlog_statement.clone();
+
+        let (keys, chain_code) = match self.role {
+            Role::Helper {
+                mut local_key,
+                chain_code,
+                ..
+            } => {
+                local_key.paillier_key_vec = public_info.paillier_key_vec;
+                local_key.h1_h2_n_tilde_vec = public_info.h1_h2_n_tilde_vec;
+                return Ok(raw_share {
+                    local_key_hd: local_key,
+                    chain_code,
+                });
+            }
+            Role::Recovering { keys } => (
+                keys,
+                self.chain_code.ok_or(ProceedError::Round3MissingChainCode)?,
+            ),
+        };
+
+        let vss_scheme = public_info.vss_scheme;
+        let h = self.params.s_l.len();
+        let contributions = input.into_vec_including_me(None);
+        let mut x_i = Scalar::<Secp256k1>::zero();
+        let mut bad_actors = vec![];
+        for (j, contribution) in contributions[..h].iter().enumerate() {
+            let contribution = match contribution {
+                Some(contribution) => Scalar::<Secp256k1>::from_bigint(&decrypt(
+                    keys.dk.expose(),
+                    &contribution.encrypted_contribution,
+                )),
+                None => {
+                    bad_actors.push(j);
+                    continue;
+                }
+            };
+            let mut expected = vss_scheme.get_point_commitment(self.params.s_l[j])
+                * lagrange_coefficient_at(i, j, &self.params.s_l);
+            for k in (0..h).filter(|&k| k != j) {
+                expected = expected + &self.mask_commitments[j][mask_index(j, k)]
+                    - &self.mask_commitments[k][mask_index(k, j)];
+            }
+            if Point::generator() * &contribution != expected {
+                bad_actors.push(j);
+                continue;
+            }
+            x_i = x_i + contribution;
+        }
+        if !bad_actors.is_empty() {
+            return Err(ProceedError::Round3VerifyContributions(ErrorType {
+                error_type: "invalid blinded contribution".to_string(),
+                bad_actors,
+                data: Vec::new(),
+            }));
+        }
+
+        let t = vss_scheme.parameters.threshold;
+        let n = vss_scheme.parameters.share_count;
+        let pk_vec = (1..=n).map(|j| vss_scheme.get_point_commitment(j)).collect();
+        let local_key = LocalKey {
+            paillier_dk: keys.dk.clone(),
+            raw_key: keys,
+            pk_vec,
+            keys_linear: SharedKeys {
+                y: self.params.public_key.clone(),
+                x_i: Secret::new(x_i),
+            },
+            paillier_key_vec: public_info.paillier_key_vec,
+            y_sum_s: self.params.public_key,
+            h1_h2_n_tilde_vec: public_info.h1_h2_n_tilde_vec,
+            vss_scheme,
+            i,
+            t,
+            n,
+            needs_refresh: false,
+        };
+        local_key
+            .validate()
+            .map_err(ProceedError::Round3ValidateKey)?;
+
+        Ok(raw_share {
+            local_key_hd: local_key,
+            chain_code,
+        })
+    }
+    pub fn is_expensive(&self) -> bool {
+        true
+    }
+    pub fn expects_messages(
+        i: u16,
+        n: u16,
+    ) -> Store<BroadcastMsgs<Option<BlindedContribution>>> {
+        containers::BroadcastMsgsStore::new(i, n)
+    }
+}
+
+/// Lagrange coefficient of helper `j` for evaluating the shared polynomial at point `x`
+fn lagrange_coefficient_at(x: u16, j: usize, s_l: &[u16]) -> Scalar<Secp256k1> {
+    let scalar = |v: u16| Scalar::<Secp256k1>::from_bigint(&BigInt::from(u64::from(v)));
+    let x = scalar(x);
+    let x_j = scalar(s_l[j]);
+    let mut numerator = scalar(1);
+    let mut denominator = scalar(1);
+    for (k, &x_k) in s_l.iter().enumerate() {
+        if k == j {
+            continue;
+        }
+        let x_k = scalar(x_k);
+        numerator = numerator * (&x - &x_k);
+        denominator = denominator * (&x_j - &x_k);
+    }
+    numerator * denominator.invert().expect("helper indexes are distinct")
+}
+
+/// Position of mask for helper `recipient` in mask commitments of helper `sender`
+fn mask_index(sender: usize, recipient: usize) -> usize {
+    if recipient < sender {
+        recipient
+    } else {
+        recipient - 1
+    }
+}
+
+fn encrypt(ek: &EncryptionKey, m: BigInt) -> Vec<u8> {
+    Paillier::encrypt(ek, RawPlaintext::from(m)).0.to_bytes()
+}
+
+fn decrypt(dk: &paillier::DecryptionKey, c: &[u8]) -> BigInt {
+    let c = RawCiphertext::from(BigInt::from_bytes(c));
+    let m: RawPlaintext<'_> = Paillier::decrypt(dk, c);
+    m.0.into_owned()
+}
+
+type PublicInfoSummary = (u16, u16, Vec<Point<Secp256k1>>, Vec<BigInt>, Vec<[BigInt; 3]>);
+
+/// Public part of helper's key which all the helpers must agree on
+fn public_info_summary(bc: &HelperBroadcast) -> PublicInfoSummary {
+    (
+        bc.vss_scheme.parameters.threshold,
+        bc.vss_scheme.parameters.share_count,
+        bc.vss_scheme.commitments.clone(),
+        bc.paillier_key_vec.iter().map(|ek| ek.n.clone()).collect(),
+        bc.h1_h2_n_tilde_vec
+            .iter()
+            .map(|s| [s.N.clone(), s.g.clone(), s.ni.clone()])
+            .collect(),
+    )
+}
+
+/// Index of the value which occurs most often in `values`
+fn majority<T: PartialEq>(values: &[T]) -> usize {
+    (0..values.len())
+        .max_by_key(|&i| values.iter().filter(|v| **v == values[i]).count())
+        .unwrap_or(0)
+}
+
+fn chain_code_from_bigint(n: &BigInt) -> Option<ChainCode> {
+    let bytes = n.to_bytes();
+    let mut chain_code = ChainCode::default();
+    if bytes.len() > chain_code.len() {
+        return None;
+    }
+    let offset = chain_code.len() - bytes.len();
+    chain_code[offset..].copy_from_slice(&bytes);
+    Some(chain_code)
+}
+
+// Errors
+
+type Result<T> = std::result::Result<T, ProceedError>;
+
+/// Proceeding protocol error
+///
+/// Subset of [recovery errors](enum@super::Error) that can occur at protocol proceeding (i.e.
+/// after every message was received and pre-validated).
+#[derive(Debug, Error)]
+pub enum ProceedError {
+    #[error("round 1: verify broadcast: {0:?}")]
+    Round1VerifyBroadcast(ErrorType),
+    #[error("round 1: helpers disagree on public info: {0:?}")]
+    Round1VerifyPublicInfo(ErrorType),
+    #[error("round 1: helpers agreed on vss scheme which doesn't match the public key")]
+    Round1VerifyPublicKey,
+    #[error("round 1: couldn't prove that paillier modulus has no small factors")]
+    Round1ProveNoSmallFactor,
+    #[error("round 2: verify masks, chain code and nsf proofs: {0:?}")]
+    Round2VerifyMessages(ErrorType),
+    #[error("round 3: chain code wasn't received")]
+    Round3MissingChainCode,
+    #[error("round 3: verify blinded contributions: {0:?}")]
+    Round3VerifyContributions(ErrorType),
+    #[error("round 3: recovered key is inconsistent: {0}")]
+    Round3ValidateKey(#[source] InvalidLocalKey),
+}
+
+impl IsCritical for ProceedError {
+    fn is_critical(&self) -> bool {
+        true
+    }
+}
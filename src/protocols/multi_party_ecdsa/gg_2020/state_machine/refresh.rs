@@ -0,0 +1,572 @@
+//! High-level proactive key refresh protocol implementation
+//!
+//! All `n` parties holding [raw shares](raw_share) of the same key jointly re-randomize their
+//! shares by adding a fresh sharing of zero, optionally replacing their Paillier keys and
+//! `h1, h2, N_tilde` parameters as well. Public key and chain code stay the same, while shares
+//! obtained before refresh can't be combined with refreshed ones.
+
+use std::fmt;
+use std::mem::replace;
+use std::time::Duration;
+
+use curv::elliptic::curves::secp256_k1::Secp256k1;
+use round_based::containers::{
+    push::{Push, PushExt},
+    *,
+};
+use round_based::{IsCritical, Msg, StateMachine};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::protocols::multi_party_ecdsa::gg_2020::hd_acount::account_manage::raw_share;
+use crate::protocols::multi_party_ecdsa::gg_2020::party_i::PrecomputedAux;
+use crate::protocols::multi_party_ecdsa::gg_2020::state_machine::keygen::InvalidLocalKey;
+use crate::utilities::transcript::SessionId;
+
+mod rounds;
+
+use private::InternalError;
+pub use rounds::{ProceedError, RefreshBroadcast, RefreshShare};
+use rounds::{Round0, Round1, Round2};
+
+/// Key refresh protocol state machine
+///
+/// Successfully completed refresh protocol produces [raw_share] with refreshed
+/// [LocalKey](super::keygen::LocalKey) of the same public key.
+pub struct Refresh {
+    round: R,
+
+    msgs1: Option<Store<BroadcastMsgs<RefreshBroadcast>>>,
+    msgs2: Option<Store<P2PMsgs<RefreshShare>>>,
+
+    msgs_queue: Vec<Msg<ProtocolMessage>>,
+
+    party_i: u16,
+    party_n: u16,
+}
+
+impl Refresh {
+    /// Constructs a party of refresh protocol
+    ///
+    /// Takes party's `raw_share`, party index and number of parties are taken from the key.
+    /// All `n` parties that took part in keygen must take part in refresh. If `refresh_aux` is
+    /// set, party generates new Paillier key and `h1, h2, N_tilde` parameters, which is
+    /// expensive. All the parties must be given the same `session_id` unique for this refresh.
+    ///
+    /// Returns error if:
+    /// * `raw_share` holds inconsistent key, returns [Error::InvalidLocalKey]
+    /// * `raw_share` holds a key with [needs_refresh](super::keygen::LocalKey::needs_refresh) set and `refresh_aux` is not set,
+    ///   returns [Error::AuxRefreshRequired]
+    pub fn new(
+        raw_share: raw_share<Secp256k1>,
+        refresh_aux: bool,
+        session_id: SessionId,
+    ) -> Result<Self> {
+        Self::new_with_round0(raw_share, refresh_aux, None, session_id)
+    }
+
+    /// Constructs a party of refresh protocol which replaces its Paillier key and
+    /// `h1, h2, N_tilde` parameters with pre-generated `aux`, e.g. taken from
+    /// [PrimePool](crate::protocols::multi_party_ecdsa::gg_2020::prime_pool::PrimePool)
+    ///
+    /// Returns the same errors as [Refresh::new], and [Error::InvalidPrecomputedAux] if `aux`
+    /// is not valid.
+    pub fn new_with_aux(
+        raw_share: raw_share<Secp256k1>,
+        aux: PrecomputedAux,
+        session_id: SessionId,
+    ) -> Result<Self> {
+        if !aux.is_valid() {
+            return Err(Error::InvalidPrecomputedAux);
+        }
+        Self::new_with_round0(raw_share, true, Some(aux), session_id)
+    }
+
+    fn new_with_round0(
+        raw_share: raw_share<Secp256k1>,
+        refresh_aux: bool,
+        precomputed: Option<PrecomputedAux>,
+        session_id: SessionId,
+    ) -> Result<Self> {
+        raw_share
+            .local_key_hd
+            .validate()
+            .map_err(Error::InvalidLocalKey)?;
+        if raw_share.local_key_hd.needs_refresh && !refresh_aux {
+            return Err(Error::AuxRefreshRequired);
+        }
+        let i = raw_share.local_key_hd.i;
+        let n = raw_share.local_key_hd.n;
+        let mut state = Self {
+            round: R::Round0(Round0 {
+                local_key: raw_share.local_key_hd,
+                chain_code: raw_share.chain_code,
+                refresh_aux,
+                precomputed,
+                session_id,
+            }),
+
+            msgs1: Some(Round1::expects_messages(i, n)),
+            msgs2: Some(Round2::expects_messages(i, n)),
+
+            msgs_queue: vec![],
+            party_i: i,
+            party_n: n,
+        };
+
+        state.proceed_round(false)?;
+        Ok(state)
+    }
+
+    fn gmap_queue<'a, T, F>(&'a mut self, mut f: F) -> impl Push<Msg<T>> + 'a
+    where
+        F: FnMut(T) -> M + 'a,
+    {
+        (&mut self.msgs_queue).gmap(move |m: Msg<T>| m.map_body(|m| ProtocolMessage(f(m))))
+    }
+
+    /// Proceeds round state if it received enough messages and if it's cheap to compute or
+    /// `may_block == true`
+    fn proceed_round(&mut self, may_block: bool) -> Result<()> {
+        let store1_wants_more = self.msgs1.as_ref().map(|s| s.wants_more()).unwrap_or(false);
+        let store2_wants_more = self.msgs2.as_ref().map(|s| s.wants_more()).unwrap_or(false);
+
+        let next_state: R;
+        let try_again: bool = match replace(&mut self.round, R::Gone) {
+            R::Round0(round) if !round.is_expensive() || may_block => {
+                next_state = round
+                    .proceed(self.gmap_queue(M::Round1))
+                    .map(R::Round1)
+                    .map_err(Error::ProceedRound)?;
+                true
+            }
+            s @ R::Round0(_) => {
+                next_state = s;
+                false
+            }
+            R::Round1(round) if !store1_wants_more && (!round.is_expensive() || may_block) => {
+                let store = self.msgs1.take().ok_or(InternalError::StoreGone)?;
+                let msgs = store
+                    .finish()
+                    .map_err(InternalError::RetrieveRoundMessages)?;
+                next_state = round
+                    .proceed(msgs, self.gmap_queue(M::Round2))
+                    .map(R::Round2)
+                    .map_err(Error::ProceedRound)?;
+                true
+            }
+            s @ R::Round1(_) => {
+                next_state = s;
+                false
+            }
+            R::Round2(round) if !store2_wants_more && (!round.is_expensive() || may_block) => {
+                let store = self.msgs2.take().ok_or(InternalError::StoreGone)?;
+                let msgs = store
+                    .finish()
+                    .map_err(InternalError::RetrieveRoundMessages)?;
+                next_state = round
+                    .proceed(msgs)
+                    .map(R::Final)
+                    .map_err(Error::ProceedRound)?;
+                true
+            }
+            s @ R::Round2(_) => {
+                next_state = s;
+                false
+            }
+            s @ R::Final(_) | s @ R::Gone => {
+                next_state = s;
+                false
+            }
+        };
+
+        self.round = next_state;
+        if try_again {
+            self.proceed_round(may_block)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl StateMachine for Refresh {
+    type MessageBody = ProtocolMessage;
+    type Err = Error;
+    type Output = raw_share<Secp256k1>;
+
+    fn handle_incoming(&mut self, msg: Msg<Self::MessageBody>) -> Result<()> {
+        let current_round = self.current_round();
+
+        match msg.body {
+            ProtocolMessage(M::Round1(m)) => {
+                let store = self
+                    .msgs1
+                    .as_mut()
+                    .ok_or(Error::ReceivedOutOfOrderMessage {
+                        current_round,
+                        msg_round: 1,
+                    })?;
+                store
+                    .push_msg(Msg {
+                        round: current_round,
+                        sender: msg.sender,
+                        receiver: msg.receiver,
+                        body: m,
+                    })
+                    .map_err(Error::HandleMessage)?;
+                self.proceed_round(false)
+            }
+            ProtocolMessage(M::Round2(m)) => {
+                let store = self
+                    .msgs2
+                    .as_mut()
+                    .ok_or(Error::ReceivedOutOfOrderMessage {
+                        current_round,
+                        msg_round: 2,
+                    })?;
+                store
+                    .push_msg(Msg {
+                        round: current_round,
+                        sender: msg.sender,
+                        receiver: msg.receiver,
+                        body: m,
+                    })
+                    .map_err(Error::HandleMessage)?;
+                self.proceed_round(false)
+            }
+        }
+    }
+
+    fn message_queue(&mut self) -> &mut Vec<Msg<Self::MessageBody>> {
+        &mut self.msgs_queue
+    }
+
+    fn wants_to_proceed(&self) -> bool {
+        let store1_wants_more = self.msgs1.as_ref().map(|s| s.wants_more()).unwrap_or(false);
+        let store2_wants_more = self.msgs2.as_ref().map(|s| s.wants_more()).unwrap_or(false);
+
+        match &self.round {
+            R::Round0(_) => true,
+            R::Round1(_) => !store1_wants_more,
+            R::Round2(_) => !store2_wants_more,
+            R::Final(_) | R::Gone => false,
+        }
+    }
+
+    fn proceed(&mut self) -> Result<()> {
+        self.proceed_round(true)
+    }
+
+    fn round_timeout(&self) -> Option<Duration> {
+        None
+    }
+
+    fn round_timeout_reached(&mut self) -> Self::Err {
+        panic!("no timeout was set")
+    }
+
+    fn is_finished(&self) -> bool {
+        matches!(self.round, R::Final(_))
+    }
+
+    fn pick_output(&mut self) -> Option<Result<Self::Output>> {
+        match self.round {
+            R::Final(_) => (),
+            R::Gone => return Some(Err(Error::DoublePickOutput)),
+            _ => return None,
+        }
+
+        match replace(&mut self.round, R::Gone) {
+            R::Final(result) => Some(Ok(result)),
+            _ => unreachable!("guaranteed by match expression above"),
+        }
+    }
+
+    fn current_round(&self) -> u16 {
+        match &self.round {
+            R::Round0(_) => 0,
+            R::Round1(_) => 1,
+            R::Round2(_) => 2,
+            R::Final(_) | R::Gone => 3,
+        }
+    }
+
+    fn total_rounds(&self) -> Option<u16> {
+        Some(2)
+    }
+
+    fn party_ind(&self) -> u16 {
+        self.party_i
+    }
+
+    fn parties(&self) -> u16 {
+        self.party_n
+    }
+}
+
+impl super::traits::RoundBlame for Refresh {
+    /// Returns number of unwilling parties and a vector of their party indexes.
+    fn round_blame(&self) -> (u16, Vec<u16>) {
+        let store1_blame = self.msgs1.as_ref().map(|s| s.blame()).unwrap_or_default();
+        let store2_blame = self.msgs2.as_ref().map(|s| s.blame()).unwrap_or_default();
+
+        let default = (0, vec![]);
+        match &self.round {
+            R::Round0(_) => default,
+            R::Round1(_) => store1_blame,
+            R::Round2(_) => store2_blame,
+            R::Final(_) | R::Gone => default,
+        }
+    }
+}
+
+impl fmt::Debug for Refresh {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let current_round = match &self.round {
+            R::Round0(_) => "0",
+            R::Round1(_) => "1",
+            R::Round2(_) => "2",
+            R::Final(_) => "[Final]",
+            R::Gone => "[Gone]",
+        };
+        let msgs1 = match self.msgs1.as_ref() {
+            Some(msgs) => format!("[{}/{}]", msgs.messages_received(), msgs.messages_total()),
+            None => "[None]".into(),
+        };
+        let msgs2 = match self.msgs2.as_ref() {
+            Some(msgs) => format!("[{}/{}]", msgs.messages_received(), msgs.messages_total()),
+            None => "[None]".into(),
+        };
+        write!(
+            f,
+            "{{Refresh at round={} msgs1={} msgs2={} queue=[len={}]}}",
+            current_round,
+            msgs1,
+            msgs2,
+            self.msgs_queue.len()
+        )
+    }
+}
+
+// Rounds
+
+enum R {
+    Round0(Round0),
+    Round1(Round1),
+    Round2(Round2),
+    Final(raw_share<Secp256k1>),
+    Gone,
+}
+
+// Messages
+
+/// Protocol message which parties send on wire
+///
+/// Hides actual messages structure so it could be changed without breaking semver policy.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProtocolMessage(M);
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+enum M {
+    Round1(RefreshBroadcast),
+    Round2(RefreshShare),
+}
+
+impl crate::MessageRoundID for ProtocolMessage {
+    fn round_id(&self) -> u16 {
+        match self.0 {
+            M::Round1(_) => 1,
+            M::Round2(_) => 2,
+        }
+    }
+}
+
+// Error
+
+type Result<T> = std::result::Result<T, Error>;
+
+/// Error type of refresh protocol
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum Error {
+    /// Round proceeding resulted in error
+    #[error("proceed round: {0}")]
+    ProceedRound(#[source] ProceedError),
+
+    /// Key given to refresh is inconsistent
+    #[error("invalid local key: {0}")]
+    InvalidLocalKey(#[source] InvalidLocalKey),
+    /// Pre-generated Paillier key doesn't match or has unacceptable size
+    #[error("invalid precomputed Paillier key or N_tilde")]
+    InvalidPrecomputedAux,
+    /// Key migrated from legacy format can only be refreshed along with its aux info
+    #[error("local key needs refresh with new Paillier key and N_tilde")]
+    AuxRefreshRequired,
+
+    /// Received message didn't pass pre-validation
+    #[error("received message didn't pass pre-validation: {0}")]
+    HandleMessage(#[source] StoreErr),
+    /// Received message which we didn't expect to receive now (e.g. message from previous round)
+    #[error(
+        "didn't expect to receive message from round {msg_round} (being at round {current_round})"
+    )]
+    ReceivedOutOfOrderMessage { current_round: u16, msg_round: u16 },
+    /// [Refresh::pick_output] called twice
+    #[error("pick_output called twice")]
+    DoublePickOutput,
+
+    /// Some internal assertions were failed, which is a bug
+    #[doc(hidden)]
+    #[error("internal error: {0:?}")]
+    InternalError(InternalError),
+}
+
+impl IsCritical for Error {
+    fn is_critical(&self) -> bool {
+        match self {
+            Error::ProceedRound(e) => e.is_critical(),
+            Error::HandleMessage(e) => !matches!(
+                e,
+                StoreErr::MsgOverwrite | StoreErr::NotForMe | StoreErr::WantsMoreMessages
+            ),
+            Error::ReceivedOutOfOrderMessage { .. } => false,
+            Error::DoublePickOutput
+            | Error::InvalidLocalKey(_)
+            | Error::InvalidPrecomputedAux
+            | Error::AuxRefreshRequired
+            | Error::InternalError(_) => true,
+        }
+    }
+}
+
+impl From<InternalError> for Error {
+    fn from(err: InternalError) -> Self {
+        Self::InternalError(err)
+    }
+}
+
+mod private {
+    #[derive(Debug)]
+    #[non_exhaustive]
+    pub enum InternalError {
+        /// [Messages store](super::MessageStore) reported that it received all messages it wanted to receive,
+        /// but refused to return message container
+        RetrieveRoundMessages(super::StoreErr),
+        #[doc(hidden)]
+        StoreGone,
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use curv::elliptic::curves::Point;
+    use round_based::dev::Simulation;
+
+    use super::*;
+    use crate::protocols::multi_party_ecdsa::gg_2020::state_machine::keygen::test::simulate_keygen;
+    use crate::protocols::multi_party_ecdsa::gg_2020::state_machine::keygen::Old_LocalKey;
+    use crate::protocols::multi_party_ecdsa::gg_2020::state_machine::test::run_with_tamper;
+
+    pub fn simulate_refresh(
+        raw_shares: Vec<raw_share<Secp256k1>>,
+        refresh_aux: bool,
+    ) -> Vec<raw_share<Secp256k1>> {
+        let session_id = SessionId::random();
+        let mut simulation = Simulation::new();
+        for raw_share in raw_shares {
+            simulation.add_party(Refresh::new(raw_share, refresh_aux, session_id).unwrap());
+        }
+
+        let refreshed = simulation.run().unwrap();
+        for share in &refreshed {
+            share.local_key_hd.validate().unwrap();
+        }
+        refreshed
+    }
+
+    fn check_refreshed(old: &[raw_share<Secp256k1>], new: &[raw_share<Secp256k1>]) {
+        for (old, new) in old.iter().zip(new) {
+            assert_eq!(old.chain_code, new.chain_code);
+            assert_eq!(old.local_key_hd.y_sum_s, new.local_key_hd.y_sum_s);
+            assert_ne!(
+                old.local_key_hd.keys_linear.x_i,
+                new.local_key_hd.keys_linear.x_i
+            );
+        }
+    }
+
+    #[test]
+    fn simulate_refresh_t1_n3() {
+        let raw_shares = simulate_keygen(1, 3);
+        let refreshed = simulate_refresh(raw_shares.clone(), false);
+        check_refreshed(&raw_shares, &refreshed);
+    }
+
+    #[test]
+    fn migrated_legacy_key_is_refreshed_with_new_aux_info() {
+        let raw_shares: Vec<_> = simulate_keygen(1, 3)
+            .into_iter()
+            .map(|share| {
+                let mut value = serde_json::to_value(&share.local_key_hd).unwrap();
+                value.as_object_mut().unwrap().remove("raw_key");
+                let legacy: Old_LocalKey<Secp256k1> = serde_json::from_value(value).unwrap();
+                raw_share {
+                    local_key_hd: legacy.to_LocalKey().unwrap(),
+                    chain_code: share.chain_code,
+                }
+            })
+            .collect();
+
+        let result = Refresh::new(raw_shares[0].clone(), false, SessionId::random());
+        assert!(matches!(result, Err(Error::AuxRefreshRequired)));
+
+        let refreshed = simulate_refresh(raw_shares.clone(), true);
+        check_refreshed(&raw_shares, &refreshed);
+        for share in &refreshed {
+            assert!(!share.local_key_hd.needs_refresh);
+            assert_ne!(
+                share.local_key_hd.raw_key.xhi.expose(),
+                &curv::BigInt::from(0)
+            );
+        }
+    }
+
+    #[test]
+    fn refresh_blames_party_whose_zero_sharing_doesnt_match_commitments() {
+        let session_id = SessionId::random();
+        let parties: Vec<_> = simulate_keygen(1, 3)
+            .into_iter()
+            .map(|share| Refresh::new(share, false, session_id).unwrap())
+            .collect();
+
+        // party 2 broadcasts commitments that don't match shares it sends
+        let results = run_with_tamper(parties, |msg| {
+            if let (2, ProtocolMessage(M::Round1(bc))) = (msg.sender, &mut msg.body) {
+                bc.commitments[0] = &bc.commitments[0] + Point::<Secp256k1>::generator().to_point();
+            }
+        });
+
+        assert!(matches!(results[1], Some(Ok(_))));
+        for &j in &[0, 2] {
+            match &results[j] {
+                Some(Err(Error::ProceedRound(ProceedError::Round2VerifyShares(err)))) => {
+                    assert_eq!(err.bad_actors, vec![1])
+                }
+                result => panic!("unexpected result: {:?}", result.as_ref().map(|r| r.is_ok())),
+            }
+        }
+    }
+
+    #[test]
+    fn simulate_refresh_with_new_paillier_keys_t2_n3() {
+        let raw_shares = simulate_keygen(2, 3);
+        let refreshed = simulate_refresh(raw_shares.clone(), true);
+        check_refreshed(&raw_shares, &refreshed);
+        for (old, new) in raw_shares.iter().zip(&refreshed) {
+            assert_ne!(
+                old.local_key_hd.paillier_key_vec[0].n,
+                new.local_key_hd.paillier_key_vec[0].n
+            );
+        }
+    }
+}
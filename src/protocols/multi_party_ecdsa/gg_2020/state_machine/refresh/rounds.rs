@@ -0,0 +1,352 @@
+use curv::arithmetic::Converter;
+use curv::elliptic::curves::{secp256_k1::Secp256k1, Point, Scalar};
+use curv::BigInt;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use paillier::{Decrypt, Encrypt, EncryptionKey, Paillier, RawCiphertext, RawPlaintext};
+use round_based::containers::push::Push;
+use round_based::containers::{self, BroadcastMsgs, P2PMsgs, Store};
+use round_based::{IsCritical, Msg};
+use zk_paillier::zkproofs::DLogStatement;
+
+use crate::no_small_proof::no_small_proof::NoSmallFactorProof;
+use crate::protocols::multi_party_ecdsa::gg_2018::VerifiableSS;
+use crate::protocols::multi_party_ecdsa::gg_2020 as gg20;
+use gg20::hd_acount::account_manage::raw_share;
+use gg20::party_i::{AuxInfoMessage, Keys, PrecomputedAux, SharedKeys};
+use gg20::state_machine::keygen::{InvalidLocalKey, LocalKey};
+use crate::utilities::secret::Secret;
+use crate::utilities::transcript::SessionId;
+use gg20::{ErrorType, REFRESH_LABEL};
+
+/// Commitments to coefficients of party's zero-sharing polynomial, and optionally party's new
+/// Paillier key and `h1, h2, N_tilde` parameters
+///
+/// Constant term of the polynomial is always zero, so its commitment is not sent.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RefreshBroadcast {
+    pub commitments: Vec<Point<Secp256k1>>,
+    pub aux_info: Option<AuxInfoMessage>,
+}
+
+/// Share of sender's zero-sharing encrypted under recipient's Paillier key
+///
+/// If sender replaced its Paillier key, it also proves that the new modulus has no small
+/// factors.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RefreshShare {
+    pub encrypted_share: Vec<u8>,
+    pub nsf_proof: Option<NoSmallFactorProof>,
+}
+
+pub struct Round0 {
+    pub local_key: LocalKey<Secp256k1>,
+    pub chain_code: bip32::ChainCode,
+    pub refresh_aux: bool,
+    pub precomputed: Option<PrecomputedAux>,
+    pub session_id: SessionId,
+}
+
+impl Round0 {
+    pub fn proceed<O>(self, mut output: O) -> Result<Round1>
+    where
+        O: Push<Msg<RefreshBroadcast>>,
+    {
+        let i = self.local_key.i;
+        // u_i of a migrated key is unknown, it isn't used after keygen anyway
+        let u_i = if self.local_key.needs_refresh {
+            Scalar::random()
+        } else {
+            self.local_key.raw_key.u_i.expose().clone()
+        };
+        let new_keys = match self.precomputed {
+            Some(aux) => Some(Keys::create_with_aux(u_i, usize::from(i), aux)),
+            None if self.refresh_aux => Some(Keys::create_from(u_i, usize::from(i))),
+            None => None,
+        };
+
+        let (vss_scheme, shares) =
+            VerifiableSS::share(self.local_key.t, self.local_key.n, &Scalar::zero());
+        let bc = RefreshBroadcast {
+            commitments: vss_scheme.commitments[1..].to_vec(),
+            aux_info: new_keys
+                .as_ref()
+                .map(|keys| keys.aux_info_message(&self.session_id.context(i, REFRESH_LABEL))),
+        };
+        output.push(Msg {
+            round: 1,
+            sender: i,
+            receiver: None,
+            body: bc.clone(),
+        });
+        Ok(Round1 {
+            local_key: self.local_key,
+            chain_code: self.chain_code,
+            new_keys,
+            own_bc: bc,
+            shares: shares.to_vec(),
+            session_id: self.session_id,
+        })
+    }
+    pub fn is_expensive(&self) -> bool {
+        self.refresh_aux && self.precomputed.is_none()
+    }
+}
+
+pub struct Round1 {
+    local_key: LocalKey<Secp256k1>,
+    chain_code: bip32::ChainCode,
+    new_keys: Option<Keys>,
+    own_bc: RefreshBroadcast,
+    shares: Vec<Scalar<Secp256k1>>,
+    session_id: SessionId,
+}
+
+impl Round1 {
+    pub fn proceed<O>(self, input: BroadcastMsgs<RefreshBroadcast>, mut output: O) -> Result<Round2>
+    where
+        O: Push<Msg<RefreshShare>>,
+    {
+        let i = self.local_key.i;
+        let t = usize::from(self.local_key.t);
+        let received = input.into_vec_including_me(self.own_bc);
+
+        let bad_actors: Vec<usize> = received
+            .iter()
+            .enumerate()
+            .filter(|(j, bc)| {
+                let prover_ctx = self.session_id.context(*j as u16 + 1, REFRESH_LABEL);
+                bc.commitments.len() != t
+                    || bc
+                        .aux_info
+                        .as_ref()
+                        .map(|aux| !aux.verify(&prover_ctx))
+                        .unwrap_or(false)
+            })
+            .map(|(j, _)| j)
+            .collect();
+        if !bad_actors.is_empty() {
+            return Err(ProceedError::Round1VerifyBroadcast(ErrorType {
+                error_type: "invalid zero-sharing commitments or aux info".to_string(),
+                bad_actors,
+                data: Vec::new(),
+            }));
+        }
+
+        let paillier_key_vec: Vec<EncryptionKey> = received
+            .iter()
+            .zip(&self.local_key.paillier_key_vec)
+            .map(|(bc, ek)| {
+                bc.aux_info
+                    .as_ref()
+                    .map(|aux| aux.e.clone())
+                    .unwrap_or_else(|| ek.clone())
+            })
+            .collect();
+        let h1_h2_n_tilde_vec: Vec<DLogStatement> = received
+            .iter()
+            .zip(&self.local_key.h1_h2_n_tilde_vec)
+            .map(|(bc, statement)| {
+                bc.aux_info
+                    .as_ref()
+                    .map(|aux| aux.dlog_statement.clone())
+                    .unwrap_or_else(|| statement.clone())
+            })
+            .collect();
+
+        for (j, share) in (1..).zip(&self.shares) {
+            if j == i {
+                continue;
+            }
+            let recipient = usize::from(j - 1);
+            let encrypted_share = Paillier::encrypt(
+                &paillier_key_vec[recipient],
+                RawPlaintext::from(share.to_bigint()),
+            );
+            let nsf_proof = match &self.new_keys {
+                Some(keys) => Some(
+                    keys.nsf_proof_for(
+                        &h1_h2_n_tilde_vec[recipient],
+                        &self.session_id.context(i, REFRESH_LABEL),
+                    )
+                        .ok_or(ProceedError::Round1ProveNoSmallFactor)?,
+                ),
+                None => None,
+            };
+            output.push(Msg {
+                round: 2,
+                sender: i,
+                receiver: Some(j),
+                body: RefreshShare {
+                    encrypted_share: encrypted_share.0.to_bytes(),
+                    nsf_proof,
+                },
+            });
+        }
+
+        Ok(Round2 {
+            own_share: self.shares[usize::from(i - 1)].clone(),
+            local_key: self.local_key,
+            chain_code: self.chain_code,
+            new_keys: self.new_keys,
+            received,
+            paillier_key_vec,
+            h1_h2_n_tilde_vec,
+            session_id: self.session_id,
+        })
+    }
+    pub fn is_expensive(&self) -> bool {
+        true
+    }
+    pub fn expects_messages(i: u16, n: u16) -> Store<BroadcastMsgs<RefreshBroadcast>> {
+        containers::BroadcastMsgsStore::new(i, n)
+    }
+}
+
+pub struct Round2 {
+    local_key: LocalKey<Secp256k1>,
+    chain_code: bip32::ChainCode,
+    new_keys: Option<Keys>,
+    received: Vec<RefreshBroadcast>,
+    own_share: Scalar<Secp256k1>,
+    paillier_key_vec: Vec<EncryptionKey>,
+    h1_h2_n_tilde_vec: Vec<DLogStatement>,
+    session_id: SessionId,
+}
+
+impl Round2 {
+    pub fn proceed(self, input: P2PMsgs<RefreshShare>) -> Result<raw_share<Secp256k1>> {
+        let needs_refresh = self.local_key.needs_refresh && self.new_keys.is_none();
+        let keys = self
+            .new_keys
+            .unwrap_or_else(|| self.local_key.raw_key.clone());
+        let i = self.local_key.i;
+
+        let mut x_i = self.local_key.keys_linear.x_i.expose() + &self.own_share;
+        let mut bad_actors = vec![];
+        for (j, msg) in input.into_iter_indexed() {
+            let sender = usize::from(j - 1);
+            let share: Scalar<Secp256k1> = {
+                let c = RawCiphertext::from(BigInt::from_bytes(&msg.encrypted_share));
+                let raw_share: RawPlaintext<'_> = Paillier::decrypt(keys.dk.expose(), c);
+                Scalar::from_bigint(&raw_share.0.into_owned())
+            };
+            let nsf_proof_is_valid = match (&self.received[sender].aux_info, &msg.nsf_proof) {
+                (None, _) => true,
+                (Some(aux), Some(proof)) => keys.verify_nsf_proof(
+                    &aux.e.n,
+                    proof,
+                    &self.session_id.context(j, REFRESH_LABEL),
+                ),
+                (Some(_), None) => false,
+            };
+            if !nsf_proof_is_valid
+                || !validate_zero_share(&self.received[sender].commitments, &share, i)
+            {
+                bad_actors.push(sender);
+                continue;
+            }
+            x_i = x_i + share;
+        }
+        if !bad_actors.is_empty() {
+            return Err(ProceedError::Round2VerifyShares(ErrorType {
+                error_type: "invalid zero-sharing share or nsf proof".to_string(),
+                bad_actors,
+                data: Vec::new(),
+            }));
+        }
+
+        // sum of zero-sharings is a sharing of zero, so only non-constant coefficients of
+        // the shared secret polynomial change
+        let mut commitments = self.local_key.vss_scheme.commitments.clone();
+        for bc in &self.received {
+            for (k, commitment) in (1..).zip(&bc.commitments) {
+                commitments[k] = &commitments[k] + commitment;
+            }
+        }
+        let vss_scheme = VerifiableSS {
+            parameters: self.local_key.vss_scheme.parameters.clone(),
+            commitments,
+            proof: self.local_key.vss_scheme.proof.clone(),
+        };
+        let pk_vec = (1..=self.local_key.n)
+            .map(|j| vss_scheme.get_point_commitment(j))
+            .collect();
+
+        let local_key = LocalKey {
+            paillier_dk: keys.dk.clone(),
+            raw_key: keys,
+            pk_vec,
+            keys_linear: SharedKeys {
+                y: self.local_key.keys_linear.y,
+                x_i: Secret::new(x_i),
+            },
+            paillier_key_vec: self.paillier_key_vec,
+            y_sum_s: self.local_key.y_sum_s,
+            h1_h2_n_tilde_vec: self.h1_h2_n_tilde_vec,
+            vss_scheme,
+            i,
+            t: self.local_key.t,
+            n: self.local_key.n,
+            needs_refresh,
+        };
+        local_key
+            .validate()
+            .map_err(ProceedError::Round2ValidateKey)?;
+
+        Ok(raw_share {
+            local_key_hd: local_key,
+            chain_code: self.chain_code,
+        })
+    }
+    pub fn is_expensive(&self) -> bool {
+        true
+    }
+    pub fn expects_messages(i: u16, n: u16) -> Store<P2PMsgs<RefreshShare>> {
+        containers::P2PMsgsStore::new(i, n)
+    }
+}
+
+/// Checks `share` of party `index` against commitments to non-constant coefficients of a
+/// zero-sharing polynomial
+fn validate_zero_share(
+    commitments: &[Point<Secp256k1>],
+    share: &Scalar<Secp256k1>,
+    index: u16,
+) -> bool {
+    let index = Scalar::<Secp256k1>::from_bigint(&BigInt::from(u64::from(index)));
+    let mut index_pow = index.clone();
+    let mut expected = Point::<Secp256k1>::zero();
+    for commitment in commitments {
+        expected = expected + commitment * &index_pow;
+        index_pow = index_pow * &index;
+    }
+    Point::generator() * share == expected
+}
+
+// Errors
+
+type Result<T> = std::result::Result<T, ProceedError>;
+
+/// Proceeding protocol error
+///
+/// Subset of [refresh errors](enum@super::Error) that can occur at protocol proceeding (i.e.
+/// after every message was received and pre-validated).
+#[derive(Debug, Error)]
+pub enum ProceedError {
+    #[error("round 1: verify zero-sharing commitments and aux info: {0:?}")]
+    Round1VerifyBroadcast(ErrorType),
+    #[error("round 1: couldn't prove that paillier modulus has no small factors")]
+    Round1ProveNoSmallFactor,
+    #[error("round 2: verify zero-sharing shares: {0:?}")]
+    Round2VerifyShares(ErrorType),
+    #[error("round 2: refreshed key is inconsistent: {0}")]
+    Round2ValidateKey(#[source] InvalidLocalKey),
+}
+
+impl IsCritical for ProceedError {
+    fn is_critical(&self) -> bool {
+        true
+    }
+}
@@ -0,0 +1,651 @@
+//! High-level resharing protocol implementation
+//!
+//! `t+1` holders of old [LocalKey](super::keygen::LocalKey)s reshare the key to a new committee
+//! of `n'` parties with threshold `t'`. Old holders send Feldman-VSS committed sub-shares of
+//! their shares, new holders generate Paillier keys and `h1, h2, N_tilde` parameters as in
+//! keygen, and everyone verifies sub-sharings against VSS scheme of the old key. Public key and
+//! chain code stay the same, so do the addresses.
+
+use std::fmt;
+use std::mem::replace;
+use std::time::Duration;
+
+use curv::elliptic::curves::secp256_k1::Secp256k1;
+use round_based::containers::{
+    push::{Push, PushExt},
+    *,
+};
+use round_based::{IsCritical, Msg, StateMachine};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::protocols::multi_party_ecdsa::gg_2020::hd_acount::account_manage::raw_share;
+use crate::protocols::multi_party_ecdsa::gg_2020::party_i::PrecomputedAux;
+use crate::protocols::multi_party_ecdsa::gg_2020::state_machine::keygen::InvalidLocalKey;
+
+mod rounds;
+
+use private::InternalError;
+pub use rounds::{
+    OldHolderBroadcast, OldHolderShares, ProceedError, ReshareBroadcast1, ReshareBroadcast2,
+    ReshareParams,
+};
+use rounds::{Round0, Round1, Round2};
+
+/// Reshare protocol state machine
+///
+/// Successfully completed reshare protocol produces [raw_share] of the new key for new
+/// holders, and `None` for old holders.
+pub struct Reshare {
+    round: R,
+
+    msgs1: Option<Store<BroadcastMsgs<ReshareBroadcast1>>>,
+    msgs2: Option<Store<BroadcastMsgs<ReshareBroadcast2>>>,
+
+    msgs_queue: Vec<Msg<ProtocolMessage>>,
+
+    party_i: u16,
+    party_n: u16,
+}
+
+impl Reshare {
+    /// Constructs a party of reshare protocol
+    ///
+    /// Takes party index `i` (in range `[1; s_l.len() + n']`) and committees description
+    /// `params`, which must be the same for all the parties. Parties `1..=s_l.len()` are old
+    /// holders and must be given their `old_share`, party `s_l.len() + j` is a new holder of
+    /// share `j`. A device which is in both committees runs two parties.
+    ///
+    /// Returns error if:
+    /// * `n'` is less than 2 or `s_l` has less than 2 parties, returns [Error::TooFewParties]
+    /// * `t'` is not in range `[1; n'-1]`, returns [Error::InvalidThreshold]
+    /// * `i` is not in range `[1; s_l.len() + n']`, returns [Error::InvalidPartyIndex]
+    /// * `s_l` has duplicated or zero indexes, returns [Error::InvalidOldHolders]
+    /// * `old_share` is given to a new holder or missing for an old holder, returns
+    ///   [Error::InvalidRole]
+    /// * `old_share` is inconsistent, returns [Error::InvalidLocalKey]
+    /// * `old_share` is not share `s_l[i-1]` of `public_key`, or `s_l` has less than `t+1`
+    ///   parties, returns [Error::OldShareMismatch]
+    pub fn new(
+        i: u16,
+        params: ReshareParams,
+        old_share: Option<raw_share<Secp256k1>>,
+    ) -> Result<Self> {
+        Self::new_with_round0(i, params, old_share, None)
+    }
+
+    /// Constructs a new holder party of resharing protocol which uses pre-generated Paillier
+    /// key and `h1, h2, N_tilde` parameters `aux`, e.g. taken from
+    /// [PrimePool](crate::protocols::multi_party_ecdsa::gg_2020::prime_pool::PrimePool)
+    ///
+    /// Returns the same errors as [Reshare::new], and [Error::InvalidPrecomputedAux] if `aux`
+    /// is not valid.
+    pub fn new_with_aux(i: u16, params: ReshareParams, aux: PrecomputedAux) -> Result<Self> {
+        if !aux.is_valid() {
+            return Err(Error::InvalidPrecomputedAux);
+        }
+        Self::new_with_round0(i, params, None, Some(aux))
+    }
+
+    fn new_with_round0(
+        i: u16,
+        params: ReshareParams,
+        old_share: Option<raw_share<Secp256k1>>,
+        precomputed: Option<PrecomputedAux>,
+    ) -> Result<Self> {
+        if params.new_n < 2 || params.s_l.len() < 2 {
+            return Err(Error::TooFewParties);
+        }
+        if params.new_t == 0 || params.new_t >= params.new_n {
+            return Err(Error::InvalidThreshold);
+        }
+        let n = params.parties();
+        if i == 0 || i > n {
+            return Err(Error::InvalidPartyIndex);
+        }
+        let mut s_l = params.s_l.clone();
+        s_l.sort_unstable();
+        s_l.dedup();
+        if s_l.len() != params.s_l.len() || s_l.contains(&0) {
+            return Err(Error::InvalidOldHolders);
+        }
+        match (&old_share, params.new_index(i)) {
+            (Some(old_share), None) => {
+                let local_key = &old_share.local_key_hd;
+                local_key.validate().map_err(Error::InvalidLocalKey)?;
+                if params.s_l[usize::from(i - 1)] != local_key.i
+                    || params.s_l.len() <= usize::from(local_key.t)
+                    || params.s_l.iter().any(|&j| j > local_key.n)
+                    || local_key.y_sum_s != params.public_key
+                {
+                    return Err(Error::OldShareMismatch);
+                }
+            }
+            (None, Some(_)) => (),
+            _ => return Err(Error::InvalidRole),
+        }
+
+        let mut state = Self {
+            round: R::Round0(Round0 {
+                party_i: i,
+                params,
+                old_share,
+                precomputed,
+            }),
+
+            msgs1: Some(Round1::expects_messages(i, n)),
+            msgs2: Some(Round2::expects_messages(i, n)),
+
+            msgs_queue: vec![],
+            party_i: i,
+            party_n: n,
+        };
+
+        state.proceed_round(false)?;
+        Ok(state)
+    }
+
+    fn gmap_queue<'a, T, F>(&'a mut self, mut f: F) -> impl Push<Msg<T>> + 'a
+    where
+        F: FnMut(T) -> M + 'a,
+    {
+        (&mut self.msgs_queue).gmap(move |m: Msg<T>| m.map_body(|m| ProtocolMessage(f(m))))
+    }
+
+    /// Proceeds round state if it received enough messages and if it's cheap to compute or
+    /// `may_block == true`
+    fn proceed_round(&mut self, may_block: bool) -> Result<()> {
+        let store1_wants_more = self.msgs1.as_ref().map(|s| s.wants_more()).unwrap_or(false);
+        let store2_wants_more = self.msgs2.as_ref().map(|s| s.wants_more()).unwrap_or(false);
+
+        let next_state: R;
+        let try_again: bool = match replace(&mut self.round, R::Gone) {
+            R::Round0(round) if !round.is_expensive() || may_block => {
+                next_state = round
+                    .proceed(self.gmap_queue(M::Round1))
+                    .map(R::Round1)
+                    .map_err(Error::ProceedRound)?;
+                true
+            }
+            s @ R::Round0(_) => {
+                next_state = s;
+                false
+            }
+            R::Round1(round) if !store1_wants_more && (!round.is_expensive() || may_block) => {
+                let store = self.msgs1.take().ok_or(InternalError::StoreGone)?;
+                let msgs = store
+                    .finish()
+                    .map_err(InternalError::RetrieveRoundMessages)?;
+                next_state = round
+                    .proceed(msgs, self.gmap_queue(M::Round2))
+                    .map(R::Round2)
+                    .map_err(Error::ProceedRound)?;
+                true
+            }
+            s @ R::Round1(_) => {
+                next_state = s;
+                false
+            }
+            R::Round2(round) if !store2_wants_more && (!round.is_expensive() || may_block) => {
+                let store = self.msgs2.take().ok_or(InternalError::StoreGone)?;
+                let msgs = store
+                    .finish()
+                    .map_err(InternalError::RetrieveRoundMessages)?;
+                next_state = round
+                    .proceed(msgs)
+                    .map(R::Final)
+                    .map_err(Error::ProceedRound)?;
+                true
+            }
+            s @ R::Round2(_) => {
+                next_state = s;
+                false
+            }
+            s @ R::Final(_) | s @ R::Gone => {
+                next_state = s;
+                false
+            }
+        };
+
+        self.round = next_state;
+        if try_again {
+            self.proceed_round(may_block)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl StateMachine for Reshare {
+    type MessageBody = ProtocolMessage;
+    type Err = Error;
+    type Output = Option<raw_share<Secp256k1>>;
+
+    fn handle_incoming(&mut self, msg: Msg<Self::MessageBody>) -> Result<()> {
+        let current_round = self.current_round();
+
+        match msg.body {
+            ProtocolMessage(M::Round1(m)) => {
+                let store = self
+                    .msgs1
+                    .as_mut()
+                    .ok_or(Error::ReceivedOutOfOrderMessage {
+                        current_round,
+                        msg_round: 1,
+                    })?;
+                store
+                    .push_msg(Msg {
+                        round: current_round,
+                        sender: msg.sender,
+                        receiver: msg.receiver,
+                        body: m,
+                    })
+                    .map_err(Error::HandleMessage)?;
+                self.proceed_round(false)
+            }
+            ProtocolMessage(M::Round2(m)) => {
+                let store = self
+                    .msgs2
+                    .as_mut()
+                    .ok_or(Error::ReceivedOutOfOrderMessage {
+                        current_round,
+                        msg_round: 2,
+                    })?;
+                store
+                    .push_msg(Msg {
+                        round: current_round,
+                        sender: msg.sender,
+                        receiver: msg.receiver,
+                        body: m,
+                    })
+                    .map_err(Error::HandleMessage)?;
+                self.proceed_round(false)
+            }
+        }
+    }
+
+    fn message_queue(&mut self) -> &mut Vec<Msg<Self::MessageBody>> {
+        &mut self.msgs_queue
+    }
+
+    fn wants_to_proceed(&self) -> bool {
+        let store1_wants_more = self.msgs1.as_ref().map(|s| s.wants_more()).unwrap_or(false);
+        let store2_wants_more = self.msgs2.as_ref().map(|s| s.wants_more()).unwrap_or(false);
+
+        match &self.round {
+            R::Round0(_) => true,
+            R::Round1(_) => !store1_wants_more,
+            R::Round2(_) => !store2_wants_more,
+            R::Final(_) | R::Gone => false,
+        }
+    }
+
+    fn proceed(&mut self) -> Result<()> {
+        self.proceed_round(true)
+    }
+
+    fn round_timeout(&self) -> Option<Duration> {
+        None
+    }
+
+    fn round_timeout_reached(&mut self) -> Self::Err {
+        panic!("no timeout was set")
+    }
+
+    fn is_finished(&self) -> bool {
+        matches!(self.round, R::Final(_))
+    }
+
+    fn pick_output(&mut self) -> Option<Result<Self::Output>> {
+        match self.round {
+            R::Final(_) => (),
+            R::Gone => return Some(Err(Error::DoublePickOutput)),
+            _ => return None,
+        }
+
+        match replace(&mut self.round, R::Gone) {
+            R::Final(result) => Some(Ok(result)),
+            _ => unreachable!("guaranteed by match expression above"),
+        }
+    }
+
+    fn current_round(&self) -> u16 {
+        match &self.round {
+            R::Round0(_) => 0,
+            R::Round1(_) => 1,
+            R::Round2(_) => 2,
+            R::Final(_) | R::Gone => 3,
+        }
+    }
+
+    fn total_rounds(&self) -> Option<u16> {
+        Some(2)
+    }
+
+    fn party_ind(&self) -> u16 {
+        self.party_i
+    }
+
+    fn parties(&self) -> u16 {
+        self.party_n
+    }
+}
+
+impl super::traits::RoundBlame for Reshare {
+    /// Returns number of unwilling parties and a vector of their party indexes.
+    fn round_blame(&self) -> (u16, Vec<u16>) {
+        let store1_blame = self.msgs1.as_ref().map(|s| s.blame()).unwrap_or_default();
+        let store2_blame = self.msgs2.as_ref().map(|s| s.blame()).unwrap_or_default();
+
+        let default = (0, vec![]);
+        match &self.round {
+            R::Round0(_) => default,
+            R::Round1(_) => store1_blame,
+            R::Round2(_) => store2_blame,
+            R::Final(_) | R::Gone => default,
+        }
+    }
+}
+
+impl fmt::Debug for Reshare {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let current_round = match &self.round {
+            R::Round0(_) => "0",
+            R::Round1(_) => "1",
+            R::Round2(_) => "2",
+            R::Final(_) => "[Final]",
+            R::Gone => "[Gone]",
+        };
+        let msgs1 = match self.msgs1.as_ref() {
+            Some(msgs) => format!("[{}/{}]", msgs.messages_received(), msgs.messages_total()),
+            None => "[None]".into(),
+        };
+        let msgs2 = match self.msgs2.as_ref() {
+            Some(msgs) => format!("[{}/{}]", msgs.messages_received(), msgs.messages_total()),
+            None => "[None]".into(),
+        };
+        write!(
+            f,
+            "{{Reshare at round={} msgs1={} msgs2={} queue=[len={}]}}",
+            current_round,
+            msgs1,
+            msgs2,
+            self.msgs_queue.len()
+        )
+    }
+}
+
+// Rounds
+
+enum R {
+    Round0(Round0),
+    Round1(Round1),
+    Round2(Round2),
+    Final(Option<raw_share<Secp256k1>>),
+    Gone,
+}
+
+// Messages
+
+/// Protocol message which parties send on wire
+///
+/// Hides actual messages structure so it could be changed without breaking semver policy.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProtocolMessage(M);
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+enum M {
+    Round1(ReshareBroadcast1),
+    Round2(ReshareBroadcast2),
+}
+
+impl crate::MessageRoundID for ProtocolMessage {
+    fn round_id(&self) -> u16 {
+        match self.0 {
+            M::Round1(_) => 1,
+            M::Round2(_) => 2,
+        }
+    }
+}
+
+// Error
+
+type Result<T> = std::result::Result<T, Error>;
+
+/// Error type of reshare protocol
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum Error {
+    /// Round proceeding resulted in error
+    #[error("proceed round: {0}")]
+    ProceedRound(#[source] ProceedError),
+
+    /// Too few parties in one of the committees
+    #[error("at least t+1 old holders and 2 new holders are required")]
+    TooFewParties,
+    /// New threshold `t'` is not in range `[1; n'-1]`
+    #[error("new threshold is not in range [1; n'-1]")]
+    InvalidThreshold,
+    /// Party index `i` is not in range `[1; s_l.len() + n']`
+    #[error("party index is not in range [1; s_l.len() + n']")]
+    InvalidPartyIndex,
+    /// `s_l` has duplicated or zero indexes
+    #[error("old holders indexes must be distinct and non-zero")]
+    InvalidOldHolders,
+    /// Old share is given to a new holder, or not given to an old holder
+    #[error("old share must be given to old holders only")]
+    InvalidRole,
+    /// Old share doesn't match [ReshareParams]
+    #[error("old share doesn't match reshare parameters")]
+    OldShareMismatch,
+    /// Old key is inconsistent
+    #[error("invalid local key: {0}")]
+    InvalidLocalKey(#[source] InvalidLocalKey),
+    /// Pre-generated Paillier key doesn't match or has unacceptable size
+    #[error("invalid precomputed Paillier key or N_tilde")]
+    InvalidPrecomputedAux,
+
+    /// Received message didn't pass pre-validation
+    #[error("received message didn't pass pre-validation: {0}")]
+    HandleMessage(#[source] StoreErr),
+    /// Received message which we didn't expect to receive now (e.g. message from previous round)
+    #[error(
+        "didn't expect to receive message from round {msg_round} (being at round {current_round})"
+    )]
+    ReceivedOutOfOrderMessage { current_round: u16, msg_round: u16 },
+    /// [Reshare::pick_output] called twice
+    #[error("pick_output called twice")]
+    DoublePickOutput,
+
+    /// Some internal assertions were failed, which is a bug
+    #[doc(hidden)]
+    #[error("internal error: {0:?}")]
+    InternalError(InternalError),
+}
+
+impl IsCritical for Error {
+    fn is_critical(&self) -> bool {
+        match self {
+            Error::ProceedRound(e) => e.is_critical(),
+            Error::HandleMessage(e) => !matches!(
+                e,
+                StoreErr::MsgOverwrite | StoreErr::NotForMe | StoreErr::WantsMoreMessages
+            ),
+            Error::ReceivedOutOfOrderMessage { .. } => false,
+            Error::DoublePickOutput
+            | Error::TooFewParties
+            | Error::InvalidThreshold
+            | Error::InvalidPartyIndex
+            | Error::InvalidOldHolders
+            | Error::InvalidRole
+            | Error::OldShareMismatch
+            | Error::InvalidLocalKey(_)
+            | Error::InvalidPrecomputedAux
+            | Error::InternalError(_) => true,
+        }
+    }
+}
+
+impl From<InternalError> for Error {
+    fn from(err: InternalError) -> Self {
+        Self::InternalError(err)
+    }
+}
+
+mod private {
+    #[derive(Debug)]
+    #[non_exhaustive]
+    pub enum InternalError {
+        /// [Messages store](super::MessageStore) reported that it received all messages it wanted to receive,
+        /// but refused to return message container
+        RetrieveRoundMessages(super::StoreErr),
+        #[doc(hidden)]
+        StoreGone,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use curv::elliptic::curves::Point;
+    use round_based::dev::Simulation;
+
+    use super::*;
+    use crate::protocols::multi_party_ecdsa::gg_2020::state_machine::keygen::test::simulate_keygen;
+    use crate::protocols::multi_party_ecdsa::gg_2020::state_machine::test::run_with_tamper;
+    use crate::utilities::transcript::SessionId;
+
+    fn simulate_reshare(
+        old_shares: Vec<raw_share<Secp256k1>>,
+        s_l: Vec<u16>,
+        new_t: u16,
+        new_n: u16,
+    ) -> Vec<raw_share<Secp256k1>> {
+        let params = ReshareParams {
+            s_l: s_l.clone(),
+            new_t,
+            new_n,
+            public_key: old_shares[0].local_key_hd.y_sum_s.clone(),
+            session_id: SessionId::random(),
+        };
+
+        let mut simulation = Simulation::new();
+        for (i, &j) in (1..).zip(&s_l) {
+            let old_share = old_shares[usize::from(j - 1)].clone();
+            simulation.add_party(Reshare::new(i, params.clone(), Some(old_share)).unwrap());
+        }
+        for i in s_l.len() as u16 + 1..=params.parties() {
+            simulation.add_party(Reshare::new(i, params.clone(), None).unwrap());
+        }
+
+        let outputs = simulation.run().unwrap();
+        assert!(outputs[..s_l.len()].iter().all(Option::is_none));
+        outputs[s_l.len()..]
+            .iter()
+            .cloned()
+            .map(Option::unwrap)
+            .collect()
+    }
+
+    #[test]
+    fn simulate_reshare_t1_n3_to_t2_n4() {
+        let old_shares = simulate_keygen(1, 3);
+        let new_shares = simulate_reshare(old_shares.clone(), vec![1, 3], 2, 4);
+
+        assert_eq!(new_shares.len(), 4);
+        for (j, share) in (1..).zip(&new_shares) {
+            share.local_key_hd.validate().unwrap();
+            assert_eq!(share.local_key_hd.i, j);
+            assert_eq!(share.local_key_hd.t, 2);
+            assert_eq!(share.local_key_hd.y_sum_s, old_shares[0].local_key_hd.y_sum_s);
+            assert_eq!(share.chain_code, old_shares[0].chain_code);
+        }
+    }
+
+    /// Runs resharing of a `t=1, n=3` key by all three holders to `t'=1, n'=2`, where old
+    /// holder 2 tampers with its round 1 broadcast, returns errors of the other parties
+    fn reshare_with_cheating_old_holder<F>(mut tamper: F) -> Vec<ProceedError>
+    where
+        F: FnMut(&mut OldHolderBroadcast),
+    {
+        let old_shares = simulate_keygen(1, 3);
+        let params = ReshareParams {
+            s_l: vec![1, 2, 3],
+            new_t: 1,
+            new_n: 2,
+            public_key: old_shares[0].local_key_hd.y_sum_s.clone(),
+            session_id: SessionId::random(),
+        };
+        let parties: Vec<_> = (1..=params.parties())
+            .map(|i| {
+                let old_share = old_shares.get(usize::from(i - 1)).cloned();
+                Reshare::new(i, params.clone(), old_share).unwrap()
+            })
+            .collect();
+
+        let results = run_with_tamper(parties, |msg| {
+            if let (2, ProtocolMessage(M::Round1(ReshareBroadcast1::Old(bc)))) =
+                (msg.sender, &mut msg.body)
+            {
+                tamper(bc)
+            }
+        });
+        results
+            .into_iter()
+            .enumerate()
+            .filter(|(j, _)| *j != 1)
+            .map(|(_, result)| match result {
+                Some(Err(Error::ProceedRound(err))) => err,
+                result => panic!("expected proceed error, got {:?}", result.map(|r| r.is_ok())),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn reshare_blames_old_holder_with_mismatched_vss_scheme() {
+        let errors = reshare_with_cheating_old_holder(|bc| {
+            let g = Point::<Secp256k1>::generator().to_point();
+            bc.vss_scheme.commitments[1] = &bc.vss_scheme.commitments[1] + g;
+        });
+        // old holders compare with their own key, new holders side with the majority
+        for err in errors {
+            match err {
+                ProceedError::Round1VerifyVssScheme(err) => assert_eq!(err.bad_actors, vec![1]),
+                err => panic!("unexpected error: {}", err),
+            }
+        }
+    }
+
+    #[test]
+    fn reshare_blames_old_holder_with_bad_sub_sharing() {
+        let errors = reshare_with_cheating_old_holder(|bc| {
+            let g = Point::<Secp256k1>::generator().to_point();
+            bc.sub_vss.commitments[0] = &bc.sub_vss.commitments[0] + g;
+        });
+        for err in errors {
+            match err {
+                ProceedError::Round1VerifyBroadcast(err) => assert_eq!(err.bad_actors, vec![1]),
+                err => panic!("unexpected error: {}", err),
+            }
+        }
+    }
+
+    #[test]
+    fn reshare_rejects_duplicated_old_holders_for_every_role() {
+        let old_shares = simulate_keygen(1, 3);
+        let params = ReshareParams {
+            s_l: vec![1, 1],
+            new_t: 1,
+            new_n: 2,
+            public_key: old_shares[0].local_key_hd.y_sum_s.clone(),
+            session_id: SessionId::random(),
+        };
+        assert!(matches!(
+            Reshare::new(1, params.clone(), Some(old_shares[0].clone())),
+            Err(Error::InvalidOldHolders)
+        ));
+        assert!(matches!(
+            Reshare::new(params.parties(), params, None),
+            Err(Error::InvalidOldHolders)
+        ));
+    }
+}
@@ -0,0 +1,543 @@
+use bip32::ChainCode;
+use curv::arithmetic::Converter;
+use curv::elliptic::curves::{secp256_k1::Secp256k1, Point, Scalar};
+use curv::BigInt;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use paillier::{Decrypt, Encrypt, EncryptionKey, Paillier, RawCiphertext, RawPlaintext};
+use round_based::containers::push::Push;
+use round_based::containers::{self, BroadcastMsgs, Store};
+use round_based::{IsCritical, Msg};
+use zk_paillier::zkproofs::DLogStatement;
+
+use crate::no_small_proof::no_small_proof::NoSmallFactorProof;
+use crate::protocols::multi_party_ecdsa::gg_2018::VerifiableSS;
+use crate::protocols::multi_party_ecdsa::gg_2020 as gg20;
+use gg20::hd_acount::account_manage::raw_share;
+use gg20::party_i::{AuxInfoMessage, Keys, PrecomputedAux, SharedKeys};
+use gg20::state_machine::keygen::{InvalidLocalKey, LocalKey};
+use crate::utilities::secret::Secret;
+use crate::utilities::transcript::SessionId;
+use gg20::{ErrorType, RESHARE_LABEL};
+
+/// Old and new committees taking part in resharing
+///
+/// Must be the same for all the parties. Parties `1..=s_l.len()` of resharing protocol are old
+/// holders, and party `s_l.len() + j` is a new holder which receives share `j` of the new key.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ReshareParams {
+    /// Keygen indexes of old holders taking part in resharing, at least `t+1` of them
+    pub s_l: Vec<u16>,
+    /// Threshold of the new key
+    pub new_t: u16,
+    /// Number of new holders
+    pub new_n: u16,
+    /// Public key being reshared
+    pub public_key: Point<Secp256k1>,
+    /// Session id unique for this resharing, bound into proofs of the parties
+    pub session_id: SessionId,
+}
+
+impl ReshareParams {
+    /// Total number of parties taking part in resharing
+    pub fn parties(&self) -> u16 {
+        self.s_l.len() as u16 + self.new_n
+    }
+
+    /// Index of new holder's share if party `i` is a new holder
+    pub fn new_index(&self, i: u16) -> Option<u16> {
+        let old_n = self.s_l.len() as u16;
+        if i > old_n {
+            Some(i - old_n)
+        } else {
+            None
+        }
+    }
+}
+
+/// Broadcast of an old holder: VSS scheme of the old key and commitments to its sub-sharing
+///
+/// Constant term of the sub-sharing is old holder's share multiplied by its lagrange
+/// coefficient, so sub-sharings of all the old holders sum up to a sharing of the same secret.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct OldHolderBroadcast {
+    pub vss_scheme: VerifiableSS<Secp256k1>,
+    pub sub_vss: VerifiableSS<Secp256k1>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum ReshareBroadcast1 {
+    Old(OldHolderBroadcast),
+    /// New holder's Paillier key and `h1, h2, N_tilde` parameters
+    New(AuxInfoMessage),
+}
+
+/// Sub-shares and chain code encrypted under Paillier keys of new holders, ordered by new index
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct OldHolderShares {
+    pub encrypted_shares: Vec<Vec<u8>>,
+    pub encrypted_chain_codes: Vec<Vec<u8>>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum ReshareBroadcast2 {
+    Old(OldHolderShares),
+    /// Proofs that new holder's Paillier modulus has no small factors, made for every new
+    /// holder's `h1, h2, N_tilde` and ordered by new index
+    New(Vec<NoSmallFactorProof>),
+}
+
+enum Role {
+    Old {
+        local_key: LocalKey<Secp256k1>,
+        chain_code: ChainCode,
+        sub_shares: Vec<Scalar<Secp256k1>>,
+    },
+    New {
+        keys: Keys,
+    },
+}
+
+pub struct Round0 {
+    pub party_i: u16,
+    pub params: ReshareParams,
+    pub old_share: Option<raw_share<Secp256k1>>,
+    pub precomputed: Option<PrecomputedAux>,
+}
+
+impl Round0 {
+    pub fn proceed<O>(self, mut output: O) -> Result<Round1>
+    where
+        O: Push<Msg<ReshareBroadcast1>>,
+    {
+        let (role, bc) = match self.old_share {
+            Some(old_share) => {
+                let local_key = old_share.local_key_hd;
+                let s: Vec<u16> = self.params.s_l.iter().map(|&j| j - 1).collect();
+                let li = VerifiableSS::<Secp256k1>::map_share_to_new_params(
+                    &local_key.vss_scheme.parameters,
+                    local_key.i - 1,
+                    &s,
+                );
+                let w_i = li * local_key.keys_linear.x_i.expose();
+                let (sub_vss, sub_shares) =
+                    VerifiableSS::share(self.params.new_t, self.params.new_n, &w_i);
+                let bc = ReshareBroadcast1::Old(OldHolderBroadcast {
+                    vss_scheme: local_key.vss_scheme.clone(),
+                    sub_vss,
+                });
+                let role = Role::Old {
+                    local_key,
+                    chain_code: old_share.chain_code,
+                    sub_shares: sub_shares.to_vec(),
+                };
+                (role, bc)
+            }
+            None => {
+                let new_index = self
+                    .params
+                    .new_index(self.party_i)
+                    .expect("guaranteed by Reshare::new");
+                let keys = match self.precomputed {
+                    Some(aux) => {
+                        Keys::create_with_aux(Scalar::random(), usize::from(new_index), aux)
+                    }
+                    None => Keys::create(usize::from(new_index)),
+                };
+                let ctx = self.params.session_id.context(self.party_i, RESHARE_LABEL);
+                let bc = ReshareBroadcast1::New(keys.aux_info_message(&ctx));
+                (Role::New { keys }, bc)
+            }
+        };
+
+        output.push(Msg {
+            round: 1,
+            sender: self.party_i,
+            receiver: None,
+            body: bc.clone(),
+        });
+        Ok(Round1 {
+            party_i: self.party_i,
+            params: self.params,
+            role,
+            own_bc: bc,
+        })
+    }
+    pub fn is_expensive(&self) -> bool {
+        // new holders generate Paillier key and safe primes for N_tilde
+        self.old_share.is_none() && self.precomputed.is_none()
+    }
+}
+
+pub struct Round1 {
+    party_i: u16,
+    params: ReshareParams,
+    role: Role,
+    own_bc: ReshareBroadcast1,
+}
+
+impl Round1 {
+    pub fn proceed<O>(
+        self,
+        input: BroadcastMsgs<ReshareBroadcast1>,
+        mut output: O,
+    ) -> Result<Round2>
+    where
+        O: Push<Msg<ReshareBroadcast2>>,
+    {
+        let params = self.params;
+        let old_n = params.s_l.len();
+        let received = input.into_vec_including_me(self.own_bc);
+
+        // every party must broadcast message of its role
+        let bad_actors: Vec<usize> = received
+            .iter()
+            .enumerate()
+            .filter(|(j, bc)| match bc {
+                ReshareBroadcast1::Old(_) => *j >= old_n,
+                ReshareBroadcast1::New(_) => *j < old_n,
+            })
+            .map(|(j, _)| j)
+            .collect();
+        if !bad_actors.is_empty() {
+            return Err(ProceedError::Round1VerifyBroadcast(ErrorType {
+                error_type: "unexpected role".to_string(),
+                bad_actors,
+                data: Vec::new(),
+            }));
+        }
+        let old_bcs: Vec<&OldHolderBroadcast> = received[..old_n]
+            .iter()
+            .map(|bc| match bc {
+                ReshareBroadcast1::Old(bc) => bc,
+                ReshareBroadcast1::New(_) => unreachable!("checked above"),
+            })
+            .collect();
+        let aux_infos: Vec<&AuxInfoMessage> = received[old_n..]
+            .iter()
+            .map(|bc| match bc {
+                ReshareBroadcast1::New(aux) => aux,
+                ReshareBroadcast1::Old(_) => unreachable!("checked above"),
+            })
+            .collect();
+
+        // old holders trust their own key, new holders take VSS scheme most old holders agree on
+        let old_vss_vec: Vec<_> = old_bcs.iter().map(|bc| vss_summary(&bc.vss_scheme)).collect();
+        let old_vss_index = match &self.role {
+            Role::Old { .. } => usize::from(self.party_i - 1),
+            Role::New { .. } => majority(&old_vss_vec),
+        };
+        let bad_actors: Vec<usize> = old_vss_vec
+            .iter()
+            .enumerate()
+            .filter(|(_, vss)| **vss != old_vss_vec[old_vss_index])
+            .map(|(j, _)| j)
+            .collect();
+        if !bad_actors.is_empty() {
+            return Err(ProceedError::Round1VerifyVssScheme(ErrorType {
+                error_type: "vss scheme mismatch".to_string(),
+                bad_actors,
+                data: Vec::new(),
+            }));
+        }
+        let old_vss = &old_bcs[old_vss_index].vss_scheme;
+        if old_vss.commitments.first() != Some(&params.public_key)
+            || usize::from(old_vss.parameters.threshold) >= old_n
+            || params
+                .s_l
+                .iter()
+                .any(|&j| j == 0 || j > old_vss.parameters.share_count)
+        {
+            return Err(ProceedError::Round1VerifyOldVss);
+        }
+        let s: Vec<u16> = params.s_l.iter().map(|&j| j - 1).collect();
+
+        let mut bad_actors = vec![];
+        for (j, bc) in old_bcs.iter().enumerate() {
+            let li = VerifiableSS::<Secp256k1>::map_share_to_new_params(
+                &old_vss.parameters,
+                s[j],
+                &s,
+            );
+            let expected_constant = old_vss.get_point_commitment(params.s_l[j]) * li;
+            let is_valid = bc.sub_vss.parameters.threshold == params.new_t
+                && bc.sub_vss.parameters.share_count == params.new_n
+                && bc.sub_vss.commitments.len() == usize::from(params.new_t) + 1
+                && bc.sub_vss.commitments[0] == expected_constant;
+            if !is_valid {
+                bad_actors.push(j);
+            }
+        }
+        for (j, aux) in aux_infos.iter().enumerate() {
+            let prover_ctx = params
+                .session_id
+                .context((old_n + j) as u16 + 1, RESHARE_LABEL);
+            if !aux.verify(&prover_ctx) {
+                bad_actors.push(old_n + j);
+            }
+        }
+        if !bad_actors.is_empty() {
+            return Err(ProceedError::Round1VerifyBroadcast(ErrorType {
+                error_type: "invalid sub-sharing or aux info".to_string(),
+                bad_actors,
+                data: Vec::new(),
+            }));
+        }
+
+        let body = match &self.role {
+            Role::Old {
+                chain_code,
+                sub_shares,
+                ..
+            } => {
+                let encrypt = |ek: &EncryptionKey, m: BigInt| {
+                    Paillier::encrypt(ek, RawPlaintext::from(m)).0.to_bytes()
+                };
+                ReshareBroadcast2::Old(OldHolderShares {
+                    encrypted_shares: aux_infos
+                        .iter()
+                        .zip(sub_shares)
+                        .map(|(aux, share)| encrypt(&aux.e, share.to_bigint()))
+                        .collect(),
+                    encrypted_chain_codes: aux_infos
+                        .iter()
+                        .map(|aux| encrypt(&aux.e, BigInt::from_bytes(chain_code)))
+                        .collect(),
+                })
+            }
+            Role::New { keys } => ReshareBroadcast2::New(
+                aux_infos
+                    .iter()
+                    .map(|aux| {
+                        keys.nsf_proof_for(
+                            &aux.dlog_statement,
+                            &params.session_id.context(self.party_i, RESHARE_LABEL),
+                        )
+                    })
+                    .collect::<Option<Vec<_>>>()
+                    .ok_or(ProceedError::Round1ProveNoSmallFactor)?,
+            ),
+        };
+        output.push(Msg {
+            round: 2,
+            sender: self.party_i,
+            receiver: None,
+            body: body.clone(),
+        });
+
+        Ok(Round2 {
+            party_i: self.party_i,
+            sub_vss_vec: old_bcs.iter().map(|bc| bc.sub_vss.clone()).collect(),
+            paillier_key_vec: aux_infos.iter().map(|aux| aux.e.clone()).collect(),
+            h1_h2_n_tilde_vec: aux_infos
+                .iter()
+                .map(|aux| aux.dlog_statement.clone())
+                .collect(),
+            params,
+            role: self.role,
+            own_bc: body,
+        })
+    }
+    pub fn is_expensive(&self) -> bool {
+        true
+    }
+    pub fn expects_messages(i: u16, n: u16) -> Store<BroadcastMsgs<ReshareBroadcast1>> {
+        containers::BroadcastMsgsStore::new(i, n)
+    }
+}
+
+pub struct Round2 {
+    party_i: u16,
+    params: ReshareParams,
+    role: Role,
+    own_bc: ReshareBroadcast2,
+    sub_vss_vec: Vec<VerifiableSS<Secp256k1>>,
+    paillier_key_vec: Vec<EncryptionKey>,
+    h1_h2_n_tilde_vec: Vec<DLogStatement>,
+}
+
+impl Round2 {
+    pub fn proceed(
+        self,
+        input: BroadcastMsgs<ReshareBroadcast2>,
+    ) -> Result<Option<raw_share<Secp256k1>>> {
+        let keys = match self.role {
+            Role::Old { .. } => return Ok(None),
+            Role::New { keys } => keys,
+        };
+        let params = self.params;
+        let old_n = params.s_l.len();
+        let new_n = usize::from(params.new_n);
+        let new_index = params
+            .new_index(self.party_i)
+            .expect("guaranteed by Reshare::new");
+        let me = usize::from(new_index - 1);
+        let received = input.into_vec_including_me(self.own_bc);
+
+        let decrypt = |c: &[u8]| -> BigInt {
+            let c = RawCiphertext::from(BigInt::from_bytes(c));
+            let m: RawPlaintext<'_> = Paillier::decrypt(keys.dk.expose(), c);
+            m.0.into_owned()
+        };
+
+        let mut bad_actors = vec![];
+        let mut x_i = Scalar::<Secp256k1>::zero();
+        let mut chain_codes = vec![];
+        for (j, bc) in received.iter().enumerate() {
+            let is_valid = match (bc, j < old_n) {
+                (ReshareBroadcast2::Old(shares), true) => {
+                    if shares.encrypted_shares.len() != new_n
+                        || shares.encrypted_chain_codes.len() != new_n
+                    {
+                        false
+                    } else {
+                        let share = Scalar::from_bigint(&decrypt(&shares.encrypted_shares[me]));
+                        let chain_code = chain_code_from_bigint(&decrypt(
+                            &shares.encrypted_chain_codes[me],
+                        ));
+                        let share_is_valid = self.sub_vss_vec[j]
+                            .validate_share(&share, new_index)
+                            .is_ok();
+                        x_i = x_i + share;
+                        chain_codes.push(chain_code);
+                        share_is_valid
+                    }
+                }
+                (ReshareBroadcast2::New(nsf_proofs), false) => {
+                    j - old_n == me
+                        || (nsf_proofs.len() == new_n
+                            && keys.verify_nsf_proof(
+                                &self.paillier_key_vec[j - old_n].n,
+                                &nsf_proofs[me],
+                                &params.session_id.context(j as u16 + 1, RESHARE_LABEL),
+                            ))
+                }
+                _ => false,
+            };
+            if !is_valid {
+                bad_actors.push(j);
+            }
+        }
+        if !bad_actors.is_empty() {
+            return Err(ProceedError::Round2VerifyShares(ErrorType {
+                error_type: "invalid sub-share or nsf proof".to_string(),
+                bad_actors,
+                data: Vec::new(),
+            }));
+        }
+
+        let chain_code = chain_codes[majority(&chain_codes)];
+        let bad_actors: Vec<usize> = chain_codes
+            .iter()
+            .enumerate()
+            .filter(|(_, c)| **c != chain_code)
+            .map(|(j, _)| j)
+            .collect();
+        let chain_code = match chain_code {
+            Some(chain_code) if bad_actors.is_empty() => chain_code,
+            _ => {
+                return Err(ProceedError::Round2VerifyChainCode(ErrorType {
+                    error_type: "chain code mismatch".to_string(),
+                    bad_actors,
+                    data: Vec::new(),
+                }))
+            }
+        };
+
+        let vss_scheme = Keys::get_global_vss(&self.sub_vss_vec);
+        let pk_vec = (1..=params.new_n)
+            .map(|j| vss_scheme.get_point_commitment(j))
+            .collect();
+        let local_key = LocalKey {
+            paillier_dk: keys.dk.clone(),
+            raw_key: keys,
+            pk_vec,
+            keys_linear: SharedKeys {
+                y: params.public_key.clone(),
+                x_i: Secret::new(x_i),
+            },
+            paillier_key_vec: self.paillier_key_vec,
+            y_sum_s: params.public_key,
+            h1_h2_n_tilde_vec: self.h1_h2_n_tilde_vec,
+            vss_scheme,
+            i: new_index,
+            t: params.new_t,
+            n: params.new_n,
+            needs_refresh: false,
+        };
+        local_key
+            .validate()
+            .map_err(ProceedError::Round2ValidateKey)?;
+
+        Ok(Some(raw_share {
+            local_key_hd: local_key,
+            chain_code,
+        }))
+    }
+    pub fn is_expensive(&self) -> bool {
+        true
+    }
+    pub fn expects_messages(i: u16, n: u16) -> Store<BroadcastMsgs<ReshareBroadcast2>> {
+        containers::BroadcastMsgsStore::new(i, n)
+    }
+}
+
+/// Public part of VSS scheme which old holders must agree on
+fn vss_summary(vss: &VerifiableSS<Secp256k1>) -> (u16, u16, Vec<Point<Secp256k1>>) {
+    (
+        vss.parameters.threshold,
+        vss.parameters.share_count,
+        vss.commitments.clone(),
+    )
+}
+
+/// Index of the value which occurs most often in `values`
+fn majority<T: PartialEq>(values: &[T]) -> usize {
+    (0..values.len())
+        .max_by_key(|&i| values.iter().filter(|v| **v == values[i]).count())
+        .unwrap_or(0)
+}
+
+fn chain_code_from_bigint(n: &BigInt) -> Option<ChainCode> {
+    let bytes = n.to_bytes();
+    let mut chain_code = ChainCode::default();
+    if bytes.len() > chain_code.len() {
+        return None;
+    }
+    let offset = chain_code.len() - bytes.len();
+    chain_code[offset..].copy_from_slice(&bytes);
+    Some(chain_code)
+}
+
+// Errors
+
+type Result<T> = std::result::Result<T, ProceedError>;
+
+/// Proceeding protocol error
+///
+/// Subset of [reshare errors](enum@super::Error) that can occur at protocol proceeding (i.e.
+/// after every message was received and pre-validated).
+#[derive(Debug, Error)]
+pub enum ProceedError {
+    #[error("round 1: verify sub-sharings and aux info: {0:?}")]
+    Round1VerifyBroadcast(ErrorType),
+    #[error("round 1: old holders disagree on vss scheme: {0:?}")]
+    Round1VerifyVssScheme(ErrorType),
+    #[error("round 1: old holders agreed on vss scheme which doesn't match the public key")]
+    Round1VerifyOldVss,
+    #[error("round 1: couldn't prove that paillier modulus has no small factors")]
+    Round1ProveNoSmallFactor,
+    #[error("round 2: verify sub-shares: {0:?}")]
+    Round2VerifyShares(ErrorType),
+    #[error("round 2: verify chain code: {0:?}")]
+    Round2VerifyChainCode(ErrorType),
+    #[error("round 2: reshared key is inconsistent: {0}")]
+    Round2ValidateKey(#[source] InvalidLocalKey),
+}
+
+impl IsCritical for ProceedError {
+    fn is_critical(&self) -> bool {
+        true
+    }
+}
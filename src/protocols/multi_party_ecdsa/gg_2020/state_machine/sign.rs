@@ -15,6 +15,13 @@
 //! `StateMachine`, but rather provides methods to construct messages and final signature manually
 //! (refer to [SignManual] documentation to see how to use it).
 //!
+//! ## Identifiable abort
+//!
+//! If `R_i` or `S_i` sent in offline stage don't add up, parties go through an extra blame round
+//! where they reveal nonces of the failed signing, and `OfflineStage` fails with
+//! [ProceedError::Blame] naming the parties who cheated. Likewise [SignManual::complete] names
+//! parties whose partial signatures are wrong. Use [Error::bad_actors] to get them.
+//!
 //! [keygen module]: super::keygen
 //! [Keygen]: super::keygen::Keygen
 //! [LocalKey]: super::keygen::LocalKey
@@ -42,9 +49,10 @@ use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
 use crate::utilities::mta::MessageA;
+use crate::utilities::transcript::SessionId;
 use crate::protocols::multi_party_ecdsa::gg_2020 as gg20;
 use gg20::party_i::{SignBroadcastPhase1, SignDecommitPhase1, SignatureRecid};
-use gg20::state_machine::keygen::LocalKey;
+use gg20::state_machine::keygen::{LocalKey, LocalKey_HD};
 
 mod fmt;
 pub mod rounds;
@@ -63,13 +71,16 @@ pub struct OfflineStage {
 
     msgs1: Option<Store<BroadcastMsgs<(MessageA, SignBroadcastPhase1)>>>,
     msgs2: Option<Store<P2PMsgs<(GammaI, WI)>>>,
-    msgs3: Option<Store<BroadcastMsgs<(DeltaI, TI, TIProof)>>>,
+    msgs3: Option<Store<BroadcastMsgs<(DeltaI, TI, TIProof, MBHashes)>>>,
     msgs4: Option<Store<BroadcastMsgs<SignDecommitPhase1>>>,
     msgs5: Option<Store<BroadcastMsgs<(RDash, Vec<PDLwSlackProof>)>>>,
     msgs6: Option<Store<BroadcastMsgs<(SI, HEGProof)>>>,
+    msgs7: Option<Store<BroadcastMsgs<BlameData>>>,
 
     msgs_queue: MsgQueue,
 
+    local_key_hd: Option<LocalKey_HD<Secp256k1>>,
+
     party_i: u16,
     party_n: u16,
 }
@@ -82,10 +93,16 @@ impl OfflineStage {
     ///
     /// Takes party index `i` (in range `[1; n]`), list `s_l` of parties' indexes from keygen protocol
     /// (`s_l[i]` must be an index of party `i` that was used by this party in keygen protocol), and
-    /// party local secret share `local_key`.
+    /// party local secret share `local_key`. All the signers must be given the same `session_id`
+    /// which is unique for this signing, it's bound into every zero-knowledge proof they send.
     ///
     /// Returns error if given arguments are contradicting.
-    pub fn new(i: u16, s_l: Vec<u16>, local_key: LocalKey<Secp256k1>) -> Result<Self> {
+    pub fn new(
+        i: u16,
+        s_l: Vec<u16>,
+        local_key: LocalKey<Secp256k1>,
+        session_id: SessionId,
+    ) -> Result<Self> {
         if s_l.len() < 2 {
             return Err(Error::TooFewParties);
         }
@@ -112,7 +129,12 @@ impl OfflineStage {
         let n = u16::try_from(s_l.len()).map_err(|_| Error::TooManyParties { n: s_l.len() })?;
 
         Ok(Self {
-            round: OfflineR::R0(Round0 { i, s_l, local_key }),
+            round: OfflineR::R0(Round0 {
+                i,
+                s_l,
+                local_key,
+                session_id,
+            }),
 
             msgs1: Some(Round1::expects_messages(i, n)),
             msgs2: Some(Round2::expects_messages(i, n)),
@@ -120,14 +142,39 @@ impl OfflineStage {
             msgs4: Some(Round4::expects_messages(i, n)),
             msgs5: Some(Round5::expects_messages(i, n)),
             msgs6: Some(Round6::expects_messages(i, n)),
+            msgs7: Some(BlameRound::expects_messages(i, n)),
 
             msgs_queue: MsgQueue(vec![]),
 
+            local_key_hd: None,
+
             party_i: i,
             party_n: n,
         })
     }
 
+    /// Construct a party of offline stage which signs on behalf of a child key
+    ///
+    /// Works the same way as [OfflineStage::new] run on root key `local_key_hd.local_key`, but
+    /// resulting [CompletedOfflineStage] produces signatures that verify under child public key
+    /// `local_key_hd.y_sum`. The child key has to be chosen here, before `R` is known, so a
+    /// presignature serves exactly one key.
+    ///
+    /// Returns [Error::InvalidTweak] if `local_key_hd.y_sum` doesn't match the tweak.
+    pub fn new_with_tweak(
+        i: u16,
+        s_l: Vec<u16>,
+        local_key_hd: LocalKey_HD<Secp256k1>,
+        session_id: SessionId,
+    ) -> Result<Self> {
+        if !local_key_hd.is_consistent() {
+            return Err(Error::InvalidTweak);
+        }
+        let mut state = Self::new(i, s_l, local_key_hd.local_key.clone(), session_id)?;
+        state.local_key_hd = Some(local_key_hd);
+        Ok(state)
+    }
+
     // fn proceed_state(&mut self, may_block: bool) -> Result<()> {
     //     self.proceed_round(may_block)?;
     //     self.proceed_decommit_round(may_block)
@@ -140,6 +187,7 @@ impl OfflineStage {
         let store4_wants_more = self.msgs4.as_ref().map(|s| s.wants_more()).unwrap_or(false);
         let store5_wants_more = self.msgs5.as_ref().map(|s| s.wants_more()).unwrap_or(false);
         let store6_wants_more = self.msgs6.as_ref().map(|s| s.wants_more()).unwrap_or(false);
+        let store7_wants_more = self.msgs7.as_ref().map(|s| s.wants_more()).unwrap_or(false);
 
         let next_state: OfflineR;
         let try_again: bool = match replace(&mut self.round, OfflineR::Gone) {
@@ -219,10 +267,13 @@ impl OfflineStage {
                 let msgs = store
                     .finish()
                     .map_err(InternalError::RetrieveMessagesFromStore)?;
-                next_state = round
+                next_state = match round
                     .proceed(msgs, &mut self.msgs_queue)
-                    .map(OfflineR::R6)
-                    .map_err(Error::ProceedRound)?;
+                    .map_err(Error::ProceedRound)?
+                {
+                    Outcome::Proceed(round) => OfflineR::R6(round),
+                    Outcome::Blame(round) => OfflineR::Blame(round),
+                };
                 false
             }
             s @ OfflineR::R5(_) => {
@@ -234,16 +285,32 @@ impl OfflineStage {
                 let msgs = store
                     .finish()
                     .map_err(InternalError::RetrieveMessagesFromStore)?;
-                next_state = round
-                    .proceed(msgs)
-                    .map(OfflineR::Finished)
-                    .map_err(Error::ProceedRound)?;
+                next_state = match round
+                    .proceed(msgs, &mut self.msgs_queue)
+                    .map_err(Error::ProceedRound)?
+                {
+                    Outcome::Proceed(output) => OfflineR::Finished(output),
+                    Outcome::Blame(round) => OfflineR::Blame(round),
+                };
                 false
             }
             s @ OfflineR::R6(_) => {
                 next_state = s;
                 false
             }
+            OfflineR::Blame(round)
+                if !store7_wants_more && (!round.is_expensive() || may_block) =>
+            {
+                let store = self.msgs7.take().ok_or(InternalError::StoreGone)?;
+                let msgs = store
+                    .finish()
+                    .map_err(InternalError::RetrieveMessagesFromStore)?;
+                return Err(Error::ProceedRound(round.proceed(msgs)));
+            }
+            s @ OfflineR::Blame(_) => {
+                next_state = s;
+                false
+            }
             s @ OfflineR::Finished(_) | s @ OfflineR::Gone => {
                 next_state = s;
                 false
@@ -370,6 +437,23 @@ impl StateMachine for OfflineStage {
                     })
                     .map_err(Error::HandleMessage)?;
             }
+            OfflineProtocolMessage(OfflineM::M7(m)) => {
+                let store = self
+                    .msgs7
+                    .as_mut()
+                    .ok_or(Error::ReceivedOutOfOrderMessage {
+                        current_round,
+                        msg_round: 7,
+                    })?;
+                store
+                    .push_msg(Msg {
+                        round: 7,
+                        sender: msg.sender,
+                        receiver: msg.receiver,
+                        body: m,
+                    })
+                    .map_err(Error::HandleMessage)?;
+            }
         }
         self.proceed_round(false)
     }
@@ -385,6 +469,7 @@ impl StateMachine for OfflineStage {
         let store4_wants_more = self.msgs4.as_ref().map(|s| s.wants_more()).unwrap_or(false);
         let store5_wants_more = self.msgs5.as_ref().map(|s| s.wants_more()).unwrap_or(false);
         let store6_wants_more = self.msgs6.as_ref().map(|s| s.wants_more()).unwrap_or(false);
+        let store7_wants_more = self.msgs7.as_ref().map(|s| s.wants_more()).unwrap_or(false);
 
         match &self.round {
             OfflineR::R0(_) => true,
@@ -394,6 +479,7 @@ impl StateMachine for OfflineStage {
             OfflineR::R4(_) => !store4_wants_more,
             OfflineR::R5(_) => !store5_wants_more,
             OfflineR::R6(_) => !store6_wants_more,
+            OfflineR::Blame(_) => !store7_wants_more,
             OfflineR::Finished(_) | OfflineR::Gone => false,
         }
     }
@@ -422,7 +508,12 @@ impl StateMachine for OfflineStage {
         }
 
         match replace(&mut self.round, OfflineR::Gone) {
-            OfflineR::Finished(result) => Some(Ok(result)),
+            OfflineR::Finished(result) => Some(match &self.local_key_hd {
+                Some(local_key_hd) => result
+                    .with_tweak(local_key_hd)
+                    .map_err(Error::ProceedRound),
+                None => Ok(result),
+            }),
             _ => unreachable!("guaranteed by match expression above"),
         }
     }
@@ -436,10 +527,12 @@ impl StateMachine for OfflineStage {
             OfflineR::R4(_) => 4,
             OfflineR::R5(_) => 5,
             OfflineR::R6(_) => 6,
-            OfflineR::Finished(_) | OfflineR::Gone => 7,
+            OfflineR::Blame(_) => 7,
+            OfflineR::Finished(_) | OfflineR::Gone => 8,
         }
     }
 
+    /// Blame round is only held if the protocol fails, so it isn't counted here
     fn total_rounds(&self) -> Option<u16> {
         Some(6)
     }
@@ -462,6 +555,7 @@ impl super::traits::RoundBlame for OfflineStage {
         let store4_blame = self.msgs4.as_ref().map(|s| s.blame()).unwrap_or_default();
         let store5_blame = self.msgs5.as_ref().map(|s| s.blame()).unwrap_or_default();
         let store6_blame = self.msgs6.as_ref().map(|s| s.blame()).unwrap_or_default();
+        let store7_blame = self.msgs7.as_ref().map(|s| s.blame()).unwrap_or_default();
 
         let default = (0, vec![]);
         match &self.round {
@@ -472,6 +566,7 @@ impl super::traits::RoundBlame for OfflineStage {
             OfflineR::R4(_) => store4_blame,
             OfflineR::R5(_) => store5_blame,
             OfflineR::R6(_) => store6_blame,
+            OfflineR::Blame(_) => store7_blame,
             OfflineR::Finished(_) => store6_blame,
             OfflineR::Gone => default,
         }
@@ -487,6 +582,7 @@ enum OfflineR {
     R4(Round4),
     R5(Round5),
     R6(Round6),
+    Blame(BlameRound),
     Finished(CompletedOfflineStage),
     Gone,
 }
@@ -503,6 +599,7 @@ impl crate::MessageRoundID for OfflineProtocolMessage {
             OfflineM::M4(_) => 4,
             OfflineM::M5(_) => 5,
             OfflineM::M6(_) => 6,
+            OfflineM::M7(_) => 7,
         }
     }
 }
@@ -512,10 +609,11 @@ impl crate::MessageRoundID for OfflineProtocolMessage {
 enum OfflineM {
     M1((MessageA, SignBroadcastPhase1)),
     M2((GammaI, WI)),
-    M3((DeltaI, TI, TIProof)),
+    M3((DeltaI, TI, TIProof, MBHashes)),
     M4(SignDecommitPhase1),
     M5((RDash, Vec<PDLwSlackProof>)),
     M6((SI, HEGProof)),
+    M7(BlameData),
 }
 
 struct MsgQueue(Vec<Msg<OfflineProtocolMessage>>);
@@ -540,10 +638,11 @@ macro_rules! make_pushable {
 make_pushable! {
     M1 (MessageA, SignBroadcastPhase1),
     M2 (GammaI, WI),
-    M3 (DeltaI, TI, TIProof),
+    M3 (DeltaI, TI, TIProof, MBHashes),
     M4 SignDecommitPhase1,
     M5 (RDash, Vec<PDLwSlackProof>),
     M6 (SI, HEGProof),
+    M7 BlameData,
 }
 
 type Result<T, E = Error> = std::result::Result<T, E>;
@@ -564,6 +663,9 @@ pub enum Error {
     /// participated in DKG (`exist i. s_l[i] = 0 || s_l[i] > keygen_n`).
     #[error("invalid s_l")]
     InvalidSl,
+    /// Child public key of [LocalKey_HD] doesn't match its tweak
+    #[error("child public key doesn't match the tweak")]
+    InvalidTweak,
 
     /// Round proceeding resulted in protocol error
     #[error("proceeding round: {0}")]
@@ -610,6 +712,7 @@ impl IsCritical for Error {
             Error::TooManyParties { .. } => true,
             Error::InvalidPartyIndex => true,
             Error::InvalidSl => true,
+            Error::InvalidTweak => true,
             Error::ProceedRound(_) => true,
             Error::ReceivedOutOfOrderMessage { .. } => false,
             Error::HandleMessage(_) => false,
@@ -619,6 +722,19 @@ impl IsCritical for Error {
     }
 }
 
+impl Error {
+    /// Indexes of the parties identified as malicious, in range `[0; n)`, see
+    /// [ErrorType::bad_actors](crate::protocols::multi_party_ecdsa::gg_2020::ErrorType::bad_actors)
+    ///
+    /// Returns `None` if error isn't caused by any party in particular.
+    pub fn bad_actors(&self) -> Option<&[usize]> {
+        match self {
+            Error::ProceedRound(err) => err.bad_actors(),
+            _ => None,
+        }
+    }
+}
+
 /// Manual GG20 signing
 ///
 /// After you completed [OfflineStage] and got [CompletedOfflineStage], parties can perform signing
@@ -667,7 +783,10 @@ impl SignManual {
     }
 
     /// `sigs` must not include partial signature produced by local party (only partial signatures produced
-    /// by other parties)
+    /// by other parties), and must be ordered by party index
+    ///
+    /// If resulting signature is invalid, returns error naming parties whose partial signatures are
+    /// wrong, see [SignError::bad_actors].
     pub fn complete(self, sigs: &[PartialSignature]) -> Result<SignatureRecid, SignError> {
         self.state
             .proceed_manual(sigs)
@@ -683,112 +802,337 @@ pub enum SignError {
     CompleteSigning(rounds::Error),
 }
 
-// #[cfg(test)]
-// mod test {
-//     use curv::arithmetic::Converter;
-//     use curv::cryptographic_primitives::hashing::{Digest, DigestExt};
-//     use round_based::dev::Simulation;
-//     use sha2::Sha256;
-
-//     use super::*;
-//     use gg20::party_i::verify;
-//     use gg20::state_machine::keygen::test::simulate_keygen;
-
-//     fn simulate_offline_stage(
-//         local_keys: Vec<LocalKey<Secp256k1>>,
-//         s_l: &[u16],
-//     ) -> Vec<CompletedOfflineStage> {
-//         let mut simulation = Simulation::new();
-//         simulation.enable_benchmarks(true);
-
-//         for (i, &keygen_i) in (1..).zip(s_l) {
-//             simulation.add_party(
-//                 OfflineStage::new(
-//                     i,
-//                     s_l.to_vec(),
-//                     local_keys[usize::from(keygen_i - 1)].clone(),
-//                 )
-//                 .unwrap(),
-//             );
-//         }
-
-//         let stages = simulation.run().unwrap();
-
-//         println!("Benchmark results:");
-//         println!("{:#?}", simulation.benchmark_results().unwrap());
-
-//         stages
-//     }
-
-//     fn simulate_signing(offline: Vec<CompletedOfflineStage>, message: &[u8]) {
-//         let message = Sha256::new()
-//             .chain_bigint(&BigInt::from_bytes(message))
-//             .result_bigint();
-//         let pk = offline[0].public_key().clone();
-
-//         let parties = offline
-//             .iter()
-//             .map(|o| SignManual::new(message.clone(), o.clone()))
-//             .collect::<Result<Vec<_>, _>>()
-//             .unwrap();
-//         let (parties, local_sigs): (Vec<_>, Vec<_>) = parties.into_iter().unzip();
-//         // parties.remove(0).complete(&local_sigs[1..]).unwrap();
-//         let local_sigs_except = |i: usize| {
-//             let mut v = vec![];
-//             v.extend_from_slice(&local_sigs[..i]);
-//             if i + 1 < local_sigs.len() {
-//                 v.extend_from_slice(&local_sigs[i + 1..]);
-//             }
-//             v
-//         };
-
-//         assert!(parties
-//             .into_iter()
-//             .enumerate()
-//             .map(|(i, p)| p.complete(&local_sigs_except(i)).unwrap())
-//             .all(|signature| verify(&signature, &pk, &message).is_ok()));
-//     }
-
-//     #[test]
-//     fn simulate_offline_stage_t1_n2_s2() {
-//         let local_keys = simulate_keygen(1, 2);
-//         simulate_offline_stage(local_keys, &[1, 2]);
-//     }
-
-//     #[test]
-//     fn simulate_offline_stage_t1_n3_s2() {
-//         let local_keys = simulate_keygen(1, 3);
-//         simulate_offline_stage(local_keys, &[1, 3]);
-//     }
-
-//     #[test]
-//     fn simulate_offline_stage_t2_n3_s3() {
-//         let local_keys = simulate_keygen(2, 3);
-//         simulate_offline_stage(local_keys, &[1, 2, 3]);
-//     }
-
-//     #[test]
-//     fn simulate_signing_t1_n2_s2() {
-//         let local_keys = simulate_keygen(1, 2);
-//         let offline_stage = simulate_offline_stage(local_keys, &[1, 2]);
-//         simulate_signing(offline_stage, b"ZenGo")
-//     }
-
-//     #[test]
-//     fn simulate_signing_t1_n3_s2() {
-//         let local_keys = simulate_keygen(1, 3);
-//         let offline_stage = simulate_offline_stage(local_keys.clone(), &[1, 2]);
-//         simulate_signing(offline_stage, b"ZenGo");
-//         let offline_stage = simulate_offline_stage(local_keys.clone(), &[1, 3]);
-//         simulate_signing(offline_stage, b"ZenGo");
-//         let offline_stage = simulate_offline_stage(local_keys, &[2, 3]);
-//         simulate_signing(offline_stage, b"ZenGo");
-//     }
-
-//     #[test]
-//     fn simulate_signing_t2_n3_s3() {
-//         let local_keys = simulate_keygen(2, 3);
-//         let offline_stage = simulate_offline_stage(local_keys, &[1, 2, 3]);
-//         simulate_signing(offline_stage, b"ZenGo")
-//     }
-// }
+impl SignError {
+    /// Indexes of the parties identified as malicious, in range `[0; n)`, see
+    /// [ErrorType::bad_actors](crate::protocols::multi_party_ecdsa::gg_2020::ErrorType::bad_actors)
+    pub fn bad_actors(&self) -> Option<&[usize]> {
+        match self {
+            SignError::LocalSigning(err) | SignError::CompleteSigning(err) => err.bad_actors(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use curv::arithmetic::Converter;
+    use curv::cryptographic_primitives::hashing::{Digest, DigestExt};
+    use curv::elliptic::curves::Scalar;
+    use round_based::dev::Simulation;
+    use sha2::Sha256;
+
+    use super::*;
+    use gg20::party_i::verify;
+    use gg20::state_machine::keygen::test::simulate_keygen;
+
+    fn generate_local_keys(t: u16, n: u16) -> Vec<LocalKey<Secp256k1>> {
+        simulate_keygen(t, n)
+            .into_iter()
+            .map(|share| share.local_key_hd)
+            .collect()
+    }
+
+    fn offline_stage_parties(
+        local_keys: &[LocalKey<Secp256k1>],
+        s_l: &[u16],
+    ) -> Vec<OfflineStage> {
+        let session_id = SessionId::random();
+        (1..)
+            .zip(s_l)
+            .map(|(i, &keygen_i)| {
+                OfflineStage::new(
+                    i,
+                    s_l.to_vec(),
+                    local_keys[usize::from(keygen_i - 1)].clone(),
+                    session_id,
+                )
+                .unwrap()
+            })
+            .collect()
+    }
+
+    fn simulate_offline_stage(
+        local_keys: &[LocalKey<Secp256k1>],
+        s_l: &[u16],
+    ) -> Vec<CompletedOfflineStage> {
+        let mut simulation = Simulation::new();
+        simulation.enable_benchmarks(true);
+
+        for party in offline_stage_parties(local_keys, s_l) {
+            simulation.add_party(party);
+        }
+
+        let stages = simulation.run().unwrap();
+
+        println!("Benchmark results:");
+        println!("{:#?}", simulation.benchmark_results().unwrap());
+
+        stages
+    }
+
+    /// Runs offline stage delivering messages by hand. `corrupt` is called on a party every time
+    /// it has proceeded, so it can tamper with party's state and messages it's about to send.
+    fn run_offline_stage<F>(
+        mut parties: Vec<OfflineStage>,
+        corrupt: F,
+    ) -> Vec<Result<CompletedOfflineStage>>
+    where
+        F: Fn(&mut OfflineStage),
+    {
+        let n = parties.len();
+        let mut results: Vec<Option<Result<CompletedOfflineStage>>> =
+            (0..n).map(|_| None).collect();
+        let mut progressed = true;
+        while progressed {
+            progressed = false;
+            for i in 0..n {
+                if results[i].is_some() {
+                    continue;
+                }
+                if parties[i].wants_to_proceed() {
+                    progressed = true;
+                    if let Err(err) = parties[i].proceed() {
+                        results[i] = Some(Err(err));
+                        continue;
+                    }
+                }
+                corrupt(&mut parties[i]);
+
+                let msgs: Vec<_> = parties[i].message_queue().drain(..).collect();
+                for msg in msgs {
+                    progressed = true;
+                    for j in 0..n {
+                        let receiver = j as u16 + 1;
+                        let addressed = msg.receiver.map_or(true, |r| r == receiver);
+                        if receiver == msg.sender || !addressed || results[j].is_some() {
+                            continue;
+                        }
+                        if let Err(err) = parties[j].handle_incoming(msg.clone()) {
+                            results[j] = Some(Err(err));
+                        }
+                    }
+                }
+
+                if parties[i].is_finished() {
+                    results[i] = parties[i].pick_output();
+                }
+            }
+        }
+        results
+            .into_iter()
+            .map(|result| result.expect("party got stuck"))
+            .collect()
+    }
+
+    fn message_hash(message: &[u8]) -> BigInt {
+        Sha256::new()
+            .chain_bigint(&BigInt::from_bytes(message))
+            .result_bigint()
+    }
+
+    /// Party `i` signs `messages[i]`, returns signature produced by each party
+    fn simulate_signing(
+        offline: Vec<CompletedOfflineStage>,
+        messages: &[BigInt],
+    ) -> Vec<Result<SignatureRecid, SignError>> {
+        let (parties, local_sigs): (Vec<_>, Vec<_>) = offline
+            .into_iter()
+            .zip(messages)
+            .map(|(o, message)| SignManual::new(message.clone(), o).unwrap())
+            .unzip();
+        parties
+            .into_iter()
+            .enumerate()
+            .map(|(i, p)| {
+                let mut sigs = local_sigs.clone();
+                sigs.remove(i);
+                p.complete(&sigs)
+            })
+            .collect()
+    }
+
+    fn simulate_honest_signing(offline: Vec<CompletedOfflineStage>, message: &[u8]) {
+        let message = message_hash(message);
+        let pk = offline[0].public_key().clone();
+        let messages = vec![message.clone(); offline.len()];
+
+        for signature in simulate_signing(offline, &messages) {
+            assert!(verify(&signature.unwrap(), &pk, &message).is_ok());
+        }
+    }
+
+    #[test]
+    fn simulate_signing_t1_n2_s2() {
+        let local_keys = generate_local_keys(1, 2);
+        let offline_stage = simulate_offline_stage(&local_keys, &[1, 2]);
+        simulate_honest_signing(offline_stage, b"ZenGo")
+    }
+
+    #[test]
+    fn simulate_signing_t1_n3_s2() {
+        let local_keys = generate_local_keys(1, 3);
+        let offline_stage = simulate_offline_stage(&local_keys, &[1, 2]);
+        simulate_honest_signing(offline_stage, b"ZenGo");
+        let offline_stage = simulate_offline_stage(&local_keys, &[1, 3]);
+        simulate_honest_signing(offline_stage, b"ZenGo");
+        let offline_stage = simulate_offline_stage(&local_keys, &[2, 3]);
+        simulate_honest_signing(offline_stage, b"ZenGo");
+    }
+
+    #[test]
+    fn simulate_signing_t2_n3_s3() {
+        let local_keys = generate_local_keys(2, 3);
+        let offline_stage = simulate_offline_stage(&local_keys, &[1, 2, 3]);
+        simulate_honest_signing(offline_stage, b"ZenGo")
+    }
+
+    #[test]
+    fn simulate_signing_with_tweak() {
+        let shares = simulate_keygen(1, 3);
+        let local_keys: Vec<_> = shares
+            .iter()
+            .map(|share| {
+                LocalKey_HD::derive(share.local_key_hd.clone(), "m/0/1", share.chain_code)
+                    .unwrap()
+            })
+            .collect();
+
+        let session_id = SessionId::random();
+        let mut simulation = Simulation::new();
+        for (i, local_key_hd) in (1..).zip(&local_keys) {
+            simulation.add_party(
+                OfflineStage::new_with_tweak(i, vec![1, 2, 3], local_key_hd.clone(), session_id)
+                    .unwrap(),
+            );
+        }
+        let offline_stage = simulation.run().unwrap();
+        assert_eq!(offline_stage[0].public_key(), &local_keys[0].y_sum);
+        simulate_honest_signing(offline_stage.clone(), b"ZenGo");
+
+        // blame accounts for the tweak as well
+        let message = message_hash(b"ZenGo");
+        let messages = [message.clone(), message_hash(b"other"), message];
+        let results = simulate_signing(offline_stage, &messages);
+        for &i in &[0, 2] {
+            let err = results[i].as_ref().unwrap_err();
+            assert_eq!(err.bad_actors(), Some(&[1][..]));
+        }
+    }
+
+    #[test]
+    fn signing_blames_party_with_wrong_partial_signature() {
+        let local_keys = generate_local_keys(1, 3);
+        let offline_stage = simulate_offline_stage(&local_keys, &[1, 2, 3]);
+
+        // party 2 signs another message, so its partial signature doesn't fit
+        let message = message_hash(b"ZenGo");
+        let messages = [message.clone(), message_hash(b"other"), message];
+        let results = simulate_signing(offline_stage, &messages);
+        for &i in &[0, 2] {
+            let err = results[i].as_ref().unwrap_err();
+            assert!(matches!(
+                err,
+                SignError::CompleteSigning(ProceedError::Blame(_))
+            ));
+            assert_eq!(err.bad_actors(), Some(&[1][..]));
+        }
+    }
+
+    #[test]
+    fn offline_stage_blames_party_with_wrong_delta() {
+        let local_keys = generate_local_keys(1, 3);
+        let parties = offline_stage_parties(&local_keys, &[1, 2, 3]);
+
+        // party 2 consistently lies about its delta_i, so R_i don't add up to the generator
+        let bad_delta = Scalar::<Secp256k1>::from(&BigInt::from(42));
+        let results = run_offline_stage(parties, |party| {
+            if party.party_i != 2 {
+                return;
+            }
+            if let OfflineR::R3(round) = &mut party.round {
+                round.delta_i = bad_delta.clone();
+            }
+            for msg in party.msgs_queue.0.iter_mut() {
+                if let OfflineProtocolMessage(OfflineM::M3((delta_i, _, _, _))) = &mut msg.body {
+                    *delta_i = DeltaI(bad_delta.clone());
+                }
+            }
+        });
+
+        for result in results {
+            match result {
+                Err(err) => {
+                    assert!(matches!(
+                        err,
+                        Error::ProceedRound(ProceedError::Blame(_))
+                    ));
+                    assert_eq!(err.bad_actors(), Some(&[1][..]));
+                }
+                Ok(_) => panic!("offline stage must fail"),
+            }
+        }
+    }
+
+    /// Party 2 lies about its delta_i, which triggers the blame round, where `liar` replaces
+    /// MtA response it received from `from` with the one it received from the other party
+    fn offline_stage_with_lie_about_received_mta_response(
+        liar: u16,
+        from: usize,
+    ) -> Vec<Result<CompletedOfflineStage>> {
+        let local_keys = generate_local_keys(1, 3);
+        let parties = offline_stage_parties(&local_keys, &[1, 2, 3]);
+
+        let bad_delta = Scalar::<Secp256k1>::from(&BigInt::from(42));
+        run_offline_stage(parties, |party| {
+            for msg in party.msgs_queue.0.iter_mut() {
+                let sender = msg.sender;
+                match &mut msg.body {
+                    OfflineProtocolMessage(OfflineM::M3((delta_i, _, _, _))) if sender == 2 => {
+                        *delta_i = DeltaI(bad_delta.clone());
+                    }
+                    OfflineProtocolMessage(OfflineM::M7(BlameData::Phase5(_, received, _)))
+                        if sender == liar =>
+                    {
+                        received[from] = received[1 - from].clone();
+                    }
+                    _ => (),
+                }
+            }
+            if let OfflineR::R3(round) = &mut party.round {
+                if party.party_i == 2 {
+                    round.delta_i = bad_delta.clone();
+                }
+            }
+        })
+    }
+
+    fn assert_blamed(results: Vec<Result<CompletedOfflineStage>>, bad_actors: &[usize]) {
+        for result in results {
+            match result {
+                Err(err) => {
+                    assert!(matches!(
+                        err,
+                        Error::ProceedRound(ProceedError::Blame(_))
+                    ));
+                    assert_eq!(err.bad_actors(), Some(bad_actors));
+                }
+                Ok(_) => panic!("offline stage must fail"),
+            }
+        }
+    }
+
+    #[test]
+    fn offline_stage_doesnt_blame_party_framed_by_accuser() {
+        // party 1 lies about MtA response it received from party 3 trying to get it blamed,
+        // which doesn't match the hash broadcast by party 3
+        let results = offline_stage_with_lie_about_received_mta_response(1, 1);
+        assert_blamed(results, &[0, 1]);
+    }
+
+    #[test]
+    fn offline_stage_blames_party_faking_dispute() {
+        // party 2 lies about MtA response it received from party 1, so that its delta_i can't
+        // be checked against the one it claims to have received
+        let results = offline_stage_with_lie_about_received_mta_response(2, 0);
+        assert_blamed(results, &[1]);
+    }
+}
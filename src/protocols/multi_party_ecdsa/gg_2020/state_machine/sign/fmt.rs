@@ -32,6 +32,7 @@ impl From<&super::OfflineStage> for OfflineStageProgress {
                 super::OfflineR::R4(_) => OfflineR::R4,
                 super::OfflineR::R5(_) => OfflineR::R5,
                 super::OfflineR::R6(_) => OfflineR::R6,
+                super::OfflineR::Blame(_) => OfflineR::Blame,
                 super::OfflineR::Finished(_) => OfflineR::Finished,
                 super::OfflineR::Gone => OfflineR::Gone,
             },
@@ -58,6 +59,7 @@ pub enum OfflineR {
     R4,
     R5,
     R6,
+    Blame,
     Finished,
     Gone,
 }
@@ -8,6 +8,7 @@ use thiserror::Error;
 
 use curv::elliptic::curves::{secp256_k1::Secp256k1, Point, Scalar};
 use curv::BigInt;
+use paillier::{Decrypt, EncryptionKey, Paillier, RawCiphertext, RawPlaintext};
 use sha2::Sha256;
 
 use round_based::containers::push::Push;
@@ -17,14 +18,20 @@ use round_based::Msg;
 use crate::utilities::mta::{MessageA, MessageB};
 
 use crate::protocols::multi_party_ecdsa::gg_2020 as gg20;
+use crate::utilities::secret::Secret;
+use crate::utilities::transcript::SessionId;
+use crate::utilities::zk_homo_elgamal::HomoElGamalProof;
 use crate::utilities::zk_pdl_with_slack::PDLwSlackProof;
-use curv::cryptographic_primitives::proofs::sigma_correct_homomorphic_elgamal_enc::HomoELGamalProof;
 use curv::cryptographic_primitives::proofs::sigma_valid_pedersen::PedersenProof;
+use gg20::blame::{
+    m_b_hash, GlobalStatePhase5, GlobalStatePhase6, GlobalStatePhase7, LocalStatePhase5,
+    LocalStatePhase6,
+};
 use gg20::party_i::{
     LocalSignature, SignBroadcastPhase1, SignDecommitPhase1, SignKeys, SignatureRecid,
 };
-use gg20::state_machine::keygen::LocalKey;
-use gg20::ErrorType;
+use gg20::state_machine::keygen::{LocalKey, LocalKey_HD};
+use gg20::{ErrorType, SIGNING_LABEL};
 
 type Result<T, E = Error> = std::result::Result<T, E>;
 
@@ -36,17 +43,51 @@ pub struct GammaI(pub MessageB);
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct WI(pub MessageB);
 #[derive(Serialize, Deserialize, Debug, Clone)]
-pub struct DeltaI(Scalar<Secp256k1>);
+pub struct DeltaI(pub Scalar<Secp256k1>);
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct TI(pub Point<Secp256k1>);
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct TIProof(pub PedersenProof<Secp256k1, Sha256>);
+/// Hashes of MtA responses sent in round 2 as Bob, in order of counterparties, see
+/// [m_b_hash](gg20::blame::m_b_hash)
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct MBHashes {
+    pub gamma: Vec<BigInt>,
+    pub w: Vec<BigInt>,
+}
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct RDash(Point<Secp256k1>);
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct SI(pub Point<Secp256k1>);
 #[derive(Serialize, Deserialize, Debug, Clone)]
-pub struct HEGProof(pub HomoELGamalProof<Secp256k1, Sha256>);
+pub struct HEGProof(pub HomoElGamalProof);
+
+/// Secrets of the offline stage revealed in [BlameRound]
+///
+/// Along with its local state, each party reveals MtA responses (`m_b`) it received in round 2 as
+/// Alice and the ones it sent as Bob, both in order of counterparties. Round 2 messages are
+/// point-to-point, so Bob broadcasts their [MBHashes] in round 3, and Alice checks received
+/// messages against them. A party is blamed if its revealed values are inconsistent with each
+/// other or with what it broadcast, including revealed messages that don't match the hashes.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[allow(clippy::large_enum_variant)]
+pub enum BlameData {
+    /// Revealed if `R_i` don't sum up to the generator, carries received and sent responses to
+    /// `k_i * gamma_j`
+    Phase5(LocalStatePhase5, Vec<MessageB>, Vec<MessageB>),
+    /// Revealed if `S_i` don't sum up to the public key, carries received and sent responses to
+    /// `k_i * w_j`
+    Phase6(LocalStatePhase6, Vec<MessageB>, Vec<MessageB>),
+}
+
+/// Result of a round whose failed check requires parties to find out who cheated
+#[allow(clippy::large_enum_variant)]
+pub enum Outcome<T> {
+    /// Checks passed, protocol goes on
+    Proceed(T),
+    /// Check failed, parties reveal their secrets in the blame round
+    Blame(BlameRound),
+}
 
 pub struct Round0 {
     /// Index of this party
@@ -62,6 +103,9 @@ pub struct Round0 {
 
     /// Party local secret share
     pub local_key: LocalKey<Secp256k1>,
+
+    /// Session id which all the signers agreed on, bound into their proofs
+    pub session_id: SessionId,
 }
 
 
@@ -72,7 +116,7 @@ impl Round0 {
         O: Push<Msg<(MessageA, SignBroadcastPhase1)>>,
     {
         let sign_keys = SignKeys::create(
-            &self.local_key.keys_linear.x_i,
+            self.local_key.keys_linear.x_i.expose(),
             &self.local_key.vss_scheme.clone(),
             usize::from(self.s_l[usize::from(self.i - 1)]) - 1,
             &self
@@ -84,7 +128,12 @@ impl Round0 {
         let (bc1, decom1) = sign_keys.phase1_broadcast();
 
         let party_ek = self.local_key.paillier_key_vec[usize::from(self.local_key.i - 1)].clone();
-        let m_a = MessageA::a(&sign_keys.k_i, &party_ek, &self.local_key.h1_h2_n_tilde_vec);
+        let m_a = MessageA::a(
+            sign_keys.k_i.expose(),
+            &party_ek,
+            &self.local_key.h1_h2_n_tilde_vec,
+            &self.session_id.context(self.i, SIGNING_LABEL),
+        );
 
         output.push(Msg {
             round:1,
@@ -101,6 +150,7 @@ impl Round0 {
             sign_keys,
             phase1_com: bc1,
             phase1_decom: decom1,
+            session_id: self.session_id,
         };
 
         Ok(round1)
@@ -120,6 +170,7 @@ pub struct Round1 {
     sign_keys: SignKeys,
     phase1_com: SignBroadcastPhase1,
     phase1_decom: SignDecommitPhase1,
+    session_id: SessionId,
 }
 
 impl Round1 {
@@ -138,6 +189,8 @@ impl Round1 {
 
         let mut m_b_gamma_vec = Vec::new();
         let mut beta_vec = Vec::new();
+        let mut beta_randomness_vec = Vec::new();
+        let mut beta_tag_vec = Vec::new();
         let mut m_b_w_vec = Vec::new();
         let mut ni_vec = Vec::new();
 
@@ -151,12 +204,14 @@ impl Round1 {
         let i = usize::from(self.i - 1);
         for j in 0..ttag - 1 {
             let ind = if j < i { j } else { j + 1 };
+            let alice_ctx = self.session_id.context(ind as u16 + 1, SIGNING_LABEL);
 
-            let (m_b_gamma, beta_gamma, _beta_randomness, _beta_tag) = MessageB::b(
-                &self.sign_keys.gamma_i,
+            let (m_b_gamma, beta_gamma, beta_randomness, beta_tag) = MessageB::b(
+                self.sign_keys.gamma_i.expose(),
                 &self.local_key.paillier_key_vec[l_s[ind]],
                 m_a_vec[ind].clone(),
                 &self.local_key.h1_h2_n_tilde_vec,
+                &alice_ctx,
             )
             .map_err(|e| {
                 Error::Round1(ErrorType {
@@ -167,10 +222,11 @@ impl Round1 {
             })?;
 
             let (m_b_w, beta_wi, _, _) = MessageB::b(
-                &self.sign_keys.w_i,
+                self.sign_keys.w_i.expose(),
                 &self.local_key.paillier_key_vec[l_s[ind]],
                 m_a_vec[ind].clone(),
                 &self.local_key.h1_h2_n_tilde_vec,
+                &alice_ctx,
             )
             .map_err(|e| {
                 Error::Round1(ErrorType {
@@ -182,6 +238,8 @@ impl Round1 {
 
             m_b_gamma_vec.push(m_b_gamma);
             beta_vec.push(beta_gamma);
+            beta_randomness_vec.push(beta_randomness);
+            beta_tag_vec.push(beta_tag);
             m_b_w_vec.push(m_b_w);
             ni_vec.push(beta_wi);
         }
@@ -189,7 +247,7 @@ impl Round1 {
         let party_indices = (1..=self.s_l.len())
             .map(|j| u16::try_from(j).unwrap())
             .filter(|&j| j != self.i);
-        for ((j, gamma_i), w_i) in party_indices.zip(m_b_gamma_vec).zip(m_b_w_vec) {
+        for ((j, gamma_i), w_i) in party_indices.zip(&m_b_gamma_vec).zip(&m_b_w_vec) {
             output.push(Msg {
                 round:2,
                 sender: self.i,
@@ -204,11 +262,16 @@ impl Round1 {
             local_key: self.local_key,
             sign_keys: self.sign_keys,
             m_a: self.m_a,
-            beta_vec,
-            ni_vec,
+            sent_mb_gamma_s: m_b_gamma_vec,
+            sent_mb_w_s: m_b_w_vec,
+            beta_vec: Secret::new(beta_vec),
+            beta_randomness_vec,
+            beta_tag_vec,
+            ni_vec: Secret::new(ni_vec),
             bc_vec,
             m_a_vec,
             phase1_decom: self.phase1_decom,
+            session_id: self.session_id,
         })
     }
 
@@ -230,18 +293,23 @@ pub struct Round2 {
     local_key: LocalKey<Secp256k1>,
     sign_keys: SignKeys,
     m_a: (MessageA, BigInt),
-    beta_vec: Vec<Scalar<Secp256k1>>,
-    ni_vec: Vec<Scalar<Secp256k1>>,
+    sent_mb_gamma_s: Vec<MessageB>,
+    sent_mb_w_s: Vec<MessageB>,
+    beta_vec: Secret<Vec<Scalar<Secp256k1>>>,
+    beta_randomness_vec: Vec<BigInt>,
+    beta_tag_vec: Vec<BigInt>,
+    ni_vec: Secret<Vec<Scalar<Secp256k1>>>,
     bc_vec: Vec<SignBroadcastPhase1>,
     m_a_vec: Vec<MessageA>,
     phase1_decom: SignDecommitPhase1,
+    session_id: SessionId,
 }
 
 impl Round2 {
    
     pub fn proceed<O>(self, input_p2p: P2PMsgs<(GammaI, WI)>, mut output: O) -> Result<Round3>
     where
-        O: Push<Msg<(DeltaI, TI, TIProof)>>, // TODO: unify TI and TIProof
+        O: Push<Msg<(DeltaI, TI, TIProof, MBHashes)>>, // TODO: unify TI and TIProof
     {
         let (m_b_gamma_s, m_b_w_s): (Vec<_>, Vec<_>) = input_p2p
             .into_vec()
@@ -270,7 +338,10 @@ impl Round2 {
             let m_b = m_b_gamma_s[j].clone();
 
             let alpha_ij_gamma = m_b
-                .verify_proofs_get_alpha(&self.local_key.paillier_dk, &self.sign_keys.k_i)
+                .verify_proofs_get_alpha(
+                    self.local_key.paillier_dk.expose(),
+                    self.sign_keys.k_i.expose(),
+                )
                 .map_err(|e| {
                     Error::Round3(ErrorType {
                         error_type: e.to_string(),
@@ -280,7 +351,10 @@ impl Round2 {
                 })?;
             let m_b = m_b_w_s[j].clone();
             let alpha_ij_wi = m_b
-                .verify_proofs_get_alpha(&self.local_key.paillier_dk, &self.sign_keys.k_i)
+                .verify_proofs_get_alpha(
+                    self.local_key.paillier_dk.expose(),
+                    self.sign_keys.k_i.expose(),
+                )
                 .map_err(|e| {
                     Error::Round3(ErrorType {
                         error_type: e.to_string(),
@@ -288,17 +362,27 @@ impl Round2 {
                         data: vec![],
                     })
                 })?;
-            assert_eq!(m_b.b_proof.pk, g_w_vec[ind]); //TODO: return error
+            if m_b.b_proof.pk != g_w_vec[ind] {
+                return Err(Error::Round2Stage4(ErrorType {
+                    error_type: "mta proof is not bound to w_j".to_string(),
+                    bad_actors: vec![ind],
+                    data: vec![],
+                }));
+            }
 
             alpha_vec.push(alpha_ij_gamma.0);
             miu_vec.push(alpha_ij_wi.0);
         }
 
-        let delta_i = self.sign_keys.phase2_delta_i(&alpha_vec, &self.beta_vec);
+        let delta_i = self.sign_keys.phase2_delta_i(&alpha_vec, self.beta_vec.expose());
 
-        let sigma_i = self.sign_keys.phase2_sigma_i(&miu_vec, &self.ni_vec);
+        let sigma_i = self.sign_keys.phase2_sigma_i(&miu_vec, self.ni_vec.expose());
         //println!("sigma_i {}",sigma_i.to_bigint());
         let (t_i, l_i, t_i_proof) = SignKeys::phase3_compute_t_i(&sigma_i);
+        let m_b_hashes = MBHashes {
+            gamma: self.sent_mb_gamma_s.iter().map(m_b_hash).collect(),
+            w: self.sent_mb_w_s.iter().map(m_b_hash).collect(),
+        };
         output.push(Msg {
             round:3,
             sender: self.i,
@@ -307,6 +391,7 @@ impl Round2 {
                 DeltaI(delta_i.clone()),
                 TI(t_i.clone()),
                 TIProof(t_i_proof.clone()),
+                m_b_hashes.clone(),
             ),
         });
 
@@ -317,14 +402,21 @@ impl Round2 {
             sign_keys: self.sign_keys,
             m_a: self.m_a,
             mb_gamma_s: m_b_gamma_s,
+            mb_w_s: m_b_w_s,
+            sent_mb_gamma_s: self.sent_mb_gamma_s,
+            sent_mb_w_s: self.sent_mb_w_s,
+            beta_randomness_vec: self.beta_randomness_vec,
+            beta_tag_vec: self.beta_tag_vec,
             bc_vec: self.bc_vec,
             m_a_vec: self.m_a_vec,
             delta_i,
             t_i,
-            l_i,
-            sigma_i,
+            l_i: Secret::new(l_i),
+            sigma_i: Secret::new(sigma_i),
             t_i_proof,
+            m_b_hashes,
             phase1_decom: self.phase1_decom,
+            session_id: self.session_id,
         })
     }
 
@@ -344,48 +436,82 @@ pub struct Round3 {
     sign_keys: SignKeys,
     m_a: (MessageA, BigInt),
     mb_gamma_s: Vec<MessageB>,
+    mb_w_s: Vec<MessageB>,
+    sent_mb_gamma_s: Vec<MessageB>,
+    sent_mb_w_s: Vec<MessageB>,
+    beta_randomness_vec: Vec<BigInt>,
+    beta_tag_vec: Vec<BigInt>,
     bc_vec: Vec<SignBroadcastPhase1>,
     m_a_vec: Vec<MessageA>,
-    delta_i: Scalar<Secp256k1>,
+    pub(super) delta_i: Scalar<Secp256k1>,
     t_i: Point<Secp256k1>,
-    l_i: Scalar<Secp256k1>,
-    sigma_i: Scalar<Secp256k1>,
+    l_i: Secret<Scalar<Secp256k1>>,
+    sigma_i: Secret<Scalar<Secp256k1>>,
     t_i_proof: PedersenProof<Secp256k1, Sha256>,
+    m_b_hashes: MBHashes,
 
     phase1_decom: SignDecommitPhase1,
+    session_id: SessionId,
 }
 
 impl Round3 {
    
     pub fn proceed<O>(
         self,
-        input: BroadcastMsgs<(DeltaI, TI, TIProof)>,
+        input: BroadcastMsgs<(DeltaI, TI, TIProof, MBHashes)>,
         mut output: O,
     ) -> Result<Round4>
     where
         O: Push<Msg<SignDecommitPhase1>>,
     {
-        let (delta_vec, t_vec, t_proof_vec) = input
+        let (round3_vec, m_b_hashes_vec): (Vec<_>, Vec<_>) = input
             .into_vec_including_me((
                 DeltaI(self.delta_i),
                 TI(self.t_i.clone()),
                 TIProof(self.t_i_proof),
+                self.m_b_hashes,
             ))
             .into_iter()
-            .map(|(delta_i, t_i, t_i_proof)| (delta_i.0, t_i.0, t_i_proof.0))
-            .unzip3();
+            .map(|(delta_i, t_i, t_i_proof, hashes)| ((delta_i.0, t_i.0, t_i_proof.0), hashes))
+            .unzip();
+        let (delta_vec, t_vec, t_proof_vec) = round3_vec.into_iter().unzip3();
 
-        for i in 0..t_vec.len() {
-            assert_eq!(t_vec[i], t_proof_vec[i].com);
+        // messages B we received in round 2 must be the ones their senders committed to
+        let index = usize::from(self.i - 1);
+        let ttag = self.s_l.len();
+        for j in 0..ttag - 1 {
+            let ind = if j < index { j } else { j + 1 };
+            let hashes = &m_b_hashes_vec[ind];
+            let me = if index < ind { index } else { index - 1 };
+            let matches = |sent: &[BigInt], received: &MessageB| {
+                sent.len() + 1 == ttag && sent[me] == m_b_hash(received)
+            };
+            let gamma_matches = matches(&hashes.gamma, &self.mb_gamma_s[j]);
+            if !gamma_matches || !matches(&hashes.w, &self.mb_w_s[j]) {
+                return Err(Error::Round3(ErrorType {
+                    error_type: "mta responses don't match their hashes".to_string(),
+                    bad_actors: vec![ind],
+                    data: vec![],
+                }));
+            }
+        }
+
+        for (i, (t_i, proof)) in t_vec.iter().zip(&t_proof_vec).enumerate() {
+            if *t_i != proof.com {
+                return Err(Error::Round3(ErrorType {
+                    error_type: "t_i doesn't match its proof".to_string(),
+                    bad_actors: vec![i],
+                    data: vec![],
+                }));
+            }
         }
 
         let delta_inv = SignKeys::phase3_reconstruct_delta(&delta_vec);
-        let ttag = self.s_l.len();
         for (idx, proof) in t_proof_vec.iter().enumerate().take(ttag) {
             PedersenProof::verify(proof).map_err(|e| {
                 Error::Round3(ErrorType {
                     error_type: e.to_string(),
-                    bad_actors: vec![idx + 1],
+                    bad_actors: vec![idx],
                     data: vec![],
                 })
             })?;
@@ -405,18 +531,29 @@ impl Round3 {
             sign_keys: self.sign_keys,
             m_a: self.m_a,
             mb_gamma_s: self.mb_gamma_s,
+            mb_w_s: self.mb_w_s,
+            sent_mb_gamma_s: self.sent_mb_gamma_s,
+            sent_mb_w_s: self.sent_mb_w_s,
+            beta_randomness_vec: self.beta_randomness_vec,
+            beta_tag_vec: self.beta_tag_vec,
             bc_vec: self.bc_vec,
             m_a_vec: self.m_a_vec,
             t_i: self.t_i,
             l_i: self.l_i,
             sigma_i: self.sigma_i,
             phase1_decom: self.phase1_decom,
+            delta_vec,
             delta_inv,
             t_vec,
+            m_b_hashes_vec,
+            session_id: self.session_id,
         })
     }
 
-    pub fn expects_messages(i: u16, n: u16) -> Store<BroadcastMsgs<(DeltaI, TI, TIProof)>> {
+    pub fn expects_messages(
+        i: u16,
+        n: u16,
+    ) -> Store<BroadcastMsgs<(DeltaI, TI, TIProof, MBHashes)>> {
         containers::BroadcastMsgsStore::new(i, n)
     }
 
@@ -432,14 +569,22 @@ pub struct Round4 {
     sign_keys: SignKeys,
     m_a: (MessageA, BigInt),
     mb_gamma_s: Vec<MessageB>,
+    mb_w_s: Vec<MessageB>,
+    sent_mb_gamma_s: Vec<MessageB>,
+    sent_mb_w_s: Vec<MessageB>,
+    beta_randomness_vec: Vec<BigInt>,
+    beta_tag_vec: Vec<BigInt>,
     bc_vec: Vec<SignBroadcastPhase1>,
     m_a_vec: Vec<MessageA>,
     t_i: Point<Secp256k1>,
-    l_i: Scalar<Secp256k1>,
-    sigma_i: Scalar<Secp256k1>,
+    l_i: Secret<Scalar<Secp256k1>>,
+    sigma_i: Secret<Scalar<Secp256k1>>,
+    delta_vec: Vec<Scalar<Secp256k1>>,
     delta_inv: Scalar<Secp256k1>,
     t_vec: Vec<Point<Secp256k1>>,
+    m_b_hashes_vec: Vec<MBHashes>,
     phase1_decom: SignDecommitPhase1,
+    session_id: SessionId,
 }
 
 impl Round4 {
@@ -453,6 +598,7 @@ impl Round4 {
         O: Push<Msg<(RDash, Vec<PDLwSlackProof>)>>,
     {
         let decom_vec: Vec<_> = decommit_round1.into_vec_including_me(self.phase1_decom.clone());
+        let g_gamma_vec: Vec<_> = decom_vec.iter().map(|d| d.g_gamma_i.clone()).collect();
 
         let ttag = self.s_l.len();
         let b_proof_vec: Vec<_> = (0..ttag - 1).map(|i| &self.mb_gamma_s[i].b_proof).collect();
@@ -465,7 +611,7 @@ impl Round4 {
         )
         .map_err(|e| Error::Round5(e))?;
 
-        let R_dash = &R * &self.sign_keys.k_i;
+        let R_dash = &R * self.sign_keys.k_i.expose();
 
         // each party sends first message to all other parties
         let mut phase5_proofs_vec = Vec::new();
@@ -476,6 +622,7 @@ impl Round4 {
             .map(|i| usize::from(i) - 1)
             .collect();
         let index = usize::from(self.i - 1);
+        let ctx = self.session_id.context(self.i, SIGNING_LABEL);
         for j in 0..ttag - 1 {
             let ind = if j < index { j } else { j + 1 };
             let proof = LocalSignature::phase5_proof_pdl(
@@ -483,9 +630,10 @@ impl Round4 {
                 &R,
                 &self.m_a.0.c,
                 &self.local_key.paillier_key_vec[l_s[index]],
-                &self.sign_keys.k_i,
+                self.sign_keys.k_i.expose(),
                 &self.m_a.1,
                 &self.local_key.h1_h2_n_tilde_vec[l_s[ind]],
+                &ctx,
             );
 
             phase5_proofs_vec.push(proof);
@@ -504,13 +652,24 @@ impl Round4 {
             local_key: self.local_key,
             sign_keys: self.sign_keys,
             t_vec: self.t_vec,
+            m_a: self.m_a,
             m_a_vec: self.m_a_vec,
+            mb_gamma_s: self.mb_gamma_s,
+            mb_w_s: self.mb_w_s,
+            sent_mb_gamma_s: self.sent_mb_gamma_s,
+            sent_mb_w_s: self.sent_mb_w_s,
+            beta_randomness_vec: self.beta_randomness_vec,
+            beta_tag_vec: self.beta_tag_vec,
+            delta_vec: self.delta_vec,
+            g_gamma_vec,
+            m_b_hashes_vec: self.m_b_hashes_vec,
             t_i: self.t_i,
             l_i: self.l_i,
             sigma_i: self.sigma_i,
             R,
             R_dash,
             phase5_proofs_vec,
+            session_id: self.session_id,
         })
     }
 
@@ -529,13 +688,24 @@ pub struct Round5 {
     local_key: LocalKey<Secp256k1>,
     sign_keys: SignKeys,
     t_vec: Vec<Point<Secp256k1>>,
+    m_a: (MessageA, BigInt),
     m_a_vec: Vec<MessageA>,
+    mb_gamma_s: Vec<MessageB>,
+    mb_w_s: Vec<MessageB>,
+    sent_mb_gamma_s: Vec<MessageB>,
+    sent_mb_w_s: Vec<MessageB>,
+    beta_randomness_vec: Vec<BigInt>,
+    beta_tag_vec: Vec<BigInt>,
+    delta_vec: Vec<Scalar<Secp256k1>>,
+    g_gamma_vec: Vec<Point<Secp256k1>>,
+    m_b_hashes_vec: Vec<MBHashes>,
     t_i: Point<Secp256k1>,
-    l_i: Scalar<Secp256k1>,
-    sigma_i: Scalar<Secp256k1>,
+    l_i: Secret<Scalar<Secp256k1>>,
+    sigma_i: Secret<Scalar<Secp256k1>>,
     R: Point<Secp256k1>,
     R_dash: Point<Secp256k1>,
     phase5_proofs_vec: Vec<PDLwSlackProof>,
+    session_id: SessionId,
 }
 
 impl Round5 {
@@ -544,9 +714,9 @@ impl Round5 {
         self,
         input: BroadcastMsgs<(RDash, Vec<PDLwSlackProof>)>,
         mut output: O,
-    ) -> Result<Round6>
+    ) -> Result<Outcome<Round6>>
     where
-        O: Push<Msg<(SI, HEGProof)>>,
+        O: Push<Msg<(SI, HEGProof)>> + Push<Msg<BlameData>>,
     {
         let (r_dash_vec, pdl_proof_mat_inc_me): (Vec<_>, Vec<_>) = input
             .into_vec_including_me((RDash(self.R_dash), self.phase5_proofs_vec))
@@ -571,22 +741,47 @@ impl Round5 {
                 &self.local_key.h1_h2_n_tilde_vec,
                 &l_s,
                 i,
+                &self.session_id.context(i as u16 + 1, SIGNING_LABEL),
             )
             .map_err(|e| Error::Round5(e))?;
         }
-        LocalSignature::phase5_check_R_dash_sum(&r_dash_vec).map_err(|e| {
-            Error::Round5(ErrorType {
-                error_type: e.to_string(),
-                bad_actors: vec![],
-                data: vec![],
-            })
-        })?;
+        if LocalSignature::phase5_check_R_dash_sum(&r_dash_vec).is_err() {
+            let encryption_key_vec: Vec<_> = l_s
+                .iter()
+                .map(|&j| self.local_key.paillier_key_vec[j].clone())
+                .collect();
+            let revealed = BlameData::Phase5(
+                LocalStatePhase5 {
+                    k: self.sign_keys.k_i.clone(),
+                    k_randomness: Secret::new(self.m_a.1.clone()),
+                    gamma: self.sign_keys.gamma_i.clone(),
+                    beta_randomness: Secret::new(self.beta_randomness_vec),
+                    beta_tag: self.beta_tag_vec,
+                    encryption_key: encryption_key_vec[usize::from(self.i - 1)].clone(),
+                },
+                self.mb_gamma_s,
+                self.sent_mb_gamma_s,
+            );
+            return Ok(Outcome::Blame(BlameRound::reveal(
+                self.i,
+                revealed,
+                encryption_key_vec,
+                self.m_a_vec,
+                BlameChecks::Phase5 {
+                    delta_vec: self.delta_vec,
+                    g_gamma_vec: self.g_gamma_vec,
+                    m_b_hash_sent_mat: self.m_b_hashes_vec.into_iter().map(|h| h.gamma).collect(),
+                },
+                output,
+            )));
+        }
 
         let (S_i, homo_elgamal_proof) = LocalSignature::phase6_compute_S_i_and_proof_of_consistency(
             &self.R,
             &self.t_i,
-            &self.sigma_i,
-            &self.l_i,
+            self.sigma_i.expose(),
+            self.l_i.expose(),
+            &self.session_id.context(self.i, SIGNING_LABEL),
         );
 
         output.push(Msg {
@@ -596,19 +791,29 @@ impl Round5 {
             body: (SI(S_i.clone()), HEGProof(homo_elgamal_proof.clone())),
         });
 
-        Ok(Round6 {
+        Ok(Outcome::Proceed(Round6 {
             S_i,
             homo_elgamal_proof,
             s_l: self.s_l,
+            session_id: self.session_id,
+            m_a: self.m_a,
+            m_a_vec: self.m_a_vec,
+            mb_w_s: self.mb_w_s,
+            sent_mb_w_s: self.sent_mb_w_s,
+            m_b_hash_sent_mat: self.m_b_hashes_vec.into_iter().map(|h| h.w).collect(),
             protocol_output: CompletedOfflineStage {
                 i: self.i,
-                local_key: self.local_key,
                 sign_keys: self.sign_keys,
                 t_vec: self.t_vec,
+                tweak_sk: Scalar::zero(),
+                y_sum: self.local_key.y_sum_s.clone(),
+                local_key: self.local_key,
                 R: self.R,
                 sigma_i: self.sigma_i,
+                R_dash_vec: r_dash_vec,
+                S_vec: vec![],
             },
-        })
+        }))
     }
 
     pub fn expects_messages(i: u16, n: u16) -> Store<BroadcastMsgs<(RDash, Vec<PDLwSlackProof>)>> {
@@ -622,8 +827,15 @@ impl Round5 {
 
 pub struct Round6 {
     S_i: Point<Secp256k1>,
-    homo_elgamal_proof: HomoELGamalProof<Secp256k1, Sha256>,
+    homo_elgamal_proof: HomoElGamalProof,
     s_l: Vec<u16>,
+    session_id: SessionId,
+    m_a: (MessageA, BigInt),
+    m_a_vec: Vec<MessageA>,
+    mb_w_s: Vec<MessageB>,
+    sent_mb_w_s: Vec<MessageB>,
+    /// Hashes of messages B `w_j` broadcast in round 3, in order of Bobs
+    m_b_hash_sent_mat: Vec<Vec<BigInt>>,
     /// Round 6 guards protocol output until final checks are taken the place
     protocol_output: CompletedOfflineStage,
 }
@@ -631,10 +843,14 @@ pub struct Round6 {
 impl Round6 {
    
     
-    pub fn proceed(
+    pub fn proceed<O>(
         self,
         input: BroadcastMsgs<(SI, HEGProof)>,
-    ) -> Result<CompletedOfflineStage, Error> {
+        output: O,
+    ) -> Result<Outcome<CompletedOfflineStage>, Error>
+    where
+        O: Push<Msg<BlameData>>,
+    {
         let (S_i_vec, hegp_vec): (Vec<_>, Vec<_>) = input
             .into_vec_including_me((SI(self.S_i), HEGProof(self.homo_elgamal_proof)))
             .into_iter()
@@ -643,18 +859,77 @@ impl Round6 {
         let R_vec: Vec<_> = iter::repeat(self.protocol_output.R.clone())
             .take(self.s_l.len())
             .collect();
+        let prover_ctx: Vec<_> = (1..=self.s_l.len())
+            .map(|j| self.session_id.context(j as u16, SIGNING_LABEL))
+            .collect();
 
         LocalSignature::phase6_verify_proof(
             &S_i_vec,
             &hegp_vec,
             &R_vec,
             &self.protocol_output.t_vec,
+            &prover_ctx,
         )
         .map_err(Error::Round6VerifyProof)?;
-        LocalSignature::phase6_check_S_i_sum(&self.protocol_output.local_key.y_sum_s, &S_i_vec)
-            .map_err(Error::Round6CheckSig)?;
+        let completed = self.protocol_output;
+        if LocalSignature::phase6_check_S_i_sum(&completed.local_key.y_sum_s, &S_i_vec).is_err() {
+            let local_key = &completed.local_key;
+            let l_s: Vec<_> = self.s_l.iter().map(|&j| usize::from(j) - 1).collect();
+            let encryption_key_vec: Vec<_> = l_s
+                .iter()
+                .map(|&j| local_key.paillier_key_vec[j].clone())
+                .collect();
+            let dk = local_key.paillier_dk.expose();
+            let miu = self
+                .mb_w_s
+                .iter()
+                .map(|m_b| {
+                    let miu: RawPlaintext =
+                        Paillier::decrypt(dk, RawCiphertext::from(m_b.c.clone()));
+                    miu.0.into_owned()
+                })
+                .collect();
+            let miu_randomness = self
+                .mb_w_s
+                .iter()
+                .map(|m_b| GlobalStatePhase6::extract_paillier_randomness(&m_b.c, dk))
+                .collect();
+            let revealed = BlameData::Phase6(
+                LocalStatePhase6 {
+                    k: completed.sign_keys.k_i.clone(),
+                    k_randomness: Secret::new(self.m_a.1),
+                    miu,
+                    miu_randomness,
+                    proof_of_eq_dlog: GlobalStatePhase6::ecddh_proof(
+                        completed.sigma_i.expose(),
+                        &completed.R,
+                        &S_i_vec[usize::from(completed.i - 1)],
+                    ),
+                },
+                self.mb_w_s,
+                self.sent_mb_w_s,
+            );
+            let g_w_vec =
+                SignKeys::g_w_vec(&local_key.pk_vec[..], &l_s[..], &local_key.vss_scheme);
+            return Ok(Outcome::Blame(BlameRound::reveal(
+                completed.i,
+                revealed,
+                encryption_key_vec,
+                self.m_a_vec,
+                BlameChecks::Phase6 {
+                    R: completed.R.clone(),
+                    S_vec: S_i_vec,
+                    g_w_vec,
+                    m_b_hash_sent_mat: self.m_b_hash_sent_mat,
+                },
+                output,
+            )));
+        }
 
-        Ok(self.protocol_output)
+        Ok(Outcome::Proceed(CompletedOfflineStage {
+            S_vec: S_i_vec,
+            ..completed
+        }))
     }
 
     pub fn expects_messages(i: u16, n: u16) -> Store<BroadcastMsgs<(SI, HEGProof)>> {
@@ -666,6 +941,167 @@ impl Round6 {
     }
 }
 
+/// Publicly known values which revealed secrets are checked against
+enum BlameChecks {
+    Phase5 {
+        delta_vec: Vec<Scalar<Secp256k1>>,
+        g_gamma_vec: Vec<Point<Secp256k1>>,
+        m_b_hash_sent_mat: Vec<Vec<BigInt>>,
+    },
+    Phase6 {
+        R: Point<Secp256k1>,
+        S_vec: Vec<Point<Secp256k1>>,
+        g_w_vec: Vec<Point<Secp256k1>>,
+        m_b_hash_sent_mat: Vec<Vec<BigInt>>,
+    },
+}
+
+/// Blame round of the offline stage
+///
+/// Entered by every party once `R_i` or `S_i` don't add up. Nonces of the failed signing are
+/// discarded, so parties can safely reveal them to find out who cheated.
+pub struct BlameRound {
+    revealed: BlameData,
+    encryption_key_vec: Vec<EncryptionKey>,
+    m_a_vec: Vec<MessageA>,
+    checks: BlameChecks,
+}
+
+impl BlameRound {
+    fn reveal<O>(
+        i: u16,
+        revealed: BlameData,
+        encryption_key_vec: Vec<EncryptionKey>,
+        m_a_vec: Vec<MessageA>,
+        checks: BlameChecks,
+        mut output: O,
+    ) -> Self
+    where
+        O: Push<Msg<BlameData>>,
+    {
+        output.push(Msg {
+            round: 7,
+            sender: i,
+            receiver: None,
+            body: revealed.clone(),
+        });
+        BlameRound {
+            revealed,
+            encryption_key_vec,
+            m_a_vec,
+            checks,
+        }
+    }
+
+    /// Blame round never completes the offline stage, it results into [Error::Blame] naming
+    /// parties whose revealed secrets don't match messages they sent
+    pub fn proceed(self, input: BroadcastMsgs<BlameData>) -> Error {
+        let revealed = input.into_vec_including_me(self.revealed);
+        let n = revealed.len();
+
+        let result = match self.checks {
+            BlameChecks::Phase5 {
+                delta_vec,
+                g_gamma_vec,
+                m_b_hash_sent_mat,
+            } => {
+                let mut local_states = Vec::with_capacity(n);
+                let mut m_b_received_mat = Vec::with_capacity(n);
+                let mut m_b_sent_mat = Vec::with_capacity(n);
+                let mut malformed = Vec::new();
+                for (j, data) in revealed.into_iter().enumerate() {
+                    match data {
+                        BlameData::Phase5(state, received, sent)
+                            if state.is_complete(n)
+                                && received.len() + 1 == n
+                                && sent.len() + 1 == n =>
+                        {
+                            local_states.push(state);
+                            m_b_received_mat.push(received);
+                            m_b_sent_mat.push(sent);
+                        }
+                        _ => malformed.push(j),
+                    }
+                }
+                if !malformed.is_empty() {
+                    return blame("phase5_blame", malformed);
+                }
+                GlobalStatePhase5::local_state_to_global_state(
+                    &self.encryption_key_vec,
+                    &delta_vec,
+                    &g_gamma_vec,
+                    &self.m_a_vec,
+                    m_b_received_mat,
+                    m_b_sent_mat,
+                    m_b_hash_sent_mat,
+                    &local_states,
+                )
+                .phase5_blame()
+            }
+            BlameChecks::Phase6 {
+                R,
+                S_vec,
+                g_w_vec,
+                m_b_hash_sent_mat,
+            } => {
+                let mut local_states = Vec::with_capacity(n);
+                let mut m_b_received_mat = Vec::with_capacity(n);
+                let mut m_b_sent_mat = Vec::with_capacity(n);
+                let mut malformed = Vec::new();
+                for (j, data) in revealed.into_iter().enumerate() {
+                    match data {
+                        BlameData::Phase6(state, received, sent)
+                            if state.is_complete(n)
+                                && received.len() + 1 == n
+                                && sent.len() + 1 == n =>
+                        {
+                            local_states.push(state);
+                            m_b_received_mat.push(received);
+                            m_b_sent_mat.push(sent);
+                        }
+                        _ => malformed.push(j),
+                    }
+                }
+                if !malformed.is_empty() {
+                    return blame("phase6_blame", malformed);
+                }
+                GlobalStatePhase6::local_state_to_global_state(
+                    &self.encryption_key_vec,
+                    &S_vec,
+                    &g_w_vec,
+                    &self.m_a_vec,
+                    m_b_received_mat,
+                    m_b_sent_mat,
+                    m_b_hash_sent_mat,
+                    &local_states,
+                )
+                .phase6_blame(&R)
+            }
+        };
+
+        match result {
+            Err(err) => Error::Blame(err),
+            Ok(()) => blame("blame", vec![]),
+        }
+    }
+
+    pub fn expects_messages(i: u16, n: u16) -> Store<BroadcastMsgs<BlameData>> {
+        containers::BroadcastMsgsStore::new(i, n)
+    }
+
+    pub fn is_expensive(&self) -> bool {
+        true
+    }
+}
+
+fn blame(error_type: &str, bad_actors: Vec<usize>) -> Error {
+    Error::Blame(ErrorType {
+        error_type: error_type.to_string(),
+        bad_actors,
+        data: vec![],
+    })
+}
+
 #[derive(Clone)]
 pub struct CompletedOfflineStage {
     i: u16,
@@ -673,12 +1109,37 @@ pub struct CompletedOfflineStage {
     sign_keys: SignKeys,
     t_vec: Vec<Point<Secp256k1>>,
     R: Point<Secp256k1>,
-    sigma_i: Scalar<Secp256k1>,
+    sigma_i: Secret<Scalar<Secp256k1>>,
+    /// BIP32 tweak of the key being signed for, zero for the root key
+    tweak_sk: Scalar<Secp256k1>,
+    /// Public key that signature will be verified against
+    y_sum: Point<Secp256k1>,
+    /// `R_i` and `S_i` of all the signers, kept to find out who cheated if signature is invalid
+    R_dash_vec: Vec<Point<Secp256k1>>,
+    S_vec: Vec<Point<Secp256k1>>,
 }
 
 impl CompletedOfflineStage {
     pub fn public_key(&self) -> &Point<Secp256k1> {
-        &self.local_key.y_sum_s
+        &self.y_sum
+    }
+
+    /// Makes signature verifiable under child public key of `local_key_hd`
+    ///
+    /// Only used by `OfflineStage::new_with_tweak`, i.e. the tweak is fixed before offline stage
+    /// starts. Choosing the tweak once `R` is known lets an attacker turn a signature for one
+    /// child key into a forgery for another (related-key attack on presignatures).
+    /// `local_key_hd.local_key` must be the root key that was used in offline stage. Returns
+    /// [Error::InvalidTweak] otherwise.
+    pub(crate) fn with_tweak(mut self, local_key_hd: &LocalKey_HD<Secp256k1>) -> Result<Self> {
+        if local_key_hd.local_key.y_sum_s != self.local_key.y_sum_s
+            || !local_key_hd.is_consistent()
+        {
+            return Err(Error::InvalidTweak);
+        }
+        self.tweak_sk = local_key_hd.tweak_sk.clone();
+        self.y_sum = local_key_hd.y_sum.clone();
+        Ok(self)
     }
 }
 
@@ -687,7 +1148,10 @@ pub struct PartialSignature(Scalar<Secp256k1>);
 
 #[derive(Clone)]
 pub struct Round7 {
+    i: u16,
     local_signature: LocalSignature,
+    R_dash_vec: Vec<Point<Secp256k1>>,
+    S_vec: Vec<Point<Secp256k1>>,
 }
 
 impl Round7 {
@@ -695,22 +1159,64 @@ impl Round7 {
         message: &BigInt,
         completed_offline_stage: CompletedOfflineStage,
     ) -> Result<(Self, PartialSignature)> {
+        // sigma_i are additive shares of k*x, so shares of k*(x + tweak) are
+        // sigma_i + k_i*tweak
+        let sigma_i = completed_offline_stage.sigma_i.expose()
+            + completed_offline_stage.sign_keys.k_i.expose() * &completed_offline_stage.tweak_sk;
         let local_signature = LocalSignature::phase7_local_sig(
-            &completed_offline_stage.sign_keys.k_i,
+            completed_offline_stage.sign_keys.k_i.expose(),
             message,
             &completed_offline_stage.R,
-            &completed_offline_stage.sigma_i,
-            &completed_offline_stage.local_key.y_sum_s,
+            &sigma_i,
+            &completed_offline_stage.y_sum,
         );
+        // likewise S_i = R^sigma_i are shifted by R_i^tweak
+        let tweak = &completed_offline_stage.tweak_sk;
+        let S_vec = completed_offline_stage
+            .S_vec
+            .iter()
+            .zip(&completed_offline_stage.R_dash_vec)
+            .map(|(S_i, R_dash_i)| S_i + R_dash_i * tweak)
+            .collect();
         let partial = PartialSignature(local_signature.s_i.clone());
-        Ok((Self { local_signature }, partial))
+        Ok((
+            Self {
+                i: completed_offline_stage.i,
+                local_signature,
+                R_dash_vec: completed_offline_stage.R_dash_vec,
+                S_vec,
+            },
+            partial,
+        ))
     }
 
+    /// `sigs` are partial signatures of the other parties in order of their indexes. If resulting
+    /// signature is invalid, returns [Error::Blame] naming parties whose partial signatures are
+    /// wrong.
     pub fn proceed_manual(self, sigs: &[PartialSignature]) -> Result<SignatureRecid> {
         let sigs = sigs.iter().map(|s_i| s_i.0.clone()).collect::<Vec<_>>();
-        self.local_signature
-            .output_signature(&sigs)
-            .map_err(Error::Round7)
+        let err = match self.local_signature.output_signature(&sigs) {
+            Ok(signature) => return Ok(signature),
+            Err(err) => err,
+        };
+        if sigs.len() + 1 != self.S_vec.len() {
+            return Err(Error::Round7(err));
+        }
+
+        let mut s_vec = sigs;
+        s_vec.insert(usize::from(self.i - 1), self.local_signature.s_i.clone());
+        let global_state = GlobalStatePhase7 {
+            s_vec,
+            r: self.local_signature.r,
+            R_dash_vec: self.R_dash_vec,
+            m: self.local_signature.m,
+            R: self.local_signature.R,
+            S_vec: self.S_vec,
+        };
+        match global_state.phase7_blame() {
+            Err(blame) if !blame.bad_actors.is_empty() => Err(Error::Blame(blame)),
+            _ => Err(Error::Round7(err)),
+        }
     }
 }
 
@@ -728,10 +1234,31 @@ pub enum Error {
     Round5(ErrorType),
     #[error("round 6: verify proof: {0:?}")]
     Round6VerifyProof(ErrorType),
-    #[error("round 6: check sig: {0:?}")]
-    Round6CheckSig(crate::Error),
     #[error("round 7: {0:?}")]
     Round7(crate::Error),
+    /// Some check failed and revealed secrets point at parties who cheated
+    #[error("blame: {0:?}")]
+    Blame(ErrorType),
+    #[error("tweak doesn't match the key used in offline stage")]
+    InvalidTweak,
+}
+
+impl Error {
+    /// Indexes of the parties identified as malicious, in range `[0; n)`, see
+    /// [ErrorType::bad_actors](crate::protocols::multi_party_ecdsa::gg_2020::ErrorType::bad_actors)
+    ///
+    /// Returns `None` if error isn't caused by any party in particular.
+    pub fn bad_actors(&self) -> Option<&[usize]> {
+        match self {
+            Error::Round1(err)
+            | Error::Round2Stage4(err)
+            | Error::Round3(err)
+            | Error::Round5(err)
+            | Error::Round6VerifyProof(err)
+            | Error::Blame(err) => Some(&err.bad_actors),
+            Error::Round2Stage3(_) | Error::Round7(_) | Error::InvalidTweak => None,
+        }
+    }
 }
 
 trait IteratorExt: Iterator {
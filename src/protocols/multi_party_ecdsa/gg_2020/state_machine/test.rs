@@ -0,0 +1,55 @@
+//! Helpers shared by tests of the state machines
+
+use round_based::{Msg, StateMachine};
+
+/// Runs protocol delivering messages by hand, `tamper` may modify any message before it's
+/// delivered to other parties. Returns outcome of each party, `None` if party got stuck.
+pub fn run_with_tamper<SM, F>(
+    mut parties: Vec<SM>,
+    mut tamper: F,
+) -> Vec<Option<Result<SM::Output, SM::Err>>>
+where
+    SM: StateMachine,
+    SM::MessageBody: Clone,
+    F: FnMut(&mut Msg<SM::MessageBody>),
+{
+    let n = parties.len();
+    let mut results: Vec<Option<Result<SM::Output, SM::Err>>> = (0..n).map(|_| None).collect();
+    let mut progressed = true;
+    while progressed {
+        progressed = false;
+        for i in 0..n {
+            if results[i].is_some() {
+                continue;
+            }
+            if parties[i].wants_to_proceed() {
+                progressed = true;
+                if let Err(err) = parties[i].proceed() {
+                    results[i] = Some(Err(err));
+                    continue;
+                }
+            }
+
+            let msgs: Vec<_> = parties[i].message_queue().drain(..).collect();
+            for mut msg in msgs {
+                progressed = true;
+                tamper(&mut msg);
+                for j in 0..n {
+                    let receiver = j as u16 + 1;
+                    let addressed = msg.receiver.map_or(true, |r| r == receiver);
+                    if receiver == msg.sender || !addressed || results[j].is_some() {
+                        continue;
+                    }
+                    if let Err(err) = parties[j].handle_incoming(msg.clone()) {
+                        results[j] = Some(Err(err));
+                    }
+                }
+            }
+
+            if parties[i].is_finished() {
+                results[i] = parties[i].pick_output();
+            }
+        }
+    }
+    results
+}
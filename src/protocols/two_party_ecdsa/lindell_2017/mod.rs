@@ -15,6 +15,10 @@
 */
 
 const SECURITY_BITS: usize = 256;
+/// Label of the protocol in proof contexts, the protocol has no session ids
+const PROTOCOL_LABEL: &str = "lindell-2017";
+/// Index of party one as a prover
+const PARTY_ONE: u16 = 1;
 
 pub mod party_one;
 pub mod party_two;
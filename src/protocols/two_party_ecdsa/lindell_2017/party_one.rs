@@ -36,7 +36,7 @@ use zk_paillier::zkproofs::NiCorrectKeyProof;
 
 use super::party_two::EphKeyGenFirstMsg as Party2EphKeyGenFirstMessage;
 use super::party_two::EphKeyGenSecondMsg as Party2EphKeyGenSecondMessage;
-use super::SECURITY_BITS;
+use super::{PARTY_ONE, PROTOCOL_LABEL, SECURITY_BITS};
 
 use crate::utilities::mta::MessageB;
 use crate::Error;
@@ -44,6 +44,7 @@ use crate::Error;
 use crate::utilities::zk_pdl_with_slack::PDLwSlackProof;
 use crate::utilities::zk_pdl_with_slack::PDLwSlackStatement;
 use crate::utilities::zk_pdl_with_slack::PDLwSlackWitness;
+use crate::utilities::transcript::ProofContext;
 use zk_paillier::zkproofs::{CompositeDLogProof, DLogStatement};
 
 //****************** Begin: Party One structs ******************//
@@ -391,7 +392,11 @@ impl PaillierKeyPair {
             r: party1_private.c_key_randomness.clone(),
         };
 
-        let pdl_w_slack_proof = PDLwSlackProof::prove(&pdl_w_slack_witness, &pdl_w_slack_statement);
+        let pdl_w_slack_proof = PDLwSlackProof::prove(
+            &pdl_w_slack_witness,
+            &pdl_w_slack_statement,
+            &ProofContext::unbound(PARTY_ONE, PROTOCOL_LABEL),
+        );
         (
             pdl_w_slack_statement,
             pdl_w_slack_proof,
@@ -35,11 +35,12 @@ use zk_paillier::zkproofs::{IncorrectProof, NiCorrectKeyProof};
 use super::party_one::EphKeyGenFirstMsg as Party1EphKeyGenFirstMsg;
 use super::party_one::KeyGenFirstMsg as Party1KeyGenFirstMessage;
 use super::party_one::KeyGenSecondMsg as Party1KeyGenSecondMessage;
-use super::SECURITY_BITS;
+use super::{PARTY_ONE, PROTOCOL_LABEL, SECURITY_BITS};
 use crate::utilities::mta::{MessageA, MessageB};
 
 use crate::utilities::zk_pdl_with_slack::PDLwSlackProof;
 use crate::utilities::zk_pdl_with_slack::PDLwSlackStatement;
+use crate::utilities::transcript::ProofContext;
 use thiserror::Error;
 use zk_paillier::zkproofs::{CompositeDLogProof, DLogStatement};
 
@@ -266,7 +267,8 @@ impl Party2Private {
             c: ciphertext.clone(),
             range_proofs: vec![],
         };
-        let (a, b, _, _) = MessageB::b(&self.x2, ek, message_a, &[]).unwrap();
+        let party_one = ProofContext::unbound(PARTY_ONE, PROTOCOL_LABEL);
+        let (a, b, _, _) = MessageB::b(&self.x2, ek, message_a, &[], &party_one).unwrap();
         (a, b)
     }
 }
@@ -291,7 +293,12 @@ impl PaillierPublic {
             ni: pdl_w_slack_statement.h2.clone(),
         };
         if composite_dlog_proof.verify(&dlog_statement).is_ok()
-            && pdl_w_slack_proof.verify(pdl_w_slack_statement).is_ok()
+            && pdl_w_slack_proof
+                .verify(
+                    pdl_w_slack_statement,
+                    &ProofContext::unbound(PARTY_ONE, PROTOCOL_LABEL),
+                )
+                .is_ok()
         {
             Ok(())
         } else {
@@ -1,3 +1,9 @@
 pub mod mta;
+pub mod secret;
+pub mod transcript;
+pub mod zk_composite_dlog;
+pub mod zk_homo_elgamal;
+pub mod zk_paillier_blum_modulus;
 pub mod zk_pdl;
 pub mod zk_pdl_with_slack;
+pub mod zk_ring_pedersen;
@@ -29,6 +29,7 @@ use sha2::Sha256;
 
 use crate::protocols::multi_party_ecdsa::gg_2018::party_i::PartyPrivate;
 use crate::utilities::mta::range_proofs::AliceProof;
+use crate::utilities::transcript::ProofContext;
 use crate::Error::{self, InvalidKey};
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -49,13 +50,16 @@ impl MessageA {
     /// - other parties' `h1,h2,N_tilde`s for range proofs.
     /// If range proofs are not needed (one example is identification of aborts where we
     /// only want to reconstruct a ciphertext), `dlog_statements` can be an empty slice.
+    /// Range proofs are bound to Alice's context `ctx`.
     pub fn a(
         a: &Scalar<Secp256k1>,
         alice_ek: &EncryptionKey,
         dlog_statements: &[DLogStatement],
+        ctx: &ProofContext,
     ) -> (Self, BigInt) {
         let randomness = BigInt::sample_below(&alice_ek.n);
-        let m_a = MessageA::a_with_predefined_randomness(a, alice_ek, &randomness, dlog_statements);
+        let m_a =
+            MessageA::a_with_predefined_randomness(a, alice_ek, &randomness, dlog_statements, ctx);
         (m_a, randomness)
     }
 
@@ -64,6 +68,7 @@ impl MessageA {
         alice_ek: &EncryptionKey,
         randomness: &BigInt,
         dlog_statements: &[DLogStatement],
+        ctx: &ProofContext,
     ) -> Self {
         let c_a = Paillier::encrypt_with_chosen_randomness(
             alice_ek,
@@ -76,7 +81,14 @@ impl MessageA {
         let alice_range_proofs = dlog_statements
             .iter()
             .map(|dlog_statement| {
-                AliceProof::generate(&a.to_bigint(), &c_a, alice_ek, dlog_statement, randomness)
+                AliceProof::generate(
+                    &a.to_bigint(),
+                    &c_a,
+                    alice_ek,
+                    dlog_statement,
+                    randomness,
+                    ctx,
+                )
             })
             .collect::<Vec<AliceProof>>();
 
@@ -88,11 +100,13 @@ impl MessageA {
 }
 
 impl MessageB {
+    /// Responds to Alice's `m_a`, whose range proofs are verified in Alice's context `alice_ctx`
     pub fn b(
         b: &Scalar<Secp256k1>,
         alice_ek: &EncryptionKey,
         m_a: MessageA,
         dlog_statements: &[DLogStatement],
+        alice_ctx: &ProofContext,
     ) -> Result<(Self, Scalar<Secp256k1>, BigInt, BigInt), Error> {
         let beta_tag = BigInt::sample_below(&alice_ek.n);
         let randomness = BigInt::sample_below(&alice_ek.n);
@@ -103,6 +117,7 @@ impl MessageB {
             &randomness,
             &beta_tag,
             dlog_statements,
+            alice_ctx,
         )?;
 
         Ok((m_b, beta, randomness, beta_tag))
@@ -115,6 +130,7 @@ impl MessageB {
         randomness: &BigInt,
         beta_tag: &BigInt,
         dlog_statements: &[DLogStatement],
+        alice_ctx: &ProofContext,
     ) -> Result<(Self, Scalar<Secp256k1>), Error> {
         if m_a.range_proofs.len() != dlog_statements.len() {
             log::info!(
@@ -129,7 +145,9 @@ impl MessageB {
             .range_proofs
             .iter()
             .zip(dlog_statements)
-            .map(|(proof, dlog_statement)| proof.verify(&m_a.c, alice_ek, dlog_statement))
+            .map(|(proof, dlog_statement)| {
+                proof.verify(&m_a.c, alice_ek, dlog_statement, alice_ctx)
+            })
             .all(|x| x)
         {
             log::info!("MP-ECDSA : Proof Mismatch");
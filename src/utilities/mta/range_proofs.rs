@@ -22,6 +22,11 @@ use serde::{Deserialize, Serialize};
 use std::borrow::Borrow;
 use zeroize::Zeroize;
 
+use crate::utilities::transcript::ProofContext;
+
+const ALICE_PROOF: &str = "mta/alice-range-proof";
+const BOB_PROOF: &str = "mta/bob-range-proof";
+
 /// Represents the first round of the interactive version of the proof
 #[derive(Zeroize)]
 #[zeroize(drop)]
@@ -107,6 +112,7 @@ impl AliceProof {
         cipher: &BigInt,
         alice_ek: &EncryptionKey,
         dlog_statement: &DLogStatement,
+        ctx: &ProofContext,
     ) -> bool {
         let N = &alice_ek.n;
         let NN = &alice_ek.nn;
@@ -141,6 +147,7 @@ impl AliceProof {
         let u = (gs1 * BigInt::mod_pow(&self.s, N, NN) * cipher_e_inv) % NN;
 
         let e = Sha256::new()
+            .chain_bigint(&ctx.tag(ALICE_PROOF))
             .chain_bigint(N)
             .chain_bigint(&Gen)
             .chain_bigint(cipher)
@@ -163,6 +170,7 @@ impl AliceProof {
         alice_ek: &EncryptionKey,
         dlog_statement: &DLogStatement,
         r: &BigInt,
+        ctx: &ProofContext,
     ) -> Self {
         let round1 = AliceZkpRound1::from(
             alice_ek,
@@ -173,6 +181,7 @@ impl AliceProof {
 
         let Gen = alice_ek.n.borrow() + 1;
         let e = Sha256::new()
+            .chain_bigint(&ctx.tag(ALICE_PROOF))
             .chain_bigint(&alice_ek.n)
             .chain_bigint(&Gen)
             .chain_bigint(cipher)
@@ -325,6 +334,7 @@ impl BobProof {
         alice_ek: &EncryptionKey,
         dlog_statement: &DLogStatement,
         check: Option<&BobCheck>,
+        ctx: &ProofContext,
     ) -> bool {
         let N = &alice_ek.n;
         let NN = &alice_ek.nn;
@@ -372,7 +382,9 @@ impl BobProof {
             % N_tilde;
 
         let Gen = alice_ek.n.borrow() + 1;
+        let tag = ctx.tag(BOB_PROOF);
         let mut values_to_hash = vec![
+            &tag,
             &alice_ek.n,
             &Gen,
             a_enc,
@@ -420,6 +432,7 @@ impl BobProof {
         dlog_statement: &DLogStatement,
         r: &Randomness,
         check: bool,
+        ctx: &ProofContext,
     ) -> (BobProof, Option<Point<Secp256k1>>) {
         let round1 = BobZkpRound1::from(
             alice_ek,
@@ -431,7 +444,9 @@ impl BobProof {
         );
 
         let Gen = alice_ek.n.borrow() + 1;
+        let tag = ctx.tag(BOB_PROOF);
         let mut values_to_hash = vec![
+            &tag,
             &alice_ek.n,
             &Gen,
             a_encrypted,
@@ -503,6 +518,7 @@ impl BobProofExt {
         alice_ek: &EncryptionKey,
         dlog_statement: &DLogStatement,
         X: &Point<Secp256k1>,
+        ctx: &ProofContext,
     ) -> bool {
         // check basic proof first
         if !self.proof.verify(
@@ -514,6 +530,7 @@ impl BobProofExt {
                 u: self.u.clone(),
                 X: X.clone(),
             }),
+            ctx,
         ) {
             return false;
         }
@@ -559,6 +576,7 @@ impl SampleFromMultiplicativeGroup for BigInt {
 #[cfg(test)]
 pub(crate) mod tests {
     use super::*;
+    use crate::utilities::transcript::SessionId;
     use paillier::traits::{Encrypt, EncryptWithChosenRandomness, KeyGeneration};
     use paillier::{Add, DecryptionKey, Mul, Paillier, RawCiphertext, RawPlaintext};
 
@@ -570,6 +588,7 @@ pub(crate) mod tests {
         alice_ek: &EncryptionKey,
         dlog_statement: &DLogStatement,
         r: &Randomness,
+        ctx: &ProofContext,
     ) -> BobProofExt {
         // proving a basic proof (with modified hash)
         let (bob_proof, u) = BobProof::generate(
@@ -581,6 +600,7 @@ pub(crate) mod tests {
             dlog_statement,
             r,
             true,
+            ctx,
         );
 
         BobProofExt {
@@ -628,14 +648,24 @@ pub(crate) mod tests {
         .clone()
         .into_owned();
 
-        let alice_proof = AliceProof::generate(&a, &cipher, &ek, &dlog_statement, &r);
+        let session = SessionId::random();
+        let ctx = session.context(1, "test");
+        let alice_proof = AliceProof::generate(&a, &cipher, &ek, &dlog_statement, &r, &ctx);
 
-        assert!(alice_proof.verify(&cipher, &ek, &dlog_statement));
+        assert!(alice_proof.verify(&cipher, &ek, &dlog_statement, &ctx));
+        assert!(!alice_proof.verify(&cipher, &ek, &dlog_statement, &session.context(2, "test")));
+        assert!(!alice_proof.verify(
+            &cipher,
+            &ek,
+            &dlog_statement,
+            &SessionId::random().context(1, "test")
+        ));
     }
 
     #[test]
     fn bob_zkp() {
         let (dlog_statement, ek, _) = generate_init();
+        let ctx = SessionId::random().context(2, "test");
 
         (0..5).for_each(|_| {
             let alice_public_key = &ek;
@@ -676,13 +706,15 @@ pub(crate) mod tests {
                     &dlog_statement,
                     &r,
                     false,
+                    &ctx,
                 );
                 assert!(bob_proof.verify(
                     &encrypted_a,
                     &mta_out.0.clone().into_owned(),
                     alice_public_key,
                     &dlog_statement,
-                    None
+                    None,
+                    &ctx
                 ));
 
                 // Bob follows MtAwc
@@ -696,13 +728,15 @@ pub(crate) mod tests {
                     alice_public_key,
                     &dlog_statement,
                     &r,
+                    &ctx,
                 );
                 assert!(bob_proof.verify(
                     &encrypted_a,
                     &mta_out.0.clone().into_owned(),
                     alice_public_key,
                     &dlog_statement,
-                    &X
+                    &X,
+                    &ctx
                 ));
             });
         });
@@ -0,0 +1,124 @@
+//! Wrapper for secret material which is wiped on drop and never printed
+//!
+//! Secret fields (shares, Paillier private keys, nonces) are stored as [Secret], so that they
+//! are overwritten once the holding struct is dropped, and `#[derive(Debug)]` on the holding
+//! struct prints `[REDACTED]` instead of them. The value is only accessible explicitly via
+//! [Secret::expose].
+
+use std::fmt;
+
+use curv::elliptic::curves::{Curve, Scalar};
+use curv::BigInt;
+use paillier::DecryptionKey;
+use serde::{Deserialize, Serialize};
+use zeroize::{Zeroize, ZeroizeOnDrop};
+
+/// Value which can be overwritten in place once it's not needed anymore
+pub trait Wipe {
+    fn wipe(&mut self);
+}
+
+impl<E: Curve> Wipe for Scalar<E> {
+    fn wipe(&mut self) {
+        // Scalar zeroizes its own memory when the previous value is dropped
+        *self = Scalar::zero();
+    }
+}
+
+impl Wipe for BigInt {
+    fn wipe(&mut self) {
+        self.zeroize();
+    }
+}
+
+impl<T: Wipe> Wipe for Vec<T> {
+    fn wipe(&mut self) {
+        self.iter_mut().for_each(Wipe::wipe);
+    }
+}
+
+impl Wipe for DecryptionKey {
+    fn wipe(&mut self) {
+        self.p.zeroize();
+        self.q.zeroize();
+    }
+}
+
+/// Secret value which is wiped on drop and redacted in `Debug` output
+///
+/// Serializes exactly as the inner value, so wrapping a field doesn't change serialization
+/// format of the holding struct.
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Secret<T: Wipe>(T);
+
+impl<T: Wipe> Secret<T> {
+    pub fn new(value: T) -> Self {
+        Secret(value)
+    }
+
+    /// Gives access to the secret value
+    pub fn expose(&self) -> &T {
+        &self.0
+    }
+
+    /// Gives mutable access to the secret value
+    pub fn expose_mut(&mut self) -> &mut T {
+        &mut self.0
+    }
+}
+
+impl<T: Wipe> From<T> for Secret<T> {
+    fn from(value: T) -> Self {
+        Secret(value)
+    }
+}
+
+impl<T: Wipe> fmt::Debug for Secret<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("[REDACTED]")
+    }
+}
+
+impl<T: Wipe> Zeroize for Secret<T> {
+    fn zeroize(&mut self) {
+        self.0.wipe()
+    }
+}
+
+impl<T: Wipe> Drop for Secret<T> {
+    fn drop(&mut self) {
+        self.0.wipe()
+    }
+}
+
+impl<T: Wipe> ZeroizeOnDrop for Secret<T> {}
+
+#[cfg(test)]
+mod test {
+    use curv::arithmetic::Converter;
+    use curv::elliptic::curves::secp256_k1::Secp256k1;
+
+    use super::*;
+
+    #[test]
+    fn secret_is_redacted_and_serialized_transparently() {
+        let scalar = Scalar::<Secp256k1>::random();
+        let secret = Secret::new(scalar.clone());
+
+        let debug = format!("{:?}", secret);
+        assert_eq!(debug, "[REDACTED]");
+        assert!(!debug.contains(&scalar.to_bigint().to_hex()));
+
+        assert_eq!(
+            serde_json::to_string(&secret).unwrap(),
+            serde_json::to_string(&scalar).unwrap()
+        );
+        let mut secret: Secret<Scalar<Secp256k1>> =
+            serde_json::from_str(&serde_json::to_string(&scalar).unwrap()).unwrap();
+        assert_eq!(secret.expose(), &scalar);
+
+        secret.zeroize();
+        assert!(secret.expose().is_zero());
+    }
+}
@@ -0,0 +1,122 @@
+//! Session identifiers and domain separation of Fiat–Shamir challenges
+//!
+//! Non-interactive proofs hash a [ProofContext] into their challenge along with the statement.
+//! The context consists of the [SessionId] agreed by the parties before running a protocol,
+//! index of the proving party and the protocol label, so a proof made in one execution (or by
+//! one party) doesn't verify in another.
+//!
+//! `NiCorrectKeyProof` (from `zk-paillier`) computes its challenge internally, in GG20 it's bound
+//! via its salt, which is set to [ProofContext::tag]. GG20 proves generation of `h1, h2` with
+//! [CompositeDLogProof](crate::utilities::zk_composite_dlog::CompositeDLogProof), which hashes
+//! the context. GG18 and two-party Lindell still use the `zk-paillier` proofs with fixed salt.
+
+use curv::arithmetic::Converter;
+use curv::BigInt;
+use rand_core::{OsRng, RngCore};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+const SESSION_ID_DOMAIN: &[u8] = b"gg20-hd/session-id";
+const PROOF_CONTEXT_DOMAIN: &[u8] = b"gg20-hd/proof-context";
+
+/// Identifier of a protocol execution
+///
+/// All the parties must use the same session id, and it must never be reused for another
+/// execution, e.g. it can be randomly chosen by the coordinator or derived from a unique request
+/// id via [SessionId::from_request_id].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct SessionId([u8; 32]);
+
+impl SessionId {
+    pub fn new(bytes: [u8; 32]) -> Self {
+        SessionId(bytes)
+    }
+
+    /// Samples a random session id
+    pub fn random() -> Self {
+        let mut bytes = [0u8; 32];
+        OsRng.fill_bytes(&mut bytes);
+        SessionId(bytes)
+    }
+
+    /// Derives session id from an identifier parties agreed on out of band
+    pub fn from_request_id(id: &[u8]) -> Self {
+        let mut bytes = [0u8; 32];
+        bytes.copy_from_slice(&Sha256::new().chain(SESSION_ID_DOMAIN).chain(id).finalize());
+        SessionId(bytes)
+    }
+
+    pub fn as_bytes(&self) -> &[u8; 32] {
+        &self.0
+    }
+
+    /// Context of proofs produced by party `prover` of protocol `label` in this session
+    pub fn context(&self, prover: u16, label: &'static str) -> ProofContext {
+        ProofContext {
+            session: *self,
+            prover,
+            label,
+        }
+    }
+}
+
+/// Context bound into Fiat–Shamir challenge of a proof
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ProofContext {
+    session: SessionId,
+    prover: u16,
+    label: &'static str,
+}
+
+impl ProofContext {
+    /// Context of a proof in a protocol that has no session id (e.g. GG18 or two-party
+    /// Lindell), it only binds the prover index and the protocol label
+    pub fn unbound(prover: u16, label: &'static str) -> Self {
+        SessionId::default().context(prover, label)
+    }
+
+    pub fn session(&self) -> &SessionId {
+        &self.session
+    }
+
+    pub fn prover(&self) -> u16 {
+        self.prover
+    }
+
+    pub fn label(&self) -> &'static str {
+        self.label
+    }
+
+    /// Hash of the context and the proof name, to be included into the challenge of proof
+    /// `proof`
+    pub fn tag(&self, proof: &str) -> BigInt {
+        let hash = Sha256::new()
+            .chain(PROOF_CONTEXT_DOMAIN)
+            .chain(self.session.as_bytes())
+            .chain(self.prover.to_be_bytes())
+            .chain((self.label.len() as u64).to_be_bytes())
+            .chain(self.label.as_bytes())
+            .chain((proof.len() as u64).to_be_bytes())
+            .chain(proof.as_bytes())
+            .finalize();
+        BigInt::from_bytes(&hash)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn tag_depends_on_every_part_of_context() {
+        let session = SessionId::random();
+        let tag = session.context(1, "keygen").tag("proof");
+
+        assert_eq!(tag, session.context(1, "keygen").tag("proof"));
+        assert_ne!(tag, SessionId::random().context(1, "keygen").tag("proof"));
+        assert_ne!(tag, session.context(2, "keygen").tag("proof"));
+        assert_ne!(tag, session.context(1, "signing").tag("proof"));
+        assert_ne!(tag, session.context(1, "keygen").tag("other-proof"));
+        assert_ne!(tag, ProofContext::unbound(1, "keygen").tag("proof"));
+    }
+}
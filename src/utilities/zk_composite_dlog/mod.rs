@@ -0,0 +1,98 @@
+#![allow(non_snake_case)]
+
+//! Proof of knowledge of discrete logarithm modulo composite `N`, bound to a [ProofContext]
+//!
+//! Same proof as `CompositeDLogProof` of `zk-paillier` (Girault's identification scheme), which
+//! is used to prove generation of `h1, h2`, except that the challenge also hashes the context,
+//! so a proof made in one session (or by one party) doesn't verify in another. Proofs have the
+//! same fields, so they are serialized the same way.
+//!
+//! Statement: (N, g, ni)
+//! witness x such that ni = g^-x mod N
+
+use curv::arithmetic::traits::*;
+use curv::cryptographic_primitives::hashing::{Digest, DigestExt};
+use curv::BigInt;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use thiserror::Error;
+use zk_paillier::zkproofs::DLogStatement;
+
+use crate::utilities::transcript::ProofContext;
+
+const COMPOSITE_DLOG_PROOF: &str = "composite-dlog";
+
+/// Bit length of the challenge
+const K: usize = 128;
+
+/// Masks are by that many bits longer than `N * 2^K`, so that the response hides the witness
+const STATISTICAL_SECURITY: usize = 128;
+
+#[derive(Error, Debug)]
+pub enum ZkCompositeDLogError {
+    #[error("composite dlog proof verification failed")]
+    Verify,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct CompositeDLogProof {
+    pub x: BigInt,
+    pub y: BigInt,
+}
+
+impl CompositeDLogProof {
+    pub fn prove(statement: &DLogStatement, secret: &BigInt, ctx: &ProofContext) -> Self {
+        let mask_bound =
+            BigInt::from(2).pow((statement.N.bit_length() + K + STATISTICAL_SECURITY) as u32);
+        let r = BigInt::sample_below(&mask_bound);
+        let x = BigInt::mod_pow(&statement.g, &r, &statement.N);
+        let e = challenge(statement, &x, ctx);
+        let y = r + e * secret;
+        CompositeDLogProof { x, y }
+    }
+
+    pub fn verify(
+        &self,
+        statement: &DLogStatement,
+        ctx: &ProofContext,
+    ) -> Result<(), ZkCompositeDLogError> {
+        let (N, g, ni) = (&statement.N, &statement.g, &statement.ni);
+        let one = BigInt::one();
+        let is_unit = |v: &BigInt| v > &one && v < N && BigInt::mod_inv(v, N).is_some();
+        if N % &BigInt::from(2) != one
+            || !is_unit(g)
+            || !is_unit(ni)
+            || !is_unit(&self.x)
+            || self.y < BigInt::zero()
+        {
+            return Err(ZkCompositeDLogError::Verify);
+        }
+
+        let e = challenge(statement, &self.x, ctx);
+        // g^y * ni^e = g^(r + e*x) * g^(-e*x) = g^r
+        let expected = BigInt::mod_mul(
+            &BigInt::mod_pow(g, &self.y, N),
+            &BigInt::mod_pow(ni, &e, N),
+            N,
+        );
+        if expected != self.x {
+            return Err(ZkCompositeDLogError::Verify);
+        }
+        Ok(())
+    }
+}
+
+/// Derives `K`-bit challenge from the transcript
+fn challenge(statement: &DLogStatement, x: &BigInt, ctx: &ProofContext) -> BigInt {
+    let hash = Sha256::new()
+        .chain_bigint(&ctx.tag(COMPOSITE_DLOG_PROOF))
+        .chain_bigint(&statement.N)
+        .chain_bigint(&statement.g)
+        .chain_bigint(&statement.ni)
+        .chain_bigint(x)
+        .finalize();
+    BigInt::from_bytes(&hash[..K / 8])
+}
+
+#[cfg(test)]
+mod test;
@@ -0,0 +1,57 @@
+#![allow(non_snake_case)]
+use crate::utilities::transcript::SessionId;
+use crate::utilities::zk_composite_dlog::*;
+use paillier::{KeyGeneration, Paillier};
+
+fn dlog_statement() -> (DLogStatement, BigInt) {
+    let (ek_tilde, dk_tilde) = Paillier::keypair_with_modulus_size(1024).keys();
+    let one = BigInt::one();
+    let phi = (&dk_tilde.p - &one) * (&dk_tilde.q - &one);
+    let h1 = BigInt::sample_below(&ek_tilde.n);
+    let xhi = BigInt::sample_below(&phi);
+    let h2 = BigInt::mod_inv(&BigInt::mod_pow(&h1, &xhi, &ek_tilde.n), &ek_tilde.n).unwrap();
+    let statement = DLogStatement {
+        N: ek_tilde.n,
+        g: h1,
+        ni: h2,
+    };
+    (statement, xhi)
+}
+
+#[test]
+fn test_composite_dlog_proof() {
+    let (statement, xhi) = dlog_statement();
+    let session = SessionId::random();
+    let ctx = session.context(1, "test");
+
+    let proof = CompositeDLogProof::prove(&statement, &xhi, &ctx);
+    assert!(proof.verify(&statement, &ctx).is_ok());
+    assert!(proof.verify(&statement, &session.context(2, "test")).is_err());
+    assert!(proof.verify(&statement, &session.context(1, "other")).is_err());
+    assert!(proof
+        .verify(&statement, &SessionId::random().context(1, "test"))
+        .is_err());
+
+    let proof_json = serde_json::to_string(&proof).unwrap();
+    let proof: CompositeDLogProof = serde_json::from_str(&proof_json).unwrap();
+    assert!(proof.verify(&statement, &ctx).is_ok());
+}
+
+#[test]
+fn test_wrong_composite_dlog_statement() {
+    let (mut statement, xhi) = dlog_statement();
+    // ni which is not a power of g known to the prover
+    statement.ni = BigInt::sample_below(&statement.N);
+    let ctx = SessionId::random().context(1, "test");
+
+    let proof = CompositeDLogProof::prove(&statement, &xhi, &ctx);
+    assert!(proof.verify(&statement, &ctx).is_err());
+
+    // even modulus
+    let even = DLogStatement {
+        N: &statement.N + BigInt::one(),
+        g: statement.g.clone(),
+        ni: statement.ni.clone(),
+    };
+    assert!(proof.verify(&even, &ctx).is_err());
+}
@@ -0,0 +1,93 @@
+#![allow(non_snake_case)]
+
+//! Proof of correct homomorphic ElGamal encryption, bound to a [ProofContext]
+//!
+//! Same proof as `sigma_correct_homomorphic_elgamal_enc` from curv (GG20, phase 6), except that
+//! the challenge includes the proof context.
+//!
+//! Statement: (G, H, Y, D, E)
+//! witness (x, r) such that D = xH + rY, E = rG
+
+use curv::cryptographic_primitives::hashing::{Digest, DigestExt};
+use curv::cryptographic_primitives::proofs::sigma_correct_homomorphic_elgamal_enc::{
+    HomoElGamalStatement, HomoElGamalWitness,
+};
+use curv::elliptic::curves::{secp256_k1::Secp256k1, Point, Scalar};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use thiserror::Error;
+
+use crate::utilities::transcript::ProofContext;
+
+const HOMO_ELGAMAL_PROOF: &str = "homo-elgamal";
+
+#[derive(Error, Debug)]
+pub enum ZkHomoElGamalError {
+    #[error("zk homomorphic elgamal verification failed")]
+    Verify,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct HomoElGamalProof {
+    T: Point<Secp256k1>,
+    A3: Point<Secp256k1>,
+    z1: Scalar<Secp256k1>,
+    z2: Scalar<Secp256k1>,
+}
+
+impl HomoElGamalProof {
+    pub fn prove(
+        witness: &HomoElGamalWitness<Secp256k1>,
+        statement: &HomoElGamalStatement<Secp256k1>,
+        ctx: &ProofContext,
+    ) -> Self {
+        let s1 = Scalar::<Secp256k1>::random();
+        let s2 = Scalar::<Secp256k1>::random();
+        let A1 = &statement.H * &s1;
+        let A2 = &statement.Y * &s2;
+        let A3 = &statement.G * &s2;
+        let T = A1 + A2;
+        let e = challenge(&T, &A3, statement, ctx);
+        let z1 = &s1 + &witness.x * &e;
+        let z2 = &s2 + &witness.r * &e;
+        HomoElGamalProof { T, A3, z1, z2 }
+    }
+
+    pub fn verify(
+        &self,
+        statement: &HomoElGamalStatement<Secp256k1>,
+        ctx: &ProofContext,
+    ) -> Result<(), ZkHomoElGamalError> {
+        let e = challenge(&self.T, &self.A3, statement, ctx);
+        let z1H_plus_z2Y = &statement.H * &self.z1 + &statement.Y * &self.z2;
+        let T_plus_eD = &self.T + &statement.D * &e;
+        let z2G = &statement.G * &self.z2;
+        let A3_plus_eE = &self.A3 + &statement.E * &e;
+        if z1H_plus_z2Y == T_plus_eD && z2G == A3_plus_eE {
+            Ok(())
+        } else {
+            Err(ZkHomoElGamalError::Verify)
+        }
+    }
+}
+
+fn challenge(
+    T: &Point<Secp256k1>,
+    A3: &Point<Secp256k1>,
+    statement: &HomoElGamalStatement<Secp256k1>,
+    ctx: &ProofContext,
+) -> Scalar<Secp256k1> {
+    Sha256::new()
+        .chain_bigint(&ctx.tag(HOMO_ELGAMAL_PROOF))
+        .chain_point(T)
+        .chain_point(A3)
+        .chain_point(&statement.G)
+        .chain_point(&statement.H)
+        .chain_point(&statement.Y)
+        .chain_point(&statement.D)
+        .chain_point(&statement.E)
+        .result_scalar()
+}
+
+#[cfg(test)]
+mod test;
@@ -0,0 +1,45 @@
+#![allow(non_snake_case)]
+use crate::utilities::transcript::SessionId;
+use crate::utilities::zk_homo_elgamal::*;
+
+fn statement_and_witness() -> (
+    HomoElGamalStatement<Secp256k1>,
+    HomoElGamalWitness<Secp256k1>,
+) {
+    let witness = HomoElGamalWitness {
+        r: Scalar::<Secp256k1>::random(),
+        x: Scalar::<Secp256k1>::random(),
+    };
+    let G = Point::generator() * Scalar::<Secp256k1>::random();
+    let H = Point::<Secp256k1>::base_point2().clone();
+    let Y = Point::generator().to_point();
+    let D = &H * &witness.x + &Y * &witness.r;
+    let E = &G * &witness.r;
+    (HomoElGamalStatement { G, H, Y, D, E }, witness)
+}
+
+#[test]
+fn test_correct_homo_elgamal() {
+    let (statement, witness) = statement_and_witness();
+    let session = SessionId::random();
+    let ctx = session.context(1, "test");
+
+    let proof = HomoElGamalProof::prove(&witness, &statement, &ctx);
+    assert!(proof.verify(&statement, &ctx).is_ok());
+    assert!(proof
+        .verify(&statement, &session.context(2, "test"))
+        .is_err());
+    assert!(proof
+        .verify(&statement, &SessionId::random().context(1, "test"))
+        .is_err());
+}
+
+#[test]
+fn test_wrong_homo_elgamal() {
+    let (mut statement, witness) = statement_and_witness();
+    statement.E = &statement.E + Point::generator().to_point();
+    let ctx = SessionId::random().context(1, "test");
+
+    let proof = HomoElGamalProof::prove(&witness, &statement, &ctx);
+    assert!(proof.verify(&statement, &ctx).is_err());
+}
@@ -0,0 +1,262 @@
+#![allow(non_snake_case)]
+
+//! Paillier-Blum modulus proof Πmod, bound to a [ProofContext]
+//!
+//! We use the proof given in figure 16 of CGGMP21, https://eprint.iacr.org/2021/060.pdf.
+//!
+//! Statement: N
+//! witness (p, q) such that N = pq, p ≡ q ≡ 3 mod 4 and gcd(N, φ(N)) = 1
+//!
+//! Prover picks `w` with Jacobi symbol `-1`, and for each of [M] challenges `y_i` derived from
+//! the transcript sends a fourth root `x_i` of `(-1)^a_i w^b_i y_i` and an `N`-th root `z_i` of
+//! `y_i`.
+
+use curv::arithmetic::traits::*;
+use curv::cryptographic_primitives::hashing::{Digest, DigestExt};
+use curv::BigInt;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use thiserror::Error;
+
+use crate::utilities::transcript::ProofContext;
+
+const PAILLIER_BLUM_MODULUS_PROOF: &str = "paillier-blum-modulus";
+
+/// Number of challenges, soundness error is `2^-M`
+pub const M: usize = 80;
+
+/// Rounds of Miller-Rabin test used to check that `N` is not a prime
+const PRIMALITY_TEST_ROUNDS: usize = 40;
+
+#[derive(Error, Debug)]
+pub enum ZkPaillierBlumModulusError {
+    #[error("paillier-blum modulus proof verification failed")]
+    Verify,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct PaillierBlumModulusStatement {
+    pub N: BigInt,
+}
+
+#[derive(Clone, Debug)]
+pub struct PaillierBlumModulusWitness {
+    pub p: BigInt,
+    pub q: BigInt,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct PaillierBlumModulusProof {
+    w: BigInt,
+    x: Vec<BigInt>,
+    a: Vec<bool>,
+    b: Vec<bool>,
+    z: Vec<BigInt>,
+}
+
+impl PaillierBlumModulusProof {
+    /// Returns `None` if the witness doesn't match the statement or `p, q` are not Blum primes
+    pub fn prove(
+        witness: &PaillierBlumModulusWitness,
+        statement: &PaillierBlumModulusStatement,
+        ctx: &ProofContext,
+    ) -> Option<Self> {
+        let (p, q, N) = (&witness.p, &witness.q, &statement.N);
+        let one = BigInt::one();
+        let three = BigInt::from(3);
+        let four = BigInt::from(4);
+        if &(p * q) != N || p == q || p % &four != three || q % &four != three {
+            return None;
+        }
+        let phi = (p - &one) * (q - &one);
+        let N_inv = BigInt::mod_inv(N, &phi)?;
+        let p_inv = BigInt::mod_inv(p, q)?;
+        let q_inv = BigInt::mod_inv(q, p)?;
+
+        let w = loop {
+            let w = BigInt::sample_below(N);
+            if jacobi(&w, N) == -1 {
+                break w;
+            }
+        };
+        let minus_one = N - &one;
+        // exponents taking a fourth root of a quadratic residue mod Blum prime
+        let e_p = ((p + &one) / &four).pow(2);
+        let e_q = ((q + &one) / &four).pow(2);
+
+        let mut proof = PaillierBlumModulusProof {
+            w,
+            x: Vec::with_capacity(M),
+            a: Vec::with_capacity(M),
+            b: Vec::with_capacity(M),
+            z: Vec::with_capacity(M),
+        };
+        for y in challenges(N, &proof.w, ctx) {
+            BigInt::mod_inv(&y, N)?;
+            let (a, b, y_tag) = [(false, false), (true, false), (false, true), (true, true)]
+                .iter()
+                .map(|&(a, b)| {
+                    let mut y_tag = y.clone();
+                    if a {
+                        y_tag = BigInt::mod_mul(&y_tag, &minus_one, N);
+                    }
+                    if b {
+                        y_tag = BigInt::mod_mul(&y_tag, &proof.w, N);
+                    }
+                    (a, b, y_tag)
+                })
+                .find(|(_, _, y_tag)| {
+                    is_quadratic_residue(y_tag, p) && is_quadratic_residue(y_tag, q)
+                })?;
+            let x_p = BigInt::mod_pow(&(&y_tag % p), &e_p, p);
+            let x_q = BigInt::mod_pow(&(&y_tag % q), &e_q, q);
+            let x = (x_p * q * &q_inv + x_q * p * &p_inv) % N;
+
+            proof.x.push(x);
+            proof.a.push(a);
+            proof.b.push(b);
+            proof.z.push(BigInt::mod_pow(&y, &N_inv, N));
+        }
+        Some(proof)
+    }
+
+    pub fn verify(
+        &self,
+        statement: &PaillierBlumModulusStatement,
+        ctx: &ProofContext,
+    ) -> Result<(), ZkPaillierBlumModulusError> {
+        let N = &statement.N;
+        let one = BigInt::one();
+        let two = BigInt::from(2);
+        let four = BigInt::from(4);
+        if N <= &BigInt::from(3)
+            || N % &two != one
+            || is_probable_prime(N)
+            || self.x.len() != M
+            || self.a.len() != M
+            || self.b.len() != M
+            || self.z.len() != M
+            || self.w <= BigInt::zero()
+            || &self.w >= N
+            || jacobi(&self.w, N) != -1
+        {
+            return Err(ZkPaillierBlumModulusError::Verify);
+        }
+
+        let minus_one = N - &one;
+        let in_range = |v: &BigInt| v >= &one && v < N;
+        for (i, y) in challenges(N, &self.w, ctx).into_iter().enumerate() {
+            let (x, z) = (&self.x[i], &self.z[i]);
+            if !in_range(x) || !in_range(z) || BigInt::mod_pow(z, N, N) != y {
+                return Err(ZkPaillierBlumModulusError::Verify);
+            }
+            let mut y_tag = y;
+            if self.a[i] {
+                y_tag = BigInt::mod_mul(&y_tag, &minus_one, N);
+            }
+            if self.b[i] {
+                y_tag = BigInt::mod_mul(&y_tag, &self.w, N);
+            }
+            if BigInt::mod_pow(x, &four, N) != y_tag {
+                return Err(ZkPaillierBlumModulusError::Verify);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Derives challenges `y_1, ..., y_M` in `Z_N` from the transcript
+fn challenges(N: &BigInt, w: &BigInt, ctx: &ProofContext) -> Vec<BigInt> {
+    let tag = ctx.tag(PAILLIER_BLUM_MODULUS_PROOF);
+    // extra 128 bits make reduction mod N statistically close to uniform
+    let bytes_len = (N.bit_length() + 128 + 7) / 8;
+    (0..M)
+        .map(|i| {
+            let mut bytes = Vec::with_capacity(bytes_len + 32);
+            let mut counter = 0u32;
+            while bytes.len() < bytes_len {
+                let hash = Sha256::new()
+                    .chain_bigint(&tag)
+                    .chain_bigint(N)
+                    .chain_bigint(w)
+                    .chain_bigint(&BigInt::from(i as u32))
+                    .chain_bigint(&BigInt::from(counter))
+                    .finalize();
+                bytes.extend_from_slice(&hash);
+                counter += 1;
+            }
+            BigInt::from_bytes(&bytes[..bytes_len]) % N
+        })
+        .collect()
+}
+
+/// Euler's criterion, `p` must be an odd prime
+fn is_quadratic_residue(x: &BigInt, p: &BigInt) -> bool {
+    let e = (p - BigInt::one()) / BigInt::from(2);
+    BigInt::mod_pow(&(x % p), &e, p) == BigInt::one()
+}
+
+/// Jacobi symbol `(a/n)` for odd positive `n`
+pub fn jacobi(a: &BigInt, n: &BigInt) -> i8 {
+    let zero = BigInt::zero();
+    let one = BigInt::one();
+    let two = BigInt::from(2);
+    let three = BigInt::from(3);
+    let four = BigInt::from(4);
+    let five = BigInt::from(5);
+    let eight = BigInt::from(8);
+
+    let mut a = BigInt::modulus(a, n);
+    let mut n = n.clone();
+    let mut t = 1;
+    while a != zero {
+        while &a % &two == zero {
+            a = &a / &two;
+            let r = &n % &eight;
+            if r == three || r == five {
+                t = -t;
+            }
+        }
+        std::mem::swap(&mut a, &mut n);
+        if &a % &four == three && &n % &four == three {
+            t = -t;
+        }
+        a = &a % &n;
+    }
+    if n == one {
+        t
+    } else {
+        0
+    }
+}
+
+/// Miller-Rabin primality test, `n` must be odd and greater than 3
+fn is_probable_prime(n: &BigInt) -> bool {
+    let one = BigInt::one();
+    let two = BigInt::from(2);
+    let n_minus_one = n - &one;
+    let mut d = n_minus_one.clone();
+    let mut s = 0;
+    while &d % &two == BigInt::zero() {
+        d = &d / &two;
+        s += 1;
+    }
+    'witness: for _ in 0..PRIMALITY_TEST_ROUNDS {
+        let a = BigInt::sample_range(&two, &n_minus_one);
+        let mut x = BigInt::mod_pow(&a, &d, n);
+        if x == one || x == n_minus_one {
+            continue;
+        }
+        for _ in 1..s {
+            x = BigInt::mod_mul(&x, &x, n);
+            if x == n_minus_one {
+                continue 'witness;
+            }
+        }
+        return false;
+    }
+    true
+}
+
+#[cfg(test)]
+mod test;
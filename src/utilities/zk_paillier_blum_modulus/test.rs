@@ -0,0 +1,77 @@
+#![allow(non_snake_case)]
+use crate::utilities::transcript::SessionId;
+use crate::utilities::zk_paillier_blum_modulus::*;
+use paillier::{KeyGeneration, Paillier};
+
+fn blum_modulus() -> (PaillierBlumModulusStatement, PaillierBlumModulusWitness) {
+    let three = BigInt::from(3);
+    let four = BigInt::from(4);
+    loop {
+        let (ek, dk) = Paillier::keypair_with_modulus_size(1024).keys();
+        if &dk.p % &four == three && &dk.q % &four == three {
+            return (
+                PaillierBlumModulusStatement { N: ek.n },
+                PaillierBlumModulusWitness { p: dk.p, q: dk.q },
+            );
+        }
+    }
+}
+
+#[test]
+fn test_paillier_blum_modulus_proof() {
+    let (statement, witness) = blum_modulus();
+    let session = SessionId::random();
+    let ctx = session.context(1, "test");
+
+    let proof = PaillierBlumModulusProof::prove(&witness, &statement, &ctx).unwrap();
+    assert!(proof.verify(&statement, &ctx).is_ok());
+    assert!(proof
+        .verify(&statement, &session.context(2, "test"))
+        .is_err());
+
+    let (other_statement, _) = blum_modulus();
+    assert!(proof.verify(&other_statement, &ctx).is_err());
+}
+
+#[test]
+fn test_malformed_modulus_is_rejected() {
+    let (statement, witness) = blum_modulus();
+    let ctx = SessionId::random().context(1, "test");
+
+    // witness doesn't factor the modulus
+    let wrong_witness = PaillierBlumModulusWitness {
+        p: witness.p.clone(),
+        q: &witness.q + BigInt::from(4),
+    };
+    assert!(PaillierBlumModulusProof::prove(&wrong_witness, &statement, &ctx).is_none());
+
+    // proof for a prime "modulus" never verifies
+    let proof = PaillierBlumModulusProof::prove(&witness, &statement, &ctx).unwrap();
+    let prime = PaillierBlumModulusStatement {
+        N: witness.p.clone(),
+    };
+    assert!(proof.verify(&prime, &ctx).is_err());
+
+    // modulus with primes which are not 3 mod 4 can't be proven
+    let four = BigInt::from(4);
+    let non_blum = loop {
+        let (ek, dk) = Paillier::keypair_with_modulus_size(1024).keys();
+        if &dk.p % &four == BigInt::one() {
+            break (
+                PaillierBlumModulusStatement { N: ek.n },
+                PaillierBlumModulusWitness { p: dk.p, q: dk.q },
+            );
+        }
+    };
+    assert!(PaillierBlumModulusProof::prove(&non_blum.1, &non_blum.0, &ctx).is_none());
+}
+
+#[test]
+fn test_jacobi_symbol() {
+    // (a/15) for a = 0..15
+    let expected = [0, 1, 1, 0, 1, 0, 0, -1, 1, 0, 0, -1, 0, -1, -1];
+    let n = BigInt::from(15);
+    for (a, &expected) in expected.iter().enumerate() {
+        assert_eq!(jacobi(&BigInt::from(a as u32), &n), expected);
+    }
+}
@@ -31,6 +31,10 @@ use serde::{Deserialize, Serialize};
 use sha2::Sha256;
 use thiserror::Error;
 
+use crate::utilities::transcript::ProofContext;
+
+const PDL_W_SLACK_PROOF: &str = "pdl-with-slack";
+
 #[derive(Error, Debug)]
 pub enum ZkPdlWithSlackError {
     #[error("zk pdl with slack verification failed")]
@@ -65,7 +69,11 @@ pub struct PDLwSlackProof {
 }
 
 impl PDLwSlackProof {
-    pub fn prove(witness: &PDLwSlackWitness, statement: &PDLwSlackStatement) -> Self {
+    pub fn prove(
+        witness: &PDLwSlackWitness,
+        statement: &PDLwSlackStatement,
+        ctx: &ProofContext,
+    ) -> Self {
         let q3 = Scalar::<Secp256k1>::group_order().pow(3);
         let q_N_tilde = Scalar::<Secp256k1>::group_order() * &statement.N_tilde;
         let q3_N_tilde = &q3 * &statement.N_tilde;
@@ -100,6 +108,7 @@ impl PDLwSlackProof {
         );
 
         let e = Sha256::new()
+            .chain_bigint(&ctx.tag(PDL_W_SLACK_PROOF))
             .chain_bigint(&BigInt::from_bytes(statement.G.to_bytes(true).as_ref()))
             .chain_bigint(&BigInt::from_bytes(statement.Q.to_bytes(true).as_ref()))
             .chain_bigint(&statement.ciphertext)
@@ -124,8 +133,13 @@ impl PDLwSlackProof {
         }
     }
 
-    pub fn verify(&self, statement: &PDLwSlackStatement) -> Result<(), ZkPdlWithSlackError> {
+    pub fn verify(
+        &self,
+        statement: &PDLwSlackStatement,
+        ctx: &ProofContext,
+    ) -> Result<(), ZkPdlWithSlackError> {
         let e = Sha256::new()
+            .chain_bigint(&ctx.tag(PDL_W_SLACK_PROOF))
             .chain_bigint(&BigInt::from_bytes(statement.G.to_bytes(true).as_ref()))
             .chain_bigint(&BigInt::from_bytes(statement.Q.to_bytes(true).as_ref()))
             .chain_bigint(&statement.ciphertext)
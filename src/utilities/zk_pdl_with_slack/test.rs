@@ -1,4 +1,5 @@
 #![allow(non_snake_case)]
+use crate::utilities::transcript::SessionId;
 use crate::utilities::zk_pdl_with_slack::*;
 use curv::elliptic::curves::{secp256_k1::Secp256k1, Point, Scalar};
 use curv::BigInt;
@@ -59,12 +60,19 @@ fn test_zk_pdl_with_slack() {
 
     let pdl_w_slack_witness = PDLwSlackWitness { x, r: randomness.0 };
 
-    let proof = PDLwSlackProof::prove(&pdl_w_slack_witness, &pdl_w_slack_statement);
+    let session = SessionId::random();
+    let ctx = session.context(1, "test");
+    let proof = PDLwSlackProof::prove(&pdl_w_slack_witness, &pdl_w_slack_statement, &ctx);
     // verify h1,h2, N_tilde
     let setup_result = composite_dlog_proof.verify(&statement);
     assert!(setup_result.is_ok());
-    let result = proof.verify(&pdl_w_slack_statement);
+    let result = proof.verify(&pdl_w_slack_statement, &ctx);
     assert!(result.is_ok());
+    // proof doesn't verify in another session or on behalf of another party
+    let result = proof.verify(&pdl_w_slack_statement, &SessionId::random().context(1, "test"));
+    assert!(result.is_err());
+    let result = proof.verify(&pdl_w_slack_statement, &session.context(2, "test"));
+    assert!(result.is_err());
 }
 
 #[test]
@@ -120,10 +128,11 @@ fn test_zk_pdl_with_slack_soundness() {
 
     let pdl_w_slack_witness = PDLwSlackWitness { x, r: randomness.0 };
 
-    let proof = PDLwSlackProof::prove(&pdl_w_slack_witness, &pdl_w_slack_statement);
+    let ctx = SessionId::random().context(1, "test");
+    let proof = PDLwSlackProof::prove(&pdl_w_slack_witness, &pdl_w_slack_statement, &ctx);
     // verify h1,h2, N_tilde
     let setup_result = composite_dlog_proof.verify(&statement);
     assert!(setup_result.is_ok());
-    let result = proof.verify(&pdl_w_slack_statement);
+    let result = proof.verify(&pdl_w_slack_statement, &ctx);
     assert!(result.is_ok());
 }
@@ -0,0 +1,143 @@
+#![allow(non_snake_case)]
+
+//! Ring-Pedersen parameters proof Πprm, bound to a [ProofContext]
+//!
+//! We use the proof given in figure 17 of CGGMP21, https://eprint.iacr.org/2021/060.pdf, except
+//! that responses are computed over the integers with statistically hiding masks instead of
+//! modulo φ(N), so the prover only needs to know λ and not the factorization of `N`.
+//!
+//! Statement: (N, s, t)
+//! witness λ such that s = t^λ mod N
+
+use curv::arithmetic::traits::*;
+use curv::cryptographic_primitives::hashing::{Digest, DigestExt};
+use curv::BigInt;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use thiserror::Error;
+use zk_paillier::zkproofs::DLogStatement;
+
+use crate::utilities::transcript::ProofContext;
+
+const RING_PEDERSEN_PROOF: &str = "ring-pedersen";
+
+/// Number of binary challenges, soundness error is `2^-M`
+pub const M: usize = 80;
+
+/// Masks are by that many bits longer than `N`, so that responses hide λ
+const STATISTICAL_SECURITY: usize = 128;
+
+#[derive(Error, Debug)]
+pub enum ZkRingPedersenError {
+    #[error("ring-pedersen parameters proof verification failed")]
+    Verify,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct RingPedersenStatement {
+    pub N: BigInt,
+    pub s: BigInt,
+    pub t: BigInt,
+}
+
+impl RingPedersenStatement {
+    /// Statement that `h2` is generated by `h1` for `h1, h2, N_tilde` parameters
+    ///
+    /// Parties prove knowledge of `xhi` such that `h1^xhi = h2^-1`, so `s` is set to `h2^-1`.
+    /// Returns `None` if `h2` is not invertible modulo `N_tilde`.
+    pub fn from_dlog_statement(dlog_statement: &DLogStatement) -> Option<Self> {
+        Some(RingPedersenStatement {
+            N: dlog_statement.N.clone(),
+            s: BigInt::mod_inv(&dlog_statement.ni, &dlog_statement.N)?,
+            t: dlog_statement.g.clone(),
+        })
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct RingPedersenWitness {
+    pub lambda: BigInt,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct RingPedersenProof {
+    A: Vec<BigInt>,
+    z: Vec<BigInt>,
+}
+
+impl RingPedersenProof {
+    pub fn prove(
+        witness: &RingPedersenWitness,
+        statement: &RingPedersenStatement,
+        ctx: &ProofContext,
+    ) -> Self {
+        let mask_bound =
+            BigInt::from(2).pow((statement.N.bit_length() + STATISTICAL_SECURITY) as u32);
+        let a: Vec<BigInt> = (0..M).map(|_| BigInt::sample_below(&mask_bound)).collect();
+        let A: Vec<BigInt> = a
+            .iter()
+            .map(|a_i| BigInt::mod_pow(&statement.t, a_i, &statement.N))
+            .collect();
+        let e = challenge(statement, &A, ctx);
+        let z = a
+            .into_iter()
+            .zip(e)
+            .map(|(a_i, e_i)| if e_i { a_i + &witness.lambda } else { a_i })
+            .collect();
+        RingPedersenProof { A, z }
+    }
+
+    pub fn verify(
+        &self,
+        statement: &RingPedersenStatement,
+        ctx: &ProofContext,
+    ) -> Result<(), ZkRingPedersenError> {
+        let (N, s, t) = (&statement.N, &statement.s, &statement.t);
+        let one = BigInt::one();
+        let is_unit = |v: &BigInt| v > &one && v < N && BigInt::mod_inv(v, N).is_some();
+        if N % &BigInt::from(2) != one
+            || !is_unit(s)
+            || !is_unit(t)
+            || self.A.len() != M
+            || self.z.len() != M
+            || !self.A.iter().all(|A_i| is_unit(A_i))
+        {
+            return Err(ZkRingPedersenError::Verify);
+        }
+
+        let e = challenge(statement, &self.A, ctx);
+        for ((A_i, z_i), e_i) in self.A.iter().zip(&self.z).zip(e) {
+            if z_i < &BigInt::zero() {
+                return Err(ZkRingPedersenError::Verify);
+            }
+            let expected = if e_i {
+                BigInt::mod_mul(A_i, s, N)
+            } else {
+                A_i.clone()
+            };
+            if BigInt::mod_pow(t, z_i, N) != expected {
+                return Err(ZkRingPedersenError::Verify);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Derives `M` binary challenges from the transcript
+fn challenge(statement: &RingPedersenStatement, A: &[BigInt], ctx: &ProofContext) -> Vec<bool> {
+    let hash = A
+        .iter()
+        .fold(
+            Sha256::new()
+                .chain_bigint(&ctx.tag(RING_PEDERSEN_PROOF))
+                .chain_bigint(&statement.N)
+                .chain_bigint(&statement.s)
+                .chain_bigint(&statement.t),
+            |hasher, A_i| hasher.chain_bigint(A_i),
+        )
+        .finalize();
+    (0..M).map(|i| (hash[i / 8] >> (i % 8)) & 1 == 1).collect()
+}
+
+#[cfg(test)]
+mod test;
@@ -0,0 +1,54 @@
+#![allow(non_snake_case)]
+use crate::utilities::transcript::SessionId;
+use crate::utilities::zk_ring_pedersen::*;
+use paillier::{KeyGeneration, Paillier};
+
+fn dlog_statement() -> (DLogStatement, BigInt) {
+    let (ek_tilde, dk_tilde) = Paillier::keypair_with_modulus_size(1024).keys();
+    let one = BigInt::one();
+    let phi = (&dk_tilde.p - &one) * (&dk_tilde.q - &one);
+    let h1 = BigInt::sample_below(&ek_tilde.n);
+    let xhi = BigInt::sample_below(&phi);
+    let h2 = BigInt::mod_inv(&BigInt::mod_pow(&h1, &xhi, &ek_tilde.n), &ek_tilde.n).unwrap();
+    let statement = DLogStatement {
+        N: ek_tilde.n,
+        g: h1,
+        ni: h2,
+    };
+    (statement, xhi)
+}
+
+#[test]
+fn test_ring_pedersen_proof() {
+    let (dlog_statement, xhi) = dlog_statement();
+    let statement = RingPedersenStatement::from_dlog_statement(&dlog_statement).unwrap();
+    let witness = RingPedersenWitness { lambda: xhi };
+    let session = SessionId::random();
+    let ctx = session.context(1, "test");
+
+    let proof = RingPedersenProof::prove(&witness, &statement, &ctx);
+    assert!(proof.verify(&statement, &ctx).is_ok());
+    assert!(proof
+        .verify(&statement, &session.context(2, "test"))
+        .is_err());
+}
+
+#[test]
+fn test_wrong_ring_pedersen_parameters() {
+    let (mut dlog_statement, xhi) = dlog_statement();
+    // h2 which is not a power of h1 known to the prover
+    dlog_statement.ni = BigInt::sample_below(&dlog_statement.N);
+    let statement = RingPedersenStatement::from_dlog_statement(&dlog_statement).unwrap();
+    let witness = RingPedersenWitness { lambda: xhi };
+    let ctx = SessionId::random().context(1, "test");
+
+    let proof = RingPedersenProof::prove(&witness, &statement, &ctx);
+    assert!(proof.verify(&statement, &ctx).is_err());
+
+    // even modulus
+    let even = RingPedersenStatement {
+        N: &statement.N + BigInt::one(),
+        ..statement.clone()
+    };
+    assert!(proof.verify(&even, &ctx).is_err());
+}